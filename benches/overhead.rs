@@ -0,0 +1,100 @@
+//! Measures the overhead a `CircuitBreaker` adds to each request over a bare
+//! service, and counts the allocations it makes per request.
+//!
+//! Run with `cargo bench --bench overhead`.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    convert::Infallible,
+    future::{self, Future},
+    hint::black_box,
+    pin::pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+use tower_breaker::{
+    policy::{ConsecutiveFailures, SlidingFailureRate},
+    CircuitBreaker, Config,
+};
+use tower_service::Service;
+
+const ITERATIONS: u32 = 1_000_000;
+
+/// Counts every allocation made by the benchmark.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// A service which responds immediately.
+struct Echo;
+
+impl Service<u64> for Echo {
+    type Response = u64;
+    type Error = Infallible;
+    type Future = future::Ready<Result<u64, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: u64) -> Self::Future {
+        future::ready(Ok(req))
+    }
+}
+
+/// Passes `ITERATIONS` requests through `svc`, returning the mean time and
+/// number of allocations per request.
+fn run<S>(svc: &mut S) -> (Duration, f64)
+where
+    S: Service<u64, Response = u64>,
+    S::Error: std::fmt::Debug,
+{
+    let mut cx = Context::from_waker(Waker::noop());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        assert!(svc.poll_ready(&mut cx).is_ready());
+        let Poll::Ready(rsp) = pin!(svc.call(black_box(i as u64))).poll(&mut cx) else {
+            panic!("response wasn't ready");
+        };
+        black_box(rsp.unwrap());
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    (elapsed / ITERATIONS, allocations as f64 / ITERATIONS as f64)
+}
+
+fn report(name: &str, (per_request, allocations): (Duration, f64)) {
+    println!("{name:<32} {per_request:>10.1?}/request {allocations:>6.2} allocations/request");
+}
+
+fn main() {
+    report("bare service", run(&mut Echo));
+
+    let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5));
+    report(
+        "ConsecutiveFailures breaker",
+        run(&mut CircuitBreaker::new(config, Echo)),
+    );
+
+    let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5);
+    let config = Config::new(policy, Duration::from_secs(5));
+    report(
+        "SlidingFailureRate breaker",
+        run(&mut CircuitBreaker::new(config, Echo)),
+    );
+}
@@ -0,0 +1,126 @@
+//! An [`axum`] [`Router`] for inspecting and controlling the breakers in a
+//! [`BreakerRegistry`].
+//!
+//! This module is only available when the `admin` feature flag is enabled.
+//!
+//! The router exposes the following endpoints:
+//!
+//! - `GET /breakers`: returns a [snapshot](crate::snapshot::BreakerSnapshot)
+//!   of every breaker in the registry.
+//! - `GET /breakers/{name}`: returns a snapshot of the named breaker.
+//! - `POST /breakers/{name}/open`: [forces](Handle::force_open) the named
+//!   breaker open.
+//! - `POST /breakers/{name}/close`: [forces](Handle::force_close) the named
+//!   breaker closed.
+//! - `POST /breakers/{name}/reset`: [resets](Handle::reset) the named breaker.
+//!
+//! All endpoints respond with JSON, and return `404 Not Found` if the named
+//! breaker does not exist. Because commands are applied by a breaker the next
+//! time it is polled, the `state` in the snapshot returned by a `POST` may not
+//! yet reflect the command, but its `forced` state will.
+//!
+//! The router does not perform any authentication; applications should
+//! take care to only expose it to operators, e.g. by nesting it under an
+//! authenticated route or serving it on an internal-only port.
+use crate::{snapshot::BreakerSnapshot, trace::debug, BreakerRegistry, Handle};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+
+/// Returns a [`Router`] for inspecting and controlling the breakers in
+/// `registry`.
+///
+/// See the [module-level documentation](self) for the endpoints it exposes.
+pub fn router(registry: BreakerRegistry) -> Router {
+    Router::new()
+        .route("/breakers", get(list))
+        .route("/breakers/{name}", get(show))
+        .route("/breakers/{name}/open", post(force_open))
+        .route("/breakers/{name}/close", post(force_close))
+        .route("/breakers/{name}/reset", post(reset))
+        .with_state(registry)
+}
+
+type Response = Result<Json<BreakerSnapshot>, StatusCode>;
+
+async fn list(State(registry): State<BreakerRegistry>) -> Json<Vec<BreakerSnapshot>> {
+    Json(registry.snapshot())
+}
+
+async fn show(State(registry): State<BreakerRegistry>, Path(name): Path<String>) -> Response {
+    with_breaker(&registry, &name, |_| {})
+}
+
+async fn force_open(State(registry): State<BreakerRegistry>, Path(name): Path<String>) -> Response {
+    with_breaker(&registry, &name, Handle::force_open)
+}
+
+async fn force_close(
+    State(registry): State<BreakerRegistry>,
+    Path(name): Path<String>,
+) -> Response {
+    with_breaker(&registry, &name, Handle::force_close)
+}
+
+async fn reset(State(registry): State<BreakerRegistry>, Path(name): Path<String>) -> Response {
+    with_breaker(&registry, &name, Handle::reset)
+}
+
+fn with_breaker(registry: &BreakerRegistry, name: &str, f: impl FnOnce(&Handle)) -> Response {
+    let handle = registry.get(name).ok_or(StatusCode::NOT_FOUND)?;
+    f(&handle);
+    debug!(breaker = name, "admin request");
+    Ok(Json(handle.snapshot()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::SlidingFailureRate, CircuitBreaker, CircuitState, Config};
+    use axum::{body::Body, http::Request};
+    use http_body_util::BodyExt;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn request(router: &Router, method: &str, uri: &str) -> (StatusCode, String) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let rsp = router.clone().oneshot(req).await.unwrap();
+        let status = rsp.status();
+        let body = rsp.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn endpoints() {
+        let registry = BreakerRegistry::new();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_name("foo")
+            .with_registry(registry.clone());
+        let breaker = CircuitBreaker::new(config, ());
+        let router = router(registry);
+
+        let (status, body) = request(&router, "GET", "/breakers").await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains(r#""name":"foo""#), "{body}");
+
+        let (status, _) = request(&router, "GET", "/breakers/bar").await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+
+        let (status, body) = request(&router, "POST", "/breakers/foo/open").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(Some(CircuitState::Open), breaker.handle().forced());
+        assert!(body.contains(r#""forced":"open""#), "{body}");
+
+        let (status, _) = request(&router, "POST", "/breakers/foo/reset").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(None, breaker.handle().forced());
+    }
+}
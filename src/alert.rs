@@ -0,0 +1,137 @@
+//! Alerting when a [`CircuitBreaker`](crate::CircuitBreaker) trips.
+//!
+//! An [`Alerting`] configuration wraps a user-provided asynchronous notifier
+//! (such as a function that posts to a webhook or paging service), and
+//! invokes it when a breaker opens, or when it remains open for longer than
+//! a threshold.
+//!
+//! This module is only available when the `alert` feature flag is enabled.
+use crate::{CircuitState, TripReason};
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+use tokio::{
+    sync::watch,
+    time::{self, Duration},
+};
+
+/// Configures alerts sent when a [`CircuitBreaker`](crate::CircuitBreaker)
+/// trips.
+///
+/// Notifications are sent from a task spawned on the current Tokio runtime,
+/// so a slow notifier never delays the breaker itself.
+///
+/// By default, an alert is sent every time the breaker opens. Use
+/// [`open_longer_than`](Alerting::open_longer_than) to also alert when the
+/// breaker stays open, or [`on_open`](Alerting::on_open) to only alert on
+/// long-lived trips.
+#[derive(Clone)]
+pub struct Alerting {
+    notify: Arc<dyn Fn(Alert) -> Notification + Send + Sync>,
+    on_open: bool,
+    open_longer_than: Option<Duration>,
+}
+
+type Notification = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// An alert describing a tripped [`CircuitBreaker`](crate::CircuitBreaker).
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Alert {
+    /// The name of the breaker, if it has one.
+    pub breaker: Option<String>,
+    /// What the alert is for.
+    pub kind: AlertKind,
+    /// The reason the breaker tripped.
+    pub reason: TripReason,
+}
+
+/// The condition that triggered an [`Alert`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AlertKind {
+    /// The breaker's circuit opened.
+    Opened,
+    /// The breaker's circuit has remained open for the given duration.
+    StillOpen(Duration),
+}
+
+// === impl Alerting ===
+
+impl Alerting {
+    /// Returns a new `Alerting` configuration which sends alerts by calling
+    /// `notify`.
+    pub fn new<F, Fut>(notify: F) -> Self
+    where
+        F: Fn(Alert) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Alerting {
+            notify: Arc::new(move |alert| Box::pin(notify(alert))),
+            on_open: true,
+            open_longer_than: None,
+        }
+    }
+
+    /// Sets whether an alert is sent as soon as the breaker opens.
+    ///
+    /// By default, this is `true`.
+    pub fn on_open(self, on_open: bool) -> Self {
+        Alerting { on_open, ..self }
+    }
+
+    /// Sends an alert if the breaker remains open for longer than
+    /// `threshold`.
+    ///
+    /// At most one such alert is sent each time the breaker trips.
+    pub fn open_longer_than(self, threshold: Duration) -> Self {
+        Alerting {
+            open_longer_than: Some(threshold),
+            ..self
+        }
+    }
+
+    /// Spawns a task which sends the alerts for a single trip.
+    pub(crate) fn tripped(
+        &self,
+        breaker: Option<&str>,
+        reason: TripReason,
+        mut state: watch::Receiver<CircuitState>,
+    ) {
+        if !self.on_open && self.open_longer_than.is_none() {
+            return;
+        }
+        let this = self.clone();
+        let breaker = breaker.map(ToOwned::to_owned);
+        tokio::spawn(async move {
+            if this.on_open {
+                let alert = Alert {
+                    breaker: breaker.clone(),
+                    kind: AlertKind::Opened,
+                    reason,
+                };
+                (this.notify)(alert).await;
+            }
+
+            let Some(threshold) = this.open_longer_than else {
+                return;
+            };
+            let closed = state.wait_for(|state| *state == CircuitState::Closed);
+            if time::timeout(threshold, closed).await.is_err() {
+                let alert = Alert {
+                    breaker,
+                    kind: AlertKind::StillOpen(threshold),
+                    reason,
+                };
+                (this.notify)(alert).await;
+            }
+        });
+    }
+}
+
+impl fmt::Debug for Alerting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Alerting")
+            .field("on_open", &self.on_open)
+            .field("open_longer_than", &self.open_longer_than)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,388 @@
+//! Bounding the number of concurrent calls to a dependency.
+//!
+//! A circuit breaker stops sending requests to a dependency which is
+//! failing, but a dependency which is merely slow can still tie up every
+//! connection, thread, or task a service has, starving its other
+//! dependencies. A [`Bulkhead`] bounds the number of requests in flight to
+//! the service it wraps, optionally queueing a bounded number of requests
+//! until a slot frees up, and rejects any further requests with a
+//! [`BulkheadFull`] error.
+//!
+//! Bulkheads are usually deployed together with a breaker. Placing a
+//! `Bulkhead` inside a [`CircuitBreaker`](crate::CircuitBreaker) means that
+//! requests rejected by the bulkhead are recorded as failures by the
+//! breaker's policy, so a dependency which stays saturated trips the
+//! breaker:
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{bulkhead::Bulkhead, policy::ConsecutiveFailures, CircuitBreaker, Config};
+//!
+//! # let svc = tower::service_fn(|()| async { Ok::<_, tower_breaker::BoxError>(()) });
+//! let bulkhead = Bulkhead::new(svc, 32)
+//!     .with_max_queue(8)
+//!     .with_name("users-api");
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5));
+//! let breaker = CircuitBreaker::new(config, bulkhead);
+//! ```
+use crate::{
+    error::{BoxError, BulkheadFull},
+    trace::trace,
+};
+use std::{
+    borrow::Cow,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+use tower_service::Service;
+
+/// A service which bounds the number of concurrent requests to an inner
+/// service.
+///
+/// Clones of a `Bulkhead` share the same slots and queue, so a bulkhead
+/// should be created once per dependency and cloned wherever the dependency
+/// is called.
+pub struct Bulkhead<S> {
+    inner: S,
+    limits: Arc<Limits>,
+    /// The slot reserved for the next request, once one has been acquired.
+    permit: Option<OwnedSemaphorePermit>,
+    /// The slot being waited for, while this service is queued.
+    waiting: Option<(QueueSlot, Acquire)>,
+    /// Whether the next request is rejected, because both the slots and the
+    /// queue were full when the service was polled.
+    rejected: bool,
+}
+
+/// A point-in-time snapshot of a [`Bulkhead`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct BulkheadSnapshot {
+    /// The bulkhead's name, if it has one.
+    pub name: Option<String>,
+    /// The maximum number of requests which may be in flight.
+    pub max_concurrent: usize,
+    /// The maximum number of requests which may wait for a slot.
+    pub max_queue: usize,
+    /// The number of slots currently in use.
+    pub in_flight: usize,
+    /// The number of requests currently waiting for a slot.
+    pub queued: usize,
+    /// The total number of requests rejected because the bulkhead was full.
+    pub rejected: u64,
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by a [`Bulkhead`].
+    pub struct ResponseFuture<F> {
+        // If this is `None`, the request was rejected.
+        #[pin]
+        future: Option<F>,
+        // Held until the request completes.
+        permit: Option<OwnedSemaphorePermit>,
+        rejected: Option<BulkheadFull>,
+    }
+}
+
+struct Limits {
+    name: Option<Cow<'static, str>>,
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    max_queue: usize,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+type Acquire = Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+/// A place in a bulkhead's queue, which is given up when dropped.
+struct QueueSlot(Arc<Limits>);
+
+// === impl Bulkhead ===
+
+impl<S> Bulkhead<S> {
+    /// Returns a new `Bulkhead` wrapping `inner`, which allows at most
+    /// `max_concurrent` requests to be in flight at once.
+    ///
+    /// By default, requests made while every slot is in use are rejected
+    /// immediately; see [`Bulkhead::with_max_queue`].
+    ///
+    /// # Panics
+    ///
+    /// If `max_concurrent` is 0.
+    pub fn new(inner: S, max_concurrent: usize) -> Self {
+        assert!(max_concurrent > 0, "max_concurrent must be greater than 0");
+        Bulkhead {
+            inner,
+            limits: Limits::new(None, max_concurrent, 0),
+            permit: None,
+            waiting: None,
+            rejected: false,
+        }
+    }
+
+    /// Allows up to `max_queue` requests to wait for a slot while every slot
+    /// is in use, rather than being rejected immediately.
+    ///
+    /// A queued request waits in `poll_ready`, so callers which wait for the
+    /// service to become ready wait for the slot.
+    pub fn with_max_queue(self, max_queue: usize) -> Self {
+        let limits = Limits::new(
+            self.limits.name.clone(),
+            self.limits.max_concurrent,
+            max_queue,
+        );
+        Bulkhead { limits, ..self }
+    }
+
+    /// Sets the bulkhead's name, which is included in [`BulkheadFull`]
+    /// errors and snapshots.
+    pub fn with_name(self, name: impl Into<Cow<'static, str>>) -> Self {
+        let limits = Limits::new(
+            Some(name.into()),
+            self.limits.max_concurrent,
+            self.limits.max_queue,
+        );
+        Bulkhead { limits, ..self }
+    }
+
+    /// Returns a snapshot of the bulkhead's current occupancy.
+    pub fn snapshot(&self) -> BulkheadSnapshot {
+        let limits = &self.limits;
+        BulkheadSnapshot {
+            name: limits.name.as_deref().map(str::to_owned),
+            max_concurrent: limits.max_concurrent,
+            max_queue: limits.max_queue,
+            in_flight: limits.max_concurrent - limits.semaphore.available_permits(),
+            queued: limits.queued.load(Ordering::Acquire),
+            rejected: limits.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the `Bulkhead`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Reserves a slot for the next request, queueing for one if the queue
+    /// isn't full. Returns `false` if the request should be rejected.
+    fn poll_slot(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        if self.permit.is_some() || self.rejected {
+            return Poll::Ready(self.permit.is_some());
+        }
+
+        if self.waiting.is_none() {
+            match self.limits.semaphore.clone().try_acquire_owned() {
+                Ok(permit) => {
+                    self.permit = Some(permit);
+                    return Poll::Ready(true);
+                }
+                Err(_) => match QueueSlot::reserve(&self.limits) {
+                    Some(slot) => {
+                        trace!(name = ?self.limits.name, "Bulkhead full; queueing request");
+                        let acquire = Box::pin(self.limits.semaphore.clone().acquire_owned());
+                        self.waiting = Some((slot, acquire));
+                    }
+                    None => {
+                        trace!(name = ?self.limits.name, "Bulkhead queue full; rejecting request");
+                        self.rejected = true;
+                        return Poll::Ready(false);
+                    }
+                },
+            }
+        }
+
+        let (_, acquire) = self.waiting.as_mut().expect("waiting was just set");
+        let permit = std::task::ready!(acquire.as_mut().poll(cx))
+            .expect("bulkhead semaphore is never closed");
+        self.waiting = None;
+        self.permit = Some(permit);
+        Poll::Ready(true)
+    }
+}
+
+impl<S: Clone> Clone for Bulkhead<S> {
+    fn clone(&self) -> Self {
+        // clones share the same slots, but don't share a reserved slot.
+        Bulkhead {
+            inner: self.inner.clone(),
+            limits: self.limits.clone(),
+            permit: None,
+            waiting: None,
+            rejected: false,
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Bulkhead<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bulkhead")
+            .field("inner", &self.inner)
+            .field("snapshot", &self.snapshot())
+            .field("reserved", &self.permit.is_some())
+            .field("queued", &self.waiting.is_some())
+            .finish()
+    }
+}
+
+impl<S, Req> Service<Req> for Bulkhead<S>
+where
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !std::task::ready!(self.poll_slot(cx)) {
+            // the rejection is returned by the response future, so that it's
+            // treated like any other failed request.
+            return Poll::Ready(Ok(()));
+        }
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        if std::mem::take(&mut self.rejected) {
+            self.limits.rejected.fetch_add(1, Ordering::Relaxed);
+            return ResponseFuture {
+                future: None,
+                permit: None,
+                rejected: Some(BulkheadFull::new(self.limits.name.clone())),
+            };
+        }
+        let permit = self.permit.take();
+        debug_assert!(permit.is_some(), "called before poll_ready");
+        ResponseFuture {
+            future: Some(self.inner.call(req)),
+            permit,
+            rejected: None,
+        }
+    }
+}
+
+// === impl Limits ===
+
+impl Limits {
+    fn new(name: Option<Cow<'static, str>>, max_concurrent: usize, max_queue: usize) -> Arc<Self> {
+        Arc::new(Limits {
+            name,
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            max_queue,
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+        })
+    }
+}
+
+// === impl QueueSlot ===
+
+impl QueueSlot {
+    fn reserve(limits: &Arc<Limits>) -> Option<Self> {
+        limits
+            .queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                (queued < limits.max_queue).then_some(queued + 1)
+            })
+            .ok()?;
+        Some(QueueSlot(limits.clone()))
+    }
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F: fmt::Debug> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture")
+            .field("future", &self.future)
+            .field("rejected", &self.rejected.is_some())
+            .finish()
+    }
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let Some(future) = this.future.as_pin_mut() else {
+            let rejected = this.rejected.take().expect("polled after completion");
+            return Poll::Ready(Err(rejected.into()));
+        };
+        let output = std::task::ready!(future.poll(cx));
+        // free the slot as soon as the request completes.
+        this.permit.take();
+        Poll::Ready(output.map_err(Into::into))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn queues_and_rejects() {
+        let svc = tower::service_fn(|rx: oneshot::Receiver<()>| async move {
+            rx.await.map_err(|_| BoxError::from("canceled"))
+        });
+        let bulkhead = Bulkhead::new(svc, 1)
+            .with_max_queue(1)
+            .with_name("users-api");
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+
+        let (tx1, rx1) = oneshot::channel();
+        let first = tokio::spawn(bulkhead.clone().oneshot(rx1));
+        tokio::task::yield_now().await;
+
+        // the second request waits for the first's slot...
+        let mut queued = bulkhead.clone();
+        assert!(Service::<oneshot::Receiver<()>>::poll_ready(&mut queued, &mut cx).is_pending());
+        assert_eq!(1, bulkhead.snapshot().queued);
+
+        // ...and the third is rejected.
+        let (_, rx3) = oneshot::channel();
+        let error = bulkhead.clone().oneshot(rx3).await.unwrap_err();
+        let error = error.downcast_ref::<BulkheadFull>().unwrap();
+        assert_eq!(Some("users-api"), error.name());
+
+        tx1.send(()).unwrap();
+        first.await.unwrap().unwrap();
+        let (tx2, rx2) = oneshot::channel();
+        let second = queued.ready().await.unwrap().call(rx2);
+        tx2.send(()).unwrap();
+        second.await.unwrap();
+
+        let snapshot = bulkhead.snapshot();
+        assert_eq!(
+            (0, 0, 1),
+            (snapshot.in_flight, snapshot.queued, snapshot.rejected)
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Converting panics in a service into errors.
+//!
+//! A service which panics while handling a request neither succeeds nor
+//! fails, as far as a breaker is concerned: the panic unwinds through the
+//! breaker's response future, so the request's outcome is never recorded.
+//! Wrapping the service in a [`CatchPanic`] inside the breaker converts
+//! panics into [`Panicked`] errors, which the breaker records as failures
+//! like any other, so a handler which keeps panicking trips the breaker:
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{catch_panic::CatchPanic, policy::ConsecutiveFailures, CircuitBreaker, Config};
+//!
+//! # let handler = tower::service_fn(|()| async { Ok::<_, tower_breaker::BoxError>(()) });
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5));
+//! let breaker = CircuitBreaker::new(config, CatchPanic::new(handler));
+//! ```
+use crate::{
+    error::{BoxError, Panicked},
+    trace::debug,
+};
+use std::{
+    any::Any,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+/// A service which converts panics in an inner service into [`Panicked`]
+/// errors.
+///
+/// Panics are caught both when the inner service is called and when its
+/// response future is polled. The inner service is not polled again after
+/// it panics, so it should only be wrapped in `CatchPanic` if it remains
+/// usable after a panic (as most services which don't hold locks or other
+/// state across a panic do).
+#[derive(Clone, Debug)]
+pub struct CatchPanic<S> {
+    inner: S,
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by a [`CatchPanic`].
+    #[derive(Debug)]
+    pub struct ResponseFuture<F> {
+        // If this is `None`, the call panicked, or the future has completed.
+        #[pin]
+        future: Option<F>,
+        panicked: Option<Panicked>,
+    }
+}
+
+// === impl CatchPanic ===
+
+impl<S> CatchPanic<S> {
+    /// Returns a new `CatchPanic` wrapping `inner`.
+    pub fn new(inner: S) -> Self {
+        CatchPanic { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the `CatchPanic`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, Req> Service<Req> for CatchPanic<S>
+where
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.inner.call(req))) {
+            Ok(future) => ResponseFuture {
+                future: Some(future),
+                panicked: None,
+            },
+            Err(payload) => ResponseFuture {
+                future: None,
+                panicked: Some(panicked(payload)),
+            },
+        }
+    }
+}
+
+/// Returns a `Panicked` error describing a caught panic.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn panicked(payload: Box<dyn Any + Send>) -> Panicked {
+    let message = payload
+        .downcast_ref::<&'static str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned());
+    debug!(message = message.as_deref(), "inner service panicked");
+    Panicked::new(message)
+}
+
+// === impl ResponseFuture ===
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let Some(future) = this.future.as_mut().as_pin_mut() else {
+            let panicked = this.panicked.take().expect("polled after completion");
+            return Poll::Ready(Err(panicked.into()));
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(result)) => Poll::Ready(result.map_err(Into::into)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => {
+                // don't poll a future which panicked again.
+                this.future.set(None);
+                Poll::Ready(Err(panicked(payload).into()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::ConsecutiveFailures, CircuitBreaker, Config};
+    use std::time::Duration;
+    use tower::{service_fn, ServiceExt};
+
+    #[tokio::test]
+    async fn panics_trip_breaker() {
+        let handler = service_fn(|panic_in_call: bool| {
+            assert!(!panic_in_call, "panicked in call");
+            std::future::poll_fn(|_| -> Poll<Result<(), BoxError>> { panic!("panicked in future") })
+        });
+        let config =
+            Config::new(ConsecutiveFailures::new(2), Duration::from_secs(5)).with_fail_fast(true);
+        let mut breaker = CircuitBreaker::new(config, CatchPanic::new(handler));
+
+        for (panic_in_call, message) in [(true, "panicked in call"), (false, "panicked in future")]
+        {
+            let error = breaker
+                .ready()
+                .await
+                .unwrap()
+                .call(panic_in_call)
+                .await
+                .unwrap_err();
+            let panicked = error.downcast_ref::<Panicked>().unwrap();
+            assert_eq!(Some(message), panicked.message());
+        }
+        assert!(breaker.ready().await.unwrap().call(false).await.is_err());
+        assert!(breaker.is_tripped());
+    }
+}
@@ -0,0 +1,236 @@
+//! Failure injection for testing circuit breaker configurations.
+//!
+//! A [`FailureInjector`] wraps a service and randomly fails or delays a
+//! configurable fraction of its requests. Placing a `FailureInjector` inside
+//! a [`CircuitBreaker`](crate::CircuitBreaker) in a staging environment
+//! allows verifying that the breaker's configuration actually trips (and
+//! recovers) under controlled failure conditions.
+use crate::{
+    error::{BoxError, InjectedFailure},
+    rng::{self, Rng, SharedRng, XorShift64},
+    timer::{self, SharedTimer, Sleep, Timer},
+    trace::trace,
+};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower_service::Service;
+
+/// A service which randomly fails or delays requests to an inner service.
+///
+/// By default, no failures or delays are injected. Failures are injected
+/// without calling the inner service, and fail with an [`InjectedFailure`]
+/// error.
+#[derive(Clone, Debug)]
+pub struct FailureInjector<S> {
+    inner: S,
+    error_rate: f64,
+    latency: Duration,
+    latency_rate: f64,
+    rng: SharedRng,
+    timer: SharedTimer,
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by a [`FailureInjector`].
+    pub struct ResponseFuture<F> {
+        delay: Option<Sleep>,
+        // If this is `None`, the request fails with an injected failure.
+        #[pin]
+        future: Option<F>,
+    }
+}
+
+// === impl FailureInjector ===
+
+impl<S> FailureInjector<S> {
+    /// Returns a new `FailureInjector` wrapping `inner`.
+    pub fn new(inner: S) -> Self {
+        FailureInjector {
+            inner,
+            error_rate: 0.0,
+            latency: Duration::ZERO,
+            latency_rate: 0.0,
+            rng: rng::shared(XorShift64::from_entropy()),
+            timer: timer::default(),
+        }
+    }
+
+    /// Fails the given fraction of requests, between 0 and 1.
+    ///
+    /// # Panics
+    ///
+    /// If `error_rate` is less than 0 or greater than 1.
+    pub fn with_error_rate(self, error_rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&error_rate),
+            "error rate ({error_rate}) must be in the range [0, 1]"
+        );
+        FailureInjector { error_rate, ..self }
+    }
+
+    /// Delays the given fraction of requests, between 0 and 1, by `latency`.
+    ///
+    /// Delayed requests may also fail, in which case the failure is returned
+    /// once the delay has elapsed.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is less than 0 or greater than 1.
+    pub fn with_latency(self, latency: Duration, rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "latency rate ({rate}) must be in the range [0, 1]"
+        );
+        FailureInjector {
+            latency,
+            latency_rate: rate,
+            ..self
+        }
+    }
+
+    /// Sets the [`Rng`] used to decide which requests fail or are delayed.
+    ///
+    /// Clones of a `FailureInjector` share the same generator. By default, a
+    /// randomly seeded generator is used; a generator with a
+    /// fixed seed makes the injected failures reproducible.
+    pub fn with_rng(self, rng: impl Rng) -> Self {
+        FailureInjector {
+            rng: rng::shared(rng),
+            ..self
+        }
+    }
+
+    /// Sets the [`Timer`] used to delay requests.
+    ///
+    /// By default, Tokio's timer is used if the `rt-tokio` feature flag is
+    /// enabled.
+    pub fn with_timer(self, timer: impl Timer) -> Self {
+        FailureInjector {
+            timer: Arc::new(timer),
+            ..self
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the `FailureInjector`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, Req> Service<Req> for FailureInjector<S>
+where
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let delay =
+            (rng::next_f64(&self.rng) < self.latency_rate).then(|| self.timer.sleep(self.latency));
+        let fail = rng::next_f64(&self.rng) < self.error_rate;
+        let future = if fail {
+            trace!("injecting failure");
+            None
+        } else {
+            Some(self.inner.call(req))
+        };
+        ResponseFuture { delay, future }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F: fmt::Debug> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture")
+            .field("delayed", &self.delay.is_some())
+            .field("future", &self.future)
+            .finish()
+    }
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(delay) = this.delay.as_mut() {
+            std::task::ready!(delay.as_mut().poll(cx));
+            *this.delay = None;
+        }
+        match this.future.as_pin_mut() {
+            Some(future) => future.poll(cx).map_err(Into::into),
+            None => Poll::Ready(Err(InjectedFailure::new().into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+    use tokio::time;
+    use tower::ServiceExt;
+
+    fn svc() -> impl Service<(), Response = (), Error = BoxError> + Clone {
+        tower::service_fn(|()| future::ready(Ok(())))
+    }
+
+    #[tokio::test]
+    async fn error_rate() {
+        let never = FailureInjector::new(svc()).with_error_rate(0.0);
+        for _ in 0..100 {
+            never.clone().oneshot(()).await.unwrap();
+        }
+
+        let always = FailureInjector::new(svc()).with_error_rate(1.0);
+        let error = always.oneshot(()).await.unwrap_err();
+        assert!(error.is::<InjectedFailure>());
+    }
+
+    #[tokio::test]
+    async fn seeded() {
+        async fn failures(seed: u64) -> Vec<bool> {
+            let mut svc = FailureInjector::new(svc())
+                .with_error_rate(0.5)
+                .with_rng(XorShift64::seed(seed));
+            let mut failures = Vec::new();
+            for _ in 0..32 {
+                failures.push(svc.ready().await.unwrap().call(()).await.is_err());
+            }
+            failures
+        }
+
+        assert_eq!(failures(1).await, failures(1).await);
+        assert_ne!(failures(1).await, failures(2).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn latency() {
+        let svc = FailureInjector::new(svc()).with_latency(Duration::from_secs(1), 1.0);
+        let start = time::Instant::now();
+        svc.oneshot(()).await.unwrap();
+        assert_eq!(Duration::from_secs(1), start.elapsed());
+    }
+}
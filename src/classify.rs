@@ -0,0 +1,141 @@
+//! Classifying errors returned by a breaker's inner service.
+//!
+//! By default, every error returned by a breaker's inner service is recorded
+//! as a failure. Some errors don't indicate that the service is unhealthy,
+//! though: for example, a request that was canceled by the caller before it
+//! completed. An error classifier, set using
+//! [`Config::with_error_classifier`](crate::Config::with_error_classifier),
+//! decides which errors are recorded.
+//!
+//! # Classifying HTTP client errors
+//!
+//! Errors returned by HTTP clients such as `hyper` typically wrap the
+//! [`io::Error`] that caused them. [`is_connection_error`] finds connection
+//! failures (such as refused connections and resets) in an error's source
+//! chain. For example, a classifier for `hyper-util`'s client, which records
+//! connection errors and incomplete messages, and ignores requests canceled
+//! by the caller, might look like this:
+//!
+//! ```ignore
+//! use tower_breaker::classify::{self, ErrorClass};
+//!
+//! let config = Config::new(policy, trip_for).with_error_classifier(|error| {
+//!     if let Some(error) = error.downcast_ref::<hyper_util::client::legacy::Error>() {
+//!         if error.is_connect() || classify::is_connection_error(error) {
+//!             return ErrorClass::Failure;
+//!         }
+//!     }
+//!     match error.downcast_ref::<hyper::Error>() {
+//!         Some(error) if error.is_canceled() => ErrorClass::Ignore,
+//!         _ => ErrorClass::Failure,
+//!     }
+//! });
+//! ```
+use std::{error::Error, fmt, io, sync::Arc};
+
+/// How an error returned by a breaker's inner service is recorded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorClass {
+    /// The error is recorded as a failure.
+    Failure,
+    /// The error is not recorded, as though the request was never made.
+    Ignore,
+    /// The error is recorded as a failure in the given category, for
+    /// policies which track categories of failure separately, such as
+    /// [`CategorizedFailureRate`](crate::policy::CategorizedFailureRate).
+    ///
+    /// Other policies record it like any other failure.
+    Category(&'static str),
+}
+
+/// A type-erased error classifier.
+#[derive(Clone, Default)]
+pub(crate) struct Classifier(Option<Arc<ClassifyFn>>);
+
+type ClassifyFn = dyn Fn(&(dyn Error + 'static)) -> ErrorClass + Send + Sync;
+
+/// Returns `true` if `error`, or any error in its [source chain], is an
+/// [`io::Error`] indicating that a connection failed.
+///
+/// This includes connections that were refused, reset, or aborted, and
+/// connections that were closed before a complete message was received.
+///
+/// [source chain]: Error::source
+pub fn is_connection_error(error: &(dyn Error + 'static)) -> bool {
+    let mut error = Some(error);
+    while let Some(e) = error {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            if matches!(
+                io.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::TimedOut
+            ) {
+                return true;
+            }
+        }
+        error = e.source();
+    }
+    false
+}
+
+// === impl Classifier ===
+
+impl Classifier {
+    pub(crate) fn new(
+        f: impl Fn(&(dyn Error + 'static)) -> ErrorClass + Send + Sync + 'static,
+    ) -> Self {
+        Classifier(Some(Arc::new(f)))
+    }
+
+    pub(crate) fn classify(&self, error: &(dyn Error + 'static)) -> ErrorClass {
+        match self.0 {
+            Some(ref f) => f(error),
+            None => ErrorClass::Failure,
+        }
+    }
+}
+
+impl fmt::Debug for Classifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Classifier")
+            .field(&self.0.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An error wrapping another error, like an HTTP client's error.
+    #[derive(Debug)]
+    struct Wrapped(io::Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("client error")
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn connection_errors_in_source_chain() {
+        let reset = Wrapped(io::ErrorKind::ConnectionReset.into());
+        assert!(is_connection_error(&reset));
+
+        let not_found = Wrapped(io::ErrorKind::NotFound.into());
+        assert!(!is_connection_error(&not_found));
+        assert!(!is_connection_error(&crate::error::InjectedFailure::new()));
+    }
+}
@@ -0,0 +1,301 @@
+//! Pluggable time sources.
+//!
+//! By default, circuit breakers and their policies read the current time
+//! from Tokio when the `rt-tokio` feature flag is enabled, so they can
+//! already be tested using Tokio's `pause` and `advance`. Otherwise, the
+//! current time is read from [`std::time::Instant::now`]. A [`Clock`] allows substituting a different time source, such as a
+//! [`ManualClock`] which only advances when told to, for tests and
+//! simulations which should not depend on a runtime's timer at all.
+//!
+//! # Coarse Clocks
+//!
+//! A breaker reads the current time at least once per request, to measure
+//! its latency, and a windowed policy reads it again to advance its window.
+//! On some platforms, reading the time is expensive enough for this to show
+//! up in profiles. A [`CoarseClock`] instead caches the current time, and is
+//! refreshed once per resolution by a single [driver](CoarseClock::driver)
+//! task, at the cost of reporting times up to one resolution stale. To use
+//! one, pass it to both the breaker's [`Config`](crate::Config::with_clock)
+//! and its policy:
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{clock::CoarseClock, policy::SlidingFailureRate, Config};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let clock = CoarseClock::new(Duration::from_millis(5));
+//! tokio::spawn(clock.driver());
+//!
+//! let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5).with_clock(clock.clone());
+//! let config = Config::new(policy, Duration::from_secs(5)).with_clock(clock);
+//! # }
+//! ```
+use crate::timer::{self, SharedTimer, Sleep, Timer};
+use std::{
+    fmt,
+    future::{self, Future},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// A source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// A [`Clock`] which reads the current time from Tokio.
+///
+/// Unlike [`SystemClock`], this clock respects Tokio's
+/// paused time. This requires the `rt-tokio` feature
+/// flag, and is the default clock when that feature is enabled.
+#[cfg(feature = "rt-tokio")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TokioClock;
+
+/// A [`Clock`] which reads the current time from [`Instant::now`].
+///
+/// This is the default clock when the `rt-tokio` feature flag is disabled.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+/// A [`Clock`] whose time only changes when it is explicitly
+/// [advanced](ManualClock::advance).
+///
+/// Cloning a `ManualClock` returns a new reference to the same clock.
+///
+/// A `ManualClock` is also a [`Timer`], whose sleeps complete once the clock
+/// has been advanced past their deadline, so a breaker can be driven
+/// entirely by one without any async runtime:
+///
+/// ```
+/// use std::time::Duration;
+/// use tower_breaker::{clock::ManualClock, policy::ConsecutiveFailures, Config};
+///
+/// let clock = ManualClock::new();
+/// let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+///     .with_clock(clock.clone())
+///     .with_timer(clock.clone());
+/// # drop(config);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<Manual>>);
+
+#[derive(Debug)]
+struct Manual {
+    now: Instant,
+    /// The wakers of sleeps which haven't completed yet.
+    sleeps: Vec<Waker>,
+}
+
+/// A [`Clock`] which caches the current time, refreshing it once per
+/// resolution.
+///
+/// Cloning a `CoarseClock` returns a new reference to the same clock. The
+/// cached time is refreshed by the future returned by
+/// [`driver`](CoarseClock::driver), which must be spawned for the clock to
+/// advance at all. The time it returns never goes backwards.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone)]
+pub struct CoarseClock(Arc<Coarse>);
+
+struct Coarse {
+    resolution: Duration,
+    source: SharedClock,
+    timer: SharedTimer,
+    started: Instant,
+    /// The nanoseconds elapsed between `started` and the last refresh.
+    elapsed: AtomicU64,
+}
+
+pub(crate) type SharedClock = Arc<dyn Clock>;
+
+pub(crate) fn default() -> SharedClock {
+    #[cfg(feature = "rt-tokio")]
+    return Arc::new(TokioClock);
+    #[cfg(not(feature = "rt-tokio"))]
+    return Arc::new(SystemClock);
+}
+
+// === impl TokioClock ===
+
+#[cfg(feature = "rt-tokio")]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+}
+
+// === impl SystemClock ===
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// === impl CoarseClock ===
+
+impl CoarseClock {
+    /// Returns a new `CoarseClock` which refreshes the current time once per
+    /// `resolution`, reading it from the default clock.
+    ///
+    /// # Panics
+    ///
+    /// If `resolution` is zero.
+    pub fn new(resolution: Duration) -> Self {
+        assert!(!resolution.is_zero(), "coarse clock resolution must be > 0");
+        Self::build(resolution, default(), timer::default())
+    }
+
+    /// Returns this clock, refreshing the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// This must be called before the clock is used.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self::build(self.0.resolution, Arc::new(clock), self.0.timer.clone())
+    }
+
+    /// Returns this clock, waiting between refreshes using the provided
+    /// [`Timer`] rather than Tokio's timer.
+    ///
+    /// This must be called before the clock is used.
+    pub fn with_timer(self, timer: impl Timer) -> Self {
+        Self::build(self.0.resolution, self.0.source.clone(), Arc::new(timer))
+    }
+
+    fn build(resolution: Duration, source: SharedClock, timer: SharedTimer) -> Self {
+        let started = source.now();
+        CoarseClock(Arc::new(Coarse {
+            resolution,
+            source,
+            timer,
+            started,
+            elapsed: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns a future which refreshes the cached time once per resolution,
+    /// until the clock has been dropped.
+    ///
+    /// The returned future should be spawned (e.g. with `tokio::spawn`).
+    pub fn driver(&self) -> impl Future<Output = ()> + Send + 'static {
+        let clock = Arc::downgrade(&self.0);
+        let timer = self.0.timer.clone();
+        let resolution = self.0.resolution;
+        async move {
+            loop {
+                timer.sleep(resolution).await;
+                let Some(clock) = Weak::upgrade(&clock) else {
+                    return;
+                };
+                clock.refresh();
+            }
+        }
+    }
+}
+
+impl Clock for CoarseClock {
+    fn now(&self) -> Instant {
+        self.0.started + Duration::from_nanos(self.0.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+impl fmt::Debug for CoarseClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoarseClock")
+            .field("resolution", &self.0.resolution)
+            .field("source", &self.0.source)
+            .field("now", &self.now())
+            .finish()
+    }
+}
+
+// === impl Coarse ===
+
+impl Coarse {
+    fn refresh(&self) {
+        let elapsed = self.source.now().saturating_duration_since(self.started);
+        self.elapsed
+            .fetch_max(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+// === impl ManualClock ===
+
+impl ManualClock {
+    /// Returns a new `ManualClock` starting at the current time, as read
+    /// from the default clock.
+    pub fn new() -> Self {
+        ManualClock(Arc::new(Mutex::new(Manual {
+            now: default().now(),
+            sleeps: Vec::new(),
+        })))
+    }
+
+    /// Advances the clock by `duration`, waking any sleeps it completes.
+    pub fn advance(&self, duration: Duration) {
+        let sleeps = {
+            let mut manual = self.0.lock().unwrap();
+            manual.now += duration;
+            std::mem::take(&mut manual.sleeps)
+        };
+        // Sleeps which haven't elapsed yet re-register when they're polled.
+        for waker in sleeps {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().now
+    }
+}
+
+impl Timer for ManualClock {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let clock = self.0.clone();
+        let deadline = self.now() + duration;
+        Box::pin(future::poll_fn(move |cx| {
+            let mut manual = clock.lock().unwrap();
+            if manual.now >= deadline {
+                return Poll::Ready(());
+            }
+            manual.sleeps.push(cx.waker().clone());
+            Poll::Pending
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "rt-tokio"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn coarse_clock_refreshes() {
+        let clock = CoarseClock::new(Duration::from_millis(10));
+        tokio::spawn(clock.driver());
+        let start = clock.now();
+
+        // the time only changes when the clock is refreshed.
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(start, clock.now());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(start + Duration::from_millis(10), clock.now());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(start + Duration::from_millis(110), clock.now());
+    }
+}
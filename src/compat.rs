@@ -0,0 +1,288 @@
+//! Compatibility with circuit breaker configurations from JVM libraries.
+//!
+//! Teams migrating services from the JVM can port their existing
+//! [Hystrix] or [resilience4j] circuit breaker settings directly, either by
+//! constructing a [`Hystrix`] or [`Resilience4j`] and setting its fields, or
+//! by parsing the settings from key-value properties with `from_properties`.
+//!
+//! Both libraries can require a minimum number of requests before the
+//! failure rate is considered (`requestVolumeThreshold` and
+//! `minimumNumberOfCalls`, respectively). These settings are parsed, but
+//! [`SlidingFailureRate`] does not currently support a minimum request
+//! volume, so they are not yet applied to the returned policy.
+//!
+//! [Hystrix]: https://github.com/Netflix/Hystrix/wiki/Configuration
+//! [resilience4j]: https://resilience4j.readme.io/docs/circuitbreaker
+use crate::{error::ParseError, parse, policy::SlidingFailureRate, Config};
+use std::time::Duration;
+
+/// Hystrix-style circuit breaker settings.
+///
+/// Each field is `None` if it was not set, in which case Hystrix's default is
+/// used.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Hystrix {
+    /// `circuitBreaker.enabled`: whether the circuit breaker is enabled.
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+    /// `circuitBreaker.errorThresholdPercentage`: the error percentage at or
+    /// above which the circuit trips. Defaults to 50.
+    pub error_threshold_percentage: Option<u32>,
+    /// `circuitBreaker.sleepWindowInMilliseconds`: how long the circuit
+    /// remains open once tripped. Defaults to 5 seconds.
+    pub sleep_window: Option<Duration>,
+    /// `circuitBreaker.requestVolumeThreshold`: the minimum number of
+    /// requests in the rolling window before the circuit can trip. Defaults
+    /// to 20.
+    pub request_volume_threshold: Option<u32>,
+    /// `metrics.rollingStats.timeInMilliseconds`: the duration of the rolling
+    /// window over which errors are counted. Defaults to 10 seconds.
+    pub rolling_stats_window: Option<Duration>,
+}
+
+/// resilience4j-style circuit breaker settings.
+///
+/// Each field is `None` if it was not set, in which case resilience4j's
+/// default is used.
+///
+/// Breakers only support time-based sliding windows, so the sliding window
+/// size is always interpreted as a number of seconds, as it is with
+/// resilience4j's `TIME_BASED` window type.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Resilience4j {
+    /// `failureRateThreshold`: the failure percentage at or above which the
+    /// circuit trips. Defaults to 50.
+    pub failure_rate_threshold: Option<f64>,
+    /// `waitDurationInOpenState`: how long the circuit remains open once
+    /// tripped. Defaults to 60 seconds.
+    pub wait_duration_in_open_state: Option<Duration>,
+    /// `slidingWindowSize`: the size of the sliding window, in seconds.
+    /// Defaults to 100.
+    pub sliding_window_size: Option<u32>,
+    /// `minimumNumberOfCalls`: the minimum number of requests in the sliding
+    /// window before the circuit can trip. Defaults to 100.
+    pub minimum_number_of_calls: Option<u32>,
+}
+
+// === impl Hystrix ===
+
+impl Hystrix {
+    /// Parses Hystrix settings from key-value properties, such as
+    /// `hystrix.command.default.circuitBreaker.errorThresholdPercentage`.
+    ///
+    /// Keys are matched by their suffix, so properties may be scoped to a
+    /// command or to the default command. Unrecognized keys are ignored.
+    ///
+    /// # Errors
+    ///
+    /// If the value of a recognized key cannot be parsed.
+    pub fn from_properties<K, V>(
+        properties: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, ParseError>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut settings = Hystrix::default();
+        for (key, value) in properties {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            let with_key = |e: ParseError| e.with_key(key);
+            if key.ends_with("circuitBreaker.enabled") {
+                settings.enabled = Some(parse::boolean(value).map_err(with_key)?);
+            } else if key.ends_with("circuitBreaker.errorThresholdPercentage") {
+                settings.error_threshold_percentage = Some(percentage(value).map_err(with_key)?);
+            } else if key.ends_with("circuitBreaker.sleepWindowInMilliseconds") {
+                settings.sleep_window = Some(millis(value).map_err(with_key)?);
+            } else if key.ends_with("circuitBreaker.requestVolumeThreshold") {
+                settings.request_volume_threshold = Some(count(value).map_err(with_key)?);
+            } else if key.ends_with("metrics.rollingStats.timeInMilliseconds") {
+                settings.rolling_stats_window = Some(millis(value).map_err(with_key)?);
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Returns a [`Config`] equivalent to these settings, or `None` if the
+    /// circuit breaker is disabled.
+    pub fn config(&self) -> Option<Config<SlidingFailureRate>> {
+        if !self.enabled.unwrap_or(true) {
+            return None;
+        }
+        let threshold = self.error_threshold_percentage.unwrap_or(50).min(100);
+        let window = self.rolling_stats_window.unwrap_or(Duration::from_secs(10));
+        let policy = SlidingFailureRate::new(window, f64::from(threshold) / 100.0);
+        let trip_for = self.sleep_window.unwrap_or(Duration::from_secs(5));
+        Some(Config::new(policy, trip_for))
+    }
+}
+
+// === impl Resilience4j ===
+
+impl Resilience4j {
+    /// Parses resilience4j settings from key-value properties, such as
+    /// `resilience4j.circuitbreaker.instances.backend.failureRateThreshold`.
+    ///
+    /// Keys are matched by their last segment, which may be written in either
+    /// camel case (`failureRateThreshold`) or kebab case
+    /// (`failure-rate-threshold`). Durations may be written with a unit
+    /// (`60s`), or as a number of milliseconds. Unrecognized keys are
+    /// ignored.
+    ///
+    /// # Errors
+    ///
+    /// If the value of a recognized key cannot be parsed, or if
+    /// `slidingWindowType` is set to anything other than `TIME_BASED`.
+    pub fn from_properties<K, V>(
+        properties: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, ParseError>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut settings = Resilience4j::default();
+        for (key, value) in properties {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            let with_key = |e: ParseError| e.with_key(key);
+            let name = key.rsplit('.').next().unwrap_or(key).replace('-', "");
+            match name.to_ascii_lowercase().as_str() {
+                "failureratethreshold" => {
+                    let threshold = value
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|t| (0.0..=100.0).contains(t))
+                        .ok_or_else(|| with_key(ParseError::new(value, "a percentage")))?;
+                    settings.failure_rate_threshold = Some(threshold);
+                }
+                "waitdurationinopenstate" => {
+                    let duration = match value.trim().parse::<u64>() {
+                        Ok(ms) => Duration::from_millis(ms),
+                        Err(_) => parse::duration(value).map_err(with_key)?,
+                    };
+                    settings.wait_duration_in_open_state = Some(duration);
+                }
+                "slidingwindowsize" => {
+                    settings.sliding_window_size = Some(count(value).map_err(with_key)?);
+                }
+                "minimumnumberofcalls" => {
+                    settings.minimum_number_of_calls = Some(count(value).map_err(with_key)?);
+                }
+                "slidingwindowtype" if !value.trim().eq_ignore_ascii_case("TIME_BASED") => {
+                    return Err(with_key(ParseError::new(
+                        value,
+                        "`TIME_BASED` (count-based windows are not supported)",
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Returns a [`Config`] equivalent to these settings.
+    pub fn config(&self) -> Config<SlidingFailureRate> {
+        let threshold = self
+            .failure_rate_threshold
+            .unwrap_or(50.0)
+            .clamp(0.0, 100.0);
+        let window = Duration::from_secs(self.sliding_window_size.unwrap_or(100).max(1).into());
+        // resilience4j trips once the failure rate reaches the threshold,
+        // rather than exceeding it, so a threshold of 100% trips once every
+        // call fails.
+        let max_rate = (threshold / 100.0).min(1.0 - f64::EPSILON);
+        let policy = SlidingFailureRate::new(window, max_rate);
+        let trip_for = self
+            .wait_duration_in_open_state
+            .unwrap_or(Duration::from_secs(60));
+        Config::new(policy, trip_for)
+    }
+}
+
+fn millis(value: &str) -> Result<Duration, ParseError> {
+    value
+        .trim()
+        .parse::<u64>()
+        .map(Duration::from_millis)
+        .map_err(|_| ParseError::new(value, "a number of milliseconds"))
+}
+
+fn count(value: &str) -> Result<u32, ParseError> {
+    value
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| ParseError::new(value, "a non-negative integer"))
+}
+
+fn percentage(value: &str) -> Result<u32, ParseError> {
+    count(value)
+        .ok()
+        .filter(|p| *p <= 100)
+        .ok_or_else(|| ParseError::new(value, "a percentage between 0 and 100"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+
+    #[test]
+    fn hystrix() {
+        let settings = Hystrix::from_properties([
+            (
+                "hystrix.command.default.circuitBreaker.errorThresholdPercentage",
+                "25",
+            ),
+            (
+                "hystrix.command.default.circuitBreaker.sleepWindowInMilliseconds",
+                "2500",
+            ),
+            (
+                "hystrix.command.default.metrics.rollingPercentile.timeInMilliseconds",
+                "1",
+            ),
+        ])
+        .unwrap();
+        let config = settings.config().unwrap();
+        assert_eq!(Duration::from_millis(2500), config.trip_for);
+        assert_eq!(Some(0.25), config.policy.snapshot().max_failure_rate);
+
+        let settings = Hystrix::from_properties([("circuitBreaker.enabled", "false")]).unwrap();
+        assert!(settings.config().is_none());
+
+        let error = Hystrix::from_properties([("circuitBreaker.errorThresholdPercentage", "150")])
+            .unwrap_err();
+        assert_eq!(Some("circuitBreaker.errorThresholdPercentage"), error.key());
+    }
+
+    #[test]
+    fn resilience4j() {
+        let settings = Resilience4j::from_properties([
+            (
+                "resilience4j.circuitbreaker.instances.backend.failure-rate-threshold",
+                "20",
+            ),
+            (
+                "resilience4j.circuitbreaker.instances.backend.waitDurationInOpenState",
+                "10s",
+            ),
+            (
+                "resilience4j.circuitbreaker.instances.backend.slidingWindowType",
+                "TIME_BASED",
+            ),
+        ])
+        .unwrap();
+        let config = settings.config();
+        assert_eq!(Duration::from_secs(10), config.trip_for);
+        assert_eq!(Some(0.2), config.policy.snapshot().max_failure_rate);
+
+        let settings = Resilience4j::from_properties([("waitDurationInOpenState", "500")]).unwrap();
+        assert_eq!(
+            Some(Duration::from_millis(500)),
+            settings.wait_duration_in_open_state
+        );
+
+        assert!(Resilience4j::from_properties([("slidingWindowType", "COUNT_BASED")]).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Diagnostics emitted in the shape of [tokio-console] resources.
+//!
+//! Each breaker is represented by a `runtime.resource` span, and changes to
+//! its state and counters are emitted as `runtime::resource::state_update`
+//! events within that span. This is the same convention Tokio uses to
+//! instrument its own resources, so `console-subscriber` (or a custom
+//! subscriber that understands it) can display breakers alongside tasks and
+//! other runtime resources.
+//!
+//! All spans and events are emitted at the `TRACE` level. When the `tracing`
+//! feature flag is disabled, a `Resource` does nothing.
+//!
+//! [tokio-console]: https://github.com/tokio-rs/console
+#[cfg(feature = "tracing")]
+use {crate::CircuitState, std::panic::Location};
+
+/// The console resource representing a single breaker.
+#[derive(Clone, Debug)]
+pub(crate) struct Resource {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+macro_rules! state_update {
+    ($resource:expr, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        $resource.span.in_scope(|| {
+            tracing::trace!(target: "runtime::resource::state_update", $($arg)+)
+        })
+    };
+}
+
+// === impl Resource ===
+
+impl Resource {
+    #[cfg(feature = "tracing")]
+    #[track_caller]
+    pub(crate) fn new(name: Option<&str>) -> Self {
+        let location = Location::caller();
+        let span = tracing::trace_span!(
+            parent: None,
+            "runtime.resource",
+            concrete_type = "CircuitBreaker",
+            kind = "circuit_breaker",
+            breaker = name,
+            loc.file = location.file(),
+            loc.line = location.line(),
+            loc.col = location.column(),
+        );
+        let resource = Resource { span };
+        state_update!(
+            resource,
+            state = CircuitState::Closed.as_str(),
+            state.op = "override"
+        );
+        resource
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn new(_: Option<&str>) -> Self {
+        Resource {}
+    }
+
+    pub(crate) fn opened(&self) {
+        state_update!(
+            self,
+            state = CircuitState::Open.as_str(),
+            state.op = "override",
+            trips = 1u64,
+            trips.op = "add"
+        );
+    }
+
+    pub(crate) fn closed(&self) {
+        state_update!(
+            self,
+            state = CircuitState::Closed.as_str(),
+            state.op = "override"
+        );
+    }
+
+    pub(crate) fn rejected(&self) {
+        state_update!(self, rejected = 1u64, rejected.op = "add");
+    }
+
+    pub(crate) fn succeeded(&self) {
+        state_update!(self, successes = 1u64, successes.op = "add");
+    }
+
+    pub(crate) fn failed(&self) {
+        state_update!(self, failures = 1u64, failures.op = "add");
+    }
+}
@@ -0,0 +1,318 @@
+//! Controlling breakers from a feature-flag or remote-config system.
+//!
+//! A [`ControlSource`] produces [`Control`] commands, which enable, disable,
+//! or force open breakers by name. [`run`] applies the commands to the
+//! breakers in a [`BreakerRegistry`], so that breaker overrides can be
+//! managed in the same place as an application's other runtime switches.
+//!
+//! Sources are provided for Tokio channels, for pushing commands from a
+//! system which notifies the application of changes, and [`PollFlags`]
+//! adapts a feature-flag client which is queried for each flag's current
+//! value:
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{control::{self, ControlCommand, PollFlags}, BreakerRegistry};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! # struct Flags;
+//! # impl Flags { fn string(&self, _: &str) -> Option<String> { None } }
+//! # let flags = Flags;
+//! let registry = BreakerRegistry::new();
+//! // the flag `breaker.users-api` may be set to `"disable"` or `"force_open"`.
+//! let source = PollFlags::new(registry.clone(), Duration::from_secs(10), move |name: &str| {
+//!     match flags.string(&format!("breaker.{name}"))?.as_str() {
+//!         "disable" => Some(ControlCommand::Disable),
+//!         "force_open" => Some(ControlCommand::ForceOpen),
+//!         _ => None,
+//!     }
+//! });
+//! tokio::spawn(control::run(registry, source));
+//! # }
+//! ```
+use crate::{
+    timer::{self, SharedTimer, Timer},
+    trace::debug,
+    BoxError, BreakerRegistry, Handle,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// A source of [`Control`] commands.
+pub trait ControlSource: Send + 'static {
+    /// Returns the next command, or `None` if the source has no more
+    /// commands.
+    fn next(&mut self) -> ControlFuture<'_>;
+}
+
+/// A future returned by a [`ControlSource`].
+pub type ControlFuture<'a> =
+    Pin<Box<dyn Future<Output = Option<Result<Control, BoxError>>> + Send + 'a>>;
+
+/// A command to apply to the breaker with a given name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Control {
+    /// The name of the breaker to apply the command to.
+    pub breaker: String,
+    /// The command to apply.
+    pub command: ControlCommand,
+}
+
+/// A command which overrides a breaker's behavior.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum ControlCommand {
+    /// Clears any override, so that the breaker's policy decides whether its
+    /// circuit is open (see [`Handle::reset`](crate::Handle::reset)).
+    Enable,
+    /// Holds the breaker's circuit closed, so that requests are always passed
+    /// to the inner service (see
+    /// [`Handle::force_close`](crate::Handle::force_close)).
+    Disable,
+    /// Holds the breaker's circuit open, so that no requests are passed to
+    /// the inner service (see
+    /// [`Handle::force_open`](crate::Handle::force_open)).
+    ForceOpen,
+}
+
+/// A [`ControlSource`] which queries a function for each breaker's command.
+///
+/// Every `interval`, the function is called with the name of each breaker in
+/// a [`BreakerRegistry`], and returns the command the breaker should be
+/// under, or `None` if it shouldn't be overridden. A command is produced
+/// whenever a breaker's command changes, including [`ControlCommand::Enable`]
+/// when an override is removed.
+pub struct PollFlags<F> {
+    registry: BreakerRegistry,
+    flag: F,
+    interval: Duration,
+    timer: SharedTimer,
+    polled: bool,
+    commands: HashMap<String, Option<ControlCommand>>,
+    pending: VecDeque<Control>,
+}
+
+/// Applies the commands produced by `source` to the breakers in `registry`,
+/// until `source` has no more commands.
+///
+/// The returned future should be spawned (e.g. with `tokio::spawn`). Errors
+/// produced by `source`, and commands for breakers which aren't in
+/// `registry`, are logged and skipped.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub async fn run(registry: BreakerRegistry, mut source: impl ControlSource) {
+    while let Some(control) = source.next().await {
+        let control = match control {
+            Ok(control) => control,
+            Err(error) => {
+                debug!(%error, "failed to receive breaker control command");
+                continue;
+            }
+        };
+        let Some(handle) = registry.get(&control.breaker) else {
+            debug!(breaker = %control.breaker, "no breaker to control");
+            continue;
+        };
+        debug!(
+            breaker = %control.breaker,
+            command = ?control.command,
+            "applying breaker control command"
+        );
+        control.command.apply(&handle);
+    }
+}
+
+// === impl Control ===
+
+impl Control {
+    /// Returns a new `Control` which applies `command` to the breaker named
+    /// `breaker`.
+    pub fn new(breaker: impl Into<String>, command: ControlCommand) -> Self {
+        Control {
+            breaker: breaker.into(),
+            command,
+        }
+    }
+}
+
+// === impl ControlCommand ===
+
+impl ControlCommand {
+    pub(crate) fn apply(self, handle: &Handle) {
+        match self {
+            ControlCommand::Enable => handle.reset(),
+            ControlCommand::Disable => handle.force_close(),
+            ControlCommand::ForceOpen => handle.force_open(),
+        }
+    }
+}
+
+impl ControlSource for mpsc::Receiver<Control> {
+    fn next(&mut self) -> ControlFuture<'_> {
+        Box::pin(async move { self.recv().await.map(Ok) })
+    }
+}
+
+impl ControlSource for mpsc::UnboundedReceiver<Control> {
+    fn next(&mut self) -> ControlFuture<'_> {
+        Box::pin(async move { self.recv().await.map(Ok) })
+    }
+}
+
+// === impl PollFlags ===
+
+impl<F> PollFlags<F>
+where
+    F: FnMut(&str) -> Option<ControlCommand> + Send + 'static,
+{
+    /// Returns a new `PollFlags` which calls `flag` for each breaker in
+    /// `registry` every `interval`.
+    pub fn new(registry: BreakerRegistry, interval: Duration, flag: F) -> Self {
+        PollFlags {
+            registry,
+            flag,
+            interval,
+            timer: timer::default(),
+            polled: false,
+            commands: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Waits between polls using the provided [`Timer`] rather than Tokio's.
+    pub fn with_timer(self, timer: impl Timer) -> Self {
+        PollFlags {
+            timer: Arc::new(timer),
+            ..self
+        }
+    }
+
+    fn poll_flags(&mut self) {
+        for name in self.registry.names() {
+            let command = (self.flag)(&name);
+            let prev = self.commands.insert(name.clone(), command).flatten();
+            if prev != command {
+                let command = command.unwrap_or(ControlCommand::Enable);
+                self.pending.push_back(Control::new(name, command));
+            }
+        }
+    }
+}
+
+impl<F> ControlSource for PollFlags<F>
+where
+    F: FnMut(&str) -> Option<ControlCommand> + Send + 'static,
+{
+    fn next(&mut self) -> ControlFuture<'_> {
+        Box::pin(async move {
+            loop {
+                if let Some(control) = self.pending.pop_front() {
+                    return Some(Ok(control));
+                }
+                if self.polled {
+                    self.timer.sleep(self.interval).await;
+                }
+                self.polled = true;
+                self.poll_flags();
+            }
+        })
+    }
+}
+
+impl<F> fmt::Debug for PollFlags<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollFlags")
+            .field("interval", &self.interval)
+            .field("commands", &self.commands)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::ConsecutiveFailures, CircuitBreaker, CircuitState, Config};
+    use std::sync::Mutex;
+    use tower::{service_fn, ServiceExt};
+
+    #[tokio::test]
+    async fn polls_flags() {
+        tokio::time::pause();
+        let registry = BreakerRegistry::new();
+        let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+            .with_registry(registry.clone());
+        let svc = || service_fn(|()| async { Ok::<_, BoxError>(()) });
+        let _users = CircuitBreaker::new(config.clone().with_name("users-api"), svc());
+        let _orders = CircuitBreaker::new(config.with_name("orders-api"), svc());
+
+        let flags = Arc::new(Mutex::new(HashMap::new()));
+        flags
+            .lock()
+            .unwrap()
+            .insert("users-api", ControlCommand::ForceOpen);
+        let mut source = PollFlags::new(registry.clone(), Duration::from_secs(10), {
+            let flags = flags.clone();
+            move |name: &str| flags.lock().unwrap().get(name).copied()
+        });
+        let next = |control: Option<Result<Control, BoxError>>| control.unwrap().unwrap();
+
+        assert_eq!(
+            Control::new("users-api", ControlCommand::ForceOpen),
+            next(source.next().await)
+        );
+
+        {
+            let mut flags = flags.lock().unwrap();
+            flags.remove("users-api");
+            flags.insert("orders-api", ControlCommand::Disable);
+        }
+        let mut controls = vec![next(source.next().await), next(source.next().await)];
+        controls.sort_by(|a, b| a.breaker.cmp(&b.breaker));
+        assert_eq!(
+            vec![
+                Control::new("orders-api", ControlCommand::Disable),
+                Control::new("users-api", ControlCommand::Enable),
+            ],
+            controls
+        );
+    }
+
+    #[tokio::test]
+    async fn applies_commands() {
+        let registry = BreakerRegistry::new();
+        let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+            .with_name("users-api")
+            .with_registry(registry.clone())
+            .with_fail_fast(true);
+        let mut breaker =
+            CircuitBreaker::new(config, service_fn(|()| async { Ok::<_, BoxError>(()) }));
+        let handle = breaker.handle();
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tx.send(Control::new("users-api", ControlCommand::ForceOpen))
+            .unwrap();
+        tx.send(Control::new("unknown", ControlCommand::Disable))
+            .unwrap();
+        drop(tx);
+        run(registry, rx).await;
+        assert_eq!(Some(CircuitState::Open), handle.forced());
+
+        // applied once the breaker is polled.
+        breaker.ready().await.unwrap();
+        assert_eq!(CircuitState::Open, breaker.state());
+    }
+}
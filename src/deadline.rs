@@ -0,0 +1,168 @@
+//! Rejecting requests which can't complete before their deadline.
+//!
+//! A request whose caller will give up on it before the inner service could
+//! possibly respond only wastes the service's capacity. A breaker configured
+//! with [`Config::with_deadline_admission`](crate::Config::with_deadline_admission)
+//! tracks the latencies of its recent successful requests, and rejects a
+//! request with a [`DeadlineUnreachable`](crate::error::DeadlineUnreachable)
+//! error if the time remaining before its [`Deadline`] is shorter than
+//! almost every recent request has taken.
+//!
+//! Requests' deadlines are read with a function set with
+//! [`Config::with_deadline`](crate::Config::with_deadline):
+//!
+//! ```
+//! use std::time::{Duration, Instant};
+//! use tower_breaker::{deadline::Deadline, policy::ConsecutiveFailures, Config};
+//!
+//! struct Query {
+//!     deadline: Option<Instant>,
+//! }
+//!
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+//!     // reject queries when 95% of recent queries took longer than the time
+//!     // they have left.
+//!     .with_deadline_admission(0.05)
+//!     .with_deadline(|query: &Query| query.deadline.map(Deadline::new));
+//! ```
+//!
+//! The [`http`](crate::http) and [`grpc`](crate::grpc) middleware read each
+//! request's deadline from a `Deadline` in its extensions instead, and the
+//! `grpc` middleware also reads the `grpc-timeout` header.
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The time by which a request must complete.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+/// The latencies of a breaker's most recent successful requests.
+#[derive(Debug, Default)]
+pub(crate) struct Latencies(Mutex<VecDeque<Duration>>);
+
+/// The number of recent latencies retained.
+const SAMPLES: usize = 128;
+
+/// The number of latencies which must be recorded before requests are
+/// rejected, so that a handful of slow requests don't reject everything.
+const MIN_SAMPLES: usize = 16;
+
+// === impl Deadline ===
+
+impl Deadline {
+    /// Returns a new `Deadline` at the given instant.
+    pub fn new(at: Instant) -> Self {
+        Deadline(at)
+    }
+
+    /// Returns the instant at which the deadline passes.
+    pub fn at(&self) -> Instant {
+        self.0
+    }
+
+    /// Returns how long remains until the deadline passes, as of `now`.
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.0.saturating_duration_since(now)
+    }
+}
+
+impl From<Instant> for Deadline {
+    fn from(at: Instant) -> Self {
+        Deadline(at)
+    }
+}
+
+// === impl Latencies ===
+
+impl Latencies {
+    pub(crate) fn record(&self, latency: Duration) {
+        let mut samples = self.0.lock().unwrap();
+        if samples.len() == SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+
+    /// Returns the given quantile of the recent latencies, or `None` if too
+    /// few have been recorded.
+    pub(crate) fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let mut samples = {
+            let samples = self.0.lock().unwrap();
+            if samples.len() < MIN_SAMPLES {
+                return None;
+            }
+            samples.iter().copied().collect::<Vec<_>>()
+        };
+        samples.sort_unstable();
+        let i = ((samples.len() - 1) as f64 * quantile).round() as usize;
+        Some(samples[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, ManualClock},
+        error::DeadlineUnreachable,
+        policy::ConsecutiveFailures,
+        BoxError, CircuitBreaker, Config,
+    };
+    use tower::{service_fn, Service, ServiceExt};
+
+    #[tokio::test]
+    async fn rejects_doomed_requests() {
+        let clock = ManualClock::new();
+        let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+            .with_name("slow")
+            .with_clock(clock.clone())
+            .with_deadline_admission(0.1)
+            .with_deadline(|deadline: &Option<Deadline>| *deadline);
+        let svc = service_fn({
+            let clock = clock.clone();
+            move |_| {
+                // each request takes 100ms.
+                clock.advance(Duration::from_millis(100));
+                async { Ok::<_, BoxError>(()) }
+            }
+        });
+        let mut breaker = CircuitBreaker::new(config, svc);
+        let deadline = |ms| Some(Deadline::new(clock.now() + Duration::from_millis(ms)));
+
+        // requests are admitted until enough latencies have been recorded.
+        for _ in 0..MIN_SAMPLES {
+            breaker
+                .ready()
+                .await
+                .unwrap()
+                .call(deadline(10))
+                .await
+                .unwrap();
+        }
+
+        let error = breaker
+            .ready()
+            .await
+            .unwrap()
+            .call(deadline(10))
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<DeadlineUnreachable>().unwrap();
+        assert_eq!(Some("slow"), error.name());
+        assert_eq!(Duration::from_millis(100), error.expected_latency());
+
+        // requests without a deadline, or with enough time left, are
+        // admitted.
+        breaker.ready().await.unwrap().call(None).await.unwrap();
+        breaker
+            .ready()
+            .await
+            .unwrap()
+            .call(deadline(150))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! Loading a [`Config`] from environment variables.
+use crate::{
+    error::{ParseError, PolicyError},
+    parse,
+    policy::SlidingFailureRate,
+    Config,
+};
+use std::env;
+
+impl Config<SlidingFailureRate> {
+    /// Returns a new `Config` for a [`SlidingFailureRate`] policy, read from
+    /// environment variables with the given `prefix`.
+    ///
+    /// The following variables are read:
+    ///
+    /// - `{prefix}_WINDOW` (required): the window over which the failure rate
+    ///   is calculated, such as `30s` or `500ms`.
+    /// - `{prefix}_MAX_FAILURE_RATE` (required): the failure rate above which
+    ///   the breaker trips, such as `0.2` or `20%`.
+    /// - `{prefix}_TRIP_FOR` (required): how long the breaker remains open
+    ///   once tripped, such as `5s`.
+    /// - `{prefix}_FAIL_FAST` (optional): whether requests
+    ///   [fail fast](Config::with_fail_fast) while the circuit is open, such
+    ///   as `true` or `false`.
+    ///
+    /// Durations without a unit are interpreted as seconds.
+    ///
+    /// # Errors
+    ///
+    /// If a required variable is not set, or if any variable cannot be
+    /// parsed. The returned [`ParseError`]'s [`key`](ParseError::key) is the
+    /// name of the offending variable.
+    pub fn from_env(prefix: &str) -> Result<Self, ParseError> {
+        Self::from_vars(prefix, |key| env::var(key).ok())
+    }
+
+    fn from_vars(prefix: &str, vars: impl Fn(&str) -> Option<String>) -> Result<Self, ParseError> {
+        let key = |name| format!("{prefix}_{name}");
+        let window = var(&vars, key("WINDOW"), parse::duration)?
+            .ok_or_else(|| ParseError::missing(key("WINDOW"), "a duration"))?;
+        let max_rate = var(&vars, key("MAX_FAILURE_RATE"), parse::rate)?
+            .ok_or_else(|| ParseError::missing(key("MAX_FAILURE_RATE"), "a rate"))?;
+        let trip_for = var(&vars, key("TRIP_FOR"), parse::duration)?
+            .ok_or_else(|| ParseError::missing(key("TRIP_FOR"), "a duration"))?;
+        let fail_fast = var(&vars, key("FAIL_FAST"), parse::boolean)?.unwrap_or(false);
+
+        let policy = SlidingFailureRate::builder(window, max_rate)
+            .build()
+            .map_err(|error| match error {
+                PolicyError::Zero { .. } => {
+                    ParseError::new(&format!("{window:?}"), "a non-zero duration")
+                        .with_key(key("WINDOW"))
+                }
+                _ => ParseError::new(&max_rate.to_string(), "a rate below 1")
+                    .with_key(key("MAX_FAILURE_RATE")),
+            })?;
+        Ok(Config::new(policy, trip_for).with_fail_fast(fail_fast))
+    }
+}
+
+/// Looks up and parses the variable `key`, returning `None` if it is not set.
+fn var<T>(
+    vars: impl Fn(&str) -> Option<String>,
+    key: String,
+    parse: fn(&str) -> Result<T, ParseError>,
+) -> Result<Option<T>, ParseError> {
+    match vars(&key) {
+        Some(value) => parse(&value).map(Some).map_err(|e| e.with_key(key)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<Config<SlidingFailureRate>, ParseError> {
+        let vars = vars.iter().copied().collect::<HashMap<_, _>>();
+        Config::from_vars("BREAKER", |key| vars.get(key).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn parses_vars() {
+        let config = from_vars(&[
+            ("BREAKER_WINDOW", "30s"),
+            ("BREAKER_MAX_FAILURE_RATE", "0.2"),
+            ("BREAKER_TRIP_FOR", "500ms"),
+        ])
+        .unwrap();
+        assert_eq!(Duration::from_millis(500), config.trip_for);
+        assert!(!config.fail_fast);
+        assert_eq!(Some(0.2), config.policy.snapshot().max_failure_rate);
+
+        let config = from_vars(&[
+            ("BREAKER_WINDOW", "30"),
+            ("BREAKER_MAX_FAILURE_RATE", "50%"),
+            ("BREAKER_TRIP_FOR", "1m"),
+            ("BREAKER_FAIL_FAST", "true"),
+        ])
+        .unwrap();
+        assert_eq!(Duration::from_secs(60), config.trip_for);
+        assert!(config.fail_fast);
+    }
+
+    #[test]
+    fn errors() {
+        let error =
+            from_vars(&[("BREAKER_WINDOW", "30s"), ("BREAKER_TRIP_FOR", "5s")]).unwrap_err();
+        assert_eq!(Some("BREAKER_MAX_FAILURE_RATE"), error.key());
+
+        let error = from_vars(&[
+            ("BREAKER_WINDOW", "thirty seconds"),
+            ("BREAKER_MAX_FAILURE_RATE", "0.2"),
+            ("BREAKER_TRIP_FOR", "5s"),
+        ])
+        .unwrap_err();
+        assert_eq!(Some("BREAKER_WINDOW"), error.key());
+        assert_eq!(
+            "invalid value \"thirty seconds\" for BREAKER_WINDOW: expected a duration (e.g. `30s` or `250ms`)",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Translating Envoy [outlier detection] settings into circuit breakers.
+//!
+//! This eases migrating from mesh-level host ejection to in-process circuit
+//! breaking: an [`OutlierDetection`] can be constructed from (or, when the
+//! `serde` feature flag is enabled, deserialized from) the same settings
+//! used to configure an Envoy cluster, and converted into equivalent
+//! breaker [`Config`]s.
+//!
+//! Envoy ejects individual hosts from a load balancing pool, while a breaker
+//! guards a single service, so only the detection types which consider a
+//! single host in isolation are translated:
+//!
+//! - **Consecutive 5xx** detection is translated into a
+//!   [`ConsecutiveFailures`] policy.
+//! - **Failure percentage** detection is translated into a
+//!   [`SlidingFailureRate`] policy evaluated over the detection `interval`.
+//!
+//! Success rate detection, which compares a host against the statistics of
+//! the rest of its cluster, has no in-process equivalent and is ignored, as
+//! are settings which only apply to load balancing pools (such as
+//! `max_ejection_percent`).
+//!
+//! Note that Envoy only counts 5xx responses and connection failures, while
+//! a breaker counts every error returned by the service it wraps.
+//!
+//! [outlier detection]: https://www.envoyproxy.io/docs/envoy/latest/api-v3/config/cluster/v3/outlier_detection.proto
+use crate::{
+    policy::{ConsecutiveFailures, SlidingFailureRate},
+    Config,
+};
+use std::time::Duration;
+
+/// A subset of Envoy's `OutlierDetection` cluster settings.
+///
+/// Each field is `None` if it was not set, in which case Envoy's default is
+/// used. When the `serde` feature flag is enabled, this can be deserialized
+/// from Envoy's JSON or YAML representation, with durations such as `"10s"`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[non_exhaustive]
+pub struct OutlierDetection {
+    /// The number of consecutive 5xx responses after which a host is
+    /// ejected. Defaults to 5.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub consecutive_5xx: Option<u32>,
+    /// The time interval between ejection analysis sweeps. Defaults to 10
+    /// seconds.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "crate::parse::deserialize_duration")
+    )]
+    pub interval: Option<Duration>,
+    /// The base time that a host is ejected for. Defaults to 30 seconds.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "crate::parse::deserialize_duration")
+    )]
+    pub base_ejection_time: Option<Duration>,
+    /// The percentage chance that a host is ejected when consecutive 5xx
+    /// detection triggers. Defaults to 100.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub enforcing_consecutive_5xx: Option<u32>,
+    /// The failure percentage at or above which a host is ejected. Defaults
+    /// to 85.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub failure_percentage_threshold: Option<u32>,
+    /// The percentage chance that a host is ejected when failure percentage
+    /// detection triggers. Defaults to 0.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub enforcing_failure_percentage: Option<u32>,
+}
+
+// === impl OutlierDetection ===
+
+impl OutlierDetection {
+    /// Returns a [`Config`] equivalent to consecutive 5xx detection, or
+    /// `None` if it is disabled.
+    ///
+    /// Consecutive 5xx detection is considered disabled if
+    /// `consecutive_5xx` or `enforcing_consecutive_5xx` is 0. Because
+    /// breakers do not trip probabilistically, any other enforcement
+    /// percentage enables it.
+    pub fn consecutive_5xx_config(&self) -> Option<Config<ConsecutiveFailures>> {
+        let max_failures = self.consecutive_5xx.unwrap_or(5);
+        if max_failures == 0 || self.enforcing_consecutive_5xx.unwrap_or(100) == 0 {
+            return None;
+        }
+        let policy = ConsecutiveFailures::new(max_failures as usize);
+        Some(Config::new(policy, self.base_ejection_time()))
+    }
+
+    /// Returns a [`Config`] equivalent to failure percentage detection, or
+    /// `None` if it is disabled.
+    ///
+    /// Failure percentage detection is considered disabled if
+    /// `enforcing_failure_percentage` is 0, as it is by default. Because
+    /// breakers do not trip probabilistically, any other enforcement
+    /// percentage enables it. A `failure_percentage_threshold` of 100 or more
+    /// can never be exceeded, so it also disables failure percentage
+    /// detection.
+    pub fn failure_percentage_config(&self) -> Option<Config<SlidingFailureRate>> {
+        let threshold = self.failure_percentage_threshold.unwrap_or(85);
+        if self.enforcing_failure_percentage.unwrap_or(0) == 0 || threshold >= 100 {
+            return None;
+        }
+        let window = self.interval.unwrap_or(Duration::from_secs(10));
+        let policy = SlidingFailureRate::new(window, f64::from(threshold) / 100.0);
+        Some(Config::new(policy, self.base_ejection_time()))
+    }
+
+    fn base_ejection_time(&self) -> Duration {
+        self.base_ejection_time.unwrap_or(Duration::from_secs(30))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+
+    #[test]
+    fn defaults() {
+        let outlier = OutlierDetection::default();
+        let config = outlier.consecutive_5xx_config().unwrap();
+        assert_eq!(Duration::from_secs(30), config.trip_for);
+        assert!(outlier.failure_percentage_config().is_none());
+    }
+
+    #[test]
+    fn failure_percentage() {
+        let outlier = OutlierDetection {
+            interval: Some(Duration::from_secs(5)),
+            base_ejection_time: Some(Duration::from_secs(10)),
+            enforcing_consecutive_5xx: Some(0),
+            failure_percentage_threshold: Some(20),
+            enforcing_failure_percentage: Some(100),
+            ..Default::default()
+        };
+        assert!(outlier.consecutive_5xx_config().is_none());
+        let config = outlier.failure_percentage_config().unwrap();
+        assert_eq!(Duration::from_secs(10), config.trip_for);
+        assert_eq!(Some(0.2), config.policy.snapshot().max_failure_rate);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize() {
+        let outlier: OutlierDetection = serde_json::from_str(
+            r#"{
+                "consecutive_5xx": 3,
+                "interval": "1s",
+                "base_ejection_time": "0.5s",
+                "success_rate_stdev_factor": 1900
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            OutlierDetection {
+                consecutive_5xx: Some(3),
+                interval: Some(Duration::from_secs(1)),
+                base_ejection_time: Some(Duration::from_millis(500)),
+                ..Default::default()
+            },
+            outlier
+        );
+    }
+}
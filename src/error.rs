@@ -0,0 +1,437 @@
+//! Errors returned by a [`CircuitBreaker`](crate::CircuitBreaker).
+use crate::policy::TripReason;
+use std::{borrow::Cow, error::Error, fmt, time::Duration};
+
+/// A type-erased error, as returned by a
+/// [`CircuitBreaker`](crate::CircuitBreaker).
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Returned by a [`CircuitBreaker`](crate::CircuitBreaker) configured to
+/// [fail fast](crate::Config::with_fail_fast) when a request is made while
+/// its circuit is open.
+#[derive(Clone, Debug)]
+pub struct CircuitOpen {
+    name: Option<Cow<'static, str>>,
+    reason: Option<TripReason>,
+    retry_after: Option<Duration>,
+    #[cfg(feature = "span-trace")]
+    span_trace: Option<Box<tracing_error::SpanTrace>>,
+}
+
+/// Returned by a [`CircuitBreaker`](crate::CircuitBreaker) when a request
+/// is made after the breaker has been [shut down](crate::Handle::shutdown).
+#[derive(Clone, Debug)]
+pub struct ShutDown {
+    name: Option<Cow<'static, str>>,
+}
+
+/// Returned by a [`CircuitBreaker`](crate::CircuitBreaker) configured with
+/// [deadline admission](crate::Config::with_deadline_admission) when a
+/// request's deadline is too soon for it to complete in time.
+#[derive(Clone, Debug)]
+pub struct DeadlineUnreachable {
+    name: Option<Cow<'static, str>>,
+    remaining: Duration,
+    expected_latency: Duration,
+}
+
+/// Returned when a breaker is constructed without a
+/// [`Timer`](crate::timer::Timer), because the `rt-tokio` feature flag is
+/// disabled and none was provided using
+/// [`Config::with_timer`](crate::Config::with_timer).
+#[derive(Clone, Debug)]
+pub struct NoTimer {
+    name: Option<Cow<'static, str>>,
+}
+
+/// Returned by a [`CatchPanic`](crate::catch_panic::CatchPanic) when its
+/// inner service panics.
+#[derive(Clone, Debug)]
+pub struct Panicked {
+    message: Option<String>,
+}
+
+/// Returned by a [`Bulkhead`](crate::bulkhead::Bulkhead) when a request is
+/// made while all of its slots are in use and its wait queue is full.
+#[derive(Clone, Debug)]
+pub struct BulkheadFull {
+    name: Option<Cow<'static, str>>,
+}
+
+/// Returned by a blocking [`sync::CircuitBreaker`](crate::sync::CircuitBreaker).
+#[derive(Clone, Debug)]
+pub enum CallError<E> {
+    /// The call was not made because the circuit is open.
+    Open(CircuitOpen),
+    /// The call was made, and failed with the given error.
+    Inner(E),
+}
+
+/// Returned by a [`FailureInjector`](crate::chaos::FailureInjector) when it
+/// injects a failure, and by the fake services in the `testkit` module.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct InjectedFailure {}
+
+/// Returned when a configuration value could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// The name of the setting, if known.
+    key: Option<String>,
+    /// The invalid value, or `None` if the setting was missing.
+    input: Option<String>,
+    expected: &'static str,
+}
+
+/// Returned when a [`Policy`](crate::Policy) is configured with invalid
+/// settings.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum PolicyError {
+    /// A setting was outside of its valid range.
+    OutOfRange {
+        /// The name of the setting.
+        parameter: &'static str,
+        /// The invalid value.
+        value: f64,
+        /// The range of valid values.
+        range: &'static str,
+    },
+    /// A setting which must be greater than zero was zero.
+    Zero {
+        /// The name of the setting.
+        parameter: &'static str,
+    },
+}
+
+// === impl CircuitOpen ===
+
+impl CircuitOpen {
+    pub(crate) fn new(name: Option<Cow<'static, str>>, reason: Option<TripReason>) -> Self {
+        CircuitOpen {
+            name,
+            reason,
+            retry_after: None,
+            #[cfg(feature = "span-trace")]
+            span_trace: None,
+        }
+    }
+
+    #[cfg(feature = "span-trace")]
+    pub(crate) fn with_span_trace(self, span_trace: tracing_error::SpanTrace) -> Self {
+        CircuitOpen {
+            span_trace: Some(Box::new(span_trace)),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_retry_after(self, retry_after: Option<Duration>) -> Self {
+        CircuitOpen {
+            retry_after,
+            ..self
+        }
+    }
+
+    /// Returns the name of the breaker that rejected the request, if it has
+    /// one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the reason the circuit was opened, if known.
+    pub fn reason(&self) -> Option<&TripReason> {
+        self.reason.as_ref()
+    }
+
+    /// Returns how long until the circuit is expected to close, if known.
+    ///
+    /// This is `None` if the circuit was forced open, since it stays open
+    /// until it is reset.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Returns the spans which were active when the request was rejected, if
+    /// the breaker [captures them](crate::Config::with_span_traces).
+    ///
+    /// This includes the breaker's own span for the request, along with the
+    /// spans of the layers which passed it the request, so that the context
+    /// of the rejection survives being reported far from where it happened.
+    /// Spans are only captured if the current subscriber includes a
+    /// [`tracing_error::ErrorLayer`].
+    #[cfg(feature = "span-trace")]
+    pub fn span_trace(&self) -> Option<&tracing_error::SpanTrace> {
+        self.span_trace.as_deref()
+    }
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.reason {
+            Some(TripReason::Degraded { .. } | TripReason::RampingUp { .. }) => "shedding load",
+            _ => "open",
+        };
+        match self.name {
+            Some(ref name) => write!(f, "circuit breaker '{name}' is {state}")?,
+            None => write!(f, "circuit breaker is {state}")?,
+        }
+        if let Some(ref reason) = self.reason {
+            write!(f, " ({reason})")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for CircuitOpen {}
+
+// === impl ShutDown ===
+
+impl ShutDown {
+    pub(crate) fn new(name: Option<Cow<'static, str>>) -> Self {
+        ShutDown { name }
+    }
+
+    /// Returns the name of the breaker that rejected the request, if it has
+    /// one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl fmt::Display for ShutDown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "circuit breaker '{name}' has shut down"),
+            None => f.write_str("circuit breaker has shut down"),
+        }
+    }
+}
+
+impl Error for ShutDown {}
+
+// === impl NoTimer ===
+
+impl NoTimer {
+    pub(crate) fn new(name: Option<Cow<'static, str>>) -> Self {
+        NoTimer { name }
+    }
+
+    /// Returns the name of the breaker which couldn't be constructed, if it
+    /// has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl fmt::Display for NoTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "circuit breaker '{name}' has no timer")?,
+            None => f.write_str("circuit breaker has no timer")?,
+        }
+        f.write_str(
+            ": enable the `rt-tokio` feature flag, or provide a `Timer` using `Config::with_timer`",
+        )
+    }
+}
+
+impl Error for NoTimer {}
+
+// === impl DeadlineUnreachable ===
+
+impl DeadlineUnreachable {
+    pub(crate) fn new(
+        name: Option<Cow<'static, str>>,
+        remaining: Duration,
+        expected_latency: Duration,
+    ) -> Self {
+        DeadlineUnreachable {
+            name,
+            remaining,
+            expected_latency,
+        }
+    }
+
+    /// Returns the name of the breaker that rejected the request, if it has
+    /// one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns how long remained before the request's deadline.
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+
+    /// Returns the latency which recent requests have almost all exceeded.
+    pub fn expected_latency(&self) -> Duration {
+        self.expected_latency
+    }
+}
+
+impl fmt::Display for DeadlineUnreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "circuit breaker '{name}' rejected request")?,
+            None => f.write_str("circuit breaker rejected request")?,
+        }
+        write!(
+            f,
+            ": {:?} left before its deadline, but requests take at least {:?}",
+            self.remaining, self.expected_latency
+        )
+    }
+}
+
+impl Error for DeadlineUnreachable {}
+
+// === impl Panicked ===
+
+impl Panicked {
+    pub(crate) fn new(message: Option<String>) -> Self {
+        Panicked { message }
+    }
+
+    /// Returns the panic's message, if it was a string.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.message {
+            Some(ref message) => write!(f, "inner service panicked: {message}"),
+            None => f.write_str("inner service panicked"),
+        }
+    }
+}
+
+impl Error for Panicked {}
+
+// === impl BulkheadFull ===
+
+impl BulkheadFull {
+    pub(crate) fn new(name: Option<Cow<'static, str>>) -> Self {
+        BulkheadFull { name }
+    }
+
+    /// Returns the name of the bulkhead that rejected the request, if it has
+    /// one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl fmt::Display for BulkheadFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "bulkhead '{name}' is full"),
+            None => f.write_str("bulkhead is full"),
+        }
+    }
+}
+
+impl Error for BulkheadFull {}
+
+// === impl CallError ===
+
+impl<E: fmt::Display> fmt::Display for CallError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Open(error) => fmt::Display::fmt(error, f),
+            CallError::Inner(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for CallError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CallError::Open(_) => None,
+            CallError::Inner(error) => Some(error),
+        }
+    }
+}
+
+// === impl InjectedFailure ===
+
+impl InjectedFailure {
+    pub(crate) fn new() -> Self {
+        InjectedFailure {}
+    }
+}
+
+impl fmt::Display for InjectedFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("injected failure")
+    }
+}
+
+impl Error for InjectedFailure {}
+
+// === impl ParseError ===
+
+impl ParseError {
+    pub(crate) fn new(input: &str, expected: &'static str) -> Self {
+        ParseError {
+            key: None,
+            input: Some(input.to_owned()),
+            expected,
+        }
+    }
+
+    pub(crate) fn missing(key: impl Into<String>, expected: &'static str) -> Self {
+        ParseError {
+            key: Some(key.into()),
+            input: None,
+            expected,
+        }
+    }
+
+    pub(crate) fn with_key(self, key: impl Into<String>) -> Self {
+        ParseError {
+            key: Some(key.into()),
+            ..self
+        }
+    }
+
+    /// Returns the name of the setting that could not be parsed, if known.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.key, &self.input) {
+            (Some(key), Some(input)) => write!(f, "invalid value {input:?} for {key}")?,
+            (None, Some(input)) => write!(f, "invalid value {input:?}")?,
+            (Some(key), None) => write!(f, "{key} is not set")?,
+            (None, None) => f.write_str("missing value")?,
+        }
+        write!(f, ": expected {}", self.expected)
+    }
+}
+
+impl Error for ParseError {}
+
+// === impl PolicyError ===
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::OutOfRange {
+                parameter,
+                value,
+                range,
+            } => write!(
+                f,
+                "{parameter} must be in the range {range}, but was {value}"
+            ),
+            PolicyError::Zero { parameter } => write!(f, "{parameter} must be greater than 0"),
+        }
+    }
+}
+
+impl Error for PolicyError {}
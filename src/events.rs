@@ -0,0 +1,259 @@
+//! A stream of the outcomes of a breaker's requests.
+//!
+//! A breaker's metrics summarize its requests, but teams feeding breaker
+//! observations into their own analytics pipelines often want every request.
+//! A breaker configured with
+//! [`Config::with_outcome_events`](crate::Config::with_outcome_events) sends
+//! an [`OutcomeEvent`] for every request it admits or rejects, which a
+//! background task can consume from the [`OutcomeEvents`] stream:
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{events, policy::ConsecutiveFailures, Config};
+//!
+//! let (sender, mut events) = events::channel(1024);
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+//!     .with_name("users")
+//!     .with_outcome_events(sender);
+//!
+//! # async fn forward(_: events::OutcomeEvent) {}
+//! # let _ = async move {
+//! while let Some(event) = events.recv().await {
+//!     forward(event).await;
+//! }
+//! # };
+//! ```
+//!
+//! Sending an event never waits: if the stream's buffer is full because its
+//! consumer has fallen behind, the event is dropped, and counted in
+//! [`OutcomeEvents::dropped`]. The same sender may be shared by several
+//! breakers, whose events are told apart by their
+//! [`breaker`](OutcomeEvent::breaker) names. A breaker configured with
+//! [`Config::with_event_rate_limit`](crate::Config::with_event_rate_limit)
+//! sends at most that many events each second, and counts the events it
+//! suppresses in the [`suppressed`](OutcomeEvent::suppressed) field of the
+//! next event it sends.
+//!
+//! Only breakers which pass requests to their inner service from a
+//! [`CircuitBreaker`](crate::CircuitBreaker), including the
+//! [`http`](crate::http) and [`grpc`](crate::grpc) middleware, send events.
+use crate::{
+    rate_limit::{self, RateLimiter},
+    CircuitState,
+};
+use std::{
+    borrow::Cow,
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::mpsc;
+
+/// Sends [`OutcomeEvent`]s to an [`OutcomeEvents`] stream.
+///
+/// Returned by [`channel`].
+#[derive(Clone)]
+pub struct OutcomeSender {
+    tx: mpsc::Sender<OutcomeEvent>,
+    dropped: Arc<AtomicU64>,
+    /// Limits the events sent by one breaker, if it has an event rate limit.
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+/// A [`Stream`](futures_core::Stream) of the [`OutcomeEvent`]s sent by
+/// breakers.
+///
+/// Returned by [`channel`]. The stream ends once every [`OutcomeSender`],
+/// and every breaker configured with one, has been dropped.
+pub struct OutcomeEvents {
+    rx: mpsc::Receiver<OutcomeEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// The outcome of a single request passed to, or rejected by, a breaker.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct OutcomeEvent {
+    /// The name of the breaker, if it has one.
+    pub breaker: Option<Cow<'static, str>>,
+    /// When the request completed, or was rejected.
+    pub timestamp: SystemTime,
+    /// How long the inner service took to respond, or `None` if the request
+    /// was rejected.
+    pub latency: Option<Duration>,
+    /// How the request's outcome was classified.
+    pub classification: Classification,
+    /// The state of the circuit when the request was admitted or rejected.
+    pub state: CircuitState,
+    /// The number of events the breaker suppressed since the last one it
+    /// sent, because of its
+    /// [event rate limit](crate::Config::with_event_rate_limit).
+    pub suppressed: u64,
+}
+
+/// How the outcome of a request was classified.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Classification {
+    /// The request succeeded.
+    Success,
+    /// The request failed.
+    Failure,
+    /// The request failed with an error in the given
+    /// [category](crate::classify::ErrorClass::Category).
+    Category(&'static str),
+    /// The request failed with an error which the breaker's classifier
+    /// ignores.
+    Ignored,
+    /// The breaker rejected the request without passing it to its inner
+    /// service.
+    Rejected,
+}
+
+/// Returns a new [`OutcomeSender`] and the [`OutcomeEvents`] stream it sends
+/// to, which buffers up to `capacity` events.
+///
+/// # Panics
+///
+/// If `capacity` is 0.
+pub fn channel(capacity: usize) -> (OutcomeSender, OutcomeEvents) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let sender = OutcomeSender {
+        tx,
+        dropped: dropped.clone(),
+        limiter: None,
+    };
+    (sender, OutcomeEvents { rx, dropped })
+}
+
+// === impl OutcomeSender ===
+
+impl OutcomeSender {
+    /// Returns a sender for one breaker, which sends events within the
+    /// limits of `limiter`.
+    pub(crate) fn limited(&self, limiter: RateLimiter) -> Self {
+        OutcomeSender {
+            limiter: Some(Arc::new(limiter)),
+            ..self.clone()
+        }
+    }
+
+    /// Sends the event returned by `event`, or drops it if the stream's
+    /// buffer is full.
+    ///
+    /// If the sender's rate limit has been reached, `event` isn't called.
+    pub(crate) fn send(&self, event: impl FnOnce() -> OutcomeEvent) {
+        let Some(suppressed) = rate_limit::acquire(self.limiter.as_deref()) else {
+            return;
+        };
+        let event = OutcomeEvent {
+            suppressed,
+            ..event()
+        };
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl fmt::Debug for OutcomeSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutcomeSender")
+            .field("capacity", &self.tx.max_capacity())
+            .field("dropped", &self.dropped.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+// === impl OutcomeEvents ===
+
+impl OutcomeEvents {
+    /// Receives the next event, or returns `None` once the stream has ended.
+    pub async fn recv(&mut self) -> Option<OutcomeEvent> {
+        self.rx.recv().await
+    }
+
+    /// Returns the number of events which were dropped because the stream's
+    /// buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl futures_core::Stream for OutcomeEvents {
+    type Item = OutcomeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<OutcomeEvent>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl fmt::Debug for OutcomeEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutcomeEvents")
+            .field("buffered", &self.rx.len())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::ManualClock, policy::ConsecutiveFailures, BoxError, CircuitBreaker, Config,
+    };
+    use tower::{service_fn, Service, ServiceExt};
+
+    #[tokio::test]
+    async fn sends_outcomes() {
+        let clock = ManualClock::new();
+        let (sender, mut events) = channel(3);
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5))
+            .with_name("events")
+            .with_clock(clock.clone())
+            .with_fail_fast(true)
+            .with_outcome_events(sender);
+        let svc = service_fn({
+            let clock = clock.clone();
+            move |fail: bool| {
+                clock.advance(Duration::from_millis(10));
+                async move {
+                    if fail {
+                        Err::<(), BoxError>("failed".into())
+                    } else {
+                        Ok(())
+                    }
+                }
+            }
+        });
+        let mut breaker = CircuitBreaker::new(config, svc);
+        for fail in [false, true, false, false] {
+            let _ = breaker.ready().await.unwrap().call(fail).await;
+        }
+
+        let mut next = || {
+            let event = events.rx.try_recv().unwrap();
+            assert_eq!(Some("events"), event.breaker.as_deref());
+            (event.classification, event.state, event.latency)
+        };
+        let ms = |ms| Some(Duration::from_millis(ms));
+        assert_eq!(
+            (Classification::Success, CircuitState::Closed, ms(10)),
+            next()
+        );
+        assert_eq!(
+            (Classification::Failure, CircuitState::Closed, ms(10)),
+            next()
+        );
+        assert_eq!((Classification::Rejected, CircuitState::Open, None), next());
+        // the buffer was full.
+        assert_eq!(1, events.dropped());
+    }
+}
@@ -0,0 +1,291 @@
+//! Publishing breaker snapshots to a sidecar or node agent.
+//!
+//! A service mesh sidecar or node agent which routes an application's
+//! traffic can't see the decisions made by the application's in-process
+//! breakers. [`run`] periodically publishes a
+//! [snapshot](crate::snapshot::BreakerSnapshot) of every breaker in a
+//! [`BreakerRegistry`] to an [`ExportSink`], so that the agent can take them
+//! into account when routing.
+//!
+//! Each export is a single JSON object, with the Unix time in milliseconds at
+//! which it was taken and the snapshots of the registry's breakers:
+//!
+//! ```json
+//! {"timestamp_ms": 1700000000000, "breakers": [{"name": "users-api", "state": "open", ...}]}
+//! ```
+//!
+//! Sinks are provided for writing exports to a [Unix socket](UnixSocket),
+//! one per line, and for pushing them to an HTTP endpoint in the style of
+//! the Prometheus [Pushgateway](PushGateway):
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use tower_breaker::{export::{self, UnixSocket}, BreakerRegistry};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let registry = BreakerRegistry::new();
+//! let sink = UnixSocket::new("/run/mesh-agent/breakers.sock");
+//! tokio::spawn(export::run(registry, sink, Duration::from_secs(5)));
+//! # }
+//! ```
+//!
+//! This module is only available when the `export` feature flag is enabled.
+use crate::{snapshot::BreakerSnapshot, timer, trace::debug, BoxError, BreakerRegistry};
+use std::{
+    future::Future,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
+
+/// A destination for exported breaker snapshots.
+pub trait ExportSink: Send + Sync + 'static {
+    /// Publishes a single export, encoded as JSON.
+    fn send<'a>(&'a self, export: &'a [u8]) -> ExportFuture<'a>;
+}
+
+/// A future returned by an [`ExportSink`].
+pub type ExportFuture<'a> = Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send + 'a>>;
+
+/// An [`ExportSink`] which writes each export to a Unix stream socket,
+/// followed by a newline.
+///
+/// The socket is connected on first use and reconnected after an I/O error,
+/// so the agent listening on it may be restarted. Writes are made on Tokio's
+/// blocking thread pool.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct UnixSocket {
+    path: Arc<Path>,
+    timeout: Duration,
+    conn: Arc<Mutex<Option<UnixStream>>>,
+}
+
+/// An [`ExportSink`] which `POST`s each export to an HTTP endpoint.
+///
+/// A new connection is made for each export, which is sent with
+/// `Content-Type: application/json`. Only plain HTTP is supported, and any
+/// response status other than `2xx` is an error. Requests are made on Tokio's
+/// blocking thread pool.
+#[derive(Clone, Debug)]
+pub struct PushGateway {
+    addr: Arc<str>,
+    path: Arc<str>,
+    timeout: Duration,
+}
+
+#[derive(serde::Serialize)]
+struct Export<'a> {
+    timestamp_ms: u128,
+    breakers: &'a [BreakerSnapshot],
+}
+
+/// Publishes a snapshot of the breakers in `registry` to `sink` every
+/// `interval`.
+///
+/// The returned future never completes, and should be spawned (e.g. with
+/// `tokio::spawn`). Errors publishing to `sink` are logged, and the next
+/// export is attempted after `interval` as usual.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub async fn run(registry: BreakerRegistry, sink: impl ExportSink, interval: Duration) {
+    let timer = timer::default();
+    loop {
+        let export = encode(&registry.snapshot());
+        if let Err(error) = sink.send(&export).await {
+            debug!(%error, "failed to export breaker snapshots");
+        }
+        timer.sleep(interval).await;
+    }
+}
+
+fn encode(breakers: &[BreakerSnapshot]) -> Vec<u8> {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let export = Export {
+        timestamp_ms,
+        breakers,
+    };
+    serde_json::to_vec(&export).expect("snapshots can always be serialized")
+}
+
+// === impl UnixSocket ===
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Returns a new `UnixSocket` which writes exports to the socket at
+    /// `path`.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        UnixSocket {
+            path: path.as_ref().into(),
+            timeout: Duration::from_secs(1),
+            conn: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets how long to wait for each write. By default, this is one second.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        UnixSocket { timeout, ..self }
+    }
+
+    fn send_blocking(&self, export: &[u8]) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let stream = match *conn {
+            Some(ref mut stream) => stream,
+            None => {
+                let stream = UnixStream::connect(&self.path)?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                conn.insert(stream)
+            }
+        };
+        let result = stream
+            .write_all(export)
+            .and_then(|()| stream.write_all(b"\n"));
+        if result.is_err() {
+            // a partial export may have been written, so start afresh.
+            *conn = None;
+        }
+        result
+    }
+}
+
+#[cfg(unix)]
+impl ExportSink for UnixSocket {
+    fn send<'a>(&'a self, export: &'a [u8]) -> ExportFuture<'a> {
+        let sink = self.clone();
+        let export = export.to_vec();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || sink.send_blocking(&export)).await??;
+            Ok(())
+        })
+    }
+}
+
+// === impl PushGateway ===
+
+impl PushGateway {
+    /// Returns a new `PushGateway` which posts exports to `path` on the HTTP
+    /// server at `addr` (such as `"127.0.0.1:9091"`).
+    pub fn new(addr: impl Into<String>, path: impl Into<String>) -> Self {
+        PushGateway {
+            addr: addr.into().into(),
+            path: path.into().into(),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets how long to wait when connecting to the server, and for each
+    /// read and write. By default, this is one second.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        PushGateway { timeout, ..self }
+    }
+
+    fn send_blocking(&self, export: &[u8]) -> Result<(), BoxError> {
+        let addr = std::net::ToSocketAddrs::to_socket_addrs(&*self.addr)?
+            .next()
+            .ok_or_else(|| format!("{} did not resolve", self.addr))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.addr,
+            export.len()
+        )?;
+        stream.write_all(export)?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(format!("unexpected response: {:?}", status.trim_end()).into()),
+        }
+    }
+}
+
+impl ExportSink for PushGateway {
+    fn send<'a>(&'a self, export: &'a [u8]) -> ExportFuture<'a> {
+        let sink = self.clone();
+        let export = export.to_vec();
+        Box::pin(
+            async move { tokio::task::spawn_blocking(move || sink.send_blocking(&export)).await? },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::ConsecutiveFailures, CircuitBreaker, Config};
+    use std::io::Read;
+    use tower::service_fn;
+
+    fn registry() -> (BreakerRegistry, impl Sized) {
+        let registry = BreakerRegistry::new();
+        let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+            .with_name("users-api")
+            .with_registry(registry.clone());
+        let breaker = CircuitBreaker::new(config, service_fn(|()| async { Ok::<_, BoxError>(()) }));
+        (registry, breaker)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn exports_to_unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let path =
+            std::env::temp_dir().join(format!("tower-breaker-export-{}", std::process::id()));
+        let listener = UnixListener::bind(&path).unwrap();
+        let (registry, _breakers) = registry();
+        let sink = UnixSocket::new(&path);
+        sink.send(&encode(&registry.snapshot())).await.unwrap();
+        sink.send(&encode(&registry.snapshot())).await.unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        for _ in 0..2 {
+            let export: serde_json::Value =
+                serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+            assert_eq!("users-api", export["breakers"][0]["name"]);
+            assert_eq!("closed", export["breakers"][0]["state"]);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pushes_to_gateway() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"}") {
+                let len = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let (registry, _breakers) = registry();
+        let sink = PushGateway::new(addr.to_string(), "/breakers/job/my-app");
+        sink.send(&encode(&registry.snapshot())).await.unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /breakers/job/my-app HTTP/1.1\r\n"));
+        assert!(request.contains("\"name\":\"users-api\""));
+    }
+}
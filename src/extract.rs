@@ -0,0 +1,48 @@
+//! Type-erased functions extracting values from requests.
+use std::{any::Any, fmt, sync::Arc};
+
+/// A type-erased function extracting a `T` from requests of a particular
+/// type.
+///
+/// [`Config`](crate::Config) isn't generic over the breaker's request type,
+/// so functions which inspect requests are stored type-erased, and return
+/// `None` for requests of any other type.
+pub(crate) struct Extractor<T>(Option<Arc<ExtractFn<T>>>);
+
+type ExtractFn<T> = dyn Fn(&dyn Any) -> Option<T> + Send + Sync;
+
+// === impl Extractor ===
+
+impl<T> Extractor<T> {
+    pub(crate) fn new<Req: 'static>(f: impl Fn(&Req) -> Option<T> + Send + Sync + 'static) -> Self {
+        Extractor(Some(Arc::new(move |req: &dyn Any| {
+            req.downcast_ref::<Req>().and_then(&f)
+        })))
+    }
+
+    /// Returns the value extracted from `req`, or `None` if no function was
+    /// provided, or it was provided for a different request type.
+    pub(crate) fn extract<Req: 'static>(&self, req: &Req) -> Option<T> {
+        self.0.as_ref().and_then(|f| f(req))
+    }
+}
+
+impl<T> Clone for Extractor<T> {
+    fn clone(&self) -> Self {
+        Extractor(self.0.clone())
+    }
+}
+
+impl<T> Default for Extractor<T> {
+    fn default() -> Self {
+        Extractor(None)
+    }
+}
+
+impl<T> fmt::Debug for Extractor<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Extractor")
+            .field(&self.0.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
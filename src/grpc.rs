@@ -0,0 +1,761 @@
+//! gRPC client middleware.
+//!
+//! gRPC calls report their outcome with a `grpc-status` code, which is sent in
+//! the response's trailers (or, for calls that fail before a response is
+//! sent, in its headers) rather than as an HTTP error. The [`GrpcBreaker`]
+//! middleware in this module wraps a gRPC client's transport, such as a
+//! `tonic` `Channel`, and classifies calls by their `grpc-status`:
+//!
+//! - Calls which fail with a status indicating that the server is unhealthy
+//!   (`UNKNOWN`, `DEADLINE_EXCEEDED`, `RESOURCE_EXHAUSTED`, `INTERNAL`,
+//!   `UNAVAILABLE`, or `DATA_LOSS`) are recorded as failures.
+//! - Calls which fail with a status caused by the request (such as
+//!   `NOT_FOUND` or `INVALID_ARGUMENT`) are recorded as successes, since the
+//!   server handled them.
+//!
+//! `RESOURCE_EXHAUSTED` failures may be
+//! [weighted more heavily](crate::Config::with_overload_weight) than others,
+//! since they signal that the server is overloaded.
+//!
+//! Lumping every server-side failure together can cause both over- and
+//! under-tripping: `UNAVAILABLE` usually means the server can't be reached at
+//! all, while `DEADLINE_EXCEEDED` may only mean that it's slow, or that the
+//! caller's deadline was too short. How calls with a particular status are
+//! recorded can be overridden with
+//! [`with_status_class`](GrpcBreaker::with_status_class):
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{
+//!     grpc::{code, GrpcBreakerLayer, StatusClass},
+//!     policy::ConsecutiveFailures,
+//!     Config,
+//! };
+//!
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(10));
+//! let layer = GrpcBreakerLayer::new(config)
+//!     // a single `UNAVAILABLE` counts as five failures.
+//!     .with_status_class(code::UNAVAILABLE, StatusClass::Failures(5))
+//!     // deadlines are the caller's problem.
+//!     .with_status_class(code::DEADLINE_EXCEEDED, StatusClass::Ignore);
+//! # drop(layer);
+//! ```
+//!
+//! While the circuit is open, calls are rejected with an `UNAVAILABLE`
+//! status rather than a transport error, so a `tonic` client sees an
+//! idiomatic [`Status::unavailable`] whose message describes the open
+//! circuit. If it's known when the circuit will close, the rejection
+//! includes a `grpc-retry-pushback-ms` trailer.
+//!
+//! Like the [`http`](crate::http) middleware, the breaker inserts the
+//! circuit's [`CircuitState`](crate::CircuitState) and the breaker's
+//! [`Handle`](crate::Handle) into the extensions of each request it forwards.
+//!
+//! Because streaming calls report their status once the response body
+//! completes, a call's outcome is recorded when its trailers are received.
+//! Calls whose response bodies are dropped before completing are not
+//! recorded.
+//!
+//! For long-lived streams, such as server-streaming subscriptions, waiting
+//! for the stream to end before recording anything means the breaker learns
+//! nothing until hours later. In [streaming mode](GrpcBreaker::with_streaming),
+//! a call is recorded as a success as soon as its response headers are
+//! received, and a stream which later disconnects or ends with a failure
+//! status is recorded as a failure in addition.
+//!
+//! This requires the `grpc` feature flag.
+//!
+//! [`Status::unavailable`]: https://docs.rs/tonic/latest/tonic/struct.Status.html#method.unavailable
+use crate::{
+    deadline::Deadline, error::ShutDown, service::Admitted, trace::Span, CircuitBreaker, Config,
+    Policy,
+};
+use ::http::{header, HeaderMap, HeaderValue, Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+#[cfg(feature = "grpc-control")]
+pub mod control;
+
+/// A [`Layer`] which wraps gRPC client transports in a [`GrpcBreaker`].
+#[derive(Clone, Debug)]
+pub struct GrpcBreakerLayer<P> {
+    config: Config<P>,
+    classes: StatusClasses,
+    streaming: bool,
+}
+
+/// gRPC client middleware which classifies calls by their `grpc-status`, and
+/// rejects calls with an `UNAVAILABLE` status while the circuit is open.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone)]
+pub struct GrpcBreaker<P, S> {
+    breaker: CircuitBreaker<P, S>,
+    classes: StatusClasses,
+    streaming: bool,
+    /// Set if the breaker has been shut down, so that `call` rejects the
+    /// request rather than passing it to the inner service.
+    shut_down: Option<ShutDown>,
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by [`GrpcBreaker`].
+    #[derive(Debug)]
+    pub struct ResponseFuture<P, F> {
+        // If this is `None`, the call was rejected.
+        #[pin]
+        future: Option<F>,
+        call: Option<Call<P>>,
+        rejected: Option<Rejected>,
+        span: Span,
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// The response body returned by [`GrpcBreaker`], which records the
+    /// call's outcome once its trailers are received.
+    #[derive(Debug)]
+    pub struct ResponseBody<P, B> {
+        #[pin]
+        inner: B,
+        call: Option<Call<P>>,
+    }
+}
+
+/// How calls which complete with a particular `grpc-status` are recorded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StatusClass {
+    /// The call is recorded as a success.
+    Success,
+    /// The call is recorded as a failure.
+    Failure,
+    /// The call is recorded as the given number of failures by the breaker's
+    /// policy, so that the status trips the breaker sooner than other
+    /// failures.
+    Failures(usize),
+    /// The call is not recorded, as though it was never made.
+    Ignore,
+}
+
+/// Overrides of how calls are recorded, indexed by `grpc-status` code.
+#[derive(Clone, Debug, Default)]
+struct StatusClasses(Arc<[Option<StatusClass>; 17]>);
+
+/// A call which has been passed to the inner service, and whose outcome has
+/// yet to be recorded.
+#[derive(Debug)]
+struct Call<P> {
+    admitted: Admitted<P>,
+    classes: StatusClasses,
+    streaming: bool,
+    /// Whether the call's stream was established and recorded as a success.
+    established: bool,
+}
+
+/// Why a call was rejected without being passed to the inner service.
+#[derive(Debug)]
+struct Rejected {
+    message: String,
+    retry_after: Option<Duration>,
+}
+
+/// `grpc-status` codes.
+///
+/// See <https://grpc.github.io/grpc/core/md_doc_statuscodes.html>.
+pub mod code {
+    #![allow(missing_docs)]
+    pub const OK: u16 = 0;
+    pub const CANCELLED: u16 = 1;
+    pub const UNKNOWN: u16 = 2;
+    pub const INVALID_ARGUMENT: u16 = 3;
+    pub const DEADLINE_EXCEEDED: u16 = 4;
+    pub const NOT_FOUND: u16 = 5;
+    pub const ALREADY_EXISTS: u16 = 6;
+    pub const PERMISSION_DENIED: u16 = 7;
+    pub const RESOURCE_EXHAUSTED: u16 = 8;
+    pub const FAILED_PRECONDITION: u16 = 9;
+    pub const ABORTED: u16 = 10;
+    pub const OUT_OF_RANGE: u16 = 11;
+    pub const UNIMPLEMENTED: u16 = 12;
+    pub const INTERNAL: u16 = 13;
+    pub const UNAVAILABLE: u16 = 14;
+    pub const DATA_LOSS: u16 = 15;
+    pub const UNAUTHENTICATED: u16 = 16;
+}
+
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+const GRPC_RETRY_PUSHBACK_MS: &str = "grpc-retry-pushback-ms";
+
+// === impl GrpcBreakerLayer ===
+
+impl<P> GrpcBreakerLayer<P> {
+    /// Returns a new `GrpcBreakerLayer` which wraps transports in a breaker
+    /// configured by `config`.
+    ///
+    /// Because calls made while the circuit is open are rejected with an
+    /// `UNAVAILABLE` status rather than waiting for the circuit to close,
+    /// the breakers are always configured to
+    /// [fail fast](Config::with_fail_fast).
+    pub fn new(config: Config<P>) -> Self {
+        GrpcBreakerLayer {
+            config: config.with_fail_fast(true),
+            classes: StatusClasses::default(),
+            streaming: false,
+        }
+    }
+
+    /// Sets whether the breakers this layer produces record calls as
+    /// long-lived streams.
+    ///
+    /// See [`GrpcBreaker::with_streaming`] for details.
+    pub fn with_streaming(self, streaming: bool) -> Self {
+        GrpcBreakerLayer { streaming, ..self }
+    }
+
+    /// Overrides how calls which complete with the `grpc-status` `code` are
+    /// recorded by the breakers this layer produces.
+    ///
+    /// See [`GrpcBreaker::with_status_class`] for details.
+    ///
+    /// # Panics
+    ///
+    /// If `code` is not a valid `grpc-status` code, or `class` is
+    /// [`StatusClass::Failures`] with a count of 0.
+    pub fn with_status_class(self, code: u16, class: StatusClass) -> Self {
+        GrpcBreakerLayer {
+            classes: self.classes.with(code, class),
+            ..self
+        }
+    }
+}
+
+impl<P, S> Layer<S> for GrpcBreakerLayer<P>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+{
+    type Service = GrpcBreaker<P, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcBreaker {
+            classes: self.classes.clone(),
+            streaming: self.streaming,
+            ..GrpcBreaker::new(CircuitBreaker::new(self.config.clone(), inner))
+        }
+    }
+}
+
+// === impl GrpcBreaker ===
+
+impl<P, S> GrpcBreaker<P, S> {
+    /// Wraps `breaker` in a `GrpcBreaker` middleware.
+    ///
+    /// Unlike [`GrpcBreakerLayer`], this does not change whether the breaker
+    /// fails fast. If it doesn't, calls wait for the circuit to close.
+    pub fn new(breaker: CircuitBreaker<P, S>) -> Self {
+        GrpcBreaker {
+            breaker,
+            classes: StatusClasses::default(),
+            streaming: false,
+            shut_down: None,
+        }
+    }
+
+    /// Sets whether calls are recorded as long-lived streams.
+    ///
+    /// When `streaming` is `true`, a call is recorded as a success once its
+    /// response headers are received, rather than once its trailers are.
+    /// If the stream then disconnects, or ends with a status which would be
+    /// recorded as a failure, a failure is recorded as well. Calls which fail
+    /// before a response is received are recorded as usual.
+    ///
+    /// Because a stream's outcome isn't known until it ends, a failed stream
+    /// is recorded as both a success and a failure. Policies which count
+    /// consecutive failures never trip on streams which fail after being
+    /// established unless they trip on a single failure, so a failure rate
+    /// policy is usually a better fit for streaming calls.
+    pub fn with_streaming(self, streaming: bool) -> Self {
+        GrpcBreaker { streaming, ..self }
+    }
+
+    /// Overrides how calls which complete with the `grpc-status` `code` are
+    /// recorded.
+    ///
+    /// By default, calls are recorded as described in the
+    /// [module-level documentation](self). Calls which fail before a
+    /// `grpc-status` is received are always recorded as failures.
+    ///
+    /// # Panics
+    ///
+    /// If `code` is not a valid `grpc-status` code, or `class` is
+    /// [`StatusClass::Failures`] with a count of 0.
+    pub fn with_status_class(self, code: u16, class: StatusClass) -> Self {
+        GrpcBreaker {
+            classes: self.classes.with(code, class),
+            ..self
+        }
+    }
+
+    /// Returns a reference to the wrapped breaker.
+    pub fn breaker(&self) -> &CircuitBreaker<P, S> {
+        &self.breaker
+    }
+}
+
+impl<P, S, ReqBody, RspBody> Service<Request<ReqBody>> for GrpcBreaker<P, S>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    S: Service<Request<ReqBody>, Response = Response<RspBody>>,
+    RspBody: Default,
+{
+    type Response = Response<ResponseBody<P, RspBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<P, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match ready!(self.breaker.poll_circuit(cx)) {
+            Ok(true) => {
+                self.shut_down = None;
+                self.breaker.poll_inner_ready(cx)
+            }
+            Ok(false) => Poll::Ready(Ok(())),
+            Err(error) => {
+                self.shut_down = Some(error);
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        if let Some(ref error) = self.shut_down {
+            return ResponseFuture::rejected(error.to_string(), None, Span::none());
+        }
+
+        let (priority, deadline) = crate::http::admission(&req);
+        let deadline = deadline.or_else(|| {
+            let timeout = grpc_timeout(req.headers())?;
+            Some(Deadline::new(self.breaker.now() + timeout))
+        });
+        let (span, admitted) = self.breaker.admit(priority, deadline);
+        match admitted {
+            Ok(admitted) => {
+                crate::http::insert_extensions(&self.breaker, &mut req);
+                ResponseFuture {
+                    future: Some(span.in_scope(|| self.breaker.inner_mut().call(req))),
+                    call: Some(Call {
+                        admitted,
+                        classes: self.classes.clone(),
+                        streaming: self.streaming,
+                        established: false,
+                    }),
+                    rejected: None,
+                    span,
+                }
+            }
+            Err(error) => {
+                self.breaker.rejected(&error, || crate::http::head(&req));
+                ResponseFuture::rejected(error.to_string(), error.retry_after(), span)
+            }
+        }
+    }
+}
+
+impl<P, S> fmt::Debug for GrpcBreaker<P, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GrpcBreaker")
+            .field("shut_down", &self.shut_down)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<P, F> ResponseFuture<P, F> {
+    fn rejected(message: String, retry_after: Option<Duration>, span: Span) -> Self {
+        ResponseFuture {
+            future: None,
+            call: None,
+            rejected: Some(Rejected {
+                message,
+                retry_after,
+            }),
+            span,
+        }
+    }
+}
+
+impl<P, F, B, E> Future for ResponseFuture<P, F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+    P: Policy,
+{
+    type Output = Result<Response<ResponseBody<P, B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _enter = this.span.enter();
+        let Some(future) = this.future.as_pin_mut() else {
+            let rejected = this.rejected.take().expect("polled after completion");
+            return Poll::Ready(Ok(rejected.into_response()));
+        };
+        let rsp = match ready!(future.poll(cx)) {
+            Ok(rsp) => rsp,
+            Err(error) => {
+                if let Some(call) = this.call.take() {
+                    call.admitted.record(false);
+                }
+                return Poll::Ready(Err(error));
+            }
+        };
+
+        let mut call = this.call.take();
+        // if the call failed before the server sent a response, the status
+        // is in the headers, and there won't be any trailers. otherwise, the
+        // call's outcome is recorded once the body completes.
+        if let Some(code) = rsp.headers().get(GRPC_STATUS) {
+            if let Some(call) = call.take() {
+                call.record_status(Some(code));
+            }
+        } else if !rsp.status().is_success() {
+            if let Some(call) = call.take() {
+                call.admitted.record(!rsp.status().is_server_error());
+            }
+        } else if let Some(call) = call.as_mut().filter(|call| call.streaming) {
+            call.admitted.record_established();
+            call.established = true;
+        }
+        Poll::Ready(Ok(rsp.map(|inner| ResponseBody { inner, call })))
+    }
+}
+
+// === impl ResponseBody ===
+
+impl<P, B> Body for ResponseBody<P, B>
+where
+    P: Policy,
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match frame {
+            Some(Ok(ref frame)) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    if let Some(call) = this.call.take() {
+                        call.record_status(trailers.get(GRPC_STATUS));
+                    }
+                }
+            }
+            // the body failed or ended without a status.
+            Some(Err(_)) | None => {
+                if let Some(call) = this.call.take() {
+                    call.admitted.record(false);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+// === impl Rejected ===
+
+impl Rejected {
+    /// Returns a "trailers-only" response which fails the call with an
+    /// `UNAVAILABLE` status.
+    fn into_response<P, B: Default>(self) -> Response<ResponseBody<P, B>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc"),
+        );
+        headers.insert(GRPC_STATUS, HeaderValue::from(code::UNAVAILABLE));
+        if let Ok(message) = HeaderValue::from_str(&percent_encode(&self.message)) {
+            headers.insert(GRPC_MESSAGE, message);
+        }
+        if let Some(retry_after) = self.retry_after {
+            let millis = u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX);
+            headers.insert(GRPC_RETRY_PUSHBACK_MS, HeaderValue::from(millis));
+        }
+
+        let mut rsp = Response::new(ResponseBody {
+            inner: B::default(),
+            call: None,
+        });
+        *rsp.headers_mut() = headers;
+        rsp
+    }
+}
+
+// === impl StatusClasses ===
+
+impl StatusClasses {
+    #[track_caller]
+    fn with(mut self, code: u16, class: StatusClass) -> Self {
+        assert!(
+            usize::from(code) < self.0.len(),
+            "invalid grpc-status code {code}"
+        );
+        assert!(
+            class != StatusClass::Failures(0),
+            "a status must count as at least one failure"
+        );
+        Arc::make_mut(&mut self.0)[usize::from(code)] = Some(class);
+        self
+    }
+
+    fn get(&self, code: u16) -> Option<StatusClass> {
+        self.0.get(usize::from(code)).copied().flatten()
+    }
+}
+
+// === impl Call ===
+
+impl<P: Policy> Call<P> {
+    /// Records the outcome of a call which completed with the `grpc-status`
+    /// `code`. Calls without a valid status are recorded as failures.
+    fn record_status(self, code: Option<&HeaderValue>) {
+        let code = code
+            .and_then(|code| code.to_str().ok())
+            .and_then(|code| code.parse::<u16>().ok());
+        let class = code.and_then(|code| self.classes.get(code));
+        if self.established {
+            // the stream was already recorded as a success when it was
+            // established, so only record failures.
+            let success = match class {
+                Some(StatusClass::Success | StatusClass::Ignore) => true,
+                Some(_) => false,
+                None => code.is_some_and(is_success),
+            };
+            if success {
+                return;
+            }
+        }
+        match class {
+            Some(StatusClass::Success) => self.admitted.record(true),
+            Some(StatusClass::Failure) => self.admitted.record(false),
+            Some(StatusClass::Failures(weight)) => self.admitted.record_failures(weight),
+            Some(StatusClass::Ignore) => {}
+            None if code == Some(code::RESOURCE_EXHAUSTED) => {
+                self.admitted.record_overloaded(false)
+            }
+            None => self.admitted.record(code.is_some_and(is_success)),
+        }
+    }
+}
+
+/// Returns `true` if `code` indicates that the server handled the call.
+fn is_success(code: u16) -> bool {
+    !matches!(
+        code,
+        code::UNKNOWN
+            | code::DEADLINE_EXCEEDED
+            | code::RESOURCE_EXHAUSTED
+            | code::INTERNAL
+            | code::UNAVAILABLE
+            | code::DATA_LOSS
+    )
+}
+
+/// Returns the call's timeout from its `grpc-timeout` header, if it has a
+/// valid one.
+///
+/// The timeout is at most eight digits, followed by a unit: `H`ours,
+/// `M`inutes, `S`econds, `m`illiseconds, `u` (microseconds), or
+/// `n`anoseconds.
+fn grpc_timeout(headers: &HeaderMap) -> Option<Duration> {
+    let timeout = headers.get("grpc-timeout")?.to_str().ok()?;
+    let (value, unit) = timeout.split_at(timeout.len().checked_sub(1)?);
+    if value.is_empty() || value.len() > 8 {
+        return None;
+    }
+    let value = value.parse::<u64>().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(value * 60 * 60)),
+        "M" => Some(Duration::from_secs(value * 60)),
+        "S" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_millis(value)),
+        "u" => Some(Duration::from_micros(value)),
+        "n" => Some(Duration::from_nanos(value)),
+        _ => None,
+    }
+}
+
+/// Percent-encodes a `grpc-message`, as required by the gRPC HTTP/2
+/// protocol.
+fn percent_encode(message: &str) -> String {
+    use std::fmt::Write;
+
+    let mut encoded = String::with_capacity(message.len());
+    for &byte in message.as_bytes() {
+        if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::ConsecutiveFailures;
+    use http_body_util::BodyExt;
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    /// A response body consisting of only trailers.
+    #[derive(Debug, Default)]
+    struct Trailers(Option<HeaderMap>);
+
+    impl Body for Trailers {
+        type Data = &'static [u8];
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(self.0.take().map(|trailers| Ok(Frame::trailers(trailers))))
+        }
+    }
+
+    /// Responds with the `grpc-status` in the request's path, in the
+    /// response's trailers.
+    async fn handle(req: Request<()>) -> Result<Response<Trailers>, Infallible> {
+        let code = req.uri().path().trim_start_matches('/').to_owned();
+        if code == "disconnect" {
+            return Ok(Response::new(Trailers(None)));
+        }
+        let mut trailers = HeaderMap::new();
+        trailers.insert(GRPC_STATUS, HeaderValue::from_str(&code).unwrap());
+        Ok(Response::new(Trailers(Some(trailers))))
+    }
+
+    async fn call<S>(svc: &S, code: u16) -> Response<ResponseBody<ConsecutiveFailures, Trailers>>
+    where
+        S: Service<
+                Request<()>,
+                Response = Response<ResponseBody<ConsecutiveFailures, Trailers>>,
+                Error = Infallible,
+            > + Clone,
+    {
+        let req = Request::builder().uri(format!("/{code}")).body(()).unwrap();
+        let mut rsp = svc.clone().oneshot(req).await.unwrap();
+        // drive the body to completion, so the outcome is recorded.
+        while rsp.body_mut().frame().await.is_some() {}
+        rsp
+    }
+
+    #[tokio::test]
+    async fn classifies_status_codes() {
+        tokio::time::pause();
+        let config = Config::new(ConsecutiveFailures::new(2), Duration::from_secs(5));
+        let svc = GrpcBreakerLayer::new(config).layer(service_fn(handle));
+
+        // errors caused by the request don't trip the breaker.
+        for _ in 0..3 {
+            call(&svc, code::NOT_FOUND).await;
+        }
+        assert!(!svc.breaker().is_tripped());
+
+        call(&svc, code::UNAVAILABLE).await;
+        call(&svc, code::DEADLINE_EXCEEDED).await;
+        let rsp = call(&svc, 0).await;
+        assert!(svc.breaker().is_tripped());
+
+        // the rejection is a trailers-only response.
+        let headers = rsp.headers();
+        assert_eq!("14", headers[GRPC_STATUS]);
+        assert_eq!("application/grpc", headers[header::CONTENT_TYPE]);
+        assert_eq!(
+            "circuit breaker is open (2 consecutive failures (threshold 2))",
+            headers[GRPC_MESSAGE]
+        );
+        assert_eq!("5000", headers[GRPC_RETRY_PUSHBACK_MS]);
+    }
+
+    #[tokio::test]
+    async fn status_classes() {
+        let config = Config::new(ConsecutiveFailures::new(3), Duration::from_secs(5));
+        let layer = GrpcBreakerLayer::new(config)
+            .with_status_class(code::UNAVAILABLE, StatusClass::Failures(3))
+            .with_status_class(code::DEADLINE_EXCEEDED, StatusClass::Ignore)
+            .with_status_class(code::NOT_FOUND, StatusClass::Failure);
+
+        let svc = layer.clone().layer(service_fn(handle));
+        for _ in 0..3 {
+            call(&svc, code::DEADLINE_EXCEEDED).await;
+        }
+        assert!(!svc.breaker().is_tripped());
+        call(&svc, code::UNAVAILABLE).await;
+        let rsp = call(&svc, code::OK).await;
+        assert_eq!("14", rsp.headers()[GRPC_STATUS]);
+
+        let svc = layer.layer(service_fn(handle));
+        for _ in 0..3 {
+            call(&svc, code::NOT_FOUND).await;
+        }
+        let rsp = call(&svc, code::OK).await;
+        assert_eq!("14", rsp.headers()[GRPC_STATUS]);
+    }
+
+    #[tokio::test]
+    async fn streaming() {
+        let config = Config::new(ConsecutiveFailures::new(2), Duration::from_secs(5));
+        let svc = GrpcBreakerLayer::new(config)
+            .with_streaming(true)
+            .layer(service_fn(handle));
+        let consecutive_failures = || {
+            let snapshot = svc.breaker().handle().snapshot();
+            snapshot.policy.consecutive_failures.unwrap()
+        };
+
+        call(&svc, code::UNAVAILABLE).await;
+        assert_eq!(1, consecutive_failures());
+
+        // the stream is recorded as a success once it's established...
+        let req = Request::builder().uri("/disconnect").body(()).unwrap();
+        let mut rsp = svc.clone().oneshot(req).await.unwrap();
+        assert_eq!(0, consecutive_failures());
+
+        // ...and as a failure once it disconnects.
+        while rsp.body_mut().frame().await.is_some() {}
+        assert_eq!(1, consecutive_failures());
+
+        call(&svc, code::OK).await;
+        assert_eq!(0, consecutive_failures());
+    }
+
+    #[test]
+    fn percent_encodes_messages() {
+        assert_eq!("100%25 ok", percent_encode("100% ok"));
+        assert_eq!("caf%C3%A9%0A", percent_encode("café\n"));
+    }
+}
@@ -0,0 +1,508 @@
+//! A gRPC service for managing the breakers in a [`BreakerRegistry`].
+//!
+//! [`ControlService`] implements the `tower_breaker.control.v1.BreakerControl`
+//! service defined in `proto/control.proto`, so that a fleet's breakers can
+//! be managed from a central operations tool using any gRPC client. It
+//! provides the following methods:
+//!
+//! - `ListBreakers`: returns the state and configuration of every breaker in
+//!   the registry.
+//! - `SetState`: applies a [`ControlCommand`] to the named breaker, forcing
+//!   it open or closed or clearing an override.
+//! - `UpdateConfig`: changes the named breaker's
+//!   [trip duration](crate::Handle::set_trip_for) and whether it
+//!   [fails fast](crate::Handle::set_fail_fast).
+//!
+//! Methods which name a breaker that isn't in the registry fail with a
+//! `NOT_FOUND` status. As with the [`control`](crate::control) module,
+//! commands are applied by a breaker the next time it is polled, so the
+//! `state` returned by `SetState` may not yet reflect the command, but its
+//! `forced` state will.
+//!
+//! `ControlService` is a [`Service`] for HTTP/2 requests with an
+//! [`http_body::Body`], and can be served by `hyper`, or routed to under its
+//! [`NAME`](ControlService::NAME) by a gRPC server such as `tonic`. It
+//! doesn't support compressed messages, and, like the
+//! `admin` router, doesn't perform any authentication.
+//!
+//! This requires the `grpc-control` feature flag.
+use super::{code, percent_encode, GRPC_MESSAGE, GRPC_STATUS};
+use crate::{
+    control::ControlCommand, trace::debug, BoxError, BreakerRegistry, CircuitState, Handle,
+};
+use ::http::{header, HeaderMap, HeaderValue, Request, Response};
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower_service::Service;
+
+/// A gRPC service for managing the breakers in a [`BreakerRegistry`].
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct ControlService {
+    registry: BreakerRegistry,
+}
+
+/// The response body returned by [`ControlService`].
+#[derive(Debug, Default)]
+pub struct ControlBody {
+    message: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+/// The response future returned by [`ControlService`].
+pub type ResponseFuture =
+    Pin<Box<dyn Future<Output = Result<Response<ControlBody>, Infallible>> + Send>>;
+
+/// A call which failed with a `grpc-status` other than `OK`.
+#[derive(Debug)]
+struct Status {
+    code: u16,
+    message: Cow<'static, str>,
+}
+
+/// A field of a protobuf message.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// A fixed-width field, which none of the service's messages contain.
+    Fixed,
+}
+
+// === impl ControlService ===
+
+impl ControlService {
+    /// The fully-qualified name of the gRPC service.
+    pub const NAME: &'static str = "tower_breaker.control.v1.BreakerControl";
+
+    /// Returns a new `ControlService` which manages the breakers in
+    /// `registry`.
+    pub fn new(registry: BreakerRegistry) -> Self {
+        ControlService { registry }
+    }
+
+    fn list_breakers(&self, _: &[u8]) -> Result<Vec<u8>, Status> {
+        let mut buf = Vec::new();
+        for handle in self.registry.handles() {
+            put_bytes(&mut buf, 1, &encode_breaker(&handle));
+        }
+        Ok(buf)
+    }
+
+    fn set_state(&self, message: &[u8]) -> Result<Vec<u8>, Status> {
+        let mut name = None;
+        let mut command = None;
+        for field in fields(message) {
+            match field? {
+                (1, Value::Bytes(bytes)) => name = Some(string(bytes)?),
+                (2, Value::Varint(value)) => command = Some(value),
+                _ => {}
+            }
+        }
+        let command = match command {
+            Some(1) => ControlCommand::Enable,
+            Some(2) => ControlCommand::Disable,
+            Some(3) => ControlCommand::ForceOpen,
+            _ => return Err(Status::new(code::INVALID_ARGUMENT, "unknown command")),
+        };
+        let handle = self.breaker(name)?;
+        debug!(breaker = ?handle.name(), ?command, "applying gRPC control command");
+        command.apply(&handle);
+        Ok(encode_breaker(&handle))
+    }
+
+    fn update_config(&self, message: &[u8]) -> Result<Vec<u8>, Status> {
+        let mut name = None;
+        let mut trip_for = None;
+        let mut fail_fast = None;
+        for field in fields(message) {
+            match field? {
+                (1, Value::Bytes(bytes)) => name = Some(string(bytes)?),
+                (2, Value::Varint(millis)) => trip_for = Some(Duration::from_millis(millis)),
+                (3, Value::Varint(value)) => fail_fast = Some(value != 0),
+                _ => {}
+            }
+        }
+        let handle = self.breaker(name)?;
+        debug!(breaker = ?handle.name(), ?trip_for, ?fail_fast, "updating breaker config");
+        if let Some(trip_for) = trip_for {
+            handle.set_trip_for(trip_for);
+        }
+        if let Some(fail_fast) = fail_fast {
+            handle.set_fail_fast(fail_fast);
+        }
+        Ok(encode_breaker(&handle))
+    }
+
+    fn breaker(&self, name: Option<String>) -> Result<Handle, Status> {
+        let name = name.unwrap_or_default();
+        self.registry
+            .get(&name)
+            .ok_or_else(|| Status::new(code::NOT_FOUND, format!("no breaker named {name:?}")))
+    }
+}
+
+impl<B> Service<Request<B>> for ControlService
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<ControlBody>;
+    type Error = Infallible;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let svc = self.clone();
+        Box::pin(async move {
+            let method = req
+                .uri()
+                .path()
+                .strip_prefix('/')
+                .and_then(|path| path.strip_prefix(Self::NAME))
+                .and_then(|path| path.strip_prefix('/'))
+                .unwrap_or_default();
+            let method = match method {
+                "ListBreakers" => Self::list_breakers,
+                "SetState" => Self::set_state,
+                "UpdateConfig" => Self::update_config,
+                _ => {
+                    let message = format!("unknown method {}", req.uri().path());
+                    return Ok(Status::new(code::UNIMPLEMENTED, message).into_response());
+                }
+            };
+            let rsp = match read_message(req.into_body()).await {
+                Ok(message) => method(&svc, &message),
+                Err(status) => Err(status),
+            };
+            Ok(match rsp {
+                Ok(message) => ControlBody::response(&message),
+                Err(status) => status.into_response(),
+            })
+        })
+    }
+}
+
+// === impl ControlBody ===
+
+impl ControlBody {
+    /// Returns a response which completes the call with `message`.
+    fn response(message: &[u8]) -> Response<Self> {
+        let mut framed = Vec::with_capacity(message.len() + 5);
+        framed.push(0);
+        let len = u32::try_from(message.len()).expect("response message is too long");
+        framed.extend_from_slice(&len.to_be_bytes());
+        framed.extend_from_slice(message);
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert(GRPC_STATUS, HeaderValue::from(code::OK));
+        let mut rsp = Response::new(ControlBody {
+            message: Some(framed.into()),
+            trailers: Some(trailers),
+        });
+        rsp.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc"),
+        );
+        rsp
+    }
+}
+
+impl Body for ControlBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(message) = this.message.take() {
+            return Poll::Ready(Some(Ok(Frame::data(message))));
+        }
+        Poll::Ready(
+            this.trailers
+                .take()
+                .map(|trailers| Ok(Frame::trailers(trailers))),
+        )
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.message.is_none() && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let len = self.message.as_ref().map_or(0, Bytes::len);
+        SizeHint::with_exact(len as u64)
+    }
+}
+
+// === impl Status ===
+
+impl Status {
+    fn new(code: u16, message: impl Into<Cow<'static, str>>) -> Self {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Returns a "trailers-only" response which fails the call with this
+    /// status.
+    fn into_response(self) -> Response<ControlBody> {
+        let mut rsp = Response::new(ControlBody::default());
+        let headers = rsp.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc"),
+        );
+        headers.insert(GRPC_STATUS, HeaderValue::from(self.code));
+        if let Ok(message) = HeaderValue::from_str(&percent_encode(&self.message)) {
+            headers.insert(GRPC_MESSAGE, message);
+        }
+        rsp
+    }
+}
+
+/// Reads the single, length-prefixed message in a unary call's request body.
+async fn read_message<B>(body: B) -> Result<Vec<u8>, Status>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    let mut body = pin!(body);
+    let mut buf = Vec::new();
+    while let Some(frame) = poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
+        let frame = frame.map_err(|error| {
+            let error = error.into();
+            Status::new(code::INTERNAL, format!("failed to read request: {error}"))
+        })?;
+        if let Ok(mut data) = frame.into_data() {
+            while data.has_remaining() {
+                let chunk = data.chunk();
+                buf.extend_from_slice(chunk);
+                let len = chunk.len();
+                data.advance(len);
+            }
+        }
+    }
+
+    if buf.len() < 5 {
+        return Err(Status::new(code::INVALID_ARGUMENT, "malformed request"));
+    }
+    let (prefix, message) = buf.split_at(5);
+    if prefix[0] != 0 {
+        return Err(Status::new(
+            code::UNIMPLEMENTED,
+            "compressed requests are not supported",
+        ));
+    }
+    if u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize != message.len() {
+        return Err(Status::new(code::INVALID_ARGUMENT, "malformed request"));
+    }
+    Ok(message.to_vec())
+}
+
+// === protobuf encoding ===
+
+fn encode_breaker(handle: &Handle) -> Vec<u8> {
+    fn state(state: CircuitState) -> u64 {
+        match state {
+            CircuitState::Closed => 1,
+            CircuitState::Open => 2,
+        }
+    }
+
+    let config = handle.config();
+    let trip_for_ms = u64::try_from(config.trip_for.as_millis()).unwrap_or(u64::MAX);
+    let mut buf = Vec::new();
+    put_bytes(&mut buf, 1, handle.name().unwrap_or_default().as_bytes());
+    put_varint_field(&mut buf, 2, state(handle.state()));
+    put_varint_field(&mut buf, 3, handle.forced().map_or(0, state));
+    put_varint_field(&mut buf, 4, trip_for_ms);
+    put_varint_field(&mut buf, 5, u64::from(config.fail_fast));
+    buf
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Writes a varint field, unless it has the default value of zero.
+fn put_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    if value != 0 {
+        put_varint(buf, field << 3);
+        put_varint(buf, value);
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(buf, field << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Returns an iterator over the field numbers and values of a message.
+fn fields(mut buf: &[u8]) -> impl Iterator<Item = Result<(u64, Value<'_>), Status>> {
+    std::iter::from_fn(move || {
+        if buf.is_empty() {
+            return None;
+        }
+        Some(next_field(&mut buf).ok_or_else(|| {
+            buf = &[];
+            Status::new(code::INVALID_ARGUMENT, "malformed request message")
+        }))
+    })
+}
+
+fn next_field<'a>(buf: &mut &'a [u8]) -> Option<(u64, Value<'a>)> {
+    let key = get_varint(buf)?;
+    let value = match key & 7 {
+        0 => Value::Varint(get_varint(buf)?),
+        1 | 5 => {
+            let len = if key & 7 == 1 { 8 } else { 4 };
+            *buf = buf.get(len..)?;
+            Value::Fixed
+        }
+        2 => {
+            let len = usize::try_from(get_varint(buf)?).ok()?;
+            let bytes = buf.get(..len)?;
+            *buf = &buf[len..];
+            Value::Bytes(bytes)
+        }
+        _ => return None,
+    };
+    Some((key >> 3, value))
+}
+
+fn get_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, &byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn string(bytes: &[u8]) -> Result<String, Status> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| Status::new(code::INVALID_ARGUMENT, "breaker name is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::ConsecutiveFailures, CircuitBreaker, Config};
+    use http_body_util::{BodyExt, Full};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    async fn call(
+        svc: &ControlService,
+        method: &str,
+        message: &[u8],
+    ) -> Result<HashMap<u64, Vec<u64>>, u16> {
+        let mut body = vec![0];
+        body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        body.extend_from_slice(message);
+        let req = Request::post(format!("/{}/{method}", ControlService::NAME))
+            .body(Full::new(Bytes::from(body)))
+            .unwrap();
+        let rsp = svc.clone().oneshot(req).await.unwrap();
+        if let Some(status) = rsp.headers().get(GRPC_STATUS) {
+            return Err(status.to_str().unwrap().parse().unwrap());
+        }
+        let body = rsp.into_body().collect().await.unwrap();
+        assert_eq!("0", body.trailers().unwrap()[GRPC_STATUS]);
+        let body = body.to_bytes();
+        assert_eq!(
+            body.len() - 5,
+            u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize
+        );
+
+        // collects the varint fields of the (first) breaker in the response.
+        let mut breaker = HashMap::new();
+        let mut message = &body[5..];
+        if method == "ListBreakers" {
+            message = match fields(message).next() {
+                Some(Ok((1, Value::Bytes(bytes)))) => bytes,
+                _ => &[],
+            };
+        }
+        for field in fields(message) {
+            match field.unwrap() {
+                (field, Value::Varint(value)) => {
+                    breaker.entry(field).or_insert_with(Vec::new).push(value)
+                }
+                (field, Value::Bytes(bytes)) => breaker
+                    .entry(field)
+                    .or_insert_with(Vec::new)
+                    .push(bytes.len() as u64),
+                _ => {}
+            }
+        }
+        Ok(breaker)
+    }
+
+    #[tokio::test]
+    async fn methods() {
+        let registry = BreakerRegistry::new();
+        let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+            .with_name("users-api")
+            .with_registry(registry.clone());
+        let breaker = CircuitBreaker::new(config, ());
+        let handle = breaker.handle();
+        let svc = ControlService::new(registry);
+
+        let listed = call(&svc, "ListBreakers", &[]).await.unwrap();
+        assert_eq!(Some(&vec![1]), listed.get(&2), "closed");
+        assert_eq!(None, listed.get(&3), "not forced");
+        assert_eq!(Some(&vec![5000]), listed.get(&4));
+
+        let mut req = Vec::new();
+        put_bytes(&mut req, 1, b"users-api");
+        put_varint_field(&mut req, 2, 3);
+        let set = call(&svc, "SetState", &req).await.unwrap();
+        assert_eq!(Some(&vec![2]), set.get(&3), "forced open");
+        assert_eq!(Some(CircuitState::Open), handle.forced());
+
+        let mut req = Vec::new();
+        put_bytes(&mut req, 1, b"users-api");
+        put_varint_field(&mut req, 2, 250);
+        put_varint_field(&mut req, 3, 1);
+        let updated = call(&svc, "UpdateConfig", &req).await.unwrap();
+        assert_eq!(Some(&vec![250]), updated.get(&4));
+        assert_eq!(Some(&vec![1]), updated.get(&5));
+        assert_eq!(Duration::from_millis(250), handle.config().trip_for);
+        assert!(handle.config().fail_fast);
+
+        let mut req = Vec::new();
+        put_bytes(&mut req, 1, b"orders-api");
+        put_varint_field(&mut req, 2, 1);
+        assert_eq!(Err(code::NOT_FOUND), call(&svc, "SetState", &req).await);
+        assert_eq!(
+            Err(code::INVALID_ARGUMENT),
+            call(&svc, "SetState", b"\x0a\x09users").await
+        );
+        assert_eq!(Err(code::UNIMPLEMENTED), call(&svc, "Restart", &[]).await);
+    }
+}
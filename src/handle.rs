@@ -0,0 +1,845 @@
+//! Handles for observing a [`CircuitBreaker`](crate::CircuitBreaker) from
+//! outside of the service stack it's part of.
+use crate::{
+    clock::{self, SharedClock},
+    hooks::Hooks,
+    policy::PolicySnapshot,
+    snapshot::{BreakerSnapshot, BreakerStatus, ConfigSnapshot},
+    store::{StateStore, StoreFuture, StoredState},
+    timer::{self, SharedTimer},
+    trace::debug,
+    BoxError, CircuitState, Policy, TripReason,
+};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fmt,
+    future::{self, Future},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{watch, Notify};
+
+/// A cloneable handle to a [`CircuitBreaker`](crate::CircuitBreaker).
+///
+/// Because a `CircuitBreaker` is typically moved into a service stack, a
+/// `Handle` can be used to inspect the breaker's state from other tasks
+/// (such as health checks or admin endpoints).
+#[derive(Clone, Debug)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+/// A record of a single time a [`CircuitBreaker`](crate::CircuitBreaker)
+/// tripped.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct TripEvent {
+    /// The time at which the breaker tripped.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub at: Instant,
+    /// The wall-clock time at which the breaker tripped.
+    pub timestamp: SystemTime,
+    /// How long the breaker was configured to remain open when it tripped.
+    pub trip_for: Duration,
+    /// How long the breaker actually remained open, or `None` if it is still
+    /// open.
+    pub open_for: Option<Duration>,
+    /// The reason the policy tripped the breaker.
+    pub reason: TripReason,
+    /// A snapshot of the policy's state at the time the breaker tripped.
+    pub policy: String,
+}
+
+/// Cumulative statistics describing a
+/// [`CircuitBreaker`](crate::CircuitBreaker)'s history.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct Stats {
+    /// The total number of times the breaker has tripped.
+    pub trips: u64,
+    /// The total amount of time the breaker has spent open, including the
+    /// current open period if the breaker is currently open.
+    pub time_open: Duration,
+    /// The longest single period for which the breaker has remained open.
+    pub longest_open: Duration,
+    /// The time elapsed since the breaker last tripped, or `None` if it has
+    /// never tripped.
+    pub since_last_trip: Option<Duration>,
+    /// The total number of requests that were refused or parked because the
+    /// circuit was open.
+    pub rejected: u64,
+}
+
+/// State shared between a `CircuitBreaker` and its `Handle`s.
+#[derive(Debug)]
+pub(crate) struct Shared {
+    pub(crate) name: Option<Cow<'static, str>>,
+    pub(crate) state: watch::Sender<CircuitState>,
+    history: Mutex<History>,
+    stats: Mutex<StatsState>,
+    rejected: AtomicU64,
+    /// The number of times the breaker's policy has been reset.
+    policy_resets: AtomicU64,
+    control: Mutex<Control>,
+    pub(crate) config: watch::Sender<ConfigSnapshot>,
+    policy: PolicyProbe,
+    pub(crate) clock: SharedClock,
+    timer: SharedTimer,
+    hooks: Hooks,
+    /// The number of requests which have been passed to the breaker's inner
+    /// service and have not yet completed.
+    in_flight: AtomicUsize,
+    /// Notified when the last in-flight request completes.
+    drained: Notify,
+    shut_down: AtomicBool,
+    /// [`OPEN`] if the circuit is open, and [`PENDING`] if a command or
+    /// configuration change is waiting to be applied. While this is zero, a
+    /// breaker which doesn't need to be polled for anything else can skip
+    /// locking its circuit.
+    status: AtomicU8,
+    /// When the circuit is expected to close, if it's open and hasn't been
+    /// forced open.
+    closes_at: Mutex<Option<Instant>>,
+    /// The most recent `Retry-After` delay sent by the inner service since
+    /// the circuit last closed, if any.
+    retry_after: Mutex<Option<Duration>>,
+    /// Whether the final snapshot has been passed to the shutdown hooks.
+    flushed: AtomicBool,
+}
+
+/// Set in [`Shared::status`] while the circuit is open.
+const OPEN: u8 = 1 << 0;
+/// Set in [`Shared::status`] while a command or configuration change is
+/// waiting to be applied.
+const PENDING: u8 = 1 << 1;
+
+/// Tracks a request passed to a breaker's inner service until it completes.
+#[derive(Debug)]
+pub(crate) struct InFlight(Arc<Shared>);
+
+/// A type-erased reference to a breaker's policy, used to snapshot it and
+/// to record the number of requests in flight.
+struct PolicyProbe(Option<Box<dyn Policy + Send + Sync>>);
+
+/// A command sent to a `CircuitBreaker` by a `Handle`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Command {
+    /// Reset the breaker's policy, close the circuit if it is open, and
+    /// clear any forced state.
+    Reset,
+    /// Hold the circuit in the given state until the next `Reset`.
+    Force(CircuitState),
+    /// Open the circuit until the given time, unless it's already open or
+    /// forced into a state.
+    Restore(SystemTime),
+    /// Trip the circuit for the configured duration, unless it's already
+    /// open or forced into a state.
+    Trip(TripReason),
+}
+
+#[derive(Debug, Default)]
+struct Control {
+    /// The next command to be applied by the breaker, if any.
+    command: Option<Command>,
+    /// The waker for a task parked in the breaker's `poll_ready`, if any.
+    waker: Option<Waker>,
+    /// The state the breaker's circuit is currently forced into, if any.
+    forced: Option<CircuitState>,
+}
+
+#[derive(Debug)]
+struct History {
+    capacity: usize,
+    trips: VecDeque<TripEvent>,
+}
+
+#[derive(Debug, Default)]
+struct StatsState {
+    trips: u64,
+    /// Time spent open in open periods that have already ended.
+    time_open: Duration,
+    longest_open: Duration,
+    last_trip: Option<Instant>,
+    open_since: Option<Instant>,
+}
+
+// === impl Handle ===
+
+impl Handle {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        Handle { shared }
+    }
+
+    pub(crate) fn downgrade(&self) -> Weak<Shared> {
+        Arc::downgrade(&self.shared)
+    }
+
+    /// Returns the name of the breaker, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.shared.name.as_deref()
+    }
+
+    /// Returns the current state of the breaker's circuit.
+    pub fn state(&self) -> CircuitState {
+        *self.shared.state.borrow()
+    }
+
+    /// Returns the number of requests which have been passed to the
+    /// breaker's inner service and haven't yet completed.
+    pub fn in_flight(&self) -> usize {
+        self.shared.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Returns a [`watch::Receiver`] that is notified whenever the state of
+    /// the breaker's circuit changes.
+    pub fn state_receiver(&self) -> watch::Receiver<CircuitState> {
+        self.shared.state.subscribe()
+    }
+
+    /// Resets the breaker's policy, closing its circuit if it is open.
+    ///
+    /// This also clears any state set by [`force_open`](Handle::force_open)
+    /// or [`force_close`](Handle::force_close).
+    ///
+    /// The reset is applied the next time the breaker is polled for
+    /// readiness. If a task is currently waiting for the breaker's circuit to
+    /// close, it is woken.
+    pub fn reset(&self) {
+        self.shared.send_command(Command::Reset);
+    }
+
+    /// Opens the breaker's circuit, and holds it open until the breaker is
+    /// [reset](Handle::reset).
+    ///
+    /// While the circuit is forced open, it will not close when its trip
+    /// duration elapses. As with [`reset`](Handle::reset), the circuit is
+    /// opened the next time the breaker is polled for readiness.
+    pub fn force_open(&self) {
+        self.shared.send_command(Command::Force(CircuitState::Open));
+    }
+
+    /// Closes the breaker's circuit, and holds it closed until the breaker is
+    /// [reset](Handle::reset).
+    ///
+    /// While the circuit is forced closed, the breaker's policy is not
+    /// consulted, so the circuit will not trip. As with
+    /// [`reset`](Handle::reset), the circuit is closed the next time the
+    /// breaker is polled for readiness.
+    pub fn force_close(&self) {
+        self.shared
+            .send_command(Command::Force(CircuitState::Closed));
+    }
+
+    /// Returns the state the breaker's circuit is currently forced into by
+    /// [`force_open`](Handle::force_open) or
+    /// [`force_close`](Handle::force_close), if any.
+    pub fn forced(&self) -> Option<CircuitState> {
+        self.shared.control.lock().unwrap().forced
+    }
+
+    /// Returns the breaker's current configuration.
+    pub fn config(&self) -> ConfigSnapshot {
+        *self.shared.config.borrow()
+    }
+
+    /// Changes how long the breaker's circuit remains open once tripped.
+    ///
+    /// If the circuit is currently open, the new duration is measured from
+    /// the time it tripped, so shortening it may close the circuit the next
+    /// time the breaker is polled for readiness.
+    pub fn set_trip_for(&self, trip_for: Duration) {
+        self.shared.reconfigure(|config| config.trip_for = trip_for);
+    }
+
+    /// Changes whether requests made while the breaker's circuit is open
+    /// fail immediately.
+    ///
+    /// See [`Config::with_fail_fast`](crate::Config::with_fail_fast) for
+    /// details.
+    pub fn set_fail_fast(&self, fail_fast: bool) {
+        self.shared
+            .reconfigure(|config| config.fail_fast = fail_fast);
+    }
+
+    /// Returns cumulative statistics describing the breaker's history.
+    pub fn stats(&self) -> Stats {
+        let stats = self.shared.stats.lock().unwrap();
+        let now = self.shared.clock.now();
+        let current_open = stats
+            .open_since
+            .map(|since| now.saturating_duration_since(since))
+            .unwrap_or_default();
+        Stats {
+            trips: stats.trips,
+            time_open: stats.time_open + current_open,
+            longest_open: stats.longest_open.max(current_open),
+            since_last_trip: stats.last_trip.map(|at| now.saturating_duration_since(at)),
+            rejected: self.shared.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Shuts the breaker down, returning a final snapshot of its state.
+    ///
+    /// Once shutdown begins, the breaker stops accepting new requests: its
+    /// `poll_ready` fails with a [`ShutDown`](crate::error::ShutDown) error,
+    /// including for any task currently waiting for its circuit to close.
+    /// This then waits up to `timeout` for requests already passed to the
+    /// breaker's inner service to complete, and passes the final snapshot to
+    /// the callbacks registered with
+    /// [`Config::on_shutdown`](crate::Config::on_shutdown).
+    ///
+    /// The timeout is measured using the breaker's
+    /// [`Timer`](crate::timer::Timer).
+    pub fn shutdown(&self, timeout: Duration) -> impl Future<Output = BreakerSnapshot> + Send {
+        let handle = self.clone();
+        async move {
+            let shared = &handle.shared;
+            shared.shut_down.store(true, Ordering::Release);
+            // fail any task parked in `poll_ready`.
+            shared.wake();
+
+            let mut drained = pin!(shared.drained());
+            let mut timeout = shared.timer.sleep(timeout);
+            future::poll_fn(|cx| {
+                if drained.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(());
+                }
+                timeout.as_mut().poll(cx)
+            })
+            .await;
+
+            let in_flight = shared.in_flight.load(Ordering::Acquire);
+            if in_flight > 0 {
+                debug!(
+                    breaker = shared.name.as_deref(),
+                    in_flight, "timed out waiting for in-flight requests"
+                );
+            }
+            shared.flush();
+            handle.snapshot()
+        }
+    }
+
+    /// Returns how long until the breaker's circuit is expected to close, or
+    /// `None` if it's closed or has been forced open.
+    ///
+    /// See [`CircuitBreaker::open_remaining`](crate::CircuitBreaker::open_remaining).
+    pub fn open_remaining(&self) -> Option<Duration> {
+        self.shared.open_remaining()
+    }
+
+    /// Saves the breaker's state to `store` under `key`, so that it can be
+    /// [restored](Handle::restore) by the next process to run.
+    ///
+    /// This is typically called once the breaker has been
+    /// [shut down](Handle::shutdown). If the circuit is open, the time it's
+    /// expected to close is saved; otherwise, the saved state is closed, so
+    /// that an earlier trip isn't restored. Trips forced by
+    /// [`force_open`](Handle::force_open) aren't saved.
+    pub fn persist<'a>(&self, store: &'a impl StateStore, key: &'a str) -> StoreFuture<'a, ()> {
+        let closes_at = *self.shared.closes_at.lock().unwrap();
+        let remaining = closes_at
+            .map(|at| at.saturating_duration_since(self.shared.clock.now()))
+            .filter(|remaining| !remaining.is_zero());
+        let (state, ttl) = match remaining {
+            Some(remaining) => (
+                StoredState::new(CircuitState::Open, Some(SystemTime::now() + remaining)),
+                remaining,
+            ),
+            None => (
+                StoredState::new(CircuitState::Closed, None),
+                self.config().trip_for,
+            ),
+        };
+        store.set(key, state, ttl)
+    }
+
+    /// Restores the state [persisted](Handle::persist) to `store` under
+    /// `key`, returning `true` if a trip was restored.
+    ///
+    /// If the persisted circuit is still open, the breaker's circuit opens for
+    /// the remainder of the trip, with [`TripReason::Restored`]. As with
+    /// [`reset`](Handle::reset), the circuit is opened the next time the
+    /// breaker is polled for readiness, replacing any other command sent by a
+    /// handle which hasn't yet been applied. This should be called when the
+    /// breaker is constructed, before it's used.
+    pub fn restore<'a>(
+        &self,
+        store: &'a impl StateStore,
+        key: &'a str,
+    ) -> impl Future<Output = Result<bool, BoxError>> + Send + 'a {
+        let shared = self.shared.clone();
+        async move {
+            let Some(state) = store.get(key).await? else {
+                return Ok(false);
+            };
+            let (Some(_), Some(open_until)) = (state.remaining(), state.open_until) else {
+                return Ok(false);
+            };
+            debug!(
+                breaker = shared.name.as_deref(),
+                ?open_until,
+                "restoring trip"
+            );
+            shared.send_command(Command::Restore(open_until));
+            Ok(true)
+        }
+    }
+
+    /// Trips the breaker's circuit with `reason` the next time it's polled,
+    /// unless it's already open or forced into a state.
+    pub(crate) fn trip(&self, reason: TripReason) {
+        self.shared.send_command(Command::Trip(reason));
+    }
+
+    /// Returns a point-in-time snapshot of the breaker's state.
+    pub fn snapshot(&self) -> BreakerSnapshot {
+        BreakerSnapshot {
+            name: self.name().map(ToOwned::to_owned),
+            state: self.state(),
+            forced: self.forced(),
+            open_remaining: self.open_remaining(),
+            config: self.config(),
+            policy: self.shared.policy.snapshot(),
+            stats: self.stats(),
+            recent_trips: self.recent_trips(),
+        }
+    }
+
+    /// Returns a summary of the breaker's current status, which can be
+    /// displayed on a single line.
+    pub fn status(&self) -> BreakerStatus {
+        let state = self.state();
+        BreakerStatus {
+            name: self.name().map(ToOwned::to_owned),
+            state,
+            forced: self.forced(),
+            remaining: self.open_remaining(),
+            reason: self
+                .last_trip()
+                .filter(|_| state == CircuitState::Open)
+                .map(|trip| trip.reason),
+            policy: self.shared.policy.snapshot(),
+        }
+    }
+
+    /// Returns the most recent times the breaker tripped, oldest first.
+    ///
+    /// The number of trips retained is configured by
+    /// [`Config::with_trip_history`](crate::Config::with_trip_history).
+    pub fn recent_trips(&self) -> Vec<TripEvent> {
+        let history = self.shared.history.lock().unwrap();
+        history.trips.iter().cloned().collect()
+    }
+
+    /// Returns the last time the breaker tripped, if it has tripped.
+    pub fn last_trip(&self) -> Option<TripEvent> {
+        let history = self.shared.history.lock().unwrap();
+        history.trips.back().cloned()
+    }
+}
+
+// === impl Shared ===
+
+impl Shared {
+    pub(crate) fn new(name: Option<Cow<'static, str>>, history_capacity: usize) -> Self {
+        Shared {
+            name,
+            state: watch::Sender::new(CircuitState::Closed),
+            history: Mutex::new(History {
+                capacity: history_capacity,
+                trips: VecDeque::with_capacity(history_capacity),
+            }),
+            stats: Mutex::new(StatsState::default()),
+            rejected: AtomicU64::new(0),
+            policy_resets: AtomicU64::new(0),
+            control: Mutex::new(Control::default()),
+            config: watch::Sender::new(ConfigSnapshot::default()),
+            policy: PolicyProbe(None),
+            clock: clock::default(),
+            timer: timer::default(),
+            hooks: Hooks::default(),
+            in_flight: AtomicUsize::new(0),
+            drained: Notify::new(),
+            shut_down: AtomicBool::new(false),
+            status: AtomicU8::new(0),
+            closes_at: Mutex::new(None),
+            retry_after: Mutex::new(None),
+            flushed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn with_config(self, config: ConfigSnapshot) -> Self {
+        self.config.send_replace(config);
+        self
+    }
+
+    pub(crate) fn with_clock(self, clock: SharedClock) -> Self {
+        Shared { clock, ..self }
+    }
+
+    pub(crate) fn with_timer(self, timer: SharedTimer) -> Self {
+        Shared { timer, ..self }
+    }
+
+    pub(crate) fn with_hooks(self, hooks: Hooks) -> Self {
+        Shared { hooks, ..self }
+    }
+
+    pub(crate) fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub(crate) fn with_policy<P>(self, policy: P) -> Self
+    where
+        P: Policy + Send + Sync + 'static,
+    {
+        Shared {
+            policy: PolicyProbe(Some(Box::new(policy))),
+            ..self
+        }
+    }
+
+    pub(crate) fn send_command(&self, command: Command) {
+        let waker = {
+            let mut control = self.control.lock().unwrap();
+            // the forced state is visible to handles immediately, even though
+            // the breaker won't apply it until it's next polled.
+            control.forced = match command {
+                Command::Reset => None,
+                Command::Force(state) => Some(state),
+                Command::Restore(_) | Command::Trip(_) => control.forced,
+            };
+            control.command = Some(command);
+            self.status.fetch_or(PENDING, Ordering::Release);
+            control.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Updates the breaker's configuration, waking any task parked in its
+    /// `poll_ready` so that the change is applied.
+    fn reconfigure(&self, f: impl FnOnce(&mut ConfigSnapshot)) {
+        self.config.send_modify(f);
+        self.status.fetch_or(PENDING, Ordering::Release);
+        self.wake();
+    }
+
+    /// Wakes the task parked in the breaker's `poll_ready`, if any.
+    pub(crate) fn wake(&self) {
+        let waker = self.control.lock().unwrap().waker.take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Takes the next pending command, if any.
+    pub(crate) fn take_command(&self) -> Option<Command> {
+        let mut control = self.control.lock().unwrap();
+        // the caller applies any configuration change after taking the
+        // command, so it's no longer pending either.
+        self.status.fetch_and(!PENDING, Ordering::AcqRel);
+        control.command.take()
+    }
+
+    /// Sets the state of the circuit, returning its previous state.
+    pub(crate) fn set_state(&self, to: CircuitState) -> CircuitState {
+        match to {
+            CircuitState::Open => self.status.fetch_or(OPEN, Ordering::Release),
+            CircuitState::Closed => self.status.fetch_and(!OPEN, Ordering::Release),
+        };
+        self.state.send_replace(to)
+    }
+
+    /// Returns `true` if the circuit is closed and nothing is waiting to be
+    /// applied to it, so that it only needs to consult its policy.
+    pub(crate) fn is_settled(&self) -> bool {
+        self.status.load(Ordering::Acquire) == 0
+    }
+
+    /// Registers a waker to be woken when a command is sent to the breaker.
+    pub(crate) fn register_waker(&self, waker: &Waker) {
+        let mut control = self.control.lock().unwrap();
+        match control.waker {
+            Some(ref current) if current.will_wake(waker) => {}
+            _ => control.waker = Some(waker.clone()),
+        }
+    }
+
+    /// Records that a request has been passed to the breaker's inner
+    /// service, returning a guard which records its completion when dropped.
+    pub(crate) fn start_request(self: &Arc<Self>) -> InFlight {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::AcqRel) + 1;
+        self.policy.record_in_flight(in_flight);
+        InFlight(self.clone())
+    }
+
+    pub(crate) fn set_closes_at(&self, closes_at: Option<Instant>) {
+        *self.closes_at.lock().unwrap() = closes_at;
+    }
+
+    pub(crate) fn open_remaining(&self) -> Option<Duration> {
+        let closes_at = (*self.closes_at.lock().unwrap())?;
+        Some(closes_at.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Records a `Retry-After` delay sent by the inner service, replacing
+    /// any previously recorded delay.
+    #[cfg(feature = "http")]
+    pub(crate) fn set_retry_after(&self, retry_after: Duration) {
+        *self.retry_after.lock().unwrap() = Some(retry_after);
+    }
+
+    pub(crate) fn take_retry_after(&self) -> Option<Duration> {
+        self.retry_after.lock().unwrap().take()
+    }
+
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.shut_down.load(Ordering::Acquire)
+    }
+
+    /// Waits until no requests are in flight.
+    async fn drained(&self) {
+        loop {
+            let mut notified = pin!(self.drained.notified());
+            notified.as_mut().enable();
+            if self.in_flight.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Passes a final snapshot of the breaker to its shutdown hooks, if this
+    /// hasn't already been done.
+    pub(crate) fn flush(self: &Arc<Self>) {
+        if self.flushed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let snapshot = Handle::new(self.clone()).snapshot();
+        debug!(
+            breaker = self.name.as_deref(),
+            stats = ?snapshot.stats,
+            "circuit breaker shut down"
+        );
+        self.hooks.shut_down(&snapshot);
+    }
+
+    pub(crate) fn record_rejection(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that the breaker's policy is being reset.
+    pub(crate) fn record_policy_reset(&self) {
+        self.policy_resets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn policy_resets(&self) -> u64 {
+        self.policy_resets.load(Ordering::Relaxed)
+    }
+
+    /// Records that the circuit tripped.
+    ///
+    /// A trip while the circuit is already open, such as when failures which
+    /// were in flight when it opened complete, extends the time it's open
+    /// rather than counting as another trip, or adding another trip to the
+    /// history.
+    pub(crate) fn record_trip(&self, trip: TripEvent) {
+        {
+            let mut stats = self.stats.lock().unwrap();
+            if stats.open_since.is_some() {
+                return;
+            }
+            stats.trips += 1;
+            stats.last_trip = Some(trip.at);
+            stats.open_since = Some(trip.at);
+        }
+
+        let mut history = self.history.lock().unwrap();
+        if history.capacity == 0 {
+            return;
+        }
+        if history.trips.len() == history.capacity {
+            history.trips.pop_front();
+        }
+        history.trips.push_back(trip);
+    }
+
+    pub(crate) fn record_close(&self, open_for: Duration) {
+        {
+            let mut stats = self.stats.lock().unwrap();
+            if stats.open_since.take().is_none() {
+                return;
+            }
+            stats.time_open += open_for;
+            stats.longest_open = stats.longest_open.max(open_for);
+        }
+
+        let mut history = self.history.lock().unwrap();
+        if let Some(trip) = history.trips.back_mut() {
+            trip.open_for = Some(open_for);
+        }
+    }
+}
+
+// === impl InFlight ===
+
+impl InFlight {
+    pub(crate) fn shared(&self) -> &Shared {
+        &self.0
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let in_flight = self.0.in_flight.fetch_sub(1, Ordering::AcqRel) - 1;
+        self.0.policy.record_in_flight(in_flight);
+        if in_flight == 0 {
+            self.0.drained.notify_waiters();
+        }
+    }
+}
+
+// === impl PolicyProbe ===
+
+impl PolicyProbe {
+    fn snapshot(&self) -> PolicySnapshot {
+        self.0
+            .as_ref()
+            .map(|policy| policy.snapshot())
+            .unwrap_or_default()
+    }
+
+    fn record_in_flight(&self, in_flight: usize) {
+        if let Some(ref policy) = self.0 {
+            policy.record_in_flight(in_flight);
+        }
+    }
+}
+
+impl fmt::Debug for PolicyProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PolicyProbe")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trip(n: u64) -> TripEvent {
+        TripEvent {
+            at: clock::default().now(),
+            timestamp: SystemTime::now(),
+            trip_for: Duration::from_secs(n),
+            open_for: None,
+            reason: TripReason::Unspecified,
+            policy: String::new(),
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let handle = Handle::new(Arc::new(Shared::new(None, 2)));
+        handle.shared.record_trip(trip(1));
+        handle.shared.record_close(Duration::from_secs(1));
+        handle.shared.record_trip(trip(2));
+        handle.shared.record_close(Duration::from_secs(2));
+        handle.shared.record_trip(trip(3));
+        // a trip while the circuit is open isn't another trip.
+        handle.shared.record_trip(trip(4));
+
+        let trips = handle.recent_trips();
+        assert_eq!(2, trips.len());
+        assert_eq!(Duration::from_secs(2), trips[0].trip_for);
+        assert_eq!(Some(Duration::from_secs(2)), trips[0].open_for);
+        assert_eq!(Duration::from_secs(3), trips[1].trip_for);
+        assert_eq!(None, trips[1].open_for);
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        tokio::time::pause();
+        let handle = Handle::new(Arc::new(Shared::new(None, 0)));
+        assert_eq!(Stats::default(), handle.stats());
+
+        handle.shared.record_trip(trip(1));
+        tokio::time::advance(Duration::from_secs(3)).await;
+        handle.shared.record_close(Duration::from_secs(3));
+        tokio::time::advance(Duration::from_secs(1)).await;
+
+        assert_eq!(
+            Stats {
+                trips: 1,
+                time_open: Duration::from_secs(3),
+                longest_open: Duration::from_secs(3),
+                since_last_trip: Some(Duration::from_secs(4)),
+                rejected: 0,
+            },
+            handle.stats()
+        );
+
+        handle.shared.record_trip(trip(1));
+        handle.shared.record_rejection();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(
+            Stats {
+                trips: 2,
+                time_open: Duration::from_secs(8),
+                longest_open: Duration::from_secs(5),
+                since_last_trip: Some(Duration::from_secs(5)),
+                rejected: 1,
+            },
+            handle.stats()
+        );
+    }
+
+    #[test]
+    fn history_disabled() {
+        let handle = Handle::new(Arc::new(Shared::new(None, 0)));
+        handle.shared.record_trip(trip(1));
+        assert!(handle.last_trip().is_none());
+    }
+
+    #[test]
+    fn concurrent_stats() {
+        use std::thread;
+
+        let handle = Handle::new(Arc::new(Shared::new(None, 4)));
+        let threads = (0..4)
+            .map(|_| {
+                let shared = handle.shared.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        shared.record_rejection();
+                        shared.record_trip(trip(1));
+                        shared.record_close(Duration::from_millis(1));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // a thread's trip while another's is open extends it, rather than
+        // counting as another trip.
+        let stats = handle.stats();
+        assert!(stats.trips > 0 && stats.trips <= 4000);
+        assert_eq!(4000, stats.rejected);
+        assert_eq!(Duration::from_millis(stats.trips), stats.time_open);
+        assert_eq!(4, handle.recent_trips().len());
+    }
+}
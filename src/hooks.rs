@@ -0,0 +1,64 @@
+use crate::{snapshot::BreakerSnapshot, Rejection, Transition};
+use std::{fmt, sync::Arc};
+
+/// User-provided callbacks invoked by a [`CircuitBreaker`](crate::CircuitBreaker).
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    on_state_change: Vec<Arc<dyn Fn(Transition) + Send + Sync>>,
+    on_shutdown: Vec<OnShutdown>,
+    on_reject: Vec<OnReject>,
+}
+
+type OnShutdown = Arc<dyn Fn(&BreakerSnapshot) + Send + Sync>;
+
+type OnReject = Arc<dyn Fn(&Rejection<'_>) + Send + Sync>;
+
+// === impl Hooks ===
+
+impl Hooks {
+    pub(crate) fn add_on_state_change(&mut self, f: impl Fn(Transition) + Send + Sync + 'static) {
+        self.on_state_change.push(Arc::new(f));
+    }
+
+    pub(crate) fn add_on_shutdown(&mut self, f: impl Fn(&BreakerSnapshot) + Send + Sync + 'static) {
+        self.on_shutdown.push(Arc::new(f));
+    }
+
+    pub(crate) fn add_on_reject(&mut self, f: impl Fn(&Rejection<'_>) + Send + Sync + 'static) {
+        self.on_reject.push(Arc::new(f));
+    }
+
+    /// Returns `true` if any callbacks are invoked when a request is
+    /// rejected, so that rejections needn't be built otherwise.
+    pub(crate) fn has_on_reject(&self) -> bool {
+        !self.on_reject.is_empty()
+    }
+
+    pub(crate) fn state_changed(&self, transition: Transition) {
+        for f in &self.on_state_change {
+            f(transition);
+        }
+    }
+
+    pub(crate) fn shut_down(&self, snapshot: &BreakerSnapshot) {
+        for f in &self.on_shutdown {
+            f(snapshot);
+        }
+    }
+
+    pub(crate) fn rejected(&self, rejection: &Rejection<'_>) {
+        for f in &self.on_reject {
+            f(rejection);
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_state_change", &self.on_state_change.len())
+            .field("on_shutdown", &self.on_shutdown.len())
+            .field("on_reject", &self.on_reject.len())
+            .finish()
+    }
+}
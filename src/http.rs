@@ -0,0 +1,804 @@
+//! HTTP server middleware which responds `503 Service Unavailable`, or
+//! routes requests to a fallback service, while the circuit is open.
+//!
+//! A [`CircuitBreaker`] fails requests made while its circuit is open with a
+//! [`CircuitOpen`] error, which a server would otherwise have to map to a
+//! response itself. The [`ServiceUnavailable`] middleware in this module
+//! wraps a breaker, and responds to those requests with a
+//! `503 Service Unavailable` response instead, with a `Retry-After` header
+//! set to when the circuit is expected to close. Responses with a `5xx`
+//! status from the inner service are recorded as failures. If the breaker is
+//! configured to [honor `Retry-After`](crate::Config::with_max_retry_after),
+//! the `Retry-After` headers of `429 Too Many Requests` and
+//! `503 Service Unavailable` responses are recorded as well. Those responses
+//! may also be [weighted more heavily](crate::Config::with_overload_weight)
+//! than other failures.
+//!
+//! Before a request is passed to the inner service, the circuit's current
+//! [`CircuitState`] and the breaker's [`Handle`] are inserted into the
+//! request's [extensions](Request::extensions). Handlers and middleware
+//! further down the stack can use them to adapt to a degraded dependency:
+//! for example, by using the handle's [snapshot](Handle::snapshot) to skip
+//! optional work while the failure rate is high.
+//!
+//! The middleware is [`Clone`], and never fails if the inner service
+//! doesn't, so it can be used with frameworks such as `axum` which require
+//! infallible services:
+//!
+//! ```
+//! use http::{Request, Response, StatusCode};
+//! use std::{convert::Infallible, time::Duration};
+//! use tower::{service_fn, Layer, ServiceExt};
+//! use tower_breaker::{http::ServiceUnavailableLayer, policy::ConsecutiveFailures, Config};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(10));
+//! let svc = ServiceUnavailableLayer::new(config).layer(service_fn(|_: Request<()>| async {
+//!     let mut rsp = Response::new(String::new());
+//!     *rsp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+//!     Ok::<_, Infallible>(rsp)
+//! }));
+//!
+//! let rsp = svc.clone().oneshot(Request::new(())).await.unwrap();
+//! assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, rsp.status());
+//!
+//! // the circuit is now open.
+//! let rsp = svc.oneshot(Request::new(())).await.unwrap();
+//! assert_eq!(StatusCode::SERVICE_UNAVAILABLE, rsp.status());
+//! assert_eq!("10", rsp.headers()["retry-after"]);
+//! # }
+//! ```
+//!
+//! Rather than responding with a 503, the [`Fallback`] middleware routes
+//! requests made while the circuit is open to a fallback service, such as a
+//! cache or a secondary region. Each request it routes to the fallback is
+//! marked with a [`Failover`] extension, and optionally a header, so that the
+//! fallback can tell failover traffic apart from requests it receives
+//! directly.
+//!
+//! [`Unavailable`] can be used as the fallback in any stack which routes
+//! requests to a fallback service while the circuit is open. It responds to
+//! every request with a `503 Service Unavailable` response with a
+//! configurable body, and a `Retry-After` header derived from the breaker's
+//! [`Handle`].
+//!
+//! This requires the `http` feature flag.
+use crate::{
+    deadline::Deadline, priority::Priority, service::Admitted, trace::Span, CircuitBreaker,
+    CircuitState, Config, Handle, Policy,
+};
+use ::http::{header, HeaderName, HeaderValue, Request, Response, StatusCode};
+use std::{
+    convert::Infallible,
+    fmt,
+    future::{self, Future},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// A [`Layer`] which wraps services in a [`ServiceUnavailable`] middleware.
+#[derive(Clone, Debug)]
+pub struct ServiceUnavailableLayer<P> {
+    config: Config<P>,
+}
+
+/// HTTP middleware which responds `503 Service Unavailable` while the circuit
+/// is open.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone)]
+pub struct ServiceUnavailable<P, S> {
+    breaker: CircuitBreaker<P, S>,
+    /// Set if the breaker has been shut down, so that `call` responds with
+    /// a 503 rather than passing the request to the inner service.
+    shut_down: bool,
+}
+
+/// A service which responds to every request with
+/// `503 Service Unavailable`.
+///
+/// The response has a `Retry-After` header set to when the breaker's circuit
+/// is expected to close, if it's open and hasn't been
+/// [forced open](crate::Handle::force_open), and a clone of the configured
+/// body.
+#[derive(Clone, Debug)]
+pub struct Unavailable<B> {
+    handle: Handle,
+    body: B,
+}
+
+/// A [`Layer`] which wraps services in a [`Fallback`] middleware.
+#[derive(Clone, Debug)]
+pub struct FallbackLayer<P, F> {
+    config: Config<P>,
+    fallback: F,
+    header: Option<(HeaderName, HeaderValue)>,
+}
+
+/// HTTP middleware which routes requests to a fallback service while the
+/// circuit is open.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone)]
+pub struct Fallback<P, S, F> {
+    breaker: CircuitBreaker<P, S>,
+    fallback: F,
+    /// A header added to requests routed to the fallback service, if any.
+    header: Option<(HeaderName, HeaderValue)>,
+    /// Set if the breaker has been shut down, so that `call` routes the
+    /// request to the fallback service.
+    shut_down: bool,
+}
+
+/// Marks a request which a [`Fallback`] middleware routed to its fallback
+/// service because the primary service's circuit is open.
+///
+/// This is inserted into the extensions of each request routed to the
+/// fallback service.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Failover {
+    /// How long until the primary service's circuit is expected to close, if
+    /// known.
+    pub retry_after: Option<Duration>,
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by [`Fallback`].
+    #[project = FallbackFutureProj]
+    #[derive(Debug)]
+    pub enum FallbackFuture<P, F, G> {
+        /// The request was passed to the primary service.
+        Primary {
+            #[pin]
+            future: F,
+            admitted: Option<Admitted<P>>,
+            span: Span,
+        },
+        /// The request was routed to the fallback service.
+        Fallback {
+            #[pin]
+            future: G,
+        },
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by [`ServiceUnavailable`].
+    #[derive(Debug)]
+    pub struct ResponseFuture<P, F> {
+        // If this is `None`, the request was rejected.
+        #[pin]
+        future: Option<F>,
+        admitted: Option<Admitted<P>>,
+        retry_after: Option<Duration>,
+        span: Span,
+    }
+}
+
+// === impl ServiceUnavailableLayer ===
+
+impl<P> ServiceUnavailableLayer<P> {
+    /// Returns a new `ServiceUnavailableLayer` which wraps services in a
+    /// breaker configured by `config`.
+    ///
+    /// Each service produced by the layer has its own breaker. Because the
+    /// middleware responds to requests made while the circuit is open rather
+    /// than waiting for the circuit to close, the breakers are always
+    /// configured to [fail fast](Config::with_fail_fast).
+    pub fn new(config: Config<P>) -> Self {
+        ServiceUnavailableLayer {
+            config: config.with_fail_fast(true),
+        }
+    }
+}
+
+impl<P, S> Layer<S> for ServiceUnavailableLayer<P>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+{
+    type Service = ServiceUnavailable<P, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ServiceUnavailable::new(CircuitBreaker::new(self.config.clone(), inner))
+    }
+}
+
+// === impl ServiceUnavailable ===
+
+impl<P, S> ServiceUnavailable<P, S> {
+    /// Wraps `breaker` in a `ServiceUnavailable` middleware.
+    ///
+    /// Unlike [`ServiceUnavailableLayer`], this does not change whether the
+    /// breaker fails fast. If it doesn't, requests wait for the circuit to
+    /// close, and a 503 is only returned once the breaker is
+    /// [shut down](crate::Handle::shutdown).
+    pub fn new(breaker: CircuitBreaker<P, S>) -> Self {
+        ServiceUnavailable {
+            breaker,
+            shut_down: false,
+        }
+    }
+
+    /// Returns a reference to the wrapped breaker.
+    pub fn breaker(&self) -> &CircuitBreaker<P, S> {
+        &self.breaker
+    }
+}
+
+impl<P, S, ReqBody, RspBody> Service<Request<ReqBody>> for ServiceUnavailable<P, S>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    S: Service<Request<ReqBody>, Response = Response<RspBody>>,
+    RspBody: Default,
+{
+    type Response = Response<RspBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<P, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match ready!(self.breaker.poll_circuit(cx)) {
+            Ok(true) => {
+                self.shut_down = false;
+                self.breaker.poll_inner_ready(cx)
+            }
+            Ok(false) => Poll::Ready(Ok(())),
+            Err(_shut_down) => {
+                self.shut_down = true;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        if self.shut_down {
+            return ResponseFuture {
+                future: None,
+                admitted: None,
+                retry_after: None,
+                span: Span::none(),
+            };
+        }
+
+        let (priority, deadline) = admission(&req);
+        let (span, admitted) = self.breaker.admit(priority, deadline);
+        match admitted {
+            Ok(admitted) => {
+                insert_extensions(&self.breaker, &mut req);
+                ResponseFuture {
+                    future: Some(span.in_scope(|| self.breaker.inner_mut().call(req))),
+                    admitted: Some(admitted),
+                    retry_after: None,
+                    span,
+                }
+            }
+            Err(error) => {
+                self.breaker.rejected(&error, || head(&req));
+                ResponseFuture {
+                    future: None,
+                    admitted: None,
+                    retry_after: error.retry_after(),
+                    span,
+                }
+            }
+        }
+    }
+}
+
+impl<P, S> fmt::Debug for ServiceUnavailable<P, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceUnavailable")
+            .field("shut_down", &self.shut_down)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<P, F, B, E> Future for ResponseFuture<P, F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+    P: Policy,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _enter = this.span.enter();
+        let Some(future) = this.future.as_pin_mut() else {
+            return Poll::Ready(Ok(unavailable(B::default(), *this.retry_after)));
+        };
+        let result = ready!(future.poll(cx));
+        if let Some(admitted) = this.admitted.take() {
+            record(admitted, &result);
+        }
+        Poll::Ready(result)
+    }
+}
+
+// === impl FallbackLayer ===
+
+impl<P, F> FallbackLayer<P, F> {
+    /// Returns a new `FallbackLayer` which wraps services in a breaker
+    /// configured by `config`, routing requests to `fallback` while the
+    /// circuit is open.
+    ///
+    /// Each service produced by the layer has its own breaker and its own
+    /// clone of `fallback`. The breakers are always configured to
+    /// [fail fast](Config::with_fail_fast).
+    pub fn new(config: Config<P>, fallback: F) -> Self {
+        FallbackLayer {
+            config: config.with_fail_fast(true),
+            fallback,
+            header: None,
+        }
+    }
+
+    /// Adds the header `name: value` to requests routed to the fallback
+    /// service.
+    pub fn with_failover_header(self, name: HeaderName, value: HeaderValue) -> Self {
+        FallbackLayer {
+            header: Some((name, value)),
+            ..self
+        }
+    }
+}
+
+impl<P, S, F> Layer<S> for FallbackLayer<P, F>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    F: Clone,
+{
+    type Service = Fallback<P, S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Fallback {
+            header: self.header.clone(),
+            ..Fallback::new(
+                CircuitBreaker::new(self.config.clone(), inner),
+                self.fallback.clone(),
+            )
+        }
+    }
+}
+
+// === impl Fallback ===
+
+impl<P, S, F> Fallback<P, S, F> {
+    /// Wraps `breaker` in a `Fallback` middleware which routes requests to
+    /// `fallback` while the circuit is open.
+    ///
+    /// Unlike [`FallbackLayer`], this does not change whether the breaker
+    /// fails fast. If it doesn't, requests wait for the circuit to close, and
+    /// are only routed to the fallback once the breaker is
+    /// [shut down](crate::Handle::shutdown).
+    pub fn new(breaker: CircuitBreaker<P, S>, fallback: F) -> Self {
+        Fallback {
+            breaker,
+            fallback,
+            header: None,
+            shut_down: false,
+        }
+    }
+
+    /// Adds the header `name: value` to requests routed to the fallback
+    /// service.
+    pub fn with_failover_header(self, name: HeaderName, value: HeaderValue) -> Self {
+        Fallback {
+            header: Some((name, value)),
+            ..self
+        }
+    }
+
+    /// Returns a reference to the wrapped breaker.
+    pub fn breaker(&self) -> &CircuitBreaker<P, S> {
+        &self.breaker
+    }
+
+    /// Returns a reference to the fallback service.
+    pub fn fallback(&self) -> &F {
+        &self.fallback
+    }
+
+    /// Marks `req` as failover traffic, and routes it to the fallback
+    /// service.
+    fn failover<ReqBody>(
+        &mut self,
+        mut req: Request<ReqBody>,
+        retry_after: Option<Duration>,
+    ) -> F::Future
+    where
+        F: Service<Request<ReqBody>>,
+    {
+        req.extensions_mut().insert(Failover { retry_after });
+        if let Some((ref name, ref value)) = self.header {
+            req.headers_mut().insert(name.clone(), value.clone());
+        }
+        self.fallback.call(req)
+    }
+}
+
+impl<P, S, F, ReqBody, RspBody> Service<Request<ReqBody>> for Fallback<P, S, F>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    S: Service<Request<ReqBody>, Response = Response<RspBody>>,
+    F: Service<Request<ReqBody>, Response = Response<RspBody>, Error = S::Error>,
+{
+    type Response = Response<RspBody>;
+    type Error = S::Error;
+    type Future = FallbackFuture<P, S::Future, F::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match ready!(self.breaker.poll_circuit(cx)) {
+            Ok(true) => {
+                self.shut_down = false;
+                self.breaker.poll_inner_ready(cx)
+            }
+            Ok(false) => self.fallback.poll_ready(cx),
+            Err(_shut_down) => {
+                self.shut_down = true;
+                self.fallback.poll_ready(cx)
+            }
+        }
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        if self.shut_down {
+            return FallbackFuture::Fallback {
+                future: self.failover(req, None),
+            };
+        }
+
+        let (priority, deadline) = admission(&req);
+        let (span, admitted) = self.breaker.admit(priority, deadline);
+        match admitted {
+            Ok(admitted) => {
+                insert_extensions(&self.breaker, &mut req);
+                FallbackFuture::Primary {
+                    future: span.in_scope(|| self.breaker.inner_mut().call(req)),
+                    admitted: Some(admitted),
+                    span,
+                }
+            }
+            Err(error) => {
+                self.breaker.rejected(&error, || head(&req));
+                let future = span.in_scope(|| self.failover(req, error.retry_after()));
+                FallbackFuture::Fallback { future }
+            }
+        }
+    }
+}
+
+impl<P, S, F: fmt::Debug> fmt::Debug for Fallback<P, S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fallback")
+            .field("fallback", &self.fallback)
+            .field("header", &self.header)
+            .field("shut_down", &self.shut_down)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl FallbackFuture ===
+
+impl<P, F, G, B, E> Future for FallbackFuture<P, F, G>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    G: Future<Output = Result<Response<B>, E>>,
+    P: Policy,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            FallbackFutureProj::Primary {
+                future,
+                admitted,
+                span,
+            } => {
+                let _enter = span.enter();
+                let result = ready!(future.poll(cx));
+                if let Some(admitted) = admitted.take() {
+                    record(admitted, &result);
+                }
+                Poll::Ready(result)
+            }
+            FallbackFutureProj::Fallback { future } => future.poll(cx),
+        }
+    }
+}
+
+// === impl Unavailable ===
+
+impl<B> Unavailable<B> {
+    /// Returns a new `Unavailable` service which responds with `body`, and
+    /// with a `Retry-After` header derived from the breaker behind `handle`.
+    pub fn new(handle: Handle, body: B) -> Self {
+        Unavailable { handle, body }
+    }
+}
+
+impl<B, ReqBody> Service<Request<ReqBody>> for Unavailable<B>
+where
+    B: Clone,
+{
+    type Response = Response<B>;
+    type Error = Infallible;
+    type Future = future::Ready<Result<Response<B>, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: Request<ReqBody>) -> Self::Future {
+        future::ready(Ok(unavailable(
+            self.body.clone(),
+            self.handle.open_remaining(),
+        )))
+    }
+}
+
+/// Records the outcome of a request passed to the inner service.
+fn record<P: Policy, B, E>(admitted: Admitted<P>, result: &Result<Response<B>, E>) {
+    if let Some(retry_after) = result.as_ref().ok().and_then(retry_after) {
+        admitted.record_retry_after(retry_after);
+    }
+    let success = matches!(result, Ok(ref rsp) if !rsp.status().is_server_error());
+    match result {
+        Ok(ref rsp) if is_overloaded(rsp.status()) => admitted.record_overloaded(success),
+        _ => admitted.record(success),
+    }
+}
+
+/// Inserts the circuit's current state and the breaker's `Handle` into the
+/// extensions of a request that's about to be passed to the inner service.
+pub(crate) fn insert_extensions<P, S, B>(breaker: &CircuitBreaker<P, S>, req: &mut Request<B>)
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+{
+    let extensions = req.extensions_mut();
+    extensions.insert::<CircuitState>(breaker.state());
+    extensions.insert::<Handle>(breaker.handle());
+}
+
+/// Returns a copy of a request without its body, to pass to rejection
+/// hooks.
+pub(crate) fn head<B>(req: &Request<B>) -> Request<()> {
+    let mut head = Request::new(());
+    *head.method_mut() = req.method().clone();
+    *head.uri_mut() = req.uri().clone();
+    *head.version_mut() = req.version();
+    *head.headers_mut() = req.headers().clone();
+    *head.extensions_mut() = req.extensions().clone();
+    head
+}
+
+/// Returns the priority and deadline of a request, from its extensions.
+pub(crate) fn admission<B>(req: &Request<B>) -> (Priority, Option<Deadline>) {
+    let extensions = req.extensions();
+    let priority = extensions.get::<Priority>().copied().unwrap_or_default();
+    (priority, extensions.get::<Deadline>().copied())
+}
+
+/// Returns the delay in a `429 Too Many Requests` or
+/// `503 Service Unavailable` response's `Retry-After` header, if it's given
+/// in seconds.
+fn retry_after<B>(rsp: &Response<B>) -> Option<Duration> {
+    if !is_overloaded(rsp.status()) {
+        return None;
+    }
+    let secs = rsp.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+    secs.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Returns `true` if `status` signals that the server is overloaded.
+fn is_overloaded(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    )
+}
+
+/// Returns a `503 Service Unavailable` response, with a `Retry-After` header
+/// if it's known when the circuit will close.
+fn unavailable<B>(body: B, retry_after: Option<Duration>) -> Response<B> {
+    let mut rsp = Response::new(body);
+    *rsp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    if let Some(retry_after) = retry_after {
+        // `Retry-After` is in whole seconds, so round up.
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        rsp.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    rsp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::ConsecutiveFailures;
+    use std::convert::Infallible;
+    use tokio::time;
+    use tower::{service_fn, ServiceExt};
+
+    async fn handle(req: Request<()>) -> Result<Response<String>, Infallible> {
+        let status = match req.uri().path() {
+            "/fail" => StatusCode::INTERNAL_SERVER_ERROR,
+            "/overloaded" => StatusCode::SERVICE_UNAVAILABLE,
+            "/busy" => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::OK,
+        };
+        let mut rsp = Response::new(String::from("hello"));
+        *rsp.status_mut() = status;
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            rsp.headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from_static("30"));
+        }
+        Ok(rsp)
+    }
+
+    fn request(path: &str) -> Request<()> {
+        Request::builder().uri(path).body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn responds_unavailable_while_open() {
+        time::pause();
+        let config = Config::new(ConsecutiveFailures::new(2), Duration::from_millis(2500));
+        let svc = ServiceUnavailableLayer::new(config).layer(service_fn(handle));
+
+        for _ in 0..2 {
+            let rsp = svc.clone().oneshot(request("/fail")).await.unwrap();
+            assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, rsp.status());
+        }
+
+        let rsp = svc.clone().oneshot(request("/")).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, rsp.status());
+        assert_eq!("3", rsp.headers()[header::RETRY_AFTER]);
+        assert_eq!("", rsp.body());
+
+        time::advance(Duration::from_secs(3)).await;
+        let rsp = svc.clone().oneshot(request("/")).await.unwrap();
+        assert_eq!(StatusCode::OK, rsp.status());
+        assert_eq!("hello", rsp.body());
+    }
+
+    #[tokio::test]
+    async fn responds_unavailable_after_shutdown() {
+        let config = Config::new(ConsecutiveFailures::new(2), Duration::from_secs(5));
+        let svc = ServiceUnavailableLayer::new(config).layer(service_fn(handle));
+        svc.breaker().handle().shutdown(Duration::ZERO).await;
+
+        let rsp = svc.oneshot(request("/")).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, rsp.status());
+        assert!(!rsp.headers().contains_key(header::RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn honors_retry_after() {
+        time::pause();
+        async fn trip_for(config: Config<ConsecutiveFailures>, path: &str) -> HeaderValue {
+            let svc = ServiceUnavailableLayer::new(config).layer(service_fn(handle));
+            svc.clone().oneshot(request(path)).await.unwrap();
+            let rsp = svc.oneshot(request("/")).await.unwrap();
+            assert_eq!(StatusCode::SERVICE_UNAVAILABLE, rsp.status());
+            rsp.headers()[header::RETRY_AFTER].clone()
+        }
+
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5));
+        assert_eq!("5", trip_for(config.clone(), "/overloaded").await);
+
+        let config = config.with_max_retry_after(Duration::from_secs(60));
+        assert_eq!("30", trip_for(config.clone(), "/overloaded").await);
+        assert_eq!("5", trip_for(config.clone(), "/fail").await);
+
+        let config = config.with_max_retry_after(Duration::from_secs(20));
+        assert_eq!("20", trip_for(config, "/overloaded").await);
+    }
+
+    #[tokio::test]
+    async fn inserts_extensions() {
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5))
+            .with_name("extensions");
+        let svc =
+            ServiceUnavailableLayer::new(config).layer(service_fn(|req: Request<()>| async move {
+                let state = req.extensions().get::<CircuitState>().copied();
+                let handle = req.extensions().get::<Handle>().unwrap();
+                let rsp = format!("{state:?} {:?} {}", handle.name(), handle.stats().trips);
+                Ok::<_, Infallible>(Response::new(rsp))
+            }));
+        let rsp = svc.oneshot(request("/")).await.unwrap();
+        assert_eq!("Some(Closed) Some(\"extensions\") 0", rsp.body());
+    }
+
+    #[tokio::test]
+    async fn overload_weight() {
+        let config = Config::new(ConsecutiveFailures::new(3), Duration::from_secs(5));
+        let svc = ServiceUnavailableLayer::new(config.clone()).layer(service_fn(handle));
+        svc.clone().oneshot(request("/busy")).await.unwrap();
+        svc.clone().oneshot(request("/overloaded")).await.unwrap();
+        assert!(!svc.breaker().is_tripped());
+
+        let config = config.with_overload_weight(3);
+        for path in ["/busy", "/overloaded"] {
+            let svc = ServiceUnavailableLayer::new(config.clone()).layer(service_fn(handle));
+            svc.clone().oneshot(request(path)).await.unwrap();
+            let rsp = svc.oneshot(request("/")).await.unwrap();
+            assert_eq!(StatusCode::SERVICE_UNAVAILABLE, rsp.status(), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_to_fallback() {
+        time::pause();
+        let fallback = service_fn(|req: Request<()>| async move {
+            let failover = req.extensions().get::<Failover>().cloned();
+            let header = req.headers().get("x-failover").cloned();
+            let rsp = format!("{:?} {header:?}", failover.and_then(|f| f.retry_after));
+            Ok::<_, Infallible>(Response::new(rsp))
+        });
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5));
+        let svc = FallbackLayer::new(config, fallback)
+            .with_failover_header(
+                HeaderName::from_static("x-failover"),
+                HeaderValue::from_static("1"),
+            )
+            .layer(service_fn(handle));
+
+        let rsp = svc.clone().oneshot(request("/")).await.unwrap();
+        assert_eq!("hello", rsp.body());
+        let rsp = svc.clone().oneshot(request("/fail")).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, rsp.status());
+
+        let rsp = svc.clone().oneshot(request("/")).await.unwrap();
+        assert_eq!(StatusCode::OK, rsp.status());
+        assert_eq!("Some(5s) Some(\"1\")", rsp.body());
+
+        time::advance(Duration::from_secs(5)).await;
+        let rsp = svc.oneshot(request("/")).await.unwrap();
+        assert_eq!("hello", rsp.body());
+    }
+
+    #[tokio::test]
+    async fn unavailable_fallback() {
+        time::pause();
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(10));
+        let svc = ServiceUnavailableLayer::new(config).layer(service_fn(handle));
+        let mut fallback = Unavailable::new(svc.breaker().handle(), "try again later");
+
+        let rsp = fallback.call(request("/")).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, rsp.status());
+        assert!(!rsp.headers().contains_key(header::RETRY_AFTER));
+
+        let rsp = svc.clone().oneshot(request("/fail")).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, rsp.status());
+        svc.clone().ready().await.unwrap();
+
+        time::advance(Duration::from_millis(5500)).await;
+        let rsp = fallback.call(request("/")).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, rsp.status());
+        assert_eq!("5", rsp.headers()[header::RETRY_AFTER]);
+        assert_eq!("try again later", *rsp.body());
+    }
+
+    #[cfg(feature = "admin")]
+    #[tokio::test]
+    async fn axum_router() {
+        use axum::{body::Body, routing::get, Router};
+
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5));
+        let app: Router = Router::new()
+            .route("/", get(|| async { "hello" }))
+            .layer(ServiceUnavailableLayer::new(config));
+        let rsp = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, rsp.status());
+    }
+}
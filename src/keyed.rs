@@ -0,0 +1,635 @@
+//! Circuit breakers with a separate circuit for each key, such as each
+//! tenant of a multi-tenant gateway.
+//!
+//! A [`KeyedCircuitBreaker`] extracts a key from each request, and passes the
+//! request through a [`CircuitBreaker`] for that key, so that failures for
+//! one key only trip that key's circuit. Each key's breaker is constructed
+//! from its own [`Config`], so keys can be given their own policies and
+//! thresholds: by default, configs are built by a function of the key, and
+//! individual keys can be [overridden](KeyedCircuitBreaker::with_key_config),
+//! such as with settings loaded from a configuration file.
+//!
+//! When keys are endpoints, breakers can also be grouped by the
+//! [zone](KeyedCircuitBreaker::with_zones) (or region) each endpoint is in,
+//! as labeled by the discovery layer. If enough of a zone's endpoints trip
+//! together, which suggests a problem with the zone rather than with
+//! individual endpoints, the whole zone is ejected.
+//!
+//! Callers waiting for a key's circuit to close each hold a timer, so when
+//! many keys' circuits may be open at once, the breakers can
+//! [share](KeyedCircuitBreaker::with_timer) a
+//! [`TimerWheel`](crate::timer::TimerWheel) instead.
+//!
+//! Each key's breaker records its own metrics. When keys are unbounded, such
+//! as URLs, OpenTelemetry metrics can be attributed to a limited number of
+//! keys, with the rest sharing a bucket, with
+//! `MetricsConfig::with_key_attribute` (`opentelemetry` feature flag).
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower::service_fn;
+//! use tower_breaker::{keyed::KeyedCircuitBreaker, policy::SlidingFailureRate, Config};
+//!
+//! struct Request {
+//!     tenant: String,
+//! }
+//!
+//! let svc = service_fn(|req: Request| async move { Ok::<_, std::io::Error>(req.tenant) });
+//! let breaker = KeyedCircuitBreaker::new(
+//!     svc,
+//!     |req: &Request| req.tenant.clone(),
+//!     |tenant: &String| {
+//!         let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.2);
+//!         Config::new(policy, Duration::from_secs(5)).with_name(format!("api/{tenant}"))
+//!     },
+//! )
+//! // a noisy tenant with a tuned threshold.
+//! .with_key_config(
+//!     "acme".to_owned(),
+//!     Config::new(SlidingFailureRate::new(Duration::from_secs(10), 0.5), Duration::from_secs(30))
+//!         .with_name("api/acme"),
+//! );
+//! # drop(breaker);
+//! ```
+use crate::{
+    error::NoTimer,
+    service,
+    timer::{SharedTimer, Timer},
+    trace::debug,
+    BoxError, CircuitBreaker, CircuitState, Config, Handle, Policy, TripReason,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+use tower_service::Service;
+
+/// A [`CircuitBreaker`] with a separate circuit for each key.
+///
+/// Cloning a `KeyedCircuitBreaker` returns a new reference to the same
+/// circuits. A breaker is created for each key the first time a request with
+/// that key is received, and is kept for the life of the
+/// `KeyedCircuitBreaker`.
+///
+/// See the [module-level documentation](self) for details.
+pub struct KeyedCircuitBreaker<K, P, S, F> {
+    inner: S,
+    key: F,
+    config: MakeConfig<K, P>,
+    overrides: Arc<HashMap<K, Config<P>>>,
+    zones: Option<(ZoneOf<K>, Arc<Zones>)>,
+    timer: Option<SharedTimer>,
+    breakers: Arc<Mutex<Breakers<K, CircuitBreaker<P, S>>>>,
+}
+
+/// Each key's breaker, stored in a list while there are few enough keys,
+/// so that finding a key's breaker doesn't require hashing the key.
+enum Breakers<K, V> {
+    Inline { max: usize, breakers: Vec<(K, V)> },
+    Hashed(HashMap<K, V>),
+}
+
+/// The default maximum number of keys whose breakers are stored inline.
+const MAX_INLINE_KEYS: usize = 8;
+
+type MakeConfig<K, P> = Arc<dyn Fn(&K) -> Config<P> + Send + Sync>;
+
+type ZoneOf<K> = Arc<dyn Fn(&K) -> Option<String> + Send + Sync>;
+
+/// The breakers in each zone.
+struct Zones {
+    /// The fraction of a zone's breakers which must be open for the zone to
+    /// be ejected.
+    eject_ratio: f64,
+    /// Handles to the breakers in each zone.
+    members: Mutex<HashMap<String, Vec<Handle>>>,
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by a [`KeyedCircuitBreaker`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<P, S, Req>
+    where
+        S: Service<Req>,
+    {
+        // Waiting for the key's breaker to become ready.
+        Ready {
+            breaker: CircuitBreaker<P, S>,
+            req: Option<Req>,
+        },
+        Called {
+            #[pin]
+            future: service::ResponseFuture<P, S::Future>,
+        },
+        // The key's breaker couldn't be constructed.
+        Failed {
+            error: Option<NoTimer>,
+        },
+    }
+}
+
+// === impl KeyedCircuitBreaker ===
+
+impl<K, P, S, F> KeyedCircuitBreaker<K, P, S, F>
+where
+    K: Hash + Eq + Clone,
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    S: Clone,
+{
+    /// Returns a new `KeyedCircuitBreaker` wrapping `inner`.
+    ///
+    /// `key` extracts the key from each request, and `config` returns the
+    /// config for each key's breaker, unless it has been
+    /// [overridden](Self::with_key_config). To register each key's breaker in
+    /// a [registry](crate::BreakerRegistry), `config` should give each key's
+    /// breaker a distinct name.
+    pub fn new(inner: S, key: F, config: impl Fn(&K) -> Config<P> + Send + Sync + 'static) -> Self {
+        KeyedCircuitBreaker {
+            inner,
+            key,
+            config: Arc::new(config),
+            overrides: Arc::new(HashMap::new()),
+            zones: None,
+            timer: None,
+            breakers: Arc::new(Mutex::new(Breakers::new(MAX_INLINE_KEYS))),
+        }
+    }
+
+    /// Groups keys' breakers by zone, ejecting a zone once at least
+    /// `eject_ratio` of its breakers have tripped.
+    ///
+    /// `zone` returns the zone (or region) label of each key, or `None` if
+    /// the key isn't in a zone. When a breaker trips and at least
+    /// `eject_ratio` of the breakers in its zone are open, every other
+    /// breaker in the zone trips too, with [`TripReason::ZoneEjected`], for
+    /// its configured trip duration. Each breaker's trip is applied the next
+    /// time it receives a request. Breakers tripped this way don't count
+    /// towards ejecting their zone again.
+    ///
+    /// This must be called before any requests are received.
+    ///
+    /// # Panics
+    ///
+    /// If `eject_ratio` is not greater than 0 and at most 1.
+    pub fn with_zones(
+        self,
+        zone: impl Fn(&K) -> Option<String> + Send + Sync + 'static,
+        eject_ratio: f64,
+    ) -> Self {
+        assert!(
+            eject_ratio > 0.0 && eject_ratio <= 1.0,
+            "zone ejection ratio ({eject_ratio}) must be in the range (0, 1]"
+        );
+        KeyedCircuitBreaker {
+            zones: Some((
+                Arc::new(zone),
+                Arc::new(Zones {
+                    eject_ratio,
+                    members: Mutex::new(HashMap::new()),
+                }),
+            )),
+            ..self
+        }
+    }
+
+    /// Uses `timer` for every key's breaker, rather than the timers in their
+    /// configs.
+    ///
+    /// With a [`TimerWheel`](crate::timer::TimerWheel), callers waiting for
+    /// any key's circuit to close share the wheel's single timer, rather
+    /// than each holding their own.
+    ///
+    /// This must be called before any requests are received.
+    pub fn with_timer(self, timer: impl Timer) -> Self {
+        KeyedCircuitBreaker {
+            timer: Some(Arc::new(timer)),
+            ..self
+        }
+    }
+
+    /// Finds keys' breakers by comparing keys, rather than by hashing them,
+    /// until there are more than `max` keys.
+    ///
+    /// With only a handful of keys, such as a few upstream regions, comparing
+    /// a request's key with each key is cheaper than hashing it. Once more
+    /// than `max` keys have been seen, breakers are moved into a hash map.
+    /// By default, up to 8 keys are compared.
+    ///
+    /// This must be called before any requests are received.
+    pub fn with_inline_keys(self, max: usize) -> Self {
+        KeyedCircuitBreaker {
+            breakers: Arc::new(Mutex::new(Breakers::new(max))),
+            ..self
+        }
+    }
+
+    /// Uses `config` for the breaker for `key`, rather than the config
+    /// returned by the function passed to [`new`](Self::new).
+    ///
+    /// This has no effect if a breaker for `key` has already been created.
+    pub fn with_key_config(mut self, key: K, config: Config<P>) -> Self {
+        Arc::make_mut(&mut self.overrides).insert(key, config);
+        self
+    }
+
+    /// Returns a handle to the breaker for `key`, if a request with that key
+    /// has been received.
+    pub fn handle(&self, key: &K) -> Option<Handle> {
+        let breakers = self.breakers.lock().unwrap();
+        breakers.get(key).map(CircuitBreaker::handle)
+    }
+
+    /// Returns the breaker for `key`, creating it if it doesn't exist yet, or
+    /// an error if its config has no timer.
+    fn breaker(&self, key: K) -> Result<CircuitBreaker<P, S>, NoTimer> {
+        let mut breakers = self.breakers.lock().unwrap();
+        if let Some(breaker) = breakers.get(&key) {
+            return Ok(breaker.clone());
+        }
+
+        let mut config = match self.overrides.get(&key) {
+            Some(config) => config.clone(),
+            None => (self.config)(&key),
+        };
+        if let Some(ref timer) = self.timer {
+            config.timer = Some(timer.clone());
+        }
+        let zone = self
+            .zones
+            .as_ref()
+            .and_then(|(zone_of, zones)| Some((zones.clone(), zone_of(&key)?)));
+        if let Some((ref zones, ref zone)) = zone {
+            let (zones, zone) = (Arc::downgrade(zones), zone.clone());
+            config = config.on_state_change(move |transition| {
+                let ejected = matches!(transition.reason, Some(TripReason::ZoneEjected));
+                if transition.to != CircuitState::Open || ejected {
+                    return;
+                }
+                if let Some(zones) = zones.upgrade() {
+                    zones.tripped(&zone);
+                }
+            });
+        }
+        let breaker = CircuitBreaker::try_new(config, self.inner.clone())?;
+        if let Some((zones, zone)) = zone {
+            let mut members = zones.members.lock().unwrap();
+            members.entry(zone).or_default().push(breaker.handle());
+        }
+        Ok(breakers.get_or_insert_with(key, |_| breaker).clone())
+    }
+}
+
+impl<K, P, S, F, Req> Service<Req> for KeyedCircuitBreaker<K, P, S, F>
+where
+    K: Hash + Eq + Clone,
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    S: Service<Req> + Clone,
+    S::Error: Into<BoxError>,
+    F: Fn(&Req) -> K,
+    Req: 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<P, S, Req>;
+
+    /// Always ready: the readiness of each key's breaker (and its clone of
+    /// the inner service) is checked once the request's key is known, by the
+    /// returned future.
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        match self.breaker((self.key)(&req)) {
+            Ok(breaker) => ResponseFuture::Ready {
+                breaker,
+                req: Some(req),
+            },
+            Err(error) => ResponseFuture::Failed { error: Some(error) },
+        }
+    }
+}
+
+impl<K, P, S: Clone, F: Clone> Clone for KeyedCircuitBreaker<K, P, S, F> {
+    fn clone(&self) -> Self {
+        KeyedCircuitBreaker {
+            inner: self.inner.clone(),
+            key: self.key.clone(),
+            config: self.config.clone(),
+            overrides: self.overrides.clone(),
+            zones: self.zones.clone(),
+            timer: self.timer.clone(),
+            breakers: self.breakers.clone(),
+        }
+    }
+}
+
+impl<K: fmt::Debug, P, S: fmt::Debug, F> fmt::Debug for KeyedCircuitBreaker<K, P, S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let breakers = self.breakers.lock().unwrap();
+        f.debug_struct("KeyedCircuitBreaker")
+            .field("inner", &self.inner)
+            .field("keys", &breakers.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl Breakers ===
+
+impl<K: Hash + Eq, V> Breakers<K, V> {
+    fn new(max: usize) -> Self {
+        Breakers::Inline {
+            max,
+            breakers: Vec::new(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        match self {
+            Breakers::Inline { breakers, .. } => breakers
+                .iter()
+                .find_map(|(k, breaker)| (k == key).then_some(breaker)),
+            Breakers::Hashed(breakers) => breakers.get(key),
+        }
+    }
+
+    fn get_or_insert_with(&mut self, key: K, f: impl FnOnce(&K) -> V) -> &V {
+        if let Breakers::Inline { max, breakers } = self {
+            if breakers.len() >= *max && !breakers.iter().any(|(k, _)| *k == key) {
+                *self = Breakers::Hashed(std::mem::take(breakers).into_iter().collect());
+            }
+        }
+        match self {
+            Breakers::Inline { breakers, .. } => {
+                let i = match breakers.iter().position(|(k, _)| *k == key) {
+                    Some(i) => i,
+                    None => {
+                        let breaker = f(&key);
+                        breakers.push((key, breaker));
+                        breakers.len() - 1
+                    }
+                };
+                &breakers[i].1
+            }
+            Breakers::Hashed(breakers) => breakers.entry(key).or_insert_with_key(f),
+        }
+    }
+}
+
+impl<K, V> Breakers<K, V> {
+    fn keys(&self) -> Vec<&K> {
+        match self {
+            Breakers::Inline { breakers, .. } => breakers.iter().map(|(k, _)| k).collect(),
+            Breakers::Hashed(breakers) => breakers.keys().collect(),
+        }
+    }
+}
+
+// === impl Zones ===
+
+impl Zones {
+    /// Called when a breaker in `zone` trips, to eject the zone if enough of
+    /// its breakers are open.
+    fn tripped(&self, zone: &str) {
+        let members = self.members.lock().unwrap();
+        let Some(members) = members.get(zone) else {
+            return;
+        };
+        let open = members
+            .iter()
+            .filter(|handle| handle.state() == CircuitState::Open)
+            .count();
+        if (open as f64) < self.eject_ratio * members.len() as f64 {
+            return;
+        }
+        debug!(zone, open, endpoints = members.len(), "ejecting zone");
+        for handle in members {
+            if handle.state() == CircuitState::Closed && handle.forced().is_none() {
+                handle.trip(TripReason::ZoneEjected);
+            }
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<P, S, Req> Future for ResponseFuture<P, S, Req>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+    Req: 'static,
+{
+    type Output = Result<S::Response, BoxError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            match self.as_mut().project() {
+                ResponseFutureProj::Ready { breaker, req } => {
+                    ready!(breaker.poll_ready(cx))?;
+                    let req = req.take().expect("polled after completion");
+                    let future = breaker.call(req);
+                    self.set(ResponseFuture::Called { future });
+                }
+                ResponseFutureProj::Called { future } => return future.poll(cx),
+                ResponseFutureProj::Failed { error } => {
+                    let error = error.take().expect("polled after completion");
+                    return Poll::Ready(Err(error.into()));
+                }
+            }
+        }
+    }
+}
+
+impl<P, S, Req> fmt::Debug for ResponseFuture<P, S, Req>
+where
+    S: Service<Req>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseFuture::Ready { .. } => f.write_str("ResponseFuture::Ready"),
+            ResponseFuture::Called { .. } => f.write_str("ResponseFuture::Called"),
+            ResponseFuture::Failed { .. } => f.write_str("ResponseFuture::Failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::ConsecutiveFailures, CircuitState};
+    use std::time::Duration;
+    use tower::{service_fn, ServiceExt};
+
+    #[tokio::test]
+    async fn per_key_circuits() {
+        // requests are `(tenant, ok)`.
+        let svc = service_fn(|(_, ok): (&'static str, bool)| async move {
+            if ok {
+                Ok(())
+            } else {
+                Err("failed")
+            }
+        });
+        let config = |failures| {
+            Config::new(ConsecutiveFailures::new(failures), Duration::from_secs(5))
+                .with_fail_fast(true)
+        };
+        let mut breaker = KeyedCircuitBreaker::new(
+            svc,
+            |&(tenant, _): &(&'static str, bool)| tenant,
+            move |_: &&str| config(1),
+        )
+        .with_key_config("tolerant", config(3));
+
+        // a noisy tenant trips only its own circuit.
+        assert!(breaker
+            .ready()
+            .await
+            .unwrap()
+            .call(("noisy", false))
+            .await
+            .is_err());
+        assert!(breaker
+            .ready()
+            .await
+            .unwrap()
+            .call(("noisy", true))
+            .await
+            .is_err());
+        assert_eq!(
+            CircuitState::Open,
+            breaker.handle(&"noisy").unwrap().state()
+        );
+        assert!(breaker
+            .ready()
+            .await
+            .unwrap()
+            .call(("quiet", true))
+            .await
+            .is_ok());
+        assert_eq!(
+            CircuitState::Closed,
+            breaker.handle(&"quiet").unwrap().state()
+        );
+
+        // a tenant with its own threshold tolerates more failures.
+        for _ in 0..2 {
+            assert!(breaker
+                .ready()
+                .await
+                .unwrap()
+                .call(("tolerant", false))
+                .await
+                .is_err());
+        }
+        assert!(breaker
+            .ready()
+            .await
+            .unwrap()
+            .call(("tolerant", true))
+            .await
+            .is_ok());
+        assert!(breaker.handle(&"unseen").is_none());
+    }
+
+    #[tokio::test]
+    async fn ejects_zones() {
+        // requests are `(endpoint, ok)`, and endpoints are in the zone named
+        // by their first letter.
+        let svc = service_fn(|(_, ok): (&'static str, bool)| async move {
+            if ok {
+                Ok(())
+            } else {
+                Err("failed")
+            }
+        });
+        let mut breaker = KeyedCircuitBreaker::new(
+            svc,
+            |&(endpoint, _): &(&'static str, bool)| endpoint,
+            |_: &&str| {
+                Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5))
+                    .with_fail_fast(true)
+            },
+        )
+        .with_zones(|endpoint: &&str| Some(endpoint[..1].to_owned()), 0.6);
+
+        // each endpoint trips when it's next polled after a failure.
+        let steps = [
+            ("a1", true, true),
+            ("a2", true, true),
+            ("a3", true, true),
+            ("b1", true, true),
+            // one endpoint failing isn't enough to eject its zone...
+            ("a1", false, false),
+            ("a1", true, false),
+            ("a3", true, true),
+            // ...but two out of three is.
+            ("a2", false, false),
+            ("a2", true, false),
+            ("a3", true, false),
+        ];
+        for (endpoint, ok, expected) in steps {
+            let rsp = breaker.ready().await.unwrap().call((endpoint, ok)).await;
+            assert_eq!(expected, rsp.is_ok(), "{endpoint}");
+        }
+        let a3 = breaker.handle(&"a3").unwrap();
+        assert_eq!(CircuitState::Open, a3.state());
+        assert_eq!(
+            Some(TripReason::ZoneEjected),
+            a3.last_trip().map(|trip| trip.reason)
+        );
+
+        // other zones are unaffected.
+        assert!(breaker
+            .ready()
+            .await
+            .unwrap()
+            .call(("b1", true))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn moves_inline_keys_into_map() {
+        let svc = service_fn(|(_, ok): (&'static str, bool)| async move {
+            if ok {
+                Ok(())
+            } else {
+                Err("failed")
+            }
+        });
+        let mut breaker = KeyedCircuitBreaker::new(
+            svc,
+            |&(key, _): &(&'static str, bool)| key,
+            |_: &&str| {
+                Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5))
+                    .with_fail_fast(true)
+            },
+        )
+        .with_inline_keys(2);
+
+        let steps = [
+            ("a", false, false),
+            ("b", true, true),
+            // a third key moves the breakers into a map, keeping their state.
+            ("c", true, true),
+            ("a", true, false),
+            ("b", true, true),
+        ];
+        for (key, ok, expected) in steps {
+            let rsp = breaker.ready().await.unwrap().call((key, ok)).await;
+            assert_eq!(expected, rsp.is_ok(), "{key}");
+        }
+        assert!(matches!(
+            *breaker.breakers.lock().unwrap(),
+            Breakers::Hashed(_)
+        ));
+        assert_eq!(CircuitState::Open, breaker.handle(&"a").unwrap().state());
+    }
+}
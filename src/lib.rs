@@ -1,10 +1,103 @@
 //! Tower circuit breaker experiments.
+//!
+//! # Feature flags
+//!
+//! The core middleware depends only on `tower-service` and on Tokio's `sync`
+//! primitives (which do not require a Tokio runtime). Everything else is
+//! opt-in:
+//!
+//! - `rt-tokio` (default): uses Tokio's timer and clock by default. See the
+//!   [`timer`] module for running without it.
+//! - `tracing` (default): emits `tracing` spans and events.
+//! - `alert`: alerting when a breaker trips. Requires a Tokio
+//!   runtime to spawn notifications on.
+//! - `serde`: `Serialize` and `Deserialize` implementations.
+//! - `opentelemetry`: OpenTelemetry metrics.
+//! - `admin`: an `axum` router for inspecting and controlling breakers.
+//! - `http`: HTTP server middleware which responds `503 Service Unavailable`
+//!   while the circuit is open.
+//! - `grpc`: gRPC client middleware, for use with clients such as `tonic`.
+//! - `grpc-control`: a gRPC [service](grpc::control) for managing breakers
+//!   from a central operations tool.
+//! - `reload`: reloading breaker settings from a file.
+//! - `redis`: a Redis [state store](store) for sharing trips between
+//!   replicas.
+//! - `udp`: broadcasting trips to [peers](peer) over UDP.
+//! - `export`: publishing breaker snapshots to a sidecar or node agent.
+//! - `runtime-signal`: a [signal](signal) reporting the depth of a Tokio
+//!   runtime's queue.
+//! - `testing`: utilities for testing breaker configurations.
+//! - `testkit`: fake services for exercising breakers.
+//! - `events`: a [stream](events) of the outcomes of breakers' requests.
+//! - `span-trace`: capturing a `tracing_error::SpanTrace` when a request is
+//!   rejected.
+#[cfg(feature = "alert")]
+pub mod alert;
+pub mod bulkhead;
+pub mod catch_panic;
+pub mod chaos;
+pub mod classify;
+pub mod clock;
+pub mod compat;
+mod console;
+pub mod control;
+pub mod deadline;
+mod env;
+pub mod envoy;
+pub mod error;
+mod extract;
+pub mod handle;
+mod hooks;
+pub mod keyed;
+pub mod local;
+mod parse;
+pub mod peer;
 pub mod policy;
+pub mod priority;
+mod rate_limit;
+pub mod registry;
+pub mod rng;
 pub mod service;
+pub mod shm;
+pub mod signal;
+pub mod sim;
+pub mod snapshot;
+mod state;
+pub mod store;
+pub mod sync;
+pub mod timer;
+mod trace;
 mod window_counter;
 
-pub use self::{policy::Policy, service::CircuitBreaker};
-use tokio::time::Duration;
+#[cfg(feature = "admin")]
+pub mod admin;
+#[cfg(feature = "events")]
+pub mod events;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+#[cfg(feature = "reload")]
+pub mod reload;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+
+pub use self::{
+    error::{BoxError, CircuitOpen},
+    handle::Handle,
+    policy::{Policy, TripReason},
+    registry::BreakerRegistry,
+    service::CircuitBreaker,
+};
+use std::{any::Any, borrow::Cow, fmt, sync::Arc, time::Duration};
+#[cfg(feature = "tracing")]
+use tracing::Level;
 
 /// Configures a [`CircuitBreaker`].
 #[derive(Debug, Clone)]
@@ -15,4 +108,962 @@ pub struct Config<P> {
     /// How long a breaker remains tripped once the policy determines it to be
     /// tripped.
     pub trip_for: Duration,
+    /// If `true`, requests made while the circuit is open fail immediately
+    /// with a [`CircuitOpen`](error::CircuitOpen) error. Otherwise, the
+    /// breaker's `poll_ready` will not become ready until the circuit closes.
+    ///
+    /// By default, this is `false`.
+    pub fail_fast: bool,
+    /// A name identifying this breaker in diagnostics, if any.
+    pub name: Option<Cow<'static, str>>,
+    /// The level at which events are emitted when the breaker trips or
+    /// closes. By default, this is [`Level::TRACE`].
+    #[cfg(feature = "tracing")]
+    pub transition_level: Level,
+    /// The level at which events are emitted when the breaker rejects or
+    /// sheds a request. By default, this is [`Level::TRACE`].
+    #[cfg(feature = "tracing")]
+    pub decision_level: Level,
+    /// The level of the span created for each request passed through the
+    /// breaker. By default, this is [`Level::TRACE`].
+    #[cfg(feature = "tracing")]
+    pub span_level: Level,
+    /// If `true`, the span created for each request is a
+    /// `circuit_breaker.call` span, which records the request's `outcome`
+    /// and `latency` when it completes. By default, this is `false`.
+    #[cfg(feature = "tracing")]
+    pub call_spans: bool,
+    /// If `true`, a [`SpanTrace`](tracing_error::SpanTrace) is captured in
+    /// the [`CircuitOpen`](error::CircuitOpen) error of each rejected
+    /// request. By default, this is `false`.
+    #[cfg(feature = "span-trace")]
+    pub span_traces: bool,
+    /// The number of recent trips retained in the breaker's
+    /// [trip history](Handle::recent_trips). By default, the last 8 trips
+    /// are retained.
+    pub trip_history: usize,
+    /// The maximum fraction by which each trip's duration is randomly
+    /// lengthened or shortened. By default, this is 0.
+    pub trip_jitter: f64,
+    /// The number of failures recorded for each response in which the server
+    /// signals that it's overloaded, or `None` if those responses are
+    /// classified like any other. By default, this is `None`.
+    pub overload_weight: Option<usize>,
+    /// The fraction of requests which are shed while the circuit is open. The
+    /// remaining requests are passed to the inner service as though the
+    /// circuit were closed. By default, this is 1, so every request is shed.
+    pub shed_fraction: f64,
+    /// The fraction of requests which are shed while the circuit is closed,
+    /// by the minimum [policy severity](Policy::severity) at which each
+    /// fraction is shed, in ascending order of severity. By default, this is
+    /// empty, so no requests are shed until the circuit opens.
+    pub degraded_shedding: Vec<(f64, f64)>,
+    /// How long traffic ramps up for after a trip ends, or `None` if every
+    /// request is admitted as soon as the circuit closes. By default, this is
+    /// `None`.
+    pub ramp_up: Option<Duration>,
+    /// The fraction of requests of specific priorities which are shed while
+    /// the circuit is closed, as `(priority, severity, fraction)`, in
+    /// ascending order of severity. Requests of other priorities are shed
+    /// according to [`degraded_shedding`](Config::degraded_shedding). By
+    /// default, this is empty.
+    pub priority_shedding: Vec<(priority::Priority, f64, f64)>,
+    /// The quantile of recent latencies which the time remaining before a
+    /// request's deadline must exceed for it to be admitted, or `None` if
+    /// deadlines are ignored. By default, this is `None`.
+    pub deadline_quantile: Option<f64>,
+    /// Roughly how often the policy is consulted while the circuit is
+    /// closed, or `None` if it's consulted every time the breaker is polled.
+    /// By default, this is `None`.
+    pub evaluation_interval: Option<Duration>,
+    /// How long the circuit stays open after the breaker is constructed, or
+    /// `None` if it starts closed. By default, this is `None`.
+    pub initially_open: Option<Duration>,
+    /// If `true`, the policy only records the outcomes of requests admitted
+    /// since it was last reset, such as when the circuit tripped. By
+    /// default, this is `false`.
+    pub probe_only: bool,
+    /// The peers to which trips are broadcast, if any.
+    pub(crate) peers: Option<peer::Peers>,
+    /// The shared memory segment in which trips are shared, if any.
+    pub(crate) shared_memory: Option<shm::SharedMemory>,
+    /// The breakers this breaker depends on.
+    pub(crate) dependencies: Vec<Handle>,
+    /// The longest trip duration which a `Retry-After` sent by the inner
+    /// service may set, or `None` if `Retry-After` is ignored. By default,
+    /// this is `None`.
+    pub max_retry_after: Option<Duration>,
+    /// The registry in which breakers constructed with this config are
+    /// registered, if any.
+    pub registry: Option<BreakerRegistry>,
+    /// Alerts sent when breakers constructed with this config trip, if any.
+    #[cfg(feature = "alert")]
+    pub alerting: Option<alert::Alerting>,
+    /// OpenTelemetry metrics recorded by the breaker, if any.
+    #[cfg(feature = "opentelemetry")]
+    pub otel: Option<otel::OtelMetrics>,
+    /// The sender to which the outcome of every request is sent, if any.
+    #[cfg(feature = "events")]
+    pub outcome_events: Option<events::OutcomeSender>,
+    /// The most rejection events logged, and the most outcome events sent,
+    /// by each breaker in any one second, or `None` if they're unlimited. By
+    /// default, this is `None`.
+    pub event_rate_limit: Option<u32>,
+    pub(crate) hooks: hooks::Hooks,
+    pub(crate) classifier: classify::Classifier,
+    pub(crate) prioritizer: extract::Extractor<priority::Priority>,
+    pub(crate) deadline: extract::Extractor<deadline::Deadline>,
+    pub(crate) clock: clock::SharedClock,
+    pub(crate) timer: Option<timer::SharedTimer>,
+    pub(crate) rng: rng::SharedRng,
+}
+
+impl<P> Config<P> {
+    /// Returns a new `Config` with the provided `policy`, which remains
+    /// tripped for `trip_for` once the policy punishes the service.
+    pub fn new(policy: P, trip_for: Duration) -> Self {
+        Config {
+            policy,
+            trip_for,
+            fail_fast: false,
+            name: None,
+            #[cfg(feature = "tracing")]
+            transition_level: Level::TRACE,
+            #[cfg(feature = "tracing")]
+            decision_level: Level::TRACE,
+            #[cfg(feature = "tracing")]
+            span_level: Level::TRACE,
+            #[cfg(feature = "tracing")]
+            call_spans: false,
+            #[cfg(feature = "span-trace")]
+            span_traces: false,
+            trip_history: 8,
+            trip_jitter: 0.0,
+            overload_weight: None,
+            shed_fraction: 1.0,
+            degraded_shedding: Vec::new(),
+            ramp_up: None,
+            priority_shedding: Vec::new(),
+            deadline_quantile: None,
+            evaluation_interval: None,
+            initially_open: None,
+            probe_only: false,
+            max_retry_after: None,
+            peers: None,
+            shared_memory: None,
+            dependencies: Vec::new(),
+            registry: None,
+            #[cfg(feature = "alert")]
+            alerting: None,
+            #[cfg(feature = "opentelemetry")]
+            otel: None,
+            #[cfg(feature = "events")]
+            outcome_events: None,
+            event_rate_limit: None,
+            hooks: hooks::Hooks::default(),
+            classifier: classify::Classifier::default(),
+            prioritizer: extract::Extractor::default(),
+            deadline: extract::Extractor::default(),
+            clock: clock::default(),
+            timer: timer::runtime(),
+            rng: rng::shared(rng::XorShift64::from_entropy()),
+        }
+    }
+
+    /// Returns a new `Config` for protecting a server from overload, rather
+    /// than protecting a client from a failing downstream service.
+    ///
+    /// The breaker wraps the server's own handler, and `policy` judges
+    /// whether the handler's failures (or latency, for a policy which records
+    /// slow responses as failures) indicate that the server is overloaded.
+    /// When it trips, the breaker sheds half of the incoming requests for
+    /// `trip_for`, rather than all of them, so that the server keeps serving
+    /// what it can while the load on it drops. Requests which are shed fail
+    /// immediately with a [`CircuitOpen`](error::CircuitOpen) error, which the
+    /// [`http`] middleware turns into a `503 Service Unavailable` response.
+    ///
+    /// This is equivalent to:
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use tower_breaker::{policy::ConsecutiveFailures, Config};
+    /// # let policy = ConsecutiveFailures::new(5);
+    /// # let trip_for = Duration::from_secs(1);
+    /// Config::new(policy, trip_for)
+    ///     .with_fail_fast(true)
+    ///     .with_shed_fraction(0.5);
+    /// ```
+    pub fn server(policy: P, trip_for: Duration) -> Self {
+        Config {
+            fail_fast: true,
+            shed_fraction: 0.5,
+            ..Config::new(policy, trip_for)
+        }
+    }
+
+    /// Sets whether requests made while the circuit is open fail immediately,
+    /// rather than waiting for the circuit to close.
+    ///
+    /// When `fail_fast` is `true`, the breaker's `poll_ready` is always ready
+    /// while the circuit is open, and requests fail with a
+    /// [`CircuitOpen`](error::CircuitOpen) error. This is similar to the
+    /// behavior of `tower`'s load-shedding middleware.
+    pub fn with_fail_fast(self, fail_fast: bool) -> Self {
+        Config { fail_fast, ..self }
+    }
+
+    /// Sets the name identifying breakers constructed with this config in
+    /// diagnostics.
+    pub fn with_name(self, name: impl Into<Cow<'static, str>>) -> Self {
+        Config {
+            name: Some(name.into()),
+            ..self
+        }
+    }
+
+    /// Sets the level at which events are emitted when the breaker trips or
+    /// closes.
+    #[cfg(feature = "tracing")]
+    pub fn with_transition_level(self, transition_level: Level) -> Self {
+        Config {
+            transition_level,
+            ..self
+        }
+    }
+
+    /// Sets the level at which events are emitted when the breaker rejects
+    /// or sheds a request.
+    ///
+    /// A breaker may reject a great many requests while its circuit is open,
+    /// so this is usually lower than the
+    /// [transition level](Config::with_transition_level), unless the
+    /// breaker's traffic is light.
+    #[cfg(feature = "tracing")]
+    pub fn with_decision_level(self, decision_level: Level) -> Self {
+        Config {
+            decision_level,
+            ..self
+        }
+    }
+
+    /// Sets the level of the span created for each request passed through
+    /// the breaker.
+    #[cfg(feature = "tracing")]
+    pub fn with_span_level(self, span_level: Level) -> Self {
+        Config { span_level, ..self }
+    }
+
+    /// Sets whether the span created for each request is a
+    /// `circuit_breaker.call` span, recording the request's outcome.
+    ///
+    /// Like the default span, the call span has `breaker` and `state`
+    /// fields, and is created at the [span level](Config::with_span_level).
+    /// When the request completes, its `outcome` field records whether it
+    /// succeeded, failed, failed in an error category, was ignored by the
+    /// [classifier](Config::with_error_classifier), or was `rejected` by the
+    /// breaker, and its `latency` field records how long the inner service
+    /// took to respond.
+    #[cfg(feature = "tracing")]
+    pub fn with_call_spans(self, call_spans: bool) -> Self {
+        Config { call_spans, ..self }
+    }
+
+    /// Sets whether a [`SpanTrace`](tracing_error::SpanTrace) is captured in
+    /// the [`CircuitOpen`](error::CircuitOpen) error of each rejected
+    /// request.
+    ///
+    /// The span trace records which breaker rejected the request, and the
+    /// spans of the request's path through the layers in front of it, for
+    /// error reporting layers which only see the error. Capturing it is
+    /// fairly expensive, so it's best left disabled for breakers which
+    /// reject many requests that are never reported.
+    ///
+    /// See [`CircuitOpen::span_trace`](error::CircuitOpen::span_trace) for
+    /// details.
+    #[cfg(feature = "span-trace")]
+    pub fn with_span_traces(self, span_traces: bool) -> Self {
+        Config {
+            span_traces,
+            ..self
+        }
+    }
+
+    /// Sets the number of recent trips retained in the breaker's
+    /// [trip history](Handle::recent_trips).
+    ///
+    /// If this is 0, no trip history is recorded.
+    pub fn with_trip_history(self, trip_history: usize) -> Self {
+        Config {
+            trip_history,
+            ..self
+        }
+    }
+
+    /// Randomly lengthens or shortens the duration of each trip by up to the
+    /// given fraction of [`trip_for`](Config::trip_for).
+    ///
+    /// For example, with a jitter of 0.1, a breaker configured to trip for
+    /// 10 seconds remains open for between 9 and 11 seconds each time it
+    /// trips. When many instances of a client trip at the same time, jitter
+    /// prevents all of them from closing at once and overwhelming a
+    /// recovering service.
+    ///
+    /// # Panics
+    ///
+    /// If `jitter` is less than 0 or greater than 1.
+    pub fn with_trip_jitter(self, jitter: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&jitter),
+            "trip jitter ({jitter}) must be in the range [0, 1]"
+        );
+        Config {
+            trip_jitter: jitter,
+            ..self
+        }
+    }
+
+    /// Records each response in which the server signals that it's
+    /// overloaded as `weight` failures.
+    ///
+    /// `429 Too Many Requests` and `503 Service Unavailable` HTTP responses,
+    /// and gRPC calls which fail with `RESOURCE_EXHAUSTED`, are explicit
+    /// requests from the server to back off right now, unlike other failures
+    /// which may be transient. Weighting them more heavily trips the breaker
+    /// sooner when the server is overloaded. When this is set, `429`
+    /// responses, which are otherwise recorded as successes, are recorded as
+    /// failures too.
+    ///
+    /// Overload signals are only recognized by the [`http`] and [`grpc`]
+    /// middleware.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is 0.
+    pub fn with_overload_weight(self, weight: usize) -> Self {
+        assert!(weight > 0, "overload weight must be > 0");
+        Config {
+            overload_weight: Some(weight),
+            ..self
+        }
+    }
+
+    /// Sheds only the given fraction of requests while the circuit is open,
+    /// chosen at random, rather than every request.
+    ///
+    /// Requests which aren't shed are passed to the inner service, and their
+    /// outcomes are recorded, as though the circuit were closed. If the
+    /// policy trips the breaker again while it's open, the trip starts over.
+    /// Requests which are shed fail with a
+    /// [`CircuitOpen`](error::CircuitOpen) error if the breaker
+    /// [fails fast](Config::with_fail_fast), and otherwise wait, with a new
+    /// chance of being admitted each time the breaker is woken. Circuits
+    /// [forced open](Handle::force_open) shed every request.
+    ///
+    /// # Panics
+    ///
+    /// If `fraction` is not greater than 0 and at most 1.
+    pub fn with_shed_fraction(self, fraction: f64) -> Self {
+        assert!(
+            fraction > 0.0 && fraction <= 1.0,
+            "shed fraction ({fraction}) must be in the range (0, 1]"
+        );
+        Config {
+            shed_fraction: fraction,
+            ..self
+        }
+    }
+
+    /// Sheds the given fraction of requests while the circuit is closed but
+    /// the policy's [severity](Policy::severity) is at least `severity`.
+    ///
+    /// This may be called more than once to shed more requests as the
+    /// severity rises, smoothing the cliff between admitting every request
+    /// and shedding every request when the circuit opens. The fraction shed
+    /// is that of the highest step the severity has reached:
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use tower_breaker::{policy::SlidingFailureRate, Config};
+    /// let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5);
+    /// // shed a quarter of requests once the failure rate passes 25%, and
+    /// // half once it passes 40%.
+    /// let config = Config::new(policy, Duration::from_secs(5))
+    ///     .with_degraded_shedding(0.5, 0.25)
+    ///     .with_degraded_shedding(0.8, 0.5);
+    /// ```
+    ///
+    /// Requests are chosen to be shed at random, and fail immediately with a
+    /// [`CircuitOpen`](error::CircuitOpen) error whose reason is
+    /// [`TripReason::Degraded`], whether or not the breaker
+    /// [fails fast](Config::with_fail_fast). Shed requests aren't recorded by
+    /// the policy.
+    ///
+    /// # Panics
+    ///
+    /// If `severity` or `fraction` is not greater than 0 and at most 1.
+    pub fn with_degraded_shedding(mut self, severity: f64, fraction: f64) -> Self {
+        assert!(
+            severity > 0.0 && severity <= 1.0,
+            "severity ({severity}) must be in the range (0, 1]"
+        );
+        assert!(
+            fraction > 0.0 && fraction <= 1.0,
+            "shed fraction ({fraction}) must be in the range (0, 1]"
+        );
+        let at = self
+            .degraded_shedding
+            .partition_point(|&(step, _)| step <= severity);
+        self.degraded_shedding.insert(at, (severity, fraction));
+        self
+    }
+
+    /// Sets the function used to extract the [`Priority`](priority::Priority)
+    /// of each request, for [priority shedding](Config::with_priority_shedding).
+    ///
+    /// By default, and for requests of any type other than `Req`, every
+    /// request has the default priority. The [`http`] and [`grpc`]
+    /// middleware ignore this function, and read each request's priority
+    /// from its extensions instead.
+    pub fn with_priority<Req: 'static>(
+        self,
+        f: impl Fn(&Req) -> priority::Priority + Send + Sync + 'static,
+    ) -> Self {
+        Config {
+            prioritizer: extract::Extractor::new(move |req| Some(f(req))),
+            ..self
+        }
+    }
+
+    /// Sheds the given fraction of requests of the given `priority` while
+    /// the circuit is closed but the policy's [severity](Policy::severity) is
+    /// at least `severity`.
+    ///
+    /// This is like [`Config::with_degraded_shedding`], but only applies to
+    /// requests of one priority, so that less important requests can be
+    /// shed before more important ones. Once any steps are added for a
+    /// priority, requests of that priority are shed according to those
+    /// steps only. Requests of [`Priority::Critical`](priority::Priority::Critical)
+    /// are never shed while the circuit is closed, unless steps are added
+    /// for them. See the [`priority`] module for an example.
+    ///
+    /// # Panics
+    ///
+    /// If `severity` or `fraction` is not greater than 0 and at most 1.
+    pub fn with_priority_shedding(
+        mut self,
+        priority: priority::Priority,
+        severity: f64,
+        fraction: f64,
+    ) -> Self {
+        assert!(
+            severity > 0.0 && severity <= 1.0,
+            "severity ({severity}) must be in the range (0, 1]"
+        );
+        assert!(
+            fraction > 0.0 && fraction <= 1.0,
+            "shed fraction ({fraction}) must be in the range (0, 1]"
+        );
+        let at = self
+            .priority_shedding
+            .partition_point(|&(_, step, _)| step <= severity);
+        self.priority_shedding
+            .insert(at, (priority, severity, fraction));
+        self
+    }
+
+    /// Rejects requests which almost certainly can't complete before their
+    /// [deadline](deadline::Deadline), rather than wasting the inner
+    /// service's capacity on them.
+    ///
+    /// The breaker tracks the latencies of its recent successful requests,
+    /// and rejects a request with a
+    /// [`DeadlineUnreachable`](error::DeadlineUnreachable) error if the time
+    /// remaining before its deadline is less than the given `quantile` of
+    /// those latencies. For example, with a `quantile` of 0.05, a request is
+    /// rejected if 95% of recent requests took longer than it has left.
+    /// Requests aren't rejected until enough latencies have been recorded,
+    /// and rejected requests aren't recorded by the policy. See the
+    /// [`deadline`] module for details.
+    ///
+    /// # Panics
+    ///
+    /// If `quantile` is not between 0 and 1.
+    pub fn with_deadline_admission(self, quantile: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&quantile),
+            "deadline quantile ({quantile}) must be in the range [0, 1]"
+        );
+        Config {
+            deadline_quantile: Some(quantile),
+            ..self
+        }
+    }
+
+    /// Sets the function used to extract the [`Deadline`](deadline::Deadline)
+    /// of each request, for
+    /// [deadline admission](Config::with_deadline_admission).
+    ///
+    /// Requests for which `f` returns `None`, and requests of any type other
+    /// than `Req`, have no deadline. The [`http`] and [`grpc`] middleware
+    /// ignore this function, and read each request's deadline from its
+    /// extensions (or `grpc-timeout` header) instead.
+    pub fn with_deadline<Req: 'static>(
+        self,
+        f: impl Fn(&Req) -> Option<deadline::Deadline> + Send + Sync + 'static,
+    ) -> Self {
+        Config {
+            deadline: extract::Extractor::new(f),
+            ..self
+        }
+    }
+
+    /// Ramps traffic up gradually over `ramp_up` after each trip ends, rather
+    /// than restoring the full load onto a service which has just recovered.
+    ///
+    /// Once the circuit closes, 10% of requests are admitted for the first
+    /// third of the ramp, 25% for the second, and 50% for the last, after
+    /// which every request is admitted. Requests which aren't admitted are
+    /// chosen at random, and fail immediately with a
+    /// [`CircuitOpen`](error::CircuitOpen) error whose reason is
+    /// [`TripReason::RampingUp`]. The outcomes of admitted requests are
+    /// recorded as usual, so the breaker may trip again during the ramp.
+    ///
+    /// Circuits which are [reset](Handle::reset) or
+    /// [forced closed](Handle::force_close) don't ramp up.
+    ///
+    /// # Panics
+    ///
+    /// If `ramp_up` is zero.
+    pub fn with_ramp_up(self, ramp_up: Duration) -> Self {
+        assert!(!ramp_up.is_zero(), "ramp up duration must be > 0");
+        Config {
+            ramp_up: Some(ramp_up),
+            ..self
+        }
+    }
+
+    /// Consults the policy roughly once per `interval` while the circuit is
+    /// closed, rather than every time the breaker is polled.
+    ///
+    /// Asking a policy whether to punish the endpoint can cost as much as
+    /// the rest of the breaker's work on each request, such as summing a
+    /// window of counts. With this set, a breaker whose circuit is closed
+    /// only consults its policy on every Nth poll, where N adapts to the
+    /// rate at which the breaker is polled so that the policy is consulted
+    /// about once per `interval`. At low request rates, the policy is still
+    /// consulted on every poll. The breaker may admit requests for up to
+    /// about `interval` after its policy starts punishing the endpoint.
+    ///
+    /// Breakers which must examine every request while their circuit is
+    /// closed, such as those which shed load while degraded, ramp up after
+    /// trips, or reject requests by deadline, consult the policy every time.
+    ///
+    /// # Panics
+    ///
+    /// If `interval` is zero.
+    pub fn with_evaluation_interval(self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "evaluation interval must be > 0");
+        Config {
+            evaluation_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Constructs breakers with their circuits already open, for `open_for`.
+    ///
+    /// This is useful when it's known at startup that the service is down,
+    /// such as when an orchestrator reports that it's unavailable, or when
+    /// state persisted by some other means says the circuit was open. The
+    /// circuit is opened with [`TripReason::Initial`], and closes once
+    /// `open_for` has passed, as it would after any other trip. The initial
+    /// trip is local to each breaker: it isn't shared with
+    /// [peers](Config::with_peers), and doesn't send alerts.
+    ///
+    /// The blocking [`sync::CircuitBreaker`] always starts closed.
+    pub fn with_initially_open(self, open_for: Duration) -> Self {
+        Config {
+            initially_open: Some(open_for),
+            ..self
+        }
+    }
+
+    /// Sets whether the policy only records the outcomes of requests admitted
+    /// since it was last reset.
+    ///
+    /// A breaker resets its policy when it trips, but requests which were
+    /// already in flight may complete after that, while the circuit is open
+    /// or once it has closed. By default, their outcomes are recorded with
+    /// the policy like any other, so the window which decides whether the
+    /// service has recovered mixes stale, pre-trip failures with the
+    /// outcomes of the requests probing it. When `probe_only` is `true`,
+    /// those outcomes are discarded by the policy (although they're still
+    /// recorded in metrics and outcome events), so a windowed policy such as
+    /// [`SlidingFailureRate`](policy::SlidingFailureRate) decides whether to
+    /// trip again based only on requests made since the trip.
+    ///
+    /// This applies to the [`CircuitBreaker`] middleware only.
+    pub fn with_probe_only(self, probe_only: bool) -> Self {
+        Config { probe_only, ..self }
+    }
+
+    /// Broadcasts trips of breakers constructed with this config to `peers`,
+    /// and opens them when a peer's breaker with the same
+    /// [name](Config::with_name) trips.
+    ///
+    /// Breakers without a name don't take part in broadcasts. See the
+    /// [`peer`] module for details.
+    pub fn with_peers(self, peers: peer::Peers) -> Self {
+        Config {
+            peers: Some(peers),
+            ..self
+        }
+    }
+
+    /// Shares the circuits of breakers constructed with this config with
+    /// breakers of the same [name](Config::with_name) in other processes on
+    /// this host, through `shm`.
+    ///
+    /// Breakers without a name don't share their circuits. See the [`shm`]
+    /// module for details.
+    pub fn with_shared_memory(self, shm: shm::SharedMemory) -> Self {
+        Config {
+            shared_memory: Some(shm),
+            ..self
+        }
+    }
+
+    /// Declares that breakers constructed with this config depend on the
+    /// breaker with the given `upstream` handle.
+    ///
+    /// For example, if checking out an order requires a payment to be taken,
+    /// a "checkout" breaker could depend on a "payments" breaker. While the
+    /// upstream breaker's circuit is open, the dependent breaker's circuit
+    /// is held open too, with [`TripReason::DependencyOpen`], rather than
+    /// discovering the failure through its own requests. It closes again once
+    /// the upstream circuit closes. Both changes are applied the next time
+    /// the dependent breaker is polled for readiness.
+    ///
+    /// This may be called more than once, in which case the circuit is held
+    /// open while any of its dependencies are open. Dependencies shouldn't
+    /// form a cycle.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use tower_breaker::{policy::ConsecutiveFailures, BreakerRegistry, Config};
+    ///
+    /// # fn main() {
+    /// # let registry = BreakerRegistry::new();
+    /// # let _payments = tower_breaker::CircuitBreaker::new(
+    /// #     Config::new(ConsecutiveFailures::new(5), Duration::from_secs(30))
+    /// #         .with_name("payments")
+    /// #         .with_registry(registry.clone()),
+    /// #     tower::service_fn(|()| async { Ok::<_, tower_breaker::BoxError>(()) }),
+    /// # );
+    /// let payments = registry.get("payments").expect("payments breaker is registered");
+    /// let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(30))
+    ///     .with_name("checkout")
+    ///     .with_dependency(payments);
+    /// # drop(config);
+    /// # }
+    /// ```
+    pub fn with_dependency(mut self, upstream: Handle) -> Self {
+        self.dependencies.push(upstream);
+        self
+    }
+
+    /// Trips the breaker for the delay in the most recent `Retry-After` sent
+    /// by the inner service, up to `max`, rather than for
+    /// [`trip_for`](Config::trip_for).
+    ///
+    /// Servers send `Retry-After` with `429 Too Many Requests` and
+    /// `503 Service Unavailable` responses to say how long clients should
+    /// back off for. Honoring it lets an overloaded server decide how long
+    /// the circuit stays open, while `max` bounds how long a misbehaving
+    /// server can keep it open. [Jitter](Config::with_trip_jitter) is still
+    /// applied to the delay. If the inner service hasn't sent a `Retry-After`
+    /// since the circuit last closed, the breaker trips for `trip_for`.
+    ///
+    /// `Retry-After` is only recorded by the [`http`] middleware, which
+    /// accepts delays in seconds; HTTP dates are ignored.
+    pub fn with_max_retry_after(self, max: Duration) -> Self {
+        Config {
+            max_retry_after: Some(max),
+            ..self
+        }
+    }
+
+    /// Sets the [`Rng`](rng::Rng) used to randomize the behavior of breakers
+    /// constructed with this config, such as [trip
+    /// jitter](Config::with_trip_jitter).
+    ///
+    /// Breakers constructed from clones of this config share the same
+    /// generator. By default, a randomly seeded generator is used; a
+    /// generator with a fixed seed makes the breakers' behavior reproducible.
+    pub fn with_rng(self, rng: impl rng::Rng) -> Self {
+        Config {
+            rng: rng::shared(rng),
+            ..self
+        }
+    }
+
+    /// Registers breakers constructed with this config in the provided
+    /// [`BreakerRegistry`].
+    ///
+    /// Breakers are registered under their [name](Config::with_name). If this
+    /// config does not have a name, breakers are not registered.
+    pub fn with_registry(self, registry: BreakerRegistry) -> Self {
+        Config {
+            registry: Some(registry),
+            ..self
+        }
+    }
+
+    /// Sends alerts when breakers constructed with this config trip.
+    ///
+    /// See [`Alerting`](alert::Alerting) for details.
+    #[cfg(feature = "alert")]
+    pub fn with_alerting(self, alerting: alert::Alerting) -> Self {
+        Config {
+            alerting: Some(alerting),
+            ..self
+        }
+    }
+
+    /// Registers a callback which is invoked every time a breaker
+    /// constructed with this config changes state.
+    ///
+    /// If multiple callbacks are registered, they are invoked in the order
+    /// they were registered.
+    ///
+    /// The callback is invoked synchronously from within the breaker's
+    /// [`poll_ready`](tower_service::Service::poll_ready), so it should not
+    /// block. Callbacks that need to perform asynchronous work (such as paging
+    /// or flushing caches) should spawn a task to do so.
+    pub fn on_state_change(mut self, f: impl Fn(Transition) + Send + Sync + 'static) -> Self {
+        self.hooks.add_on_state_change(f);
+        self
+    }
+
+    /// Registers a callback which is invoked with a final snapshot of a
+    /// breaker constructed with this config when it shuts down.
+    ///
+    /// This is invoked once per breaker, either when the breaker is
+    /// [shut down](Handle::shutdown) or when it is dropped, whichever happens
+    /// first. It can be used to report a breaker's lifetime statistics when a
+    /// long-running process exits.
+    pub fn on_shutdown(
+        mut self,
+        f: impl Fn(&snapshot::BreakerSnapshot) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.add_on_shutdown(f);
+        self
+    }
+
+    /// Registers a callback which is invoked every time a breaker
+    /// constructed with this config refuses a request, or parks a caller in
+    /// `poll_ready`, because its circuit is open.
+    ///
+    /// The refused request can be recovered from the [`Rejection`], so that
+    /// applications can record their own telemetry for it, or enqueue it to
+    /// be retried later. Like [`on_state_change`](Config::on_state_change)
+    /// callbacks, the callback is invoked synchronously, so it should not
+    /// block.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use tower_breaker::{policy::ConsecutiveFailures, Config};
+    ///
+    /// struct Job {
+    ///     id: u64,
+    /// }
+    ///
+    /// let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(5))
+    ///     .with_fail_fast(true)
+    ///     .on_reject(|rejection| {
+    ///         if let Some(job) = rejection.request::<Job>() {
+    ///             println!("job {} refused: {}", job.id, rejection.error);
+    ///         }
+    ///     });
+    /// ```
+    pub fn on_reject(mut self, f: impl Fn(&Rejection<'_>) + Send + Sync + 'static) -> Self {
+        self.hooks.add_on_reject(f);
+        self
+    }
+
+    /// Sets a function which decides how errors returned by the inner service
+    /// of breakers constructed with this config are recorded.
+    ///
+    /// By default, every error is recorded as a failure. See the [`classify`]
+    /// module for details.
+    pub fn with_error_classifier(
+        self,
+        f: impl Fn(&(dyn std::error::Error + 'static)) -> classify::ErrorClass + Send + Sync + 'static,
+    ) -> Self {
+        Config {
+            classifier: classify::Classifier::new(f),
+            ..self
+        }
+    }
+
+    /// Sets the [`Clock`](clock::Clock) used by breakers constructed with
+    /// this config to determine when their circuit should close, and to
+    /// timestamp their statistics.
+    ///
+    /// By default, the current time is read from Tokio. Note that policies
+    /// read the current time separately, so time-based policies (such as
+    /// [`SlidingFailureRate`](policy::SlidingFailureRate::with_clock)) should
+    /// be given the same clock.
+    pub fn with_clock(self, clock: impl clock::Clock) -> Self {
+        Config {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Sets the [`Timer`](timer::Timer) used by breakers constructed with
+    /// this config to wake callers waiting for the circuit to close.
+    ///
+    /// By default, Tokio's timer is used if the `rt-tokio` feature flag is
+    /// enabled. Otherwise, a timer must be provided, and constructing a
+    /// breaker without one fails with a [`NoTimer`](error::NoTimer) error.
+    pub fn with_timer(self, timer: impl timer::Timer) -> Self {
+        Config {
+            timer: Some(Arc::new(timer)),
+            ..self
+        }
+    }
+
+    /// Records OpenTelemetry metrics for breakers constructed with this
+    /// config.
+    #[cfg(feature = "opentelemetry")]
+    pub fn with_otel_metrics(self, otel: otel::OtelMetrics) -> Self {
+        Config {
+            otel: Some(otel),
+            ..self
+        }
+    }
+
+    /// Sends an [`OutcomeEvent`](events::OutcomeEvent) to `sender` for every
+    /// request passed to, or rejected by, breakers constructed with this
+    /// config.
+    ///
+    /// See the [`events`] module for details.
+    #[cfg(feature = "events")]
+    pub fn with_outcome_events(self, sender: events::OutcomeSender) -> Self {
+        Config {
+            outcome_events: Some(sender),
+            ..self
+        }
+    }
+
+    /// Limits breakers constructed with this config to logging at most
+    /// `per_second` rejections, and sending at most `per_second`
+    /// [outcome events](Config::with_outcome_events), each second.
+    ///
+    /// A circuit which is open under heavy traffic rejects every request
+    /// it's sent, and reporting each rejection can flood a logging or
+    /// analytics pipeline. Events beyond the limit are suppressed, and the
+    /// next event emitted reports how many were suppressed before it: in a
+    /// `suppressed` field for rejection events, and in
+    /// [`OutcomeEvent::suppressed`](events::OutcomeEvent::suppressed) for
+    /// outcome events.
+    pub fn with_event_rate_limit(self, per_second: u32) -> Self {
+        Config {
+            event_rate_limit: Some(per_second),
+            ..self
+        }
+    }
+}
+
+/// The state of a [`CircuitBreaker`]'s circuit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum CircuitState {
+    /// The circuit is closed, and requests are passed through to the inner
+    /// service.
+    Closed,
+    /// The circuit is open, and the breaker will not accept requests until
+    /// it closes again.
+    Open,
+}
+
+/// A change in a [`CircuitBreaker`]'s [`CircuitState`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Transition {
+    /// The state the circuit was in prior to this transition.
+    pub from: CircuitState,
+    /// The state the circuit is in after this transition.
+    pub to: CircuitState,
+    /// If the circuit was opened, the reason the policy tripped it.
+    pub reason: Option<TripReason>,
+}
+
+/// A request refused by a [`CircuitBreaker`] because its circuit is open,
+/// which is passed to callbacks registered with [`Config::on_reject`].
+#[derive(Copy, Clone, Debug)]
+#[non_exhaustive]
+pub struct Rejection<'a> {
+    /// The error the request was refused with, or would be refused with if
+    /// the breaker failed fast.
+    pub error: &'a CircuitOpen,
+    /// If `true`, a caller was parked in `poll_ready` until the circuit
+    /// closes, rather than a request being refused, so there is no request.
+    pub parked: bool,
+    request: Option<&'a dyn Any>,
+}
+
+// === impl Rejection ===
+
+impl<'a> Rejection<'a> {
+    pub(crate) fn refused(error: &'a CircuitOpen, request: Option<&'a dyn Any>) -> Self {
+        Rejection {
+            error,
+            parked: false,
+            request,
+        }
+    }
+
+    pub(crate) fn parked(error: &'a CircuitOpen) -> Self {
+        Rejection {
+            error,
+            parked: true,
+            request: None,
+        }
+    }
+
+    /// Returns the refused request, if it's a `Req`.
+    ///
+    /// The [`http`] and [`grpc`] middleware pass an `http::Request<()>` with
+    /// the refused request's method, URI, version, headers and extensions.
+    /// There is no request when a caller is [`parked`](Rejection::parked),
+    /// or when a [`LocalCircuitBreaker`](local::LocalCircuitBreaker) or a
+    /// [`sync::CircuitBreaker`] refuses a call.
+    pub fn request<Req: 'static>(&self) -> Option<&'a Req> {
+        self.request?.downcast_ref()
+    }
+}
+
+// === impl CircuitState ===
+
+impl CircuitState {
+    /// Returns a string representation of this state, suitable for use as a
+    /// metric label or structured logging field.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+        }
+    }
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
 }
@@ -0,0 +1,448 @@
+//! A circuit breaker for single-threaded runtimes.
+//!
+//! The [`LocalCircuitBreaker`] in this module is a variant of the
+//! [`CircuitBreaker`](crate::CircuitBreaker) for services which run on a
+//! single thread, such as on a Tokio [`LocalSet`] or a current-thread
+//! executor. It does not require its policy to be `Send` or `Sync`, and it
+//! keeps its state in the breaker itself rather than behind a lock, so it
+//! can't be observed or controlled by a [`Handle`](crate::Handle).
+//!
+//! The policies in this module are non-atomic variants of the policies in
+//! the [`policy`](crate::policy) module, which track requests using [`Rc`]
+//! and [`Cell`] rather than [`Arc`](std::sync::Arc) and atomics. A
+//! `LocalCircuitBreaker` may also be used with any other [`Policy`].
+//!
+//! [`LocalSet`]: https://docs.rs/tokio/latest/tokio/task/struct.LocalSet.html
+#[cfg(feature = "tracing")]
+use crate::rate_limit::{self, RateLimiter};
+use crate::{
+    clock::SharedClock,
+    error::{BoxError, CircuitOpen, NoTimer},
+    policy::{PolicySnapshot, TripReason},
+    state::StateMachine,
+    timer::{SharedTimer, Sleep},
+    trace::{dyn_event, trace},
+    CircuitState, Config, Policy, Rejection, Transition,
+};
+use std::{
+    cell::Cell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{ready, Context, Poll},
+    time::Instant,
+};
+use tower_service::Service;
+
+/// A circuit breaker for services which run on a single thread.
+///
+/// See the [module-level documentation](self) for details.
+pub struct LocalCircuitBreaker<P, S> {
+    inner: S,
+    config: Config<P>,
+    machine: StateMachine,
+    timer: SharedTimer,
+    /// Wakes the task once the current trip ends, along with the deadline it
+    /// was created for.
+    tripped_until: Option<(Instant, Sleep)>,
+    /// Limits the rejections logged, if the breaker's events are limited.
+    #[cfg(feature = "tracing")]
+    rejection_logs: Option<RateLimiter>,
+}
+
+pin_project_lite::pin_project! {
+    #[derive(Debug)]
+    pub struct ResponseFuture<P, F> {
+        // If this is `None`, the request was rejected because the circuit was
+        // open.
+        #[pin]
+        future: Option<F>,
+        rejected: Option<CircuitOpen>,
+        policy: P,
+        // The clock and the time the request started, if the policy
+        // measures latency.
+        started: Option<(SharedClock, Instant)>,
+    }
+}
+
+/// A non-atomic variant of
+/// [`policy::ConsecutiveFailures`](crate::policy::ConsecutiveFailures).
+///
+/// This punishes an endpoint after a number of consecutive failures. Any
+/// successful request resets the count of consecutive failures.
+#[derive(Clone)]
+pub struct ConsecutiveFailures(Rc<ConsecutiveInner>);
+
+struct ConsecutiveInner {
+    max_failures: usize,
+    failures: Cell<usize>,
+}
+
+// === impl LocalCircuitBreaker ===
+
+impl<P: Policy + Clone + fmt::Debug, S> LocalCircuitBreaker<P, S> {
+    /// Returns a new `LocalCircuitBreaker` wrapping `inner`.
+    ///
+    /// The [`registry`](Config::registry) and alerting configured by
+    /// `config`, if any, are not used by a `LocalCircuitBreaker`.
+    ///
+    /// # Panics
+    ///
+    /// If `config` has no timer, because the `rt-tokio` feature flag is
+    /// disabled and none was provided using [`Config::with_timer`]. Use
+    /// [`try_new`](Self::try_new) to handle this as an error instead.
+    #[track_caller]
+    pub fn new(config: Config<P>, inner: S) -> Self {
+        match Self::try_new(config, inner) {
+            Ok(breaker) => breaker,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a new `LocalCircuitBreaker` wrapping `inner`, or an error if
+    /// `config` has no timer.
+    pub fn try_new(config: Config<P>, inner: S) -> Result<Self, NoTimer> {
+        let timer = config
+            .timer
+            .clone()
+            .ok_or_else(|| NoTimer::new(config.name.clone()))?;
+        let mut machine = StateMachine::default();
+        if let Some(open_for) = config.initially_open {
+            machine.open(config.clock.now(), TripReason::Initial, open_for);
+        }
+        Ok(LocalCircuitBreaker {
+            inner,
+            #[cfg(feature = "tracing")]
+            rejection_logs: RateLimiter::from_config(&config),
+            config,
+            machine,
+            timer,
+            tripped_until: None,
+        })
+    }
+
+    /// Returns the current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        self.machine.state()
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.state() == CircuitState::Open
+    }
+
+    /// Opens or closes the circuit as the policy and the current trip
+    /// dictate.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn evaluate(&mut self) {
+        if !self.machine.is_open() {
+            if let Some(reason) = self.config.policy.punish_reason() {
+                self.trip(reason);
+            }
+        }
+
+        // if the clock jumped backwards, the trip restarts, rather than being
+        // extended by the size of the jump.
+        let now = self.config.clock.now();
+        if self.machine.expired(now) != Ok(true) {
+            return;
+        }
+
+        let open_for = self.machine.close(now);
+        dyn_event!(
+            self.config.transition_level,
+            breaker = self.config.name.as_deref(),
+            ?open_for,
+            "circuit breaker closed"
+        );
+        self.tripped_until = None;
+        self.config.hooks.state_changed(Transition {
+            from: CircuitState::Open,
+            to: CircuitState::Closed,
+            reason: None,
+        });
+    }
+
+    fn trip(&mut self, reason: TripReason) {
+        #[cfg(feature = "tracing")]
+        crate::trace::circuit_opened(
+            self.config.transition_level,
+            self.config.name.as_deref(),
+            reason,
+            Some(&self.config.policy),
+            &self.config.policy.snapshot(),
+            self.config.trip_for,
+        );
+        let now = self.config.clock.now();
+        self.machine.open(now, reason, self.config.trip_for);
+        self.config.policy.reset();
+        self.config.hooks.state_changed(Transition {
+            from: CircuitState::Closed,
+            to: CircuitState::Open,
+            reason: Some(reason),
+        });
+    }
+}
+
+impl<P, S, Req> Service<Req> for LocalCircuitBreaker<P, S>
+where
+    P: Policy + Clone + fmt::Debug,
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<P, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.config.policy.is_disabled() {
+            return self.inner.poll_ready(cx).map_err(Into::into);
+        }
+        self.evaluate();
+        let Some(deadline) = self.machine.deadline() else {
+            return self.inner.poll_ready(cx).map_err(Into::into);
+        };
+        if self.config.fail_fast {
+            // if we're failing fast, the circuit is "ready", but `call` will
+            // reject the request, so nothing needs to wake us.
+            self.tripped_until = None;
+            return Poll::Ready(Ok(()));
+        }
+
+        // wake up when the trip ends.
+        let remaining = deadline.saturating_duration_since(self.config.clock.now());
+        let timer = &self.timer;
+        if !matches!(self.tripped_until, Some((armed, _)) if armed == deadline) {
+            self.tripped_until = Some((deadline, timer.sleep(remaining)));
+        }
+        let (_, sleep) = self.tripped_until.as_mut().expect("timer was just set");
+        if sleep.as_mut().poll(cx).is_ready() {
+            // the timer fired, but the clock says the trip isn't over yet.
+            // sleep for the remainder of the trip.
+            let mut sleep = timer.sleep(remaining);
+            let _ = sleep.as_mut().poll(cx);
+            self.tripped_until = Some((deadline, sleep));
+        }
+        Poll::Pending
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        debug_assert!(
+            self.config.fail_fast || !self.is_tripped(),
+            "tried to call a tripped circuit breaker!"
+        );
+        let (future, rejected) = if self.machine.is_open() {
+            let error = CircuitOpen::new(self.config.name.clone(), self.machine.reason());
+            #[cfg(feature = "tracing")]
+            if let Some(suppressed) = rate_limit::acquire(self.rejection_logs.as_ref()) {
+                dyn_event!(
+                    self.config.decision_level,
+                    breaker = self.config.name.as_deref(),
+                    reason = %error,
+                    suppressed,
+                    "rejecting request"
+                );
+            }
+            self.config
+                .hooks
+                .rejected(&Rejection::refused(&error, None));
+            (None, Some(error))
+        } else {
+            (Some(self.inner.call(req)), None)
+        };
+        let clock = &self.config.clock;
+        let started = (future.is_some() && self.config.policy.measures_latency())
+            .then(|| (clock.clone(), clock.now()));
+        ResponseFuture {
+            future,
+            rejected,
+            policy: self.config.policy.clone(),
+            started,
+        }
+    }
+}
+
+impl<P: fmt::Debug, S: fmt::Debug> fmt::Debug for LocalCircuitBreaker<P, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalCircuitBreaker")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .field("state", &self.machine)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<P, F, T, E> Future for ResponseFuture<P, F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+    P: Policy,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let future = match this.future.as_pin_mut() {
+            Some(future) => future,
+            None => {
+                let error = this.rejected.take().expect("polled after completion");
+                return Poll::Ready(Err(error.into()));
+            }
+        };
+        let result = ready!(future.poll(cx));
+        if let Some((clock, started)) = this.started.take() {
+            let latency = clock.now().saturating_duration_since(started);
+            this.policy.record_latency(latency);
+        }
+        match result {
+            Ok(res) => {
+                this.policy.record_success();
+                Poll::Ready(Ok(res))
+            }
+            Err(err) => {
+                this.policy.record_failure();
+                Poll::Ready(Err(err.into()))
+            }
+        }
+    }
+}
+
+// === impl ConsecutiveFailures ===
+
+impl ConsecutiveFailures {
+    /// Returns a new `ConsecutiveFailures` policy which punishes an endpoint
+    /// once `max_failures` requests in a row have failed.
+    ///
+    /// # Panics
+    ///
+    /// If `max_failures` is 0.
+    pub fn new(max_failures: usize) -> Self {
+        assert!(max_failures > 0, "maximum consecutive failures must be > 0");
+        ConsecutiveFailures(Rc::new(ConsecutiveInner {
+            max_failures,
+            failures: Cell::new(0),
+        }))
+    }
+}
+
+impl Policy for ConsecutiveFailures {
+    fn record_success(&self) {
+        self.0.failures.set(0);
+    }
+
+    fn record_failure(&self) {
+        self.0.failures.set(self.0.failures.get() + 1);
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let failures = self.0.failures.get();
+        if failures >= self.0.max_failures {
+            trace!(
+                failures,
+                max_failures = self.0.max_failures,
+                "Too many consecutive failures; punishing endpoint!"
+            );
+            return Some(TripReason::ConsecutiveFailures {
+                failures,
+                threshold: self.0.max_failures,
+            });
+        }
+        None
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        PolicySnapshot {
+            consecutive_failures: Some(self.0.failures.get()),
+            max_consecutive_failures: Some(self.0.max_failures),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    fn reset(&self) {
+        self.0.failures.set(0);
+    }
+}
+
+impl fmt::Debug for ConsecutiveFailures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsecutiveFailures")
+            .field("max_failures", &self.0.max_failures)
+            .field("failures", &self.0.failures.get())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{future, rc::Rc, task::Waker, time::Duration};
+    use tokio::time;
+
+    /// A `!Send` service that succeeds if the request is `true`, and fails
+    /// otherwise.
+    struct Svc(Rc<Cell<usize>>);
+
+    impl Service<bool> for Svc {
+        type Response = ();
+        type Error = &'static str;
+        type Future = future::Ready<Result<(), &'static str>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, ok: bool) -> Self::Future {
+            self.0.set(self.0.get() + 1);
+            future::ready(if ok { Ok(()) } else { Err("failed") })
+        }
+    }
+
+    fn poll_ready(
+        breaker: &mut LocalCircuitBreaker<ConsecutiveFailures, Svc>,
+    ) -> Poll<Result<(), BoxError>> {
+        Service::<bool>::poll_ready(breaker, &mut Context::from_waker(Waker::noop()))
+    }
+
+    #[tokio::test]
+    async fn trips_and_recovers() {
+        time::pause();
+        let calls = Rc::new(Cell::new(0));
+        let config = Config::new(ConsecutiveFailures::new(2), Duration::from_secs(5));
+        let mut breaker = LocalCircuitBreaker::new(config, Svc(calls.clone()));
+
+        for _ in 0..2 {
+            assert!(poll_ready(&mut breaker).is_ready());
+            assert!(breaker.call(false).await.is_err());
+        }
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert!(breaker.is_tripped());
+
+        time::advance(Duration::from_secs(6)).await;
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(true).await.is_ok());
+        assert!(!breaker.is_tripped());
+        assert_eq!(3, calls.get());
+    }
+
+    #[tokio::test]
+    async fn fail_fast() {
+        time::pause();
+        let calls = Rc::new(Cell::new(0));
+        let config =
+            Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5)).with_fail_fast(true);
+        let mut breaker = LocalCircuitBreaker::new(config, Svc(calls.clone()));
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_ready());
+        let error = breaker.call(true).await.unwrap_err();
+        assert!(error.is::<CircuitOpen>());
+        assert_eq!(1, calls.get());
+    }
+}
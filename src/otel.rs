@@ -0,0 +1,366 @@
+//! [OpenTelemetry] metrics for circuit breakers.
+//!
+//! This module is only available when the `opentelemetry` feature flag is
+//! enabled.
+//!
+//! [OpenTelemetry]: https://opentelemetry.io
+use crate::CircuitState;
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    Key, KeyValue,
+};
+use std::time::Duration;
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Records circuit breaker state transitions, request outcomes, and open
+/// durations using an OpenTelemetry [`Meter`].
+///
+/// The following instruments are recorded:
+///
+/// - `circuit_breaker.transitions` (counter): the number of state
+///   transitions, with a `circuit_breaker.state` attribute set to the state
+///   the breaker transitioned _into_.
+/// - `circuit_breaker.outcomes` (counter): the number of completed requests,
+///   with a `circuit_breaker.outcome` attribute of either `success` or
+///   `failure`.
+/// - `circuit_breaker.rejections` (counter): the number of requests that were
+///   refused or parked because the circuit was open.
+/// - `circuit_breaker.open.duration` (histogram, in seconds): how long the
+///   breaker remained open each time it tripped.
+///
+/// Any attributes added with [`OtelMetrics::with_attributes`] (such as
+/// `server.address` or `peer.service`) are attached to every measurement.
+/// The instruments' names, and attributes identifying each breaker, can be
+/// customized with a [`MetricsConfig`].
+///
+/// Cloning an `OtelMetrics` is cheap, and all clones record to the same
+/// instruments.
+#[derive(Clone)]
+pub struct OtelMetrics(Arc<Inner>);
+
+#[derive(Clone)]
+struct Inner {
+    transitions: Counter<u64>,
+    outcomes: Counter<u64>,
+    rejections: Counter<u64>,
+    open_duration: Histogram<f64>,
+    attributes: Vec<KeyValue>,
+    state: Key,
+    outcome: Key,
+    key_attributes: Option<Arc<KeyAttributes>>,
+    key_budget: Option<Arc<KeyBudget>>,
+}
+
+/// Customizes the names and attributes of the instruments recorded by an
+/// [`OtelMetrics`].
+///
+/// By default, instruments and their attributes are prefixed with
+/// `circuit_breaker`, and no attribute identifies the breaker which recorded
+/// a measurement: breakers created for each key of a
+/// [`KeyedCircuitBreaker`](crate::keyed::KeyedCircuitBreaker) could
+/// otherwise give an attribute unbounded cardinality. Instead, a function
+/// set with [`MetricsConfig::with_key_attributes`] can derive a bounded set
+/// of attributes from each breaker's name, such as the tier of the tenant a
+/// breaker is for, or [`MetricsConfig::with_key_attribute`] can attribute
+/// measurements to each breaker's name, up to a fixed number of names.
+///
+/// ```
+/// use opentelemetry::KeyValue;
+/// use tower_breaker::otel::{MetricsConfig, OtelMetrics};
+///
+/// let config = MetricsConfig::new()
+///     .with_prefix("acme.breaker")
+///     .with_attributes([KeyValue::new("service.namespace", "checkout")])
+///     .with_key_attributes(|name| {
+///         let tier = if name.starts_with("api/enterprise-") {
+///             "enterprise"
+///         } else {
+///             "standard"
+///         };
+///         vec![KeyValue::new("acme.tenant.tier", tier)]
+///     });
+/// let meter = opentelemetry::global::meter("checkout");
+/// let metrics = OtelMetrics::with_config(&meter, &config);
+/// # drop(metrics);
+/// ```
+#[derive(Clone)]
+pub struct MetricsConfig {
+    prefix: Cow<'static, str>,
+    attributes: Vec<KeyValue>,
+    key_attributes: Option<Arc<KeyAttributes>>,
+    key_budget: Option<(Key, usize)>,
+}
+
+type KeyAttributes = dyn Fn(&str) -> Vec<KeyValue> + Send + Sync;
+
+/// The breaker names which have been given their own value of a key
+/// attribute.
+struct KeyBudget {
+    key: Key,
+    max: usize,
+    names: Mutex<HashSet<String>>,
+}
+
+/// The value of a key attribute for breakers whose names are over budget.
+const OTHER_KEY: &str = "other";
+
+impl OtelMetrics {
+    /// Returns a new `OtelMetrics` whose instruments are created by the
+    /// provided [`Meter`].
+    pub fn new(meter: &Meter) -> Self {
+        Self::with_config(meter, &MetricsConfig::default())
+    }
+
+    /// Returns a new `OtelMetrics` whose instruments are created by the
+    /// provided [`Meter`], and named and attributed according to `config`.
+    pub fn with_config(meter: &Meter, config: &MetricsConfig) -> Self {
+        let prefix = &config.prefix;
+        let transitions = meter
+            .u64_counter(format!("{prefix}.transitions"))
+            .with_description("The number of circuit breaker state transitions.")
+            .with_unit("{transition}")
+            .build();
+        let outcomes = meter
+            .u64_counter(format!("{prefix}.outcomes"))
+            .with_description("The number of requests completed through a circuit breaker.")
+            .with_unit("{request}")
+            .build();
+        let rejections = meter
+            .u64_counter(format!("{prefix}.rejections"))
+            .with_description("The number of requests refused because a circuit breaker was open.")
+            .with_unit("{request}")
+            .build();
+        let open_duration = meter
+            .f64_histogram(format!("{prefix}.open.duration"))
+            .with_description("How long a circuit breaker remained open after tripping.")
+            .with_unit("s")
+            .build();
+        OtelMetrics(Arc::new(Inner {
+            transitions,
+            outcomes,
+            rejections,
+            open_duration,
+            attributes: config.attributes.clone(),
+            state: Key::new(format!("{prefix}.state")),
+            outcome: Key::new(format!("{prefix}.outcome")),
+            key_attributes: config.key_attributes.clone(),
+            key_budget: config.key_budget.clone().map(|(key, max)| {
+                Arc::new(KeyBudget {
+                    key,
+                    max,
+                    names: Mutex::new(HashSet::new()),
+                })
+            }),
+        }))
+    }
+
+    /// Adds attributes which will be attached to every measurement recorded
+    /// by this `OtelMetrics`.
+    pub fn with_attributes(mut self, attributes: impl IntoIterator<Item = KeyValue>) -> Self {
+        Arc::make_mut(&mut self.0).attributes.extend(attributes);
+        self
+    }
+
+    /// Returns the `OtelMetrics` recorded by the breaker named `name`, with
+    /// the attributes derived from its name.
+    pub(crate) fn for_breaker(&self, name: Option<&str>) -> Self {
+        let Some(name) = name else {
+            return self.clone();
+        };
+        let mut attributes = match self.0.key_attributes {
+            Some(ref f) => f(name),
+            None => Vec::new(),
+        };
+        if let Some(ref budget) = self.0.key_budget {
+            attributes.push(budget.attribute(name));
+        }
+        self.clone().with_attributes(attributes)
+    }
+
+    pub(crate) fn record_transition(&self, to: CircuitState) {
+        self.0
+            .transitions
+            .add(1, &self.0.attrs(&self.0.state, to.as_str()));
+    }
+
+    pub(crate) fn record_open_duration(&self, open_for: Duration) {
+        self.0
+            .open_duration
+            .record(open_for.as_secs_f64(), &self.0.attributes);
+    }
+
+    pub(crate) fn record_rejection(&self) {
+        self.0.rejections.add(1, &self.0.attributes);
+    }
+
+    pub(crate) fn record_success(&self) {
+        self.0
+            .outcomes
+            .add(1, &self.0.attrs(&self.0.outcome, "success"));
+    }
+
+    pub(crate) fn record_failure(&self) {
+        self.0
+            .outcomes
+            .add(1, &self.0.attrs(&self.0.outcome, "failure"));
+    }
+}
+
+impl fmt::Debug for OtelMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OtelMetrics")
+            .field("attributes", &self.0.attributes)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    fn attrs(&self, key: &Key, value: &'static str) -> Vec<KeyValue> {
+        let mut attrs = Vec::with_capacity(self.attributes.len() + 1);
+        attrs.extend_from_slice(&self.attributes);
+        attrs.push(KeyValue::new(key.clone(), value));
+        attrs
+    }
+}
+
+// === impl KeyBudget ===
+
+impl KeyBudget {
+    /// Returns the key attribute for the breaker named `name`, admitting the
+    /// name if there's room in the budget.
+    fn attribute(&self, name: &str) -> KeyValue {
+        let mut names = self.names.lock().unwrap();
+        let value = if names.contains(name) {
+            name.to_owned()
+        } else if names.len() < self.max {
+            names.insert(name.to_owned());
+            name.to_owned()
+        } else {
+            OTHER_KEY.to_owned()
+        };
+        KeyValue::new(self.key.clone(), value)
+    }
+}
+
+// === impl MetricsConfig ===
+
+impl MetricsConfig {
+    /// Returns a new `MetricsConfig` with the default names and no
+    /// attributes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prefixes the names of the instruments, and of the `state` and
+    /// `outcome` attributes, with `prefix` rather than `circuit_breaker`.
+    pub fn with_prefix(self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        MetricsConfig {
+            prefix: prefix.into(),
+            ..self
+        }
+    }
+
+    /// Adds attributes which will be attached to every measurement.
+    pub fn with_attributes(mut self, attributes: impl IntoIterator<Item = KeyValue>) -> Self {
+        self.attributes.extend(attributes);
+        self
+    }
+
+    /// Attaches the attributes returned by `f` for each breaker's
+    /// [name](crate::Config::with_name) to the measurements recorded by that
+    /// breaker.
+    ///
+    /// `f` is called once for each breaker, when it's constructed, and isn't
+    /// called for breakers without a name. Breakers created for each key of a
+    /// [`KeyedCircuitBreaker`](crate::keyed::KeyedCircuitBreaker) are usually
+    /// named after their key.
+    pub fn with_key_attributes(
+        self,
+        f: impl Fn(&str) -> Vec<KeyValue> + Send + Sync + 'static,
+    ) -> Self {
+        MetricsConfig {
+            key_attributes: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Attaches an attribute named `key`, whose value is the
+    /// [name](crate::Config::with_name) of the breaker which recorded the
+    /// measurement, to every measurement, for at most `max_keys` distinct
+    /// names.
+    ///
+    /// This gives each key of a
+    /// [`KeyedCircuitBreaker`](crate::keyed::KeyedCircuitBreaker) its own
+    /// metrics, without letting a breaker keyed by something unbounded, such
+    /// as URLs, create unbounded numbers of time series in the metrics
+    /// backend. Names are given their own value in the order their breakers
+    /// are constructed; once `max_keys` have been seen, the measurements of
+    /// breakers with any other name are attributed to `other`. A breaker's
+    /// attribute is fixed when it's constructed, so a key's time series
+    /// never moves in or out of the `other` bucket. Breakers without a name
+    /// don't have the attribute.
+    pub fn with_key_attribute(self, key: impl Into<Key>, max_keys: usize) -> Self {
+        MetricsConfig {
+            key_budget: Some((key.into(), max_keys)),
+            ..self
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            prefix: Cow::Borrowed("circuit_breaker"),
+            attributes: Vec::new(),
+            key_attributes: None,
+            key_budget: None,
+        }
+    }
+}
+
+impl fmt::Debug for MetricsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsConfig")
+            .field("prefix", &self.prefix)
+            .field("attributes", &self.attributes)
+            .field(
+                "key_attributes",
+                &self.key_attributes.as_ref().map(|_| "..."),
+            )
+            .field("key_budget", &self.key_budget)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_key_cardinality() {
+        let meter = opentelemetry::global::meter("test");
+        let config = MetricsConfig::new().with_key_attribute("breaker.key", 2);
+        let metrics = OtelMetrics::with_config(&meter, &config);
+        let key = |name| {
+            let metrics = metrics.for_breaker(Some(name));
+            let attribute = metrics.0.attributes.last().unwrap();
+            assert_eq!("breaker.key", attribute.key.as_str());
+            attribute.value.to_string()
+        };
+        assert_eq!("/users", key("/users"));
+        assert_eq!("/orders", key("/orders"));
+        // the budget is spent, so other keys share a bucket...
+        assert_eq!("other", key("/users/1"));
+        assert_eq!("other", key("/users/2"));
+        // ...but keys within it keep their own series.
+        assert_eq!("/users", key("/users"));
+        assert!(metrics.for_breaker(None).0.attributes.is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+//! Parsing for human-readable configuration values.
+use crate::error::ParseError;
+use std::time::Duration;
+
+/// Parses a duration such as `30s`, `250ms`, `1.5m`, or `2h`.
+///
+/// A number without a unit is interpreted as a number of seconds.
+pub(crate) fn duration(input: &str) -> Result<Duration, ParseError> {
+    let err = || ParseError::new(input, "a duration (e.g. `30s` or `250ms`)");
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (value, unit) = trimmed.split_at(split);
+    let value = value.parse::<f64>().map_err(|_| err())?;
+    let secs = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 60.0 * 60.0,
+        _ => return Err(err()),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| err())
+}
+
+/// Parses a rate between 0 and 1, such as `0.2`, or a percentage, such as
+/// `20%`.
+pub(crate) fn rate(input: &str) -> Result<f64, ParseError> {
+    let err = || ParseError::new(input, "a rate between 0 and 1 (e.g. `0.2` or `20%`)");
+    let trimmed = input.trim();
+    let rate = match trimmed.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map_err(|_| err())? / 100.0,
+        None => trimmed.parse::<f64>().map_err(|_| err())?,
+    };
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(err());
+    }
+    Ok(rate)
+}
+
+/// Parses a boolean such as `true`, `false`, `1`, `0`, `yes`, or `no`.
+pub(crate) fn boolean(input: &str) -> Result<bool, ParseError> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(ParseError::new(input, "a boolean (e.g. `true` or `false`)")),
+    }
+}
+
+/// Deserializes an optional duration from a string such as `"30s"`.
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+
+    let Some(s) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    duration(&s).map(Some).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(Ok(Duration::from_secs(30)), duration("30s"));
+        assert_eq!(Ok(Duration::from_secs(30)), duration("30"));
+        assert_eq!(Ok(Duration::from_millis(250)), duration("250ms"));
+        assert_eq!(Ok(Duration::from_secs(90)), duration("1.5m"));
+        assert_eq!(Ok(Duration::from_secs(7200)), duration(" 2h "));
+        assert!(duration("").is_err());
+        assert!(duration("30 parsecs").is_err());
+        assert!(duration("-1s").is_err());
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(Ok(0.2), rate("0.2"));
+        assert_eq!(Ok(0.2), rate("20%"));
+        assert_eq!(Ok(1.0), rate("100 %"));
+        assert!(rate("1.5").is_err());
+        assert!(rate("-0.1").is_err());
+        assert!(rate("lots").is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Broadcasting trips to peer processes.
+//!
+//! A [state store](crate::store) shares trips between replicas, but each
+//! replica only learns about a peer's trip the next time it syncs with the
+//! store. [`Peers`] pushes trips to the other replicas as they happen
+//! instead: when a breaker configured [with peers](crate::Config::with_peers)
+//! trips, the trip is broadcast over a [`Transport`], and breakers with the
+//! same [name](crate::Config::with_name) in the receiving processes open for
+//! the remainder of the trip. When the breaker closes again, that's broadcast
+//! too, so that peers which opened because of the trip close with it.
+//!
+//! Messages can be sent over any [`Transport`], such as a message bus the
+//! application already uses. When the `udp` feature flag is enabled,
+//! [`UdpTransport`] broadcasts messages over UDP, using either IP multicast
+//! or a fixed list of peers.
+//!
+//! ```
+//! # #[cfg(feature = "udp")]
+//! # fn main() -> std::io::Result<()> {
+//! use std::{net::Ipv4Addr, time::Duration};
+//! use tower_breaker::{peer::{Peers, UdpTransport}, policy::ConsecutiveFailures, Config};
+//!
+//! let transport = UdpTransport::multicast(Ipv4Addr::new(239, 255, 77, 77), 7777)?;
+//! let peers = Peers::new(transport);
+//! // `Peers::run` must be spawned to send and receive broadcasts.
+//! # drop(|| {
+//! tokio::spawn(peers.run());
+//! # });
+//!
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(30))
+//!     .with_name("users-api")
+//!     .with_peers(peers);
+//! # drop(config);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "udp"))]
+//! # fn main() {}
+//! ```
+use crate::{
+    rng::{Rng, XorShift64},
+    store::StoredState,
+    trace::debug,
+    BoxError,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+
+#[cfg(feature = "udp")]
+mod udp;
+#[cfg(feature = "udp")]
+pub use self::udp::UdpTransport;
+
+/// Sends and receives messages between peer processes.
+pub trait Transport: fmt::Debug + Send + Sync + 'static {
+    /// Sends `message` to every peer.
+    fn send<'a>(&'a self, message: &'a [u8]) -> TransportFuture<'a, ()>;
+
+    /// Receives the next message sent by a peer.
+    ///
+    /// A transport may deliver the messages this process sends back to it;
+    /// they're ignored.
+    fn recv(&self) -> TransportFuture<'_, Vec<u8>>;
+}
+
+/// A future returned by a [`Transport`].
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+/// Broadcasts trips to, and receives trips from, peer processes.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct Peers(Arc<Inner>);
+
+struct Inner {
+    transport: Box<dyn Transport>,
+    /// Identifies this process's messages, so that they're ignored if the
+    /// transport delivers them back to it.
+    id: u64,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    rx: Mutex<Option<mpsc::UnboundedReceiver<Vec<u8>>>>,
+    breakers: Mutex<HashMap<String, Vec<Listener>>>,
+}
+
+/// Applies a state received from a peer to a breaker, returning `false` if
+/// the breaker has been dropped.
+pub(crate) type Listener = Box<dyn Fn(&StoredState) -> bool + Send + Sync>;
+
+const MAGIC: &str = "tower-breaker/1";
+
+// === impl Peers ===
+
+impl Peers {
+    /// Returns a new `Peers` which broadcasts over `transport`.
+    pub fn new(transport: impl Transport) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Peers(Arc::new(Inner {
+            transport: Box::new(transport),
+            id: XorShift64::from_entropy().next_u64(),
+            tx,
+            rx: Mutex::new(Some(rx)),
+            breakers: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Returns a future which sends this process's broadcasts and applies
+    /// broadcasts received from peers.
+    ///
+    /// The returned future should be spawned (e.g. with `tokio::spawn`), and
+    /// runs until it's dropped. Errors sending or receiving messages are
+    /// logged.
+    ///
+    /// # Panics
+    ///
+    /// If `run` has already been called on this `Peers` or a clone of it.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn run(&self) -> impl Future<Output = ()> + Send + 'static {
+        let inner = self.0.clone();
+        let mut rx = inner
+            .rx
+            .lock()
+            .unwrap()
+            .take()
+            .expect("`Peers::run` may only be called once");
+        async move {
+            let send = pin!(async {
+                while let Some(message) = rx.recv().await {
+                    if let Err(error) = inner.transport.send(&message).await {
+                        debug!(%error, "failed to broadcast breaker state");
+                    }
+                }
+            });
+            let recv = pin!(async {
+                loop {
+                    match inner.transport.recv().await {
+                        Ok(message) => inner.receive(&message),
+                        Err(error) => {
+                            debug!(%error, "failed to receive breaker state");
+                        }
+                    }
+                }
+            });
+            let (mut send, mut recv) = (Some(send), recv);
+            poll_fn(|cx| {
+                if send
+                    .as_mut()
+                    .is_some_and(|send| send.as_mut().poll(cx).is_ready())
+                {
+                    send = None;
+                }
+                recv.as_mut().poll(cx)
+            })
+            .await
+        }
+    }
+
+    /// Broadcasts the state of the breaker named `name`.
+    pub(crate) fn publish(&self, name: &str, state: &StoredState) {
+        let message = format!("{MAGIC} {:016x} {name}\n{}", self.0.id, state.encode());
+        // if `run` has been dropped, there's nobody to send to.
+        let _ = self.0.tx.send(message.into_bytes());
+    }
+
+    /// Registers `listener` to receive states broadcast by peers for
+    /// breakers named `name`.
+    pub(crate) fn subscribe(&self, name: &str, listener: Listener) {
+        self.0
+            .breakers
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default()
+            .push(listener);
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn receive(&self, message: &[u8]) {
+        let (name, state) = match self.parse(message) {
+            Ok(Some(parsed)) => parsed,
+            // our own message.
+            Ok(None) => return,
+            Err(error) => {
+                debug!(%error, "received an invalid breaker state");
+                return;
+            }
+        };
+        let mut breakers = self.breakers.lock().unwrap();
+        if let Some(listeners) = breakers.get_mut(name) {
+            listeners.retain(|listener| listener(&state));
+            if listeners.is_empty() {
+                breakers.remove(name);
+            }
+        }
+    }
+
+    /// Parses a message, returning `None` if it was sent by this process.
+    fn parse<'a>(&self, message: &'a [u8]) -> Result<Option<(&'a str, StoredState)>, BoxError> {
+        let message = std::str::from_utf8(message)?;
+        let (header, state) = message.split_once('\n').ok_or("missing state")?;
+        let mut header = header.splitn(3, ' ');
+        if header.next() != Some(MAGIC) {
+            return Err("not a breaker state".into());
+        }
+        let id = u64::from_str_radix(header.next().ok_or("missing ID")?, 16)?;
+        if id == self.id {
+            return Ok(None);
+        }
+        let name = header.next().ok_or("missing breaker name")?;
+        Ok(Some((name, StoredState::decode(state)?)))
+    }
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Peers")
+            .field("transport", &self.transport)
+            .field("id", &format_args!("{:016x}", self.id))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::ConsecutiveFailures, CircuitBreaker, CircuitState, Config, TripReason};
+    use std::time::Duration;
+    use tokio::sync::broadcast;
+    use tower::{service_fn, Service, ServiceExt};
+
+    /// A transport which delivers every message to every process on the bus,
+    /// including the sender.
+    #[derive(Debug)]
+    struct Bus {
+        tx: broadcast::Sender<Vec<u8>>,
+        rx: tokio::sync::Mutex<broadcast::Receiver<Vec<u8>>>,
+    }
+
+    impl Transport for Bus {
+        fn send<'a>(&'a self, message: &'a [u8]) -> TransportFuture<'a, ()> {
+            let result = self.tx.send(message.to_vec()).map(drop);
+            Box::pin(async move { Ok(result?) })
+        }
+
+        fn recv(&self) -> TransportFuture<'_, Vec<u8>> {
+            Box::pin(async move { Ok(self.rx.lock().await.recv().await?) })
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcasts_trips() {
+        tokio::time::pause();
+        let (tx, _) = broadcast::channel(16);
+        let peers = || {
+            let peers = Peers::new(Bus {
+                rx: tokio::sync::Mutex::new(tx.subscribe()),
+                tx: tx.clone(),
+            });
+            tokio::spawn(peers.run());
+            peers
+        };
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(5))
+            .with_name("users-api")
+            .with_fail_fast(true);
+        let svc = || {
+            service_fn(|ok: bool| async move {
+                if ok {
+                    Ok(())
+                } else {
+                    Err("failed")
+                }
+            })
+        };
+        let mut a = CircuitBreaker::new(config.clone().with_peers(peers()), svc());
+        let b = CircuitBreaker::new(config.clone().with_peers(peers()), svc());
+        let other = CircuitBreaker::new(config.with_name("orders-api").with_peers(peers()), svc());
+        let mut rx = b.state_receiver();
+
+        assert!(a.ready().await.unwrap().call(false).await.is_err());
+        assert!(a.ready().await.unwrap().call(true).await.is_err());
+        assert!(a.is_tripped());
+
+        rx.changed().await.unwrap();
+        assert_eq!(CircuitState::Open, *rx.borrow_and_update());
+        assert_eq!(
+            Some(TripReason::Shared),
+            b.handle().last_trip().map(|trip| trip.reason)
+        );
+        assert!(!other.is_tripped());
+
+        // when `a` closes, so does `b`.
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(a.ready().await.unwrap().call(true).await.is_ok());
+        rx.changed().await.unwrap();
+        assert_eq!(CircuitState::Closed, *rx.borrow_and_update());
+    }
+}
@@ -0,0 +1,123 @@
+use super::{Transport, TransportFuture};
+use std::{
+    future, io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+
+/// A [`Transport`] which sends messages over UDP.
+///
+/// Messages are sent either to an IP multicast group, which every peer
+/// [joins](UdpTransport::multicast), or to a fixed list of peers added with
+/// [`with_peer`](UdpTransport::with_peer). Because UDP is unreliable, a peer
+/// may miss a broadcast; pairing broadcasts with a
+/// [state store](crate::store) ensures that peers eventually learn about
+/// trips regardless.
+///
+/// Messages are received on Tokio's blocking thread pool, so a Tokio runtime
+/// is required.
+///
+/// This requires the `udp` feature flag.
+#[derive(Clone, Debug)]
+pub struct UdpTransport {
+    socket: Arc<UdpSocket>,
+    peers: Vec<SocketAddr>,
+}
+
+/// The largest message that can be received.
+const MAX_MESSAGE: usize = 1500;
+
+/// How long a blocking receive waits before checking whether the receiving
+/// future has been dropped.
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+// === impl UdpTransport ===
+
+impl UdpTransport {
+    /// Returns a new `UdpTransport` which receives messages on `addr`, and
+    /// sends messages to no peers until some are added using
+    /// [`with_peer`](Self::with_peer).
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        Ok(UdpTransport {
+            socket: Arc::new(socket),
+            peers: Vec::new(),
+        })
+    }
+
+    /// Returns a new `UdpTransport` which joins the IPv4 multicast `group`,
+    /// sending and receiving messages on `port`.
+    ///
+    /// The socket is bound to `port` on all interfaces, so only one process
+    /// on each host can join the group on a given port.
+    pub fn multicast(group: Ipv4Addr, port: u16) -> io::Result<Self> {
+        let transport = Self::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        transport
+            .socket
+            .join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+        Ok(transport.with_peer(SocketAddrV4::new(group, port).into()))
+    }
+
+    /// Adds `peer` to the addresses each message is sent to.
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.peers.push(peer);
+        self
+    }
+
+    /// Returns the local address the transport receives messages on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send<'a>(&'a self, message: &'a [u8]) -> TransportFuture<'a, ()> {
+        // sending a datagram doesn't block for long, so send it inline.
+        let result = self
+            .peers
+            .iter()
+            .try_for_each(|peer| self.socket.send_to(message, peer).map(drop));
+        Box::pin(future::ready(result.map_err(Into::into)))
+    }
+
+    fn recv(&self) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            loop {
+                let socket = self.socket.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    let mut buf = vec![0; MAX_MESSAGE];
+                    let (len, _) = socket.recv_from(&mut buf)?;
+                    buf.truncate(len);
+                    Ok::<_, io::Error>(buf)
+                })
+                .await?;
+                match result {
+                    Ok(message) => return Ok(message),
+                    Err(error)
+                        if matches!(
+                            error.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) => {}
+                    Err(error) => return Err(error.into()),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unicast() {
+        let a = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let b = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let a = a.with_peer(b.local_addr().unwrap());
+
+        a.send(b"hello").await.unwrap();
+        assert_eq!(b"hello".to_vec(), b.recv().await.unwrap());
+    }
+}
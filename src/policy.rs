@@ -1,3 +1,8 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
 pub trait Policy {
     fn record_success(&self);
 
@@ -5,8 +10,461 @@ pub trait Policy {
 
     fn is_punished(&self) -> bool;
 
+    /// Records a failure in the given `category`, as assigned by an
+    /// [error classifier](crate::Config::with_error_classifier) returning
+    /// [`ErrorClass::Category`](crate::classify::ErrorClass::Category).
+    ///
+    /// By default, this records a failure with
+    /// [`record_failure`](Policy::record_failure). Policies which track
+    /// categories of failure separately, such as
+    /// [`CategorizedFailureRate`], should override this method.
+    fn record_failure_in(&self, category: &'static str) {
+        let _ = category;
+        self.record_failure();
+    }
+
+    /// Records how long the breaker's inner service took to become ready,
+    /// each time it becomes ready.
+    ///
+    /// This is zero if the inner service was ready as soon as it was polled.
+    /// A service which takes a long time to become ready is applying
+    /// backpressure, which may mean that it's saturated, even if the
+    /// requests it accepts succeed.
+    ///
+    /// By default, this does nothing.
+    fn record_ready_delay(&self, delay: Duration) {
+        let _ = delay;
+    }
+
+    /// Records how long a request passed to the breaker's inner service took
+    /// to complete, each time one succeeds or fails.
+    ///
+    /// This is only called if [`measures_latency`](Policy::measures_latency)
+    /// returns `true`, or if the breaker times requests for another reason.
+    ///
+    /// By default, this does nothing.
+    fn record_latency(&self, latency: Duration) {
+        let _ = latency;
+    }
+
+    /// Returns `true` if the breaker should time each request, so that its
+    /// latency can be passed to [`record_latency`](Policy::record_latency).
+    ///
+    /// By default, this returns `false`, so that breakers don't read the
+    /// clock twice per request for policies which ignore latency.
+    fn measures_latency(&self) -> bool {
+        false
+    }
+
+    /// Records the number of requests which have been passed to the
+    /// breaker's inner service and haven't yet completed, each time it
+    /// changes.
+    ///
+    /// Requests which hang neither succeed nor fail, so they're invisible to
+    /// policies which only see outcomes. The number of requests in flight
+    /// climbs as they pile up, though.
+    ///
+    /// By default, this does nothing.
+    fn record_in_flight(&self, in_flight: usize) {
+        let _ = in_flight;
+    }
+
+    /// Returns the reason this policy is punishing the service, or `None` if
+    /// the service is not punished.
+    ///
+    /// By default, this returns [`TripReason::Unspecified`] if
+    /// [`is_punished`](Policy::is_punished) returns `true`. Policies which
+    /// can describe _why_ they are punishing a service should override this
+    /// method.
+    fn punish_reason(&self) -> Option<TripReason> {
+        if self.is_punished() {
+            Some(TripReason::Unspecified)
+        } else {
+            None
+        }
+    }
+
+    /// Returns how close this policy is to punishing the service, from 0
+    /// (healthy) to 1 (punished).
+    ///
+    /// A breaker configured with
+    /// [degraded shedding](crate::Config::with_degraded_shedding) sheds part
+    /// of its load as the severity rises, before the policy trips it.
+    ///
+    /// By default, this returns 1 if [`is_punished`](Policy::is_punished)
+    /// returns `true`, and 0 otherwise.
+    fn severity(&self) -> f64 {
+        if self.is_punished() {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns a point-in-time summary of this policy's state.
+    ///
+    /// By default, this returns an empty [`PolicySnapshot`].
+    fn snapshot(&self) -> PolicySnapshot {
+        PolicySnapshot::default()
+    }
+
+    /// Pre-loads this policy with the outcomes of past requests, such as
+    /// outcomes persisted by a previous process.
+    ///
+    /// This allows a restarted client to remember that a service was
+    /// unhealthy shortly before it restarted. The `outcomes` should be in
+    /// chronological order.
+    ///
+    /// By default, this records each outcome as though the request had just
+    /// completed. Policies which track outcomes over a window of time should
+    /// override this method to account for when each request completed.
+    fn warm_start(&self, outcomes: &[Outcome]) {
+        for outcome in outcomes {
+            if outcome.success {
+                self.record_success();
+            } else {
+                self.record_failure();
+            }
+        }
+    }
+
+    /// Returns `true` if breakers using this policy should pass every
+    /// request straight through to their inner service.
+    ///
+    /// By default, this returns `false`. Only [`Disabled`] returns `true`.
+    #[inline]
+    fn is_disabled(&self) -> bool {
+        false
+    }
+
+    /// Discards every outcome this policy has recorded.
+    ///
+    /// A breaker resets its policy each time it trips, so once the trip
+    /// ends, the policy's decisions aren't based on the failures which
+    /// tripped it. Requests which were in flight when it tripped may still
+    /// record their outcomes afterwards, unless the breaker is configured
+    /// to [record only probes](crate::Config::with_probe_only).
     fn reset(&self);
 }
 
+/// The outcome of a past request, used to [warm-start](Policy::warm_start) a
+/// policy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Outcome {
+    /// The time at which the request completed.
+    pub at: SystemTime,
+    /// Whether the request succeeded.
+    pub success: bool,
+}
+
+/// A point-in-time summary of a [`Policy`]'s state.
+///
+/// Each field is `None` if the policy does not track that value.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct PolicySnapshot {
+    /// The number of requests in the policy's window.
+    pub requests: Option<usize>,
+    /// The number of failed requests in the policy's window.
+    pub failures: Option<usize>,
+    /// The failure rate over the policy's window.
+    pub failure_rate: Option<f64>,
+    /// The failure rate above which the policy trips the breaker.
+    pub max_failure_rate: Option<f64>,
+    /// The number of consecutive failed requests.
+    pub consecutive_failures: Option<usize>,
+    /// The number of consecutive failed requests at which the policy trips
+    /// the breaker.
+    pub max_consecutive_failures: Option<usize>,
+    /// The length of the policy's window.
+    pub window: Option<Duration>,
+}
+
+/// Describes why a [`Policy`] tripped a circuit breaker.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(tag = "kind", rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum TripReason {
+    /// The failure rate over the policy's window exceeded its threshold.
+    FailureRate {
+        /// The observed failure rate.
+        rate: f64,
+        /// The maximum allowable failure rate.
+        threshold: f64,
+        /// The number of requests the failure rate was calculated over.
+        samples: usize,
+    },
+    /// The failure rate of one category of failure over the policy's window
+    /// exceeded that category's threshold.
+    CategoryFailureRate {
+        /// The category of failure.
+        category: &'static str,
+        /// The observed failure rate for the category.
+        rate: f64,
+        /// The maximum allowable failure rate for the category.
+        threshold: f64,
+        /// The number of requests the failure rate was calculated over.
+        samples: usize,
+    },
+    /// Too many consecutive requests failed.
+    ConsecutiveFailures {
+        /// The number of consecutive failed requests.
+        failures: usize,
+        /// The number of consecutive failures after which the policy trips.
+        threshold: usize,
+    },
+    /// Too many consecutive requests took longer than a latency threshold.
+    SlowCalls {
+        /// The number of consecutive slow requests.
+        calls: usize,
+        /// The number of consecutive slow requests after which the policy
+        /// trips.
+        threshold: usize,
+        /// The latency above which a request is slow.
+        slow_call_duration: Duration,
+    },
+    /// Too few requests over the policy's window completed within a target
+    /// latency.
+    SloAttainment {
+        /// The fraction of requests which completed within the target
+        /// latency.
+        attainment: f64,
+        /// The fraction of requests which should complete within the target
+        /// latency.
+        objective: f64,
+        /// The latency within which requests should complete.
+        target_latency: Duration,
+        /// The number of requests the attainment was calculated over.
+        samples: usize,
+    },
+    /// The failure rate over the policy's recent window rose too far above
+    /// the failure rate over the preceding baseline window.
+    FailureRateRegression {
+        /// The failure rate over the recent window.
+        recent: f64,
+        /// The failure rate over the baseline window.
+        baseline: f64,
+        /// The number of requests in the recent window.
+        samples: usize,
+    },
+    /// The mean latency over the policy's recent window rose too far above
+    /// the mean latency over the preceding baseline window.
+    LatencyRegression {
+        /// The mean latency over the recent window.
+        recent: Duration,
+        /// The mean latency over the baseline window.
+        baseline: Duration,
+        /// The number of requests timed in the recent window.
+        samples: usize,
+    },
+    /// The failure rate over the policy's window exceeded the failure rate
+    /// usually seen at the current time of day.
+    SeasonalAnomaly {
+        /// The observed failure rate.
+        rate: f64,
+        /// The failure rate usually seen at the current time of day.
+        baseline: f64,
+        /// The number of requests the failure rate was calculated over.
+        samples: usize,
+    },
+    /// The circuit was forced open by a [`Handle`](crate::Handle).
+    Forced,
+    /// Another breaker sharing the same [state store](crate::store) tripped.
+    Shared,
+    /// The breaker was open when its state was
+    /// [persisted](crate::Handle::persist), and the trip was
+    /// [restored](crate::Handle::restore).
+    Restored,
+    /// The breaker was [configured](crate::Config::with_initially_open) to
+    /// start with its circuit open.
+    Initial,
+    /// Enough breakers for other endpoints in the same zone tripped that the
+    /// whole zone was [ejected](crate::keyed::KeyedCircuitBreaker::with_zones).
+    ZoneEjected,
+    /// The inner service took longer than a target delay to become ready,
+    /// every time it was polled, for a sustained period.
+    QueueDelay {
+        /// The delay the inner service should become ready within.
+        target: Duration,
+        /// How long the inner service's readiness delay has exceeded the
+        /// target.
+        sustained_for: Duration,
+    },
+    /// Too many requests were in flight for a sustained period.
+    InFlight {
+        /// The number of requests in flight.
+        in_flight: usize,
+        /// The number of requests in flight above which the policy trips.
+        threshold: usize,
+    },
+    /// A resource signal exceeded its threshold.
+    ResourceSignal {
+        /// The value of the signal.
+        value: f64,
+        /// The value above which the policy trips.
+        threshold: f64,
+    },
+    /// A breaker this breaker [depends on](crate::Config::with_dependency)
+    /// is open.
+    DependencyOpen,
+    /// The policy's severity reached a
+    /// [degraded shedding](crate::Config::with_degraded_shedding) step, so
+    /// the breaker shed part of its load without tripping.
+    Degraded {
+        /// The policy's severity.
+        severity: f64,
+    },
+    /// The circuit recently closed, and traffic is
+    /// [ramping up](crate::Config::with_ramp_up), so the breaker shed part of
+    /// its load without tripping.
+    RampingUp {
+        /// The fraction of requests being admitted.
+        admitted: f64,
+    },
+    /// The policy did not provide a reason.
+    Unspecified,
+}
+
+mod baseline;
+mod buffered;
+mod categorized;
+mod cluster;
+mod consecutive;
+mod disabled;
 mod failure_rate;
-pub use failure_rate::SlidingFailureRate;
+mod in_flight;
+mod queue_delay;
+mod resource;
+mod seasonal;
+mod slo;
+mod slow_calls;
+pub use baseline::{BaselineRegression, BaselineRegressionBuilder};
+pub use buffered::Buffered;
+pub use categorized::{CategorizedFailureRate, CategorizedFailureRateBuilder};
+pub use cluster::ClusterFailureRate;
+pub use consecutive::ConsecutiveFailures;
+pub use disabled::Disabled;
+pub use failure_rate::{SlidingFailureRate, SlidingFailureRateBuilder};
+pub use in_flight::InFlightLimit;
+pub use queue_delay::QueueDelay;
+pub use resource::ResourceThreshold;
+pub use seasonal::{SeasonalBaseline, SeasonalBaselineBuilder};
+pub use slo::SloAttainment;
+pub use slow_calls::ConsecutiveSlowCalls;
+
+// === impl Outcome ===
+
+impl Outcome {
+    /// Returns a successful outcome of a request that completed at `at`.
+    pub fn success(at: SystemTime) -> Self {
+        Outcome { at, success: true }
+    }
+
+    /// Returns a failed outcome of a request that completed at `at`.
+    pub fn failure(at: SystemTime) -> Self {
+        Outcome { at, success: false }
+    }
+}
+
+// === impl TripReason ===
+
+impl fmt::Display for TripReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TripReason::FailureRate {
+                rate,
+                threshold,
+                samples,
+            } => write!(
+                f,
+                "failure rate {rate} exceeded {threshold} over {samples} requests"
+            ),
+            TripReason::CategoryFailureRate {
+                category,
+                rate,
+                threshold,
+                samples,
+            } => write!(
+                f,
+                "{category} failure rate {rate} exceeded {threshold} over {samples} requests"
+            ),
+            TripReason::ConsecutiveFailures {
+                failures,
+                threshold,
+            } => write!(f, "{failures} consecutive failures (threshold {threshold})"),
+            TripReason::SlowCalls {
+                calls,
+                threshold,
+                slow_call_duration,
+            } => write!(
+                f,
+                "{calls} consecutive calls slower than {slow_call_duration:?} (threshold {threshold})"
+            ),
+            TripReason::SloAttainment {
+                attainment,
+                objective,
+                target_latency,
+                samples,
+            } => write!(
+                f,
+                "{attainment} of {samples} requests completed within {target_latency:?} (objective {objective})"
+            ),
+            TripReason::FailureRateRegression {
+                recent,
+                baseline,
+                samples,
+            } => write!(
+                f,
+                "failure rate regressed to {recent} over {samples} requests (baseline {baseline})"
+            ),
+            TripReason::LatencyRegression {
+                recent,
+                baseline,
+                samples,
+            } => write!(
+                f,
+                "mean latency regressed to {recent:?} over {samples} requests (baseline {baseline:?})"
+            ),
+            TripReason::SeasonalAnomaly {
+                rate,
+                baseline,
+                samples,
+            } => write!(
+                f,
+                "failure rate {rate} over {samples} requests deviated from seasonal baseline {baseline}"
+            ),
+            TripReason::Forced => f.write_str("forced open"),
+            TripReason::Shared => f.write_str("tripped by a peer"),
+            TripReason::Restored => f.write_str("restored after a restart"),
+            TripReason::Initial => f.write_str("opened at startup"),
+            TripReason::ZoneEjected => f.write_str("zone ejected"),
+            TripReason::QueueDelay {
+                target,
+                sustained_for,
+            } => write!(
+                f,
+                "readiness delay exceeded {target:?} for {sustained_for:?}"
+            ),
+            TripReason::InFlight {
+                in_flight,
+                threshold,
+            } => write!(f, "{in_flight} requests in flight (threshold {threshold})"),
+            TripReason::ResourceSignal { value, threshold } => {
+                write!(f, "resource signal {value} exceeded {threshold}")
+            }
+            TripReason::DependencyOpen => f.write_str("a dependency is open"),
+            TripReason::Degraded { severity } => write!(f, "degraded (severity {severity})"),
+            TripReason::RampingUp { admitted } => {
+                write!(f, "ramping up (admitting {admitted} of requests)")
+            }
+            TripReason::Unspecified => f.write_str("punished by policy"),
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use super::{PolicySnapshot, TripReason};
+use crate::{
+    clock::{self, Clock, SharedClock},
+    error::PolicyError,
+    trace::trace,
+    window_counter::WindowedCounter,
+};
+use std::{fmt, sync::Arc, time::Duration};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint when its most
+/// recent requests regress significantly compared to the requests before
+/// them.
+///
+/// Absolute thresholds have to be loose enough for an endpoint's worst
+/// normal day, so a deploy which doubles an endpoint's usual failure rate
+/// may never trip them. This policy instead compares the failure rate and
+/// mean latency over a short `recent` window to those over the `baseline`
+/// window which preceded it, and punishes the endpoint when the recent
+/// window is worse by more than the configured margins:
+///
+/// ```
+/// use std::time::Duration;
+/// use tower_breaker::policy::BaselineRegression;
+///
+/// // compare the last 10 seconds to the 5 minutes before them.
+/// let policy = BaselineRegression::builder(Duration::from_secs(10), Duration::from_secs(300))
+///     // trip if the failure rate rises by 10 percentage points...
+///     .with_failure_rate_increase(0.1)
+///     // ...or if requests take three times as long on average.
+///     .with_latency_ratio(3.0)
+///     .with_min_requests(20)
+///     .build()
+///     .expect("policy settings should be valid");
+/// ```
+///
+/// When the breaker trips, the policy is reset along with the baseline, so
+/// a regression which persists after the trip ends becomes the new
+/// baseline.
+#[derive(Clone)]
+pub struct BaselineRegression(Arc<Inner>);
+
+/// Builds a [`BaselineRegression`] policy.
+///
+/// This is returned by [`BaselineRegression::builder`].
+#[derive(Clone, Debug)]
+pub struct BaselineRegressionBuilder {
+    settings: Settings,
+    clock: SharedClock,
+}
+
+struct Inner {
+    settings: Settings,
+    /// Requests over the recent window.
+    recent: Window,
+    /// Requests over the recent and baseline windows together.
+    total: Window,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Settings {
+    recent: Duration,
+    baseline: Duration,
+    failure_rate_increase: Option<f64>,
+    latency_ratio: Option<f64>,
+    min_requests: usize,
+}
+
+struct Window {
+    reqs: WindowedCounter,
+    fails: WindowedCounter,
+    timed: WindowedCounter,
+    /// The total latency of the timed requests, in microseconds.
+    latency_us: WindowedCounter,
+}
+
+/// The requests in part of a [`BaselineRegression`]'s window.
+#[derive(Copy, Clone, Debug, Default)]
+struct Counts {
+    reqs: usize,
+    fails: usize,
+    timed: usize,
+    latency_us: usize,
+}
+
+impl BaselineRegression {
+    /// Returns a builder for a `BaselineRegression` policy comparing the
+    /// requests over the `recent` window to the requests over the `baseline`
+    /// window which preceded it.
+    pub fn builder(recent: Duration, baseline: Duration) -> BaselineRegressionBuilder {
+        BaselineRegressionBuilder {
+            settings: Settings {
+                recent,
+                baseline,
+                failure_rate_increase: None,
+                latency_ratio: None,
+                min_requests: 1,
+            },
+            clock: clock::default(),
+        }
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// Any requests already recorded by this policy are discarded, so this
+    /// should be called when the policy is constructed. When using a custom
+    /// clock, the same clock should also be provided to the breaker using
+    /// [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self::build(self.0.settings, Arc::new(clock))
+    }
+
+    fn build(settings: Settings, clock: SharedClock) -> Self {
+        BaselineRegression(Arc::new(Inner {
+            settings,
+            recent: Window::new(settings.recent, &clock),
+            total: Window::new(settings.recent + settings.baseline, &clock),
+        }))
+    }
+
+    /// Returns the counts over the recent and baseline windows, if both
+    /// have enough requests to compare.
+    fn counts(&self) -> Option<(Counts, Counts)> {
+        let recent = self.0.recent.counts();
+        let baseline = self.0.total.counts().minus(recent);
+        let min_requests = self.0.settings.min_requests.max(1);
+        (recent.reqs >= min_requests && baseline.reqs >= min_requests).then_some((recent, baseline))
+    }
+}
+
+// === impl BaselineRegressionBuilder ===
+
+impl BaselineRegressionBuilder {
+    /// Punishes an endpoint if the failure rate over the recent window is
+    /// more than `increase` higher than over the baseline window.
+    ///
+    /// For example, an `increase` of 0.1 trips the breaker if the failure
+    /// rate rises from 2% to more than 12%.
+    pub fn with_failure_rate_increase(mut self, increase: f64) -> Self {
+        self.settings.failure_rate_increase = Some(increase);
+        self
+    }
+
+    /// Punishes an endpoint if the mean latency over the recent window is
+    /// more than `ratio` times the mean latency over the baseline window.
+    pub fn with_latency_ratio(mut self, ratio: f64) -> Self {
+        self.settings.latency_ratio = Some(ratio);
+        self
+    }
+
+    /// Sets the minimum number of requests which must be in both the recent
+    /// and baseline windows before the policy punishes an endpoint. By
+    /// default, this is 1.
+    pub fn with_min_requests(mut self, min_requests: usize) -> Self {
+        self.settings.min_requests = min_requests;
+        self
+    }
+
+    /// Reads the current time from the provided [`Clock`] rather than from
+    /// Tokio.
+    ///
+    /// When using a custom clock, the same clock should also be provided to
+    /// the breaker using [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the configured policy.
+    ///
+    /// # Errors
+    ///
+    /// - If either window is zero.
+    /// - If neither a failure rate increase nor a latency ratio is set, in
+    ///   which case the policy could never punish an endpoint.
+    /// - If the failure rate increase isn't between 0 and 1, exclusive, or
+    ///   the latency ratio isn't greater than 1.
+    pub fn build(self) -> Result<BaselineRegression, PolicyError> {
+        let Settings {
+            recent,
+            baseline,
+            failure_rate_increase,
+            latency_ratio,
+            ..
+        } = self.settings;
+        if recent.is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "recent window",
+            });
+        }
+        if baseline.is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "baseline window",
+            });
+        }
+        if failure_rate_increase.is_none() && latency_ratio.is_none() {
+            return Err(PolicyError::Zero {
+                parameter: "number of regression thresholds",
+            });
+        }
+        if let Some(increase) = failure_rate_increase.filter(|&i| !(i > 0.0 && i < 1.0)) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "failure rate increase",
+                value: increase,
+                range: "(0, 1)",
+            });
+        }
+        if let Some(ratio) = latency_ratio.filter(|&r| !(r > 1.0 && r.is_finite())) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "latency ratio",
+                value: ratio,
+                range: "(1, inf)",
+            });
+        }
+        Ok(BaselineRegression::build(self.settings, self.clock))
+    }
+}
+
+impl super::Policy for BaselineRegression {
+    fn record_success(&self) {
+        self.0.recent.reqs.add(1);
+        self.0.total.reqs.add(1);
+    }
+
+    fn record_failure(&self) {
+        self.record_success();
+        self.0.recent.fails.add(1);
+        self.0.total.fails.add(1);
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        if self.0.settings.latency_ratio.is_none() {
+            return;
+        }
+        let us = latency.as_micros().try_into().unwrap_or(usize::MAX);
+        for window in [&self.0.recent, &self.0.total] {
+            window.timed.add(1);
+            window.latency_us.add(us);
+        }
+    }
+
+    fn measures_latency(&self) -> bool {
+        self.0.settings.latency_ratio.is_some()
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let (recent, baseline) = self.counts()?;
+        let settings = &self.0.settings;
+        if let Some(increase) = settings.failure_rate_increase {
+            let (recent_rate, baseline_rate) = (recent.failure_rate(), baseline.failure_rate());
+            if recent_rate - baseline_rate > increase {
+                trace!(
+                    recent_rate,
+                    baseline_rate,
+                    "Failure rate regressed from baseline; punishing endpoint!"
+                );
+                return Some(TripReason::FailureRateRegression {
+                    recent: recent_rate,
+                    baseline: baseline_rate,
+                    samples: recent.reqs,
+                });
+            }
+        }
+        let ratio = settings.latency_ratio?;
+        let (recent_latency, baseline_latency) = (recent.mean_latency()?, baseline.mean_latency()?);
+        if recent_latency.as_secs_f64() > baseline_latency.as_secs_f64() * ratio {
+            trace!(
+                ?recent_latency,
+                ?baseline_latency,
+                "Latency regressed from baseline; punishing endpoint!"
+            );
+            return Some(TripReason::LatencyRegression {
+                recent: recent_latency,
+                baseline: baseline_latency,
+                samples: recent.timed,
+            });
+        }
+        None
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        let recent = self.0.recent.counts();
+        PolicySnapshot {
+            requests: Some(recent.reqs),
+            failures: Some(recent.fails),
+            failure_rate: Some(recent.failure_rate()).filter(|_| recent.reqs > 0),
+            window: Some(self.0.settings.recent),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    fn reset(&self) {
+        self.0.recent.reset();
+        self.0.total.reset();
+    }
+}
+
+impl fmt::Debug for BaselineRegression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let recent = self.0.recent.counts();
+        f.debug_struct("BaselineRegression")
+            .field("settings", &self.0.settings)
+            .field("recent", &recent)
+            .field("baseline", &self.0.total.counts().minus(recent))
+            .finish()
+    }
+}
+
+// === impl Window ===
+
+impl Window {
+    fn new(window: Duration, clock: &SharedClock) -> Self {
+        Window {
+            reqs: WindowedCounter::new(window, clock.clone()),
+            fails: WindowedCounter::new(window, clock.clone()),
+            timed: WindowedCounter::new(window, clock.clone()),
+            latency_us: WindowedCounter::new(window, clock.clone()),
+        }
+    }
+
+    fn counts(&self) -> Counts {
+        Counts {
+            reqs: self.reqs.sum(),
+            fails: self.fails.sum(),
+            timed: self.timed.sum(),
+            latency_us: self.latency_us.sum(),
+        }
+    }
+
+    fn reset(&self) {
+        self.reqs.reset();
+        self.fails.reset();
+        self.timed.reset();
+        self.latency_us.reset();
+    }
+}
+
+// === impl Counts ===
+
+impl Counts {
+    fn minus(self, other: Counts) -> Counts {
+        Counts {
+            reqs: self.reqs.saturating_sub(other.reqs),
+            fails: self.fails.saturating_sub(other.fails),
+            timed: self.timed.saturating_sub(other.timed),
+            latency_us: self.latency_us.saturating_sub(other.latency_us),
+        }
+    }
+
+    fn failure_rate(&self) -> f64 {
+        if self.reqs == 0 {
+            return 0.0;
+        }
+        self.fails as f64 / self.reqs as f64
+    }
+
+    fn mean_latency(&self) -> Option<Duration> {
+        let mean = self.latency_us.checked_div(self.timed)?;
+        Some(Duration::from_micros(mean as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, Policy};
+
+    #[test]
+    fn trips_on_regression() {
+        let clock = ManualClock::new();
+        let policy = BaselineRegression::builder(Duration::from_secs(10), Duration::from_secs(60))
+            .with_failure_rate_increase(0.2)
+            .with_latency_ratio(2.0)
+            .with_min_requests(5)
+            .with_clock(clock.clone())
+            .build()
+            .unwrap();
+        let record = |failed: bool, ms: u64| {
+            policy.record_latency(Duration::from_millis(ms));
+            if failed {
+                policy.record_failure();
+            } else {
+                policy.record_success();
+            }
+        };
+
+        // a baseline with a 10% failure rate, taking 100ms on average.
+        for i in 0..10 {
+            record(i == 0, 100);
+        }
+        clock.advance(Duration::from_secs(20));
+
+        // 20% more failures than the baseline isn't a regression...
+        for i in 0..10 {
+            record(i < 3, 150);
+        }
+        assert!(!policy.is_punished());
+
+        // ...but more than that is.
+        record(true, 150);
+        assert!(matches!(
+            policy.punish_reason(),
+            Some(TripReason::FailureRateRegression { samples: 11, .. })
+        ));
+
+        // a recent window which is more than twice as slow as everything
+        // before it also regresses.
+        clock.advance(Duration::from_secs(20));
+        for _ in 0..5 {
+            record(false, 400);
+        }
+        assert_eq!(
+            Some(TripReason::LatencyRegression {
+                recent: Duration::from_millis(400),
+                baseline: Duration::from_micros((1000 + 1650) * 1000 / 21),
+                samples: 5,
+            }),
+            policy.punish_reason()
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use super::{Outcome, PolicySnapshot, TripReason};
+use crate::{
+    clock::{self, Clock, SharedClock},
+    error::PolicyError,
+};
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+/// A [`Policy`](super::Policy) which buffers the outcomes recorded on each
+/// thread, and records them with another policy in batches.
+///
+/// When many threads share a breaker, every outcome they record touches the
+/// same counters, and the contention on those counters can come to dominate
+/// the cost of passing requests through the breaker. This policy counts the
+/// outcomes recorded on each thread locally, and flushes them into the
+/// wrapped policy once `max_buffered` have been buffered on that thread, or
+/// once the oldest has been buffered for `flush_interval`. The wrapped
+/// policy therefore sees each thread's outcomes a little late, in exchange
+/// for far less contention.
+///
+/// A thread's buffer is also flushed whenever the policy is asked whether to
+/// punish the endpoint on that thread, and when the thread exits. Outcomes
+/// buffered on a thread which has stopped recording outcomes, but hasn't
+/// exited, aren't flushed until it records another.
+///
+/// The order of the outcomes in each batch isn't preserved: successes are
+/// recorded before failures. Policies which count consecutive failures may
+/// therefore trip slightly sooner than they would otherwise.
+pub struct Buffered<P>(Arc<Inner<P>>);
+
+struct Inner<P> {
+    policy: P,
+    max_buffered: usize,
+    flush_interval: Duration,
+    clock: SharedClock,
+}
+
+/// The outcomes buffered on one thread for one [`Buffered`] policy.
+struct Buffer {
+    policy: Weak<dyn Flush>,
+    successes: usize,
+    failures: usize,
+    since: Instant,
+}
+
+/// The buffers of every [`Buffered`] policy used on a thread, which are
+/// flushed when the thread exits.
+#[derive(Default)]
+struct Buffers(Vec<Buffer>);
+
+trait Flush {
+    fn flush(&self, successes: usize, failures: usize);
+}
+
+thread_local! {
+    static BUFFERS: RefCell<Buffers> = RefCell::new(Buffers::default());
+}
+
+impl<P: super::Policy + 'static> Buffered<P> {
+    /// Returns a new `Buffered` policy which records outcomes with `policy`
+    /// once `max_buffered` have been buffered on a thread, or once they've
+    /// been buffered for `flush_interval`.
+    ///
+    /// # Panics
+    ///
+    /// If `max_buffered` is 0. Use [`Buffered::try_new`] to handle an
+    /// invalid `max_buffered` without panicking.
+    pub fn new(policy: P, max_buffered: usize, flush_interval: Duration) -> Self {
+        match Self::try_new(policy, max_buffered, flush_interval) {
+            Ok(policy) => policy,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a new `Buffered` policy which records outcomes with `policy`
+    /// once `max_buffered` have been buffered on a thread, or once they've
+    /// been buffered for `flush_interval`.
+    ///
+    /// # Errors
+    ///
+    /// If `max_buffered` is 0.
+    pub fn try_new(
+        policy: P,
+        max_buffered: usize,
+        flush_interval: Duration,
+    ) -> Result<Self, PolicyError> {
+        if max_buffered == 0 {
+            return Err(PolicyError::Zero {
+                parameter: "maximum buffered outcomes",
+            });
+        }
+        Ok(Buffered(Arc::new(Inner {
+            policy,
+            max_buffered,
+            flush_interval,
+            clock: clock::default(),
+        })))
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    pub fn with_clock(self, clock: impl Clock) -> Self
+    where
+        P: Clone,
+    {
+        Buffered(Arc::new(Inner {
+            policy: self.0.policy.clone(),
+            max_buffered: self.0.max_buffered,
+            flush_interval: self.0.flush_interval,
+            clock: Arc::new(clock),
+        }))
+    }
+
+    /// Returns a reference to the wrapped policy.
+    pub fn get_ref(&self) -> &P {
+        &self.0.policy
+    }
+
+    /// Records the outcomes buffered on this thread with the wrapped policy.
+    pub fn flush(&self) {
+        if let Some((successes, failures)) = self.with_buffer(|buffer| Some(buffer.take())) {
+            self.0.flush(successes, failures);
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let max_buffered = self.0.max_buffered;
+        let flush_interval = self.0.flush_interval;
+        let now = self.0.clock.now();
+        let flushed = self.with_buffer(|buffer| {
+            if buffer.successes + buffer.failures == 0 {
+                buffer.since = now;
+            }
+            if success {
+                buffer.successes += 1;
+            } else {
+                buffer.failures += 1;
+            }
+            let full = buffer.successes + buffer.failures >= max_buffered;
+            (full || now.saturating_duration_since(buffer.since) >= flush_interval)
+                .then(|| buffer.take())
+        });
+        if let Some((successes, failures)) = flushed {
+            self.0.flush(successes, failures);
+        }
+    }
+
+    /// Calls `f` with this thread's buffer for this policy, creating it if
+    /// it doesn't exist yet.
+    ///
+    /// Returns `None` if the thread is exiting.
+    fn with_buffer<T>(&self, f: impl FnOnce(&mut Buffer) -> Option<T>) -> Option<T> {
+        BUFFERS
+            .try_with(|buffers| {
+                let buffers = &mut buffers.borrow_mut().0;
+                let this = Arc::as_ptr(&self.0) as *const ();
+                // a buffer's `Weak` keeps its policy's allocation alive, so
+                // no other policy can have the same address.
+                let i = match buffers
+                    .iter()
+                    .position(|buffer| buffer.policy.as_ptr() as *const () == this)
+                {
+                    Some(i) => i,
+                    None => {
+                        // drop the buffers of policies which no longer exist.
+                        buffers.retain(|buffer| buffer.policy.strong_count() > 0);
+                        let policy: Arc<dyn Flush> = self.0.clone();
+                        buffers.push(Buffer {
+                            policy: Arc::downgrade(&policy),
+                            successes: 0,
+                            failures: 0,
+                            since: self.0.clock.now(),
+                        });
+                        buffers.len() - 1
+                    }
+                };
+                f(&mut buffers[i])
+            })
+            .ok()
+            .flatten()
+    }
+}
+
+impl<P: super::Policy + 'static> super::Policy for Buffered<P> {
+    fn record_success(&self) {
+        self.record(true);
+    }
+
+    fn record_failure(&self) {
+        self.record(false);
+    }
+
+    fn record_failure_in(&self, category: &'static str) {
+        // only plain outcomes are buffered.
+        self.0.policy.record_failure_in(category);
+    }
+
+    fn record_ready_delay(&self, delay: Duration) {
+        self.0.policy.record_ready_delay(delay);
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        self.0.policy.record_latency(latency);
+    }
+
+    fn measures_latency(&self) -> bool {
+        self.0.policy.measures_latency()
+    }
+
+    fn record_in_flight(&self, in_flight: usize) {
+        self.0.policy.record_in_flight(in_flight);
+    }
+
+    fn is_punished(&self) -> bool {
+        self.flush();
+        self.0.policy.is_punished()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        self.flush();
+        self.0.policy.punish_reason()
+    }
+
+    fn severity(&self) -> f64 {
+        self.flush();
+        self.0.policy.severity()
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        self.0.policy.snapshot()
+    }
+
+    fn warm_start(&self, outcomes: &[Outcome]) {
+        self.0.policy.warm_start(outcomes);
+    }
+
+    fn reset(&self) {
+        // outcomes buffered on other threads are flushed into the reset
+        // policy, but there's little enough of them not to matter.
+        self.with_buffer(|buffer| Some(buffer.take()));
+        self.0.policy.reset();
+    }
+}
+
+impl<P> Clone for Buffered<P> {
+    fn clone(&self) -> Self {
+        Buffered(self.0.clone())
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for Buffered<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Buffered")
+            .field("policy", &self.0.policy)
+            .field("max_buffered", &self.0.max_buffered)
+            .field("flush_interval", &self.0.flush_interval)
+            .finish()
+    }
+}
+
+impl<P: super::Policy> Flush for Inner<P> {
+    fn flush(&self, successes: usize, failures: usize) {
+        for _ in 0..successes {
+            self.policy.record_success();
+        }
+        for _ in 0..failures {
+            self.policy.record_failure();
+        }
+    }
+}
+
+// === impl Buffer ===
+
+impl Buffer {
+    fn take(&mut self) -> (usize, usize) {
+        (
+            std::mem::take(&mut self.successes),
+            std::mem::take(&mut self.failures),
+        )
+    }
+}
+
+// === impl Buffers ===
+
+impl Drop for Buffers {
+    fn drop(&mut self) {
+        // the thread is exiting, so flush whatever it buffered.
+        for buffer in &mut self.0 {
+            if let Some(policy) = buffer.policy.upgrade() {
+                let (successes, failures) = buffer.take();
+                policy.flush(successes, failures);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::ManualClock,
+        policy::{ConsecutiveFailures, Policy, SlidingFailureRate},
+    };
+
+    #[test]
+    fn flushes_in_batches() {
+        let clock = ManualClock::new();
+        let inner = SlidingFailureRate::new(Duration::from_secs(60), 0.5).with_clock(clock.clone());
+        let policy =
+            Buffered::new(inner.clone(), 3, Duration::from_secs(1)).with_clock(clock.clone());
+
+        // outcomes are flushed once enough are buffered...
+        policy.record_success();
+        policy.record_failure();
+        assert_eq!(Some(0), inner.snapshot().requests);
+        policy.record_failure();
+        assert_eq!(Some(3), inner.snapshot().requests);
+
+        // ...or once they've been buffered for long enough...
+        policy.record_failure();
+        clock.advance(Duration::from_secs(1));
+        policy.record_failure();
+        assert_eq!(Some(5), inner.snapshot().requests);
+
+        // ...or when the policy is asked whether to punish the endpoint.
+        policy.record_failure();
+        assert!(policy.is_punished());
+        assert_eq!(Some(6), inner.snapshot().requests);
+
+        // threads flush their buffers when they exit.
+        let inner = ConsecutiveFailures::new(2);
+        let policy = Buffered::new(inner.clone(), 10, Duration::from_secs(60));
+        std::thread::spawn({
+            let policy = policy.clone();
+            move || {
+                policy.record_failure();
+                policy.record_failure();
+            }
+        })
+        .join()
+        .unwrap();
+        assert!(inner.is_punished());
+    }
+}
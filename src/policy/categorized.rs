@@ -0,0 +1,377 @@
+use super::{PolicySnapshot, TripReason};
+use crate::{
+    clock::{self, Clock, SharedClock},
+    error::PolicyError,
+    trace::trace,
+    window_counter::WindowedCounter,
+};
+use std::{fmt, sync::Arc, time::Duration};
+
+/// A [`Policy`](super::Policy) which tracks the failure rate of each category
+/// of failure separately, and punishes an endpoint if any category's failure
+/// rate exceeds that category's threshold.
+///
+/// Different kinds of failure often warrant different thresholds: a handful
+/// of connection failures may mean an endpoint is unreachable, while a
+/// service which is throttling some requests is still serving the rest. An
+/// [error classifier](crate::Config::with_error_classifier) assigns each
+/// error a category by returning [`ErrorClass::Category`]:
+///
+/// ```
+/// use std::time::Duration;
+/// use tower_breaker::{classify::{self, ErrorClass}, policy::CategorizedFailureRate, Config};
+///
+/// let policy = CategorizedFailureRate::builder(Duration::from_secs(30))
+///     .with_category("transport", 0.05)
+///     .with_category(CategorizedFailureRate::UNCATEGORIZED, 0.25)
+///     .build()
+///     .expect("policy settings should be valid");
+/// let config = Config::new(policy, Duration::from_secs(5)).with_error_classifier(|error| {
+///     if classify::is_connection_error(error) {
+///         ErrorClass::Category("transport")
+///     } else {
+///         ErrorClass::Failure
+///     }
+/// });
+/// ```
+///
+/// Each category's failure rate is the number of failures in that category
+/// divided by the total number of requests over the window. Failures
+/// recorded without a category are counted in the
+/// [`UNCATEGORIZED`](Self::UNCATEGORIZED) category, and failures in a
+/// category without a threshold only count towards the total number of
+/// requests.
+#[derive(Clone)]
+pub struct CategorizedFailureRate(Arc<Inner>);
+
+/// Builds a [`CategorizedFailureRate`] policy.
+///
+/// This is returned by [`CategorizedFailureRate::builder`].
+#[derive(Clone, Debug)]
+pub struct CategorizedFailureRateBuilder {
+    window: Duration,
+    min_requests: usize,
+    categories: Vec<(&'static str, f64)>,
+    clock: SharedClock,
+}
+
+struct Inner {
+    window: Duration,
+    min_requests: usize,
+    reqs: WindowedCounter,
+    categories: Vec<Category>,
+}
+
+struct Category {
+    name: &'static str,
+    max_rate: f64,
+    fails: WindowedCounter,
+}
+
+impl CategorizedFailureRate {
+    /// The category of failures recorded without a category.
+    pub const UNCATEGORIZED: &'static str = "uncategorized";
+
+    /// Returns a builder for a `CategorizedFailureRate` policy, tracking each
+    /// category's failure rate over `window`.
+    pub fn builder(window: Duration) -> CategorizedFailureRateBuilder {
+        CategorizedFailureRateBuilder {
+            window,
+            min_requests: 1,
+            categories: Vec::new(),
+            clock: clock::default(),
+        }
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// Any requests already recorded by this policy are discarded, so this
+    /// should be called when the policy is constructed. When using a custom
+    /// clock, the same clock should also be provided to the breaker using
+    /// [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        let categories = self
+            .0
+            .categories
+            .iter()
+            .map(|category| (category.name, category.max_rate))
+            .collect();
+        Self::build(
+            self.0.window,
+            self.0.min_requests,
+            categories,
+            Arc::new(clock),
+        )
+    }
+
+    /// Returns the failure rate of `category` over the current window, or
+    /// `None` if the policy has no threshold for that category.
+    pub fn failure_rate(&self, category: &str) -> Option<f64> {
+        let category = self.0.category(category)?;
+        Some(rate(self.0.reqs.sum(), category.fails.sum()))
+    }
+
+    fn build(
+        window: Duration,
+        min_requests: usize,
+        categories: Vec<(&'static str, f64)>,
+        clock: SharedClock,
+    ) -> Self {
+        CategorizedFailureRate(Arc::new(Inner {
+            window,
+            min_requests,
+            reqs: WindowedCounter::new(window, clock.clone()),
+            categories: categories
+                .into_iter()
+                .map(|(name, max_rate)| Category {
+                    name,
+                    max_rate,
+                    fails: WindowedCounter::new(window, clock.clone()),
+                })
+                .collect(),
+        }))
+    }
+}
+
+// === impl CategorizedFailureRateBuilder ===
+
+impl CategorizedFailureRateBuilder {
+    /// Punishes an endpoint if the failure rate of `category` exceeds
+    /// `max_rate`.
+    ///
+    /// If the category already has a threshold, it's replaced.
+    pub fn with_category(mut self, category: &'static str, max_rate: f64) -> Self {
+        self.categories.retain(|&(name, _)| name != category);
+        self.categories.push((category, max_rate));
+        self
+    }
+
+    /// Sets the minimum number of requests which must be in the window
+    /// before the policy punishes an endpoint. By default, this is 1.
+    ///
+    /// An endpoint is never punished while the window is empty, even if
+    /// this is 0.
+    pub fn with_min_requests(mut self, min_requests: usize) -> Self {
+        self.min_requests = min_requests;
+        self
+    }
+
+    /// Reads the current time from the provided [`Clock`] rather than from
+    /// Tokio.
+    ///
+    /// When using a custom clock, the same clock should also be provided to
+    /// the breaker using [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the configured policy.
+    ///
+    /// # Errors
+    ///
+    /// - If the window is zero, or no categories have a threshold.
+    /// - If any category's maximum failure rate is less than 0, or is 1 or
+    ///   greater, in which case that category could never punish an
+    ///   endpoint.
+    pub fn build(self) -> Result<CategorizedFailureRate, PolicyError> {
+        if self.window.is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "window",
+            });
+        }
+        if self.categories.is_empty() {
+            return Err(PolicyError::Zero {
+                parameter: "number of categories",
+            });
+        }
+        if let Some(&(_, max_rate)) = self
+            .categories
+            .iter()
+            .find(|(_, max_rate)| !(0.0..1.0).contains(max_rate))
+        {
+            return Err(PolicyError::OutOfRange {
+                parameter: "maximum failure rate",
+                value: max_rate,
+                range: "[0, 1)",
+            });
+        }
+        Ok(CategorizedFailureRate::build(
+            self.window,
+            self.min_requests,
+            self.categories,
+            self.clock,
+        ))
+    }
+}
+
+impl super::Policy for CategorizedFailureRate {
+    fn record_success(&self) {
+        self.0.reqs.add(1);
+    }
+
+    fn record_failure(&self) {
+        self.record_failure_in(Self::UNCATEGORIZED);
+    }
+
+    fn record_failure_in(&self, category: &'static str) {
+        self.0.reqs.add(1);
+        if let Some(category) = self.0.category(category) {
+            category.fails.add(1);
+        }
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let reqs = self.0.reqs.sum();
+        if reqs == 0 || reqs < self.0.min_requests {
+            return None;
+        }
+        self.0.categories.iter().find_map(|category| {
+            let rate = rate(reqs, category.fails.sum());
+            if rate <= category.max_rate {
+                return None;
+            }
+            trace!(
+                category = category.name,
+                failure_rate = rate,
+                max_rate = category.max_rate,
+                "Category failure rate exceeds max; punishing endpoint!"
+            );
+            Some(TripReason::CategoryFailureRate {
+                category: category.name,
+                rate,
+                threshold: category.max_rate,
+                samples: reqs,
+            })
+        })
+    }
+
+    fn severity(&self) -> f64 {
+        let reqs = self.0.reqs.sum();
+        if reqs == 0 || reqs < self.0.min_requests {
+            return 0.0;
+        }
+        self.0
+            .categories
+            .iter()
+            .map(|category| {
+                let fails = category.fails.sum();
+                if fails == 0 {
+                    return 0.0;
+                }
+                (rate(reqs, fails) / category.max_rate).min(1.0)
+            })
+            .fold(0.0, f64::max)
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        PolicySnapshot {
+            requests: Some(self.0.reqs.sum()),
+            failures: Some(
+                self.0
+                    .categories
+                    .iter()
+                    .map(|category| category.fails.sum())
+                    .sum(),
+            ),
+            window: Some(self.0.window),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    fn reset(&self) {
+        self.0.reqs.reset();
+        for category in &self.0.categories {
+            category.fails.reset();
+        }
+    }
+}
+
+impl fmt::Debug for CategorizedFailureRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CategorizedFailureRate")
+            .field("reqs", &self.0.reqs.sum())
+            .field("categories", &self.0.categories)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Category")
+            .field("name", &self.name)
+            .field("max_rate", &self.max_rate)
+            .field("fails", &self.fails.sum())
+            .finish()
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    fn category(&self, name: &str) -> Option<&Category> {
+        self.categories
+            .iter()
+            .find(|category| category.name == name)
+    }
+}
+
+/// Returns the failure rate of `fails` failures in `reqs` requests, or 0 if
+/// there were no requests.
+fn rate(reqs: usize, fails: usize) -> f64 {
+    if reqs == 0 {
+        return 0.0;
+    }
+    fails as f64 / reqs as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, Policy};
+
+    #[test]
+    fn trips_on_any_category() {
+        let policy = CategorizedFailureRate::builder(Duration::from_secs(10))
+            .with_category("transport", 0.1)
+            .with_category("throttled", 0.5)
+            .with_clock(ManualClock::new())
+            .build()
+            .unwrap();
+        for _ in 0..6 {
+            policy.record_success();
+        }
+
+        // failures in other categories only count as requests.
+        policy.record_failure();
+        policy.record_failure_in("server");
+        for _ in 0..2 {
+            policy.record_failure_in("throttled");
+        }
+        assert_eq!(Some(0.2), policy.failure_rate("throttled"));
+        assert!(!policy.is_punished());
+
+        // a fifth of the requests failing to connect trips the breaker, even
+        // though far fewer are throttled than that category's threshold.
+        for _ in 0..2 {
+            policy.record_failure_in("transport");
+        }
+        assert_eq!(
+            Some(TripReason::CategoryFailureRate {
+                category: "transport",
+                rate: 2.0 / 12.0,
+                threshold: 0.1,
+                samples: 12,
+            }),
+            policy.punish_reason()
+        );
+
+        policy.reset();
+        assert!(!policy.is_punished());
+    }
+}
@@ -0,0 +1,453 @@
+use super::{PolicySnapshot, TripReason};
+use crate::{
+    clock::{self, Clock, SharedClock},
+    error::PolicyError,
+    store::StateStore,
+    timer::{self, Timer},
+    trace::{debug, trace},
+    window_counter::WindowedCounter,
+};
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint if the failure rate
+/// observed by every replica in a cluster exceeds a threshold.
+///
+/// A replica which sends few requests to an endpoint can't tell whether the
+/// endpoint is failing from its own requests alone. This policy shares each
+/// replica's outcomes through a [`StateStore`], and punishes the endpoint
+/// based on the outcomes observed across the cluster, combined with any
+/// outcomes this replica has observed since it last
+/// [synced](ClusterFailureRate::sync) with the store. If the policy hasn't
+/// synced with the store for a whole window (such as when the store is
+/// unavailable), it falls back to the outcomes observed by this replica.
+///
+/// Outcomes are shared in buckets of one fifth of the window, keyed by the
+/// time at which each bucket started, so the replicas' clocks should be
+/// reasonably well synchronized.
+#[derive(Clone)]
+pub struct ClusterFailureRate(Arc<Inner>);
+
+struct Inner {
+    max_rate: f64,
+    min_requests: usize,
+    window: Duration,
+    clock: SharedClock,
+    timer: Arc<dyn Timer>,
+    reqs: WindowedCounter,
+    fails: WindowedCounter,
+    /// Outcomes which haven't yet been added to the store.
+    unsynced_reqs: AtomicUsize,
+    unsynced_fails: AtomicUsize,
+    cluster: Mutex<Cluster>,
+}
+
+#[derive(Debug, Default)]
+struct Cluster {
+    /// The requests and failures observed across the cluster as of the last
+    /// sync, and when they were fetched.
+    counts: Option<(usize, usize, Instant)>,
+    /// Buckets which started before this bucket are ignored, because they
+    /// started before the policy was reset.
+    min_bucket: u64,
+}
+
+/// Outcomes taken from a policy's unsynced counts to be added to the store,
+/// which are returned to the policy unless they were added.
+struct Unsynced<'a> {
+    inner: &'a Inner,
+    reqs: usize,
+    fails: usize,
+}
+
+/// The number of buckets in the window.
+const BUCKETS: u32 = 5;
+
+impl ClusterFailureRate {
+    /// Returns a new `ClusterFailureRate` policy which punishes an endpoint if
+    /// the failure rate across the cluster over `window` exceeds `max_rate`.
+    ///
+    /// # Panics
+    ///
+    /// If `max_rate` is less than 0, or is 1 or greater, or if `window` is
+    /// zero. Use [`ClusterFailureRate::try_new`] to handle invalid settings
+    /// without panicking.
+    pub fn new(window: Duration, max_rate: f64) -> Self {
+        match Self::try_new(window, max_rate) {
+            Ok(policy) => policy,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a new `ClusterFailureRate` policy which punishes an endpoint if
+    /// the failure rate across the cluster over `window` exceeds `max_rate`.
+    ///
+    /// # Errors
+    ///
+    /// If `max_rate` is less than 0, or is 1 or greater, in which case the
+    /// policy could never punish an endpoint, or if `window` is zero.
+    pub fn try_new(window: Duration, max_rate: f64) -> Result<Self, PolicyError> {
+        if !(0.0..1.0).contains(&max_rate) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "maximum failure rate",
+                value: max_rate,
+                range: "[0, 1)",
+            });
+        }
+        if window.is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "window",
+            });
+        }
+        Ok(Self::build(
+            window,
+            max_rate,
+            1,
+            clock::default(),
+            timer::default(),
+        ))
+    }
+
+    /// Sets the minimum number of requests which must have been observed
+    /// before the policy punishes an endpoint. By default, this is 1.
+    pub fn with_min_requests(self, min_requests: usize) -> Self {
+        let inner = &self.0;
+        Self::build(
+            inner.window,
+            inner.max_rate,
+            min_requests,
+            inner.clock.clone(),
+            inner.timer.clone(),
+        )
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// Any requests already recorded by this policy are discarded, so this
+    /// should be called when the policy is constructed.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        let inner = &self.0;
+        Self::build(
+            inner.window,
+            inner.max_rate,
+            inner.min_requests,
+            Arc::new(clock),
+            inner.timer.clone(),
+        )
+    }
+
+    /// Returns this policy, waiting between [syncs](Self::sync) using the
+    /// provided [`Timer`] rather than Tokio's.
+    pub fn with_timer(self, timer: impl Timer) -> Self {
+        let inner = &self.0;
+        Self::build(
+            inner.window,
+            inner.max_rate,
+            inner.min_requests,
+            inner.clock.clone(),
+            Arc::new(timer),
+        )
+    }
+
+    fn build(
+        window: Duration,
+        max_rate: f64,
+        min_requests: usize,
+        clock: SharedClock,
+        timer: Arc<dyn Timer>,
+    ) -> Self {
+        ClusterFailureRate(Arc::new(Inner {
+            max_rate,
+            min_requests,
+            window,
+            reqs: WindowedCounter::new(window, clock.clone()),
+            fails: WindowedCounter::new(window, clock.clone()),
+            clock,
+            timer,
+            unsynced_reqs: AtomicUsize::new(0),
+            unsynced_fails: AtomicUsize::new(0),
+            cluster: Mutex::new(Cluster::default()),
+        }))
+    }
+
+    /// Returns a future which, every `interval`, adds the outcomes this
+    /// replica has observed to `store` under keys prefixed with `key`, and
+    /// fetches the outcomes observed across the cluster.
+    ///
+    /// Every replica should sync using the same `key`. The returned future
+    /// should be spawned (e.g. with `tokio::spawn`), and completes once the
+    /// policy and its clones have been dropped. Errors talking to the store
+    /// are logged.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn sync(
+        &self,
+        store: impl StateStore,
+        key: impl Into<String>,
+        interval: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let inner = Arc::downgrade(&self.0);
+        let timer = self.0.timer.clone();
+        let key = key.into();
+        async move {
+            loop {
+                timer.sleep(interval).await;
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                if let Err(error) = inner.sync(&store, &key).await {
+                    debug!(key = %key, %error, "failed to sync cluster failure rate");
+                }
+            }
+        }
+    }
+}
+
+impl super::Policy for ClusterFailureRate {
+    fn record_success(&self) {
+        self.0.reqs.add(1);
+        self.0.unsynced_reqs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_failure(&self) {
+        self.0.reqs.add(1);
+        self.0.fails.add(1);
+        self.0.unsynced_reqs.fetch_add(1, Ordering::Relaxed);
+        self.0.unsynced_fails.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let (reqs, fails) = self.0.counts();
+        if reqs == 0 || reqs < self.0.min_requests {
+            return None;
+        }
+        let rate = fails as f64 / reqs as f64;
+        if rate > self.0.max_rate {
+            trace!(
+                failure_rate = rate,
+                max_rate = self.0.max_rate,
+                requests = reqs,
+                "Cluster failure rate exceeds max; punishing endpoint!"
+            );
+            return Some(TripReason::FailureRate {
+                rate,
+                threshold: self.0.max_rate,
+                samples: reqs,
+            });
+        }
+        None
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        let (requests, failures) = self.0.counts();
+        PolicySnapshot {
+            requests: Some(requests),
+            failures: Some(failures),
+            failure_rate: Some(failures as f64 / requests as f64).filter(|rate| rate.is_finite()),
+            max_failure_rate: Some(self.0.max_rate),
+            window: Some(self.0.window),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    fn reset(&self) {
+        self.0.reqs.reset();
+        self.0.fails.reset();
+        self.0.unsynced_reqs.store(0, Ordering::Relaxed);
+        self.0.unsynced_fails.store(0, Ordering::Relaxed);
+        // the cluster's earlier outcomes would trip the breaker again as soon
+        // as it closes, so only count outcomes from the next bucket onwards.
+        let mut cluster = self.0.cluster.lock().unwrap();
+        cluster.counts = None;
+        cluster.min_bucket = self.0.bucket(SystemTime::now()) + 1;
+    }
+}
+
+impl fmt::Debug for ClusterFailureRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (reqs, fails) = self.0.counts();
+        f.debug_struct("ClusterFailureRate")
+            .field("max_rate", &self.0.max_rate)
+            .field("min_requests", &self.0.min_requests)
+            .field("reqs", &reqs)
+            .field("fails", &fails)
+            .finish()
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    /// Returns the requests and failures the policy's decision is based on.
+    fn counts(&self) -> (usize, usize) {
+        let cluster = self.cluster.lock().unwrap();
+        match cluster.counts {
+            Some((reqs, fails, fetched_at))
+                if self.clock.now().saturating_duration_since(fetched_at) < self.window =>
+            {
+                (
+                    reqs + self.unsynced_reqs.load(Ordering::Relaxed),
+                    fails + self.unsynced_fails.load(Ordering::Relaxed),
+                )
+            }
+            _ => (self.reqs.sum(), self.fails.sum()),
+        }
+    }
+
+    fn bucket_len(&self) -> Duration {
+        (self.window / BUCKETS).max(Duration::from_millis(1))
+    }
+
+    fn bucket(&self, at: SystemTime) -> u64 {
+        let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        (since_epoch.as_millis() / self.bucket_len().as_millis()) as u64
+    }
+
+    async fn sync(&self, store: &impl StateStore, key: &str) -> Result<(), crate::BoxError> {
+        let bucket = self.bucket(SystemTime::now());
+        let mut unsynced = Unsynced::take(self);
+        if unsynced.reqs > 0 {
+            let ttl = self.window + self.bucket_len();
+            store
+                .add_window(
+                    &format!("{key}/{bucket}"),
+                    unsynced.reqs,
+                    unsynced.fails,
+                    ttl,
+                )
+                .await?;
+        }
+        unsynced.synced();
+
+        let min_bucket = self.cluster.lock().unwrap().min_bucket;
+        let first = (bucket + 1)
+            .saturating_sub(u64::from(BUCKETS))
+            .max(min_bucket);
+        let (mut cluster_reqs, mut cluster_fails) = (0, 0);
+        for bucket in first..=bucket {
+            if let Some(state) = store.get(&format!("{key}/{bucket}")).await? {
+                cluster_reqs += state.requests.unwrap_or(0);
+                cluster_fails += state.failures.unwrap_or(0);
+            }
+        }
+
+        let mut cluster = self.cluster.lock().unwrap();
+        // if the policy was reset while fetching, the counts are stale.
+        if cluster.min_bucket == min_bucket {
+            cluster.counts = Some((cluster_reqs, cluster_fails, self.clock.now()));
+        }
+        Ok(())
+    }
+}
+
+// === impl Unsynced ===
+
+impl<'a> Unsynced<'a> {
+    fn take(inner: &'a Inner) -> Self {
+        Unsynced {
+            inner,
+            reqs: inner.unsynced_reqs.swap(0, Ordering::Relaxed),
+            fails: inner.unsynced_fails.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    fn synced(&mut self) {
+        self.reqs = 0;
+        self.fails = 0;
+    }
+}
+
+impl Drop for Unsynced<'_> {
+    fn drop(&mut self) {
+        // adding the outcomes to the store failed, or the sync was dropped
+        // before they were added, so try again next time.
+        self.inner
+            .unsynced_reqs
+            .fetch_add(self.reqs, Ordering::Relaxed);
+        self.inner
+            .unsynced_fails
+            .fetch_add(self.fails, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::{MemoryStore, StoreFuture, StoredState},
+        Policy,
+    };
+
+    #[tokio::test]
+    async fn combines_cluster_outcomes() {
+        let store = MemoryStore::new();
+        let window = Duration::from_secs(60);
+        let busy = ClusterFailureRate::new(window, 0.5);
+        let quiet = ClusterFailureRate::new(window, 0.5).with_min_requests(5);
+        for _ in 0..8 {
+            busy.record_failure();
+        }
+        busy.record_success();
+        busy.record_success();
+        quiet.record_failure();
+
+        // on its own, the quiet replica hasn't seen enough requests.
+        assert!(!quiet.is_punished());
+
+        busy.0.sync(&store, "users-api").await.unwrap();
+        quiet.0.sync(&store, "users-api").await.unwrap();
+        let snapshot = quiet.snapshot();
+        assert_eq!(Some(11), snapshot.requests);
+        assert_eq!(Some(9), snapshot.failures);
+        assert!(quiet.is_punished());
+
+        // outcomes recorded since the last sync are counted too.
+        for _ in 0..20 {
+            quiet.record_success();
+        }
+        assert!(!quiet.is_punished());
+
+        // once reset, the cluster's earlier outcomes are ignored.
+        quiet.reset();
+        quiet.0.sync(&store, "users-api").await.unwrap();
+        assert_eq!(Some(0), quiet.snapshot().requests);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_syncs() {
+        #[derive(Debug)]
+        struct Unavailable;
+
+        impl StateStore for Unavailable {
+            fn get<'a>(&'a self, _: &'a str) -> StoreFuture<'a, Option<StoredState>> {
+                Box::pin(async { Err("unavailable".into()) })
+            }
+
+            fn set<'a>(&'a self, _: &'a str, _: StoredState, _: Duration) -> StoreFuture<'a, ()> {
+                Box::pin(async { Err("unavailable".into()) })
+            }
+        }
+
+        let policy = ClusterFailureRate::new(Duration::from_secs(60), 0.5);
+        policy.record_failure();
+        policy.record_success();
+        assert!(policy.0.sync(&Unavailable, "users-api").await.is_err());
+
+        // the outcomes are added once the store is available again.
+        let store = MemoryStore::new();
+        policy.0.sync(&store, "users-api").await.unwrap();
+        let snapshot = policy.snapshot();
+        assert_eq!((Some(2), Some(1)), (snapshot.requests, snapshot.failures));
+    }
+}
@@ -0,0 +1,148 @@
+use super::{PolicySnapshot, TripReason};
+use crate::{error::PolicyError, trace::trace};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint after a number of
+/// consecutive failures.
+///
+/// Any successful request resets the count of consecutive failures.
+#[derive(Clone)]
+pub struct ConsecutiveFailures(Arc<Inner>);
+
+struct Inner {
+    /// The number of consecutive failures after which the policy punishes
+    /// the endpoint.
+    max_failures: usize,
+    failures: AtomicUsize,
+}
+
+impl ConsecutiveFailures {
+    /// Returns a new `ConsecutiveFailures` policy which punishes an endpoint
+    /// once `max_failures` requests in a row have failed.
+    ///
+    /// # Panics
+    ///
+    /// If `max_failures` is 0. Use [`ConsecutiveFailures::try_new`] to
+    /// handle an invalid `max_failures` without panicking.
+    pub fn new(max_failures: usize) -> Self {
+        match Self::try_new(max_failures) {
+            Ok(policy) => policy,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a new `ConsecutiveFailures` policy which punishes an endpoint
+    /// once `max_failures` requests in a row have failed.
+    ///
+    /// # Errors
+    ///
+    /// If `max_failures` is 0.
+    pub fn try_new(max_failures: usize) -> Result<Self, PolicyError> {
+        if max_failures == 0 {
+            return Err(PolicyError::Zero {
+                parameter: "maximum consecutive failures",
+            });
+        }
+        Ok(ConsecutiveFailures(Arc::new(Inner {
+            max_failures,
+            failures: AtomicUsize::new(0),
+        })))
+    }
+}
+
+impl super::Policy for ConsecutiveFailures {
+    fn record_success(&self) {
+        self.0.failures.store(0, Ordering::Release);
+    }
+
+    fn record_failure(&self) {
+        self.0.failures.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let failures = self.0.failures.load(Ordering::Acquire);
+        if failures >= self.0.max_failures {
+            trace!(
+                failures,
+                max_failures = self.0.max_failures,
+                "Too many consecutive failures; punishing endpoint!"
+            );
+            return Some(TripReason::ConsecutiveFailures {
+                failures,
+                threshold: self.0.max_failures,
+            });
+        }
+        None
+    }
+
+    fn severity(&self) -> f64 {
+        let failures = self.0.failures.load(Ordering::Acquire);
+        (failures as f64 / self.0.max_failures as f64).min(1.0)
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        PolicySnapshot {
+            consecutive_failures: Some(self.0.failures.load(Ordering::Acquire)),
+            max_consecutive_failures: Some(self.0.max_failures),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    fn reset(&self) {
+        self.0.failures.store(0, Ordering::Release);
+    }
+}
+
+impl fmt::Debug for ConsecutiveFailures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsecutiveFailures")
+            .field("max_failures", &self.0.max_failures)
+            .field("failures", &self.0.failures.load(Ordering::Acquire))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+
+    #[test]
+    fn successes_reset_count() {
+        let policy = ConsecutiveFailures::new(2);
+        policy.record_failure();
+        policy.record_success();
+        policy.record_failure();
+        assert!(!policy.is_punished());
+
+        policy.record_failure();
+        assert_eq!(
+            Some(TripReason::ConsecutiveFailures {
+                failures: 2,
+                threshold: 2,
+            }),
+            policy.punish_reason()
+        );
+        let snapshot = policy.snapshot();
+        assert_eq!(Some(2), snapshot.consecutive_failures);
+        assert_eq!(Some(2), snapshot.max_consecutive_failures);
+
+        policy.reset();
+        assert!(!policy.is_punished());
+
+        assert_eq!(
+            "maximum consecutive failures must be greater than 0",
+            ConsecutiveFailures::try_new(0).unwrap_err().to_string()
+        );
+    }
+}
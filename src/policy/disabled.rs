@@ -0,0 +1,68 @@
+/// A [`Policy`](super::Policy) which never punishes an endpoint, turning a
+/// breaker into a transparent passthrough.
+///
+/// A breaker using this policy passes every request straight through to its
+/// inner service, without evaluating its circuit, recording outcomes, or
+/// entering a span. Since the check is made on the policy's type, it's
+/// compiled away entirely, so a library can wrap its services in a breaker
+/// unconditionally, and leave applications which don't want one to choose
+/// this policy:
+///
+/// ```
+/// use std::time::Duration;
+/// use tower_breaker::{policy::Disabled, CircuitBreaker, Config};
+///
+/// # let svc = tower::service_fn(|_: ()| async { Ok::<_, tower_breaker::BoxError>(()) });
+/// let breaker = CircuitBreaker::new(Config::new(Disabled, Duration::ZERO), svc);
+/// ```
+///
+/// A breaker which never evaluates its circuit can't be forced open by a
+/// [`Handle`](crate::Handle) either.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Disabled;
+
+impl super::Policy for Disabled {
+    fn record_success(&self) {}
+
+    fn record_failure(&self) {}
+
+    fn is_punished(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn is_disabled(&self) -> bool {
+        true
+    }
+
+    fn reset(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::CircuitOpen, BoxError, CircuitBreaker, Config};
+    use std::time::Duration;
+    use tower::{service_fn, Service, ServiceExt};
+
+    #[tokio::test]
+    async fn passes_requests_through() {
+        let svc = service_fn(|ok: bool| async move {
+            if ok {
+                Ok(())
+            } else {
+                Err(BoxError::from("failed"))
+            }
+        });
+        let mut breaker = CircuitBreaker::new(Config::new(Disabled, Duration::ZERO), svc);
+        let handle = breaker.handle();
+        handle.force_open();
+        for ok in [false, false, false, true] {
+            let result = breaker.ready().await.unwrap().call(ok).await;
+            assert_eq!(ok, result.is_ok());
+            if let Err(error) = result {
+                assert!(!error.is::<CircuitOpen>());
+            }
+        }
+    }
+}
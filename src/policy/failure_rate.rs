@@ -1,18 +1,47 @@
-use crate::window_counter::WindowedCounter;
-use std::sync::Arc;
-use tokio::time::Duration;
+use super::{Outcome, PolicySnapshot, TripReason};
+use crate::{
+    clock::{self, Clock, SharedClock},
+    error::PolicyError,
+    trace::trace,
+    window_counter::{WindowedCounter, NUM_BUCKETS},
+};
+use std::time::Duration;
+use std::{fmt, sync::Arc, time::SystemTime};
 
-#[derive(Clone, Debug)]
+/// A [`Policy`](super::Policy) which punishes an endpoint if its failure
+/// rate over a sliding window of time exceeds a threshold.
+///
+/// A policy with the default settings can be constructed with
+/// [`SlidingFailureRate::new`], or configured further with
+/// [`SlidingFailureRate::builder`].
+#[derive(Clone)]
 pub struct SlidingFailureRate(Arc<Inner>);
 
-#[derive(Debug)]
+/// Builds a [`SlidingFailureRate`] policy.
+///
+/// This is returned by [`SlidingFailureRate::builder`].
+#[derive(Clone, Debug)]
+pub struct SlidingFailureRateBuilder {
+    settings: Settings,
+    clock: SharedClock,
+}
+
 struct Inner {
-    /// The maximum allowable failure rate.
-    max_rate: f64,
+    settings: Settings,
     reqs: WindowedCounter,
     fails: WindowedCounter,
 }
 
+#[derive(Copy, Clone, Debug)]
+struct Settings {
+    window: Duration,
+    /// The maximum allowable failure rate.
+    max_rate: f64,
+    min_requests: usize,
+    buckets: usize,
+    failure_weight: f64,
+}
+
 impl SlidingFailureRate {
     /// Returns a new `SlidingFailureRate` policy over the given time `window`.
     /// The returned policy will punish an endpoint if its failure rate over
@@ -20,18 +49,194 @@ impl SlidingFailureRate {
     ///
     /// # Panics
     ///
-    /// If `max_rate` is less than 0 or greater than 1.
+    /// If `max_rate` is less than 0, or is 1 or greater, or if `window` is
+    /// zero. Use [`SlidingFailureRate::builder`] to handle invalid settings
+    /// without panicking.
     pub fn new(window: Duration, max_rate: f64) -> Self {
-        assert!(
-            (0.0..=0.1).contains(&max_rate),
-            "maximum failure rate ({max_rate}) must be in the range [0, 1] "
-        );
+        match Self::builder(window, max_rate).build() {
+            Ok(policy) => policy,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a builder for a `SlidingFailureRate` policy which punishes an
+    /// endpoint if its failure rate over `window` exceeds `max_rate`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use tower_breaker::policy::SlidingFailureRate;
+    ///
+    /// let policy = SlidingFailureRate::builder(Duration::from_secs(30), 0.25)
+    ///     // don't trip on the first few requests after a quiet period.
+    ///     .with_min_requests(20)
+    ///     .with_buckets(30)
+    ///     .build()
+    ///     .expect("policy settings should be valid");
+    /// ```
+    pub fn builder(window: Duration, max_rate: f64) -> SlidingFailureRateBuilder {
+        SlidingFailureRateBuilder {
+            settings: Settings {
+                window,
+                max_rate,
+                min_requests: 1,
+                buckets: NUM_BUCKETS,
+                failure_weight: 1.0,
+            },
+            clock: clock::default(),
+        }
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// Any requests already recorded by this policy are discarded, so this
+    /// should be called when the policy is constructed. When using a custom
+    /// clock, the same clock should also be provided to the breaker using
+    /// [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self::build(self.0.settings, Arc::new(clock))
+    }
+
+    /// Returns the failure rate over the current window, or 0 if there are
+    /// no requests in the window.
+    ///
+    /// Failures are weighted by the policy's
+    /// [failure weight](SlidingFailureRateBuilder::with_failure_weight).
+    pub fn failure_rate(&self) -> f64 {
+        self.rate(self.request_count(), self.failure_count())
+    }
+
+    /// Returns the number of requests in the current window.
+    pub fn request_count(&self) -> usize {
+        self.0.reqs.sum()
+    }
+
+    /// Returns the number of failed requests in the current window.
+    pub fn failure_count(&self) -> usize {
+        self.0.fails.sum()
+    }
+
+    fn build(settings: Settings, clock: SharedClock) -> Self {
+        let Settings {
+            window, buckets, ..
+        } = settings;
         SlidingFailureRate(Arc::new(Inner {
-            max_rate,
-            reqs: WindowedCounter::new(window),
-            fails: WindowedCounter::new(window),
+            settings,
+            reqs: WindowedCounter::with_buckets(window, buckets, clock.clone()),
+            fails: WindowedCounter::with_buckets(window, buckets, clock),
         }))
     }
+
+    /// Returns the failure rate given the number of requests and failures in
+    /// the window, with each failure weighted by the failure weight.
+    ///
+    /// An empty window has a failure rate of 0.
+    fn rate(&self, reqs: usize, fails: usize) -> f64 {
+        if reqs == 0 {
+            return 0.0;
+        }
+        let successes = reqs.saturating_sub(fails) as f64;
+        let fails = fails as f64 * self.0.settings.failure_weight;
+        fails / (successes + fails)
+    }
+}
+
+// === impl SlidingFailureRateBuilder ===
+
+impl SlidingFailureRateBuilder {
+    /// Sets the minimum number of requests which must be in the window
+    /// before the policy punishes an endpoint. By default, this is 1.
+    ///
+    /// An endpoint is never punished while the window is empty, even if
+    /// this is 0, since there's no failure rate to compare to the maximum.
+    pub fn with_min_requests(mut self, min_requests: usize) -> Self {
+        self.settings.min_requests = min_requests;
+        self
+    }
+
+    /// Sets the number of buckets the window is divided into. By default,
+    /// this is 10.
+    ///
+    /// Requests expire from the window a bucket at a time, so more buckets
+    /// make the failure rate slide more smoothly, at the cost of a little
+    /// more memory and work as the window advances.
+    pub fn with_buckets(mut self, buckets: usize) -> Self {
+        self.settings.buckets = buckets;
+        self
+    }
+
+    /// Sets how many requests each failure counts as when calculating the
+    /// failure rate. By default, this is 1.
+    ///
+    /// A weight above 1 trips the breaker on fewer failures, such as for
+    /// endpoints where each failure is expensive.
+    pub fn with_failure_weight(mut self, weight: f64) -> Self {
+        self.settings.failure_weight = weight;
+        self
+    }
+
+    /// Reads the current time from the provided [`Clock`] rather than from
+    /// Tokio.
+    ///
+    /// When using a custom clock, the same clock should also be provided to
+    /// the breaker using [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the configured policy.
+    ///
+    /// # Errors
+    ///
+    /// - If the maximum failure rate is less than 0, or is 1 or greater, in
+    ///   which case the policy could never punish an endpoint.
+    /// - If the window or the number of buckets is 0.
+    /// - If the window is divided into buckets narrower than 1ms.
+    /// - If the failure weight isn't a positive number.
+    pub fn build(self) -> Result<SlidingFailureRate, PolicyError> {
+        let Settings {
+            window,
+            max_rate,
+            buckets,
+            failure_weight,
+            ..
+        } = self.settings;
+        if !(0.0..1.0).contains(&max_rate) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "maximum failure rate",
+                value: max_rate,
+                range: "[0, 1)",
+            });
+        }
+        if window.is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "window",
+            });
+        }
+        if buckets == 0 {
+            return Err(PolicyError::Zero {
+                parameter: "number of buckets",
+            });
+        }
+        if window.as_millis() < buckets as u128 {
+            return Err(PolicyError::OutOfRange {
+                parameter: "bucket width in milliseconds",
+                value: window.as_secs_f64() * 1000.0 / buckets as f64,
+                range: "[1, inf)",
+            });
+        }
+        if !(failure_weight > 0.0 && failure_weight.is_finite()) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "failure weight",
+                value: failure_weight,
+                range: "(0, inf)",
+            });
+        }
+        Ok(SlidingFailureRate::build(self.settings, self.clock))
+    }
 }
 
 impl super::Policy for SlidingFailureRate {
@@ -45,18 +250,70 @@ impl super::Policy for SlidingFailureRate {
     }
 
     fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
         let reqs = self.0.reqs.sum();
+        if reqs == 0 || reqs < self.0.settings.min_requests {
+            return None;
+        }
         let fails = self.0.fails.sum();
-        let rate = fails as f64 / reqs as f64;
-        let punished = rate > self.0.max_rate;
-        if punished {
-            tracing::trace!(
+        let rate = self.rate(reqs, fails);
+        let max_rate = self.0.settings.max_rate;
+        if rate > max_rate {
+            trace!(
                 failure_rate = rate,
-                max_rate = self.0.max_rate,
+                max_rate,
                 "Failure rate exceeds max; punishing endpoint!"
             );
+            return Some(TripReason::FailureRate {
+                rate,
+                threshold: max_rate,
+                samples: reqs,
+            });
+        }
+        None
+    }
+
+    fn severity(&self) -> f64 {
+        let fails = self.0.fails.sum();
+        let reqs = self.0.reqs.sum();
+        if fails == 0 || reqs < self.0.settings.min_requests {
+            return 0.0;
+        }
+        (self.rate(reqs, fails) / self.0.settings.max_rate).min(1.0)
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        let requests = self.request_count();
+        PolicySnapshot {
+            requests: Some(requests),
+            failures: Some(self.failure_count()),
+            failure_rate: Some(self.failure_rate()).filter(|_| requests > 0),
+            max_failure_rate: Some(self.0.settings.max_rate),
+            window: Some(self.0.settings.window),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    /// Pre-loads this policy with the outcomes of past requests.
+    ///
+    /// Each outcome is counted as though it was recorded at the time it
+    /// completed, so outcomes older than the policy's window are ignored,
+    /// and more recent outcomes expire from the window as they would have if
+    /// they had been recorded by this policy.
+    fn warm_start(&self, outcomes: &[Outcome]) {
+        let now = SystemTime::now();
+        for outcome in outcomes {
+            // outcomes from the future (e.g. due to clock skew between
+            // processes) are treated as having just completed.
+            let ago = now.duration_since(outcome.at).unwrap_or_default();
+            self.0.reqs.add_ago(1, ago);
+            if !outcome.success {
+                self.0.fails.add_ago(1, ago);
+            }
         }
-        punished
     }
 
     fn reset(&self) {
@@ -64,3 +321,98 @@ impl super::Policy for SlidingFailureRate {
         self.0.fails.reset();
     }
 }
+
+impl fmt::Debug for SlidingFailureRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlidingFailureRate")
+            .field("settings", &self.0.settings)
+            .field("reqs", &self.request_count())
+            .field("fails", &self.failure_count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, Policy};
+
+    #[tokio::test]
+    async fn warm_start() {
+        tokio::time::pause();
+        let now = SystemTime::now();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5);
+        policy.warm_start(&[
+            Outcome::success(now - Duration::from_secs(3600)),
+            Outcome::failure(now - Duration::from_secs(5)),
+            Outcome::failure(now - Duration::from_secs(2)),
+            Outcome::success(now - Duration::from_secs(1)),
+        ]);
+        let snapshot = policy.snapshot();
+        assert_eq!(Some(3), snapshot.requests);
+        assert_eq!(Some(2), snapshot.failures);
+        assert!(policy.is_punished());
+
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert_eq!(Some(2), policy.snapshot().requests);
+        assert!(!policy.is_punished());
+    }
+
+    #[test]
+    fn builder() {
+        let clock = ManualClock::new();
+        let policy = SlidingFailureRate::builder(Duration::from_secs(10), 0.5)
+            .with_min_requests(4)
+            .with_failure_weight(2.0)
+            .with_clock(clock)
+            .build()
+            .unwrap();
+
+        // too few requests to punish the endpoint.
+        assert_eq!(0.0, policy.failure_rate());
+        policy.record_failure();
+        policy.record_success();
+        policy.record_success();
+        assert!(!policy.is_punished());
+
+        // one failure in four, weighted twice, is a rate of 2/5.
+        policy.record_success();
+        assert_eq!((4, 1), (policy.request_count(), policy.failure_count()));
+        assert_eq!(0.4, policy.failure_rate());
+        assert_eq!(Some(0.4), policy.snapshot().failure_rate);
+        assert!(!policy.is_punished());
+        policy.record_failure();
+        assert!(policy.is_punished());
+
+        // an empty window is never punished, even with no minimum.
+        let strict = SlidingFailureRate::builder(Duration::from_secs(10), 0.0)
+            .with_min_requests(0)
+            .build()
+            .unwrap();
+        assert!(!strict.is_punished());
+        assert_eq!(0.0, strict.severity());
+        assert_eq!(None, strict.snapshot().failure_rate);
+
+        let invalid = |builder: SlidingFailureRateBuilder| builder.build().unwrap_err().to_string();
+        let builder = |max_rate| SlidingFailureRate::builder(Duration::from_secs(10), max_rate);
+        assert_eq!(
+            "maximum failure rate must be in the range [0, 1), but was 1",
+            invalid(builder(1.0))
+        );
+        assert_eq!(
+            "number of buckets must be greater than 0",
+            invalid(builder(0.5).with_buckets(0))
+        );
+        assert_eq!(
+            "bucket width in milliseconds must be in the range [1, inf), but was 0.5",
+            invalid(
+                SlidingFailureRate::builder(Duration::from_millis(500), 0.5).with_buckets(1000)
+            )
+        );
+        assert!(builder(0.5).with_buckets(usize::MAX).build().is_err());
+        assert_eq!(
+            "failure weight must be in the range (0, inf), but was NaN",
+            invalid(builder(0.5).with_failure_weight(f64::NAN))
+        );
+    }
+}
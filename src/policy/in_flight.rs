@@ -0,0 +1,167 @@
+use super::TripReason;
+use crate::{
+    clock::{self, Clock, SharedClock},
+    trace::trace,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint when more than a
+/// number of requests have been in flight for a sustained period.
+///
+/// Requests to an endpoint which hangs neither succeed nor fail, so a policy
+/// which only sees outcomes won't trip until they time out, if they ever
+/// do. This policy tracks the number of requests the breaker has passed to
+/// its inner service which haven't completed, and punishes the endpoint once
+/// more than `max_in_flight` have been in flight for `duration`.
+///
+/// Outcomes are ignored.
+#[derive(Clone)]
+pub struct InFlightLimit(Arc<Inner>);
+
+struct Inner {
+    max_in_flight: usize,
+    duration: Duration,
+    clock: SharedClock,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    /// When the number of requests in flight rose above the limit, if it
+    /// hasn't fallen back to the limit since.
+    above_since: Option<Instant>,
+}
+
+impl InFlightLimit {
+    /// Returns a new `InFlightLimit` policy which punishes an endpoint once
+    /// more than `max_in_flight` requests have been in flight for
+    /// `duration`.
+    pub fn new(max_in_flight: usize, duration: Duration) -> Self {
+        Self::build(max_in_flight, duration, clock::default())
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self::build(self.0.max_in_flight, self.0.duration, Arc::new(clock))
+    }
+
+    fn build(max_in_flight: usize, duration: Duration, clock: SharedClock) -> Self {
+        InFlightLimit(Arc::new(Inner {
+            max_in_flight,
+            duration,
+            clock,
+            state: Mutex::new(State::default()),
+        }))
+    }
+}
+
+impl super::Policy for InFlightLimit {
+    fn record_success(&self) {}
+
+    fn record_failure(&self) {}
+
+    fn record_in_flight(&self, in_flight: usize) {
+        let mut state = self.0.state.lock().unwrap();
+        state.in_flight = in_flight;
+        if in_flight <= self.0.max_in_flight {
+            state.above_since = None;
+        } else if state.above_since.is_none() {
+            state.above_since = Some(self.0.clock.now());
+        }
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let state = self.0.state.lock().unwrap();
+        let above_since = state.above_since?;
+        if self.0.clock.now().saturating_duration_since(above_since) < self.0.duration {
+            return None;
+        }
+        trace!(
+            in_flight = state.in_flight,
+            max_in_flight = self.0.max_in_flight,
+            "Too many requests in flight; punishing endpoint!"
+        );
+        Some(TripReason::InFlight {
+            in_flight: state.in_flight,
+            threshold: self.0.max_in_flight,
+        })
+    }
+
+    fn reset(&self) {
+        // requests which are still in flight don't count towards the next
+        // trip until they've been in flight for the whole duration again.
+        let mut state = self.0.state.lock().unwrap();
+        if state.above_since.is_some() {
+            state.above_since = Some(self.0.clock.now());
+        }
+    }
+}
+
+impl fmt::Debug for InFlightLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0.state.lock().unwrap();
+        f.debug_struct("InFlightLimit")
+            .field("max_in_flight", &self.0.max_in_flight)
+            .field("duration", &self.0.duration)
+            .field("in_flight", &state.in_flight)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, BoxError, CircuitBreaker, Config};
+    use tokio::sync::oneshot;
+    use tower::{service_fn, Service, ServiceExt};
+
+    #[tokio::test]
+    async fn trips_on_hung_requests() {
+        let clock = ManualClock::new();
+        let policy = InFlightLimit::new(1, Duration::from_secs(10)).with_clock(clock.clone());
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_clock(clock.clone())
+            .with_fail_fast(true);
+        let svc = service_fn(|rx: oneshot::Receiver<()>| async move {
+            rx.await.map_err(|_| BoxError::from("canceled"))
+        });
+        let mut breaker = CircuitBreaker::new(config, svc);
+        let handle = breaker.handle();
+
+        let (_tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        let hung = breaker.ready().await.unwrap().call(rx1);
+        let done = breaker.ready().await.unwrap().call(rx2);
+        assert_eq!(2, handle.in_flight());
+
+        // a brief spike isn't punished.
+        clock.advance(Duration::from_secs(5));
+        tx2.send(()).unwrap();
+        done.await.unwrap();
+        assert_eq!(1, handle.in_flight());
+        clock.advance(Duration::from_secs(10));
+        assert!(breaker.ready().await.is_ok());
+
+        // requests which pile up are.
+        let (_tx3, rx3) = oneshot::channel();
+        let _hung2 = breaker.ready().await.unwrap().call(rx3);
+        clock.advance(Duration::from_secs(10));
+        let (_, rx4) = oneshot::channel();
+        let error = breaker.ready().await.unwrap().call(rx4).await.unwrap_err();
+        assert!(
+            error.to_string().contains("2 requests in flight"),
+            "{error}"
+        );
+        drop(hung);
+    }
+}
@@ -0,0 +1,190 @@
+use super::TripReason;
+use crate::{
+    clock::{self, Clock, SharedClock},
+    trace::trace,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint when its readiness
+/// delay has stayed above a target for a sustained interval.
+///
+/// A saturated service may apply backpressure by not becoming ready, so that
+/// the requests which do get through still succeed, and an outcome-based
+/// policy never trips. This policy measures how long the breaker's inner
+/// service takes to become ready each time it's polled, and punishes the
+/// endpoint once every delay recorded over `interval` has exceeded `target`,
+/// in the manner of the [CoDel] queue management algorithm: occasional
+/// bursts of backpressure are tolerated, but a standing queue isn't.
+///
+/// Outcomes are ignored.
+///
+/// [CoDel]: https://en.wikipedia.org/wiki/CoDel
+#[derive(Clone)]
+pub struct QueueDelay(Arc<Inner>);
+
+struct Inner {
+    target: Duration,
+    interval: Duration,
+    clock: SharedClock,
+    /// When the readiness delay rose above the target, if every delay
+    /// recorded since has been above it.
+    above_since: Mutex<Option<Instant>>,
+}
+
+impl QueueDelay {
+    /// Returns a new `QueueDelay` policy which punishes an endpoint once its
+    /// readiness delay has exceeded `target` for `interval`.
+    pub fn new(target: Duration, interval: Duration) -> Self {
+        Self::build(target, interval, clock::default())
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self::build(self.0.target, self.0.interval, Arc::new(clock))
+    }
+
+    fn build(target: Duration, interval: Duration, clock: SharedClock) -> Self {
+        QueueDelay(Arc::new(Inner {
+            target,
+            interval,
+            clock,
+            above_since: Mutex::new(None),
+        }))
+    }
+}
+
+impl super::Policy for QueueDelay {
+    fn record_success(&self) {}
+
+    fn record_failure(&self) {}
+
+    fn record_ready_delay(&self, delay: Duration) {
+        let mut above_since = self.0.above_since.lock().unwrap();
+        if delay <= self.0.target {
+            *above_since = None;
+            return;
+        }
+        // the delay rose above the target when the wait for readiness began.
+        let now = self.0.clock.now();
+        above_since.get_or_insert(now.checked_sub(delay).unwrap_or(now));
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let above_since = (*self.0.above_since.lock().unwrap())?;
+        let sustained_for = self.0.clock.now().saturating_duration_since(above_since);
+        if sustained_for < self.0.interval {
+            return None;
+        }
+        trace!(
+            target_delay = ?self.0.target,
+            ?sustained_for,
+            "Readiness delay exceeds target; punishing endpoint!"
+        );
+        Some(TripReason::QueueDelay {
+            target: self.0.target,
+            sustained_for,
+        })
+    }
+
+    fn reset(&self) {
+        *self.0.above_since.lock().unwrap() = None;
+    }
+}
+
+impl fmt::Debug for QueueDelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueDelay")
+            .field("target", &self.0.target)
+            .field("interval", &self.0.interval)
+            .field("above_since", &self.0.above_since.lock().unwrap())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, CircuitBreaker, Config, Policy};
+    use std::{
+        future,
+        sync::atomic::{AtomicBool, Ordering},
+        task::{Context, Poll},
+    };
+    use tower::{Service, ServiceExt};
+
+    #[test]
+    fn sustained_delay() {
+        let clock = ManualClock::new();
+        clock.advance(Duration::from_secs(1));
+        let policy = QueueDelay::new(Duration::from_millis(10), Duration::from_millis(100))
+            .with_clock(clock.clone());
+
+        policy.record_ready_delay(Duration::from_millis(20));
+        clock.advance(Duration::from_millis(50));
+        policy.record_ready_delay(Duration::from_millis(30));
+        assert!(!policy.is_punished());
+        clock.advance(Duration::from_millis(40));
+        assert!(policy.is_punished());
+
+        // a service which becomes ready promptly ends the standing queue.
+        policy.record_ready_delay(Duration::ZERO);
+        assert!(!policy.is_punished());
+
+        // a single long wait is sustained too.
+        policy.record_ready_delay(Duration::from_millis(150));
+        assert!(policy.is_punished());
+    }
+
+    /// A service which is only ready when its flag is set.
+    #[derive(Clone)]
+    struct Gate(Arc<AtomicBool>);
+
+    impl Service<()> for Gate {
+        type Response = ();
+        type Error = &'static str;
+        type Future = future::Ready<Result<(), &'static str>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            if self.0.load(Ordering::Relaxed) {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn measures_readiness() {
+        let clock = ManualClock::new();
+        let policy = QueueDelay::new(Duration::from_millis(10), Duration::from_millis(100))
+            .with_clock(clock.clone());
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_clock(clock.clone())
+            .with_fail_fast(true);
+        let open = Arc::new(AtomicBool::new(false));
+        let mut breaker = CircuitBreaker::new(config, Gate(open.clone()));
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+
+        assert!(Service::<()>::poll_ready(&mut breaker, &mut cx).is_pending());
+        clock.advance(Duration::from_millis(200));
+        open.store(true, Ordering::Relaxed);
+        breaker.ready().await.unwrap().call(()).await.unwrap();
+
+        // the breaker trips the next time it's polled.
+        let err = breaker.ready().await.unwrap().call(()).await.unwrap_err();
+        assert!(err.to_string().contains("readiness delay"), "{err}");
+    }
+}
@@ -0,0 +1,158 @@
+use super::TripReason;
+use crate::{
+    clock::{self, Clock, SharedClock},
+    signal::SignalSource,
+    trace::trace,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint while a resource
+/// [signal](crate::signal) exceeds a threshold.
+///
+/// This ignores the outcomes of requests, and is intended for protecting a
+/// [server](crate::Config::server) from overload: for example, shedding
+/// requests while the host's CPU utilization is above 90%. The signal is
+/// sampled at most once per sample interval (one second, by default), and
+/// the policy is not punishing an endpoint while the signal can't be read.
+#[derive(Clone)]
+pub struct ResourceThreshold {
+    source: Arc<dyn SignalSource>,
+    threshold: f64,
+    sample_interval: Duration,
+    clock: SharedClock,
+    /// The last value read from the source, and when it was read.
+    sample: Arc<Mutex<Sample>>,
+}
+
+type Sample = Option<(Instant, Option<f64>)>;
+
+impl ResourceThreshold {
+    /// Returns a new `ResourceThreshold` policy which punishes an endpoint
+    /// while the signal read from `source` is greater than `threshold`.
+    pub fn new(source: impl SignalSource, threshold: f64) -> Self {
+        ResourceThreshold {
+            source: Arc::new(source),
+            threshold,
+            sample_interval: Duration::from_secs(1),
+            clock: clock::default(),
+            sample: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets how often the signal is sampled. By default, this is once per
+    /// second.
+    pub fn with_sample_interval(self, sample_interval: Duration) -> Self {
+        ResourceThreshold {
+            sample_interval,
+            ..self
+        }
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        ResourceThreshold {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Returns the current value of the signal, reading it from the source
+    /// if the last sample is too old.
+    fn value(&self) -> Option<f64> {
+        let now = self.clock.now();
+        let mut sample = self.sample.lock().unwrap();
+        match *sample {
+            Some((at, value)) if now.saturating_duration_since(at) < self.sample_interval => value,
+            _ => {
+                let value = self.source.read();
+                *sample = Some((now, value));
+                value
+            }
+        }
+    }
+}
+
+impl super::Policy for ResourceThreshold {
+    fn record_success(&self) {}
+
+    fn record_failure(&self) {}
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let value = self.value()?;
+        if value > self.threshold {
+            trace!(
+                signal = value,
+                threshold = self.threshold,
+                "Resource signal exceeds threshold; punishing endpoint!"
+            );
+            return Some(TripReason::ResourceSignal {
+                value,
+                threshold: self.threshold,
+            });
+        }
+        None
+    }
+
+    fn reset(&self) {
+        // read the signal afresh once the circuit closes.
+        *self.sample.lock().unwrap() = None;
+    }
+}
+
+impl fmt::Debug for ResourceThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sample = self.sample.lock().unwrap().and_then(|(_, value)| value);
+        f.debug_struct("ResourceThreshold")
+            .field("threshold", &self.threshold)
+            .field("sample_interval", &self.sample_interval)
+            .field("sample", &sample)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, Policy};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn samples_signal() {
+        let clock = ManualClock::new();
+        let signal = Arc::new(AtomicU64::new(50));
+        let policy = ResourceThreshold::new(
+            {
+                let signal = signal.clone();
+                move || Some(signal.load(Ordering::Relaxed) as f64 / 100.0)
+            },
+            0.9,
+        )
+        .with_clock(clock.clone());
+        assert!(!policy.is_punished());
+
+        // the signal isn't read again until the sample interval has passed.
+        signal.store(95, Ordering::Relaxed);
+        assert!(!policy.is_punished());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            Some(TripReason::ResourceSignal {
+                value: 0.95,
+                threshold: 0.9
+            }),
+            policy.punish_reason()
+        );
+
+        signal.store(20, Ordering::Relaxed);
+        policy.reset();
+        assert!(!policy.is_punished());
+    }
+}
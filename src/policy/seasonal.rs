@@ -0,0 +1,418 @@
+use super::{PolicySnapshot, TripReason};
+use crate::{
+    clock::{self, Clock, SharedClock},
+    error::PolicyError,
+    trace::trace,
+    window_counter::WindowedCounter,
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// A [`Policy`](super::Policy) which learns an endpoint's usual failure rate
+/// at each time of day, and punishes the endpoint when its failure rate
+/// deviates too far from what's usual for the current time.
+///
+/// Some dependencies fail more often at predictable times, such as during
+/// nightly batch jobs or daily traffic peaks. A fixed threshold has to
+/// tolerate their worst time of day, and so misses failures at any other
+/// time. This policy divides a `period` (by default, a day) into `slots`
+/// (by default, 24 hours), and learns a baseline failure rate for each slot
+/// as a moving average over successive periods. It punishes the endpoint
+/// when the failure rate over the recent `window` exceeds the current
+/// slot's baseline by more than a `tolerance`:
+///
+/// ```
+/// use std::time::Duration;
+/// use tower_breaker::policy::SeasonalBaseline;
+///
+/// let policy = SeasonalBaseline::builder(Duration::from_secs(60), 0.1)
+///     // learn a baseline for every half hour of the week.
+///     .with_period(Duration::from_secs(7 * 24 * 60 * 60), 7 * 48)
+///     .with_min_requests(50)
+///     .build()
+///     .expect("policy settings should be valid");
+/// ```
+///
+/// Slots are aligned to the Unix epoch, so with the default settings each
+/// slot is an hour of the day in UTC. The endpoint isn't punished during a
+/// slot until that slot's baseline has been learned.
+///
+/// When the breaker trips, the recent window and the outcomes recorded in
+/// the current slot are discarded, so that the failures which tripped the
+/// breaker aren't learned as normal, but the baselines learned for every
+/// slot are kept.
+#[derive(Clone)]
+pub struct SeasonalBaseline(Arc<Inner>);
+
+/// Builds a [`SeasonalBaseline`] policy.
+///
+/// This is returned by [`SeasonalBaseline::builder`].
+#[derive(Clone, Debug)]
+pub struct SeasonalBaselineBuilder {
+    settings: Settings,
+    clock: SharedClock,
+}
+
+struct Inner {
+    settings: Settings,
+    clock: SharedClock,
+    /// The time at which the policy was constructed, according to both the
+    /// clock and the system's wall-clock time, so that the clock can be
+    /// used to tell the wall-clock time.
+    anchor: (Instant, SystemTime),
+    reqs: WindowedCounter,
+    fails: WindowedCounter,
+    /// The number of the slot, counting from the Unix epoch, in which
+    /// `slot_reqs` and `slot_fails` were recorded.
+    slot: AtomicU64,
+    slot_reqs: AtomicUsize,
+    slot_fails: AtomicUsize,
+    /// The baseline failure rate learned for each slot of the period, if any.
+    baselines: Mutex<Vec<Option<f64>>>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Settings {
+    window: Duration,
+    tolerance: f64,
+    period: Duration,
+    slots: usize,
+    learning_rate: f64,
+    min_requests: usize,
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl SeasonalBaseline {
+    /// Returns a builder for a `SeasonalBaseline` policy which punishes an
+    /// endpoint when its failure rate over `window` exceeds the baseline for
+    /// the current time of day by more than `tolerance`.
+    pub fn builder(window: Duration, tolerance: f64) -> SeasonalBaselineBuilder {
+        SeasonalBaselineBuilder {
+            settings: Settings {
+                window,
+                tolerance,
+                period: DAY,
+                slots: 24,
+                learning_rate: 0.3,
+                min_requests: 1,
+            },
+            clock: clock::default(),
+        }
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// Any requests already recorded by this policy, and any baselines it
+    /// has learned, are discarded, so this should be called when the policy
+    /// is constructed. When using a custom clock, the same clock should also
+    /// be provided to the breaker using
+    /// [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self::build(self.0.settings, Arc::new(clock))
+    }
+
+    /// Returns the baseline failure rate learned for the current slot, or
+    /// `None` if it hasn't been learned yet.
+    pub fn baseline(&self) -> Option<f64> {
+        let slot = self.roll();
+        self.0.baselines.lock().unwrap()[self.0.settings.index(slot)]
+    }
+
+    fn build(settings: Settings, clock: SharedClock) -> Self {
+        let anchor = (clock.now(), SystemTime::now());
+        let policy = SeasonalBaseline(Arc::new(Inner {
+            settings,
+            anchor,
+            reqs: WindowedCounter::new(settings.window, clock.clone()),
+            fails: WindowedCounter::new(settings.window, clock.clone()),
+            clock,
+            slot: AtomicU64::new(0),
+            slot_reqs: AtomicUsize::new(0),
+            slot_fails: AtomicUsize::new(0),
+            baselines: Mutex::new(vec![None; settings.slots]),
+        }));
+        policy
+            .0
+            .slot
+            .store(policy.current_slot(), Ordering::Release);
+        policy
+    }
+
+    /// Returns the number of the current slot, counting from the Unix epoch.
+    fn current_slot(&self) -> u64 {
+        let (instant, wall) = self.0.anchor;
+        let elapsed = self.0.clock.now().saturating_duration_since(instant);
+        let since_epoch = (wall + elapsed)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (since_epoch.as_nanos() / self.0.settings.slot_length().as_nanos()) as u64
+    }
+
+    /// Learns the baseline of the slot in which outcomes were last recorded,
+    /// if that slot has ended, returning the current slot.
+    fn roll(&self) -> u64 {
+        let now = self.current_slot();
+        let slot = self.0.slot.load(Ordering::Acquire);
+        if now == slot {
+            return now;
+        }
+        let mut baselines = self.0.baselines.lock().unwrap();
+        if self.0.slot.load(Ordering::Acquire) != slot {
+            // another thread already learned the slot's baseline.
+            return now;
+        }
+        let reqs = self.0.slot_reqs.swap(0, Ordering::AcqRel);
+        let fails = self.0.slot_fails.swap(0, Ordering::AcqRel);
+        self.0.slot.store(now, Ordering::Release);
+        let settings = &self.0.settings;
+        if reqs >= settings.min_requests.max(1) {
+            let rate = fails as f64 / reqs as f64;
+            let baseline = &mut baselines[settings.index(slot)];
+            *baseline = Some(match *baseline {
+                Some(baseline) => baseline + (rate - baseline) * settings.learning_rate,
+                None => rate,
+            });
+        }
+        now
+    }
+
+    fn record(&self, failed: bool) {
+        self.roll();
+        self.0.reqs.add(1);
+        self.0.slot_reqs.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.0.fails.add(1);
+            self.0.slot_fails.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// === impl SeasonalBaselineBuilder ===
+
+impl SeasonalBaselineBuilder {
+    /// Sets the period over which the endpoint's failure rate varies, and
+    /// the number of slots it's divided into. By default, this is a day
+    /// divided into 24 slots.
+    pub fn with_period(mut self, period: Duration, slots: usize) -> Self {
+        self.settings.period = period;
+        self.settings.slots = slots;
+        self
+    }
+
+    /// Sets how much each period's failure rate in a slot moves the slot's
+    /// baseline, from 0 (never) to 1 (the baseline is the last period's
+    /// failure rate). By default, this is 0.3.
+    pub fn with_learning_rate(mut self, learning_rate: f64) -> Self {
+        self.settings.learning_rate = learning_rate;
+        self
+    }
+
+    /// Sets the minimum number of requests which must be in the window
+    /// before the policy punishes an endpoint, and which must be recorded
+    /// in a slot for the policy to learn from it. By default, this is 1.
+    pub fn with_min_requests(mut self, min_requests: usize) -> Self {
+        self.settings.min_requests = min_requests;
+        self
+    }
+
+    /// Reads the current time from the provided [`Clock`] rather than from
+    /// Tokio.
+    ///
+    /// When using a custom clock, the same clock should also be provided to
+    /// the breaker using [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the configured policy.
+    ///
+    /// # Errors
+    ///
+    /// - If the window, the period, or the number of slots is zero, or the
+    ///   period is too short to divide into that many slots.
+    /// - If the tolerance isn't between 0 and 1, exclusive.
+    /// - If the learning rate isn't greater than 0 and at most 1.
+    pub fn build(self) -> Result<SeasonalBaseline, PolicyError> {
+        let settings = self.settings;
+        if settings.window.is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "window",
+            });
+        }
+        if settings.slots == 0 {
+            return Err(PolicyError::Zero {
+                parameter: "number of slots",
+            });
+        }
+        if settings.slot_length().is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "slot length",
+            });
+        }
+        if !(settings.tolerance > 0.0 && settings.tolerance < 1.0) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "tolerance",
+                value: settings.tolerance,
+                range: "(0, 1)",
+            });
+        }
+        if !(settings.learning_rate > 0.0 && settings.learning_rate <= 1.0) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "learning rate",
+                value: settings.learning_rate,
+                range: "(0, 1]",
+            });
+        }
+        Ok(SeasonalBaseline::build(settings, self.clock))
+    }
+}
+
+impl super::Policy for SeasonalBaseline {
+    fn record_success(&self) {
+        self.record(false);
+    }
+
+    fn record_failure(&self) {
+        self.record(true);
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let baseline = self.baseline()?;
+        let reqs = self.0.reqs.sum();
+        if reqs == 0 || reqs < self.0.settings.min_requests {
+            return None;
+        }
+        let rate = self.0.fails.sum() as f64 / reqs as f64;
+        if rate - baseline <= self.0.settings.tolerance {
+            return None;
+        }
+        trace!(
+            failure_rate = rate,
+            baseline,
+            "Failure rate deviates from seasonal baseline; punishing endpoint!"
+        );
+        Some(TripReason::SeasonalAnomaly {
+            rate,
+            baseline,
+            samples: reqs,
+        })
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        let requests = self.0.reqs.sum();
+        let failures = self.0.fails.sum();
+        PolicySnapshot {
+            requests: Some(requests),
+            failures: Some(failures),
+            failure_rate: Some(failures as f64 / requests as f64).filter(|_| requests > 0),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    fn reset(&self) {
+        self.roll();
+        self.0.reqs.reset();
+        self.0.fails.reset();
+        self.0.slot_reqs.store(0, Ordering::Release);
+        self.0.slot_fails.store(0, Ordering::Release);
+    }
+}
+
+impl fmt::Debug for SeasonalBaseline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeasonalBaseline")
+            .field("settings", &self.0.settings)
+            .field("reqs", &self.0.reqs.sum())
+            .field("fails", &self.0.fails.sum())
+            .field("baselines", &self.0.baselines.lock().unwrap())
+            .finish()
+    }
+}
+
+// === impl Settings ===
+
+impl Settings {
+    fn slot_length(&self) -> Duration {
+        self.period / self.slots as u32
+    }
+
+    /// Returns the index of the baseline for the slot with the given
+    /// number.
+    fn index(&self, slot: u64) -> usize {
+        (slot % self.slots as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, Policy};
+
+    #[test]
+    fn learns_each_slot() {
+        let clock = ManualClock::new();
+        let policy = SeasonalBaseline::builder(Duration::from_secs(60), 0.1)
+            .with_period(Duration::from_secs(4 * 60), 2)
+            .with_learning_rate(0.5)
+            .with_clock(clock.clone())
+            .build()
+            .unwrap();
+        // start at the beginning of a slot.
+        let slot = policy.current_slot();
+        while policy.current_slot() == slot {
+            clock.advance(Duration::from_secs(1));
+        }
+        let record = |fails: usize| {
+            for i in 0..10 {
+                if i < fails {
+                    policy.record_failure();
+                } else {
+                    policy.record_success();
+                }
+            }
+        };
+        let next_slot = || clock.advance(Duration::from_secs(2 * 60));
+
+        // nothing is punished until a slot's baseline has been learned.
+        record(8);
+        next_slot();
+        assert_eq!(None, policy.baseline());
+        record(1);
+        assert!(!policy.is_punished());
+        next_slot();
+        assert_eq!(Some(0.8), policy.baseline());
+
+        // the slot which usually fails 80% of requests isn't punished for
+        // doing so...
+        record(8);
+        assert!(!policy.is_punished());
+        next_slot();
+
+        // ...but the other slot is.
+        assert_eq!(Some(0.1), policy.baseline());
+        record(8);
+        assert!(matches!(
+            policy.punish_reason(),
+            Some(TripReason::SeasonalAnomaly { samples: 10, .. })
+        ));
+
+        // the failures which tripped the breaker aren't learned.
+        policy.reset();
+        next_slot();
+        next_slot();
+        assert_eq!(Some(0.1), policy.baseline());
+    }
+}
@@ -0,0 +1,261 @@
+use super::{PolicySnapshot, TripReason};
+use crate::{
+    clock::{self, Clock, SharedClock},
+    error::PolicyError,
+    trace::trace,
+    window_counter::WindowedCounter,
+};
+use std::{fmt, sync::Arc, time::Duration};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint when too few of its
+/// requests meet a latency objective over a sliding window of time.
+///
+/// Latency SLOs are usually expressed as "`objective` of requests complete
+/// within `target_latency`", such as 99% of requests completing within
+/// 200ms. This policy tracks the fraction of requests over `window` which
+/// completed within `target_latency`, and punishes the endpoint once that
+/// fraction drops below `objective`.
+///
+/// Requests are judged by their latency alone, whether they succeed or fail.
+/// To also trip on errors, combine this with a failure rate policy.
+#[derive(Clone)]
+pub struct SloAttainment(Arc<Inner>);
+
+struct Inner {
+    objective: f64,
+    target_latency: Duration,
+    window: Duration,
+    min_requests: usize,
+    reqs: WindowedCounter,
+    slow: WindowedCounter,
+}
+
+impl SloAttainment {
+    /// Returns a new `SloAttainment` policy which punishes an endpoint once
+    /// fewer than `objective` of the requests over `window` completed within
+    /// `target_latency`.
+    ///
+    /// # Panics
+    ///
+    /// If `objective` isn't between 0 and 1, exclusive, or if `window` is
+    /// zero. Use [`SloAttainment::try_new`] to handle invalid settings
+    /// without panicking.
+    pub fn new(objective: f64, target_latency: Duration, window: Duration) -> Self {
+        match Self::try_new(objective, target_latency, window) {
+            Ok(policy) => policy,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a new `SloAttainment` policy which punishes an endpoint once
+    /// fewer than `objective` of the requests over `window` completed within
+    /// `target_latency`.
+    ///
+    /// # Errors
+    ///
+    /// If `objective` isn't between 0 and 1, exclusive, or if `window` is
+    /// zero.
+    pub fn try_new(
+        objective: f64,
+        target_latency: Duration,
+        window: Duration,
+    ) -> Result<Self, PolicyError> {
+        if !(objective > 0.0 && objective < 1.0) {
+            return Err(PolicyError::OutOfRange {
+                parameter: "objective",
+                value: objective,
+                range: "(0, 1)",
+            });
+        }
+        if window.is_zero() {
+            return Err(PolicyError::Zero {
+                parameter: "window",
+            });
+        }
+        Ok(Self::build(
+            objective,
+            target_latency,
+            window,
+            1,
+            clock::default(),
+        ))
+    }
+
+    /// Sets the minimum number of requests which must be in the window
+    /// before the policy punishes an endpoint. By default, this is 1.
+    ///
+    /// Any requests already recorded by this policy are discarded, so this
+    /// should be called when the policy is constructed.
+    pub fn with_min_requests(self, min_requests: usize) -> Self {
+        let inner = &self.0;
+        Self::build(
+            inner.objective,
+            inner.target_latency,
+            inner.window,
+            min_requests,
+            inner.reqs.clock(),
+        )
+    }
+
+    /// Returns this policy, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// Any requests already recorded by this policy are discarded, so this
+    /// should be called when the policy is constructed. When using a custom
+    /// clock, the same clock should also be provided to the breaker using
+    /// [`Config::with_clock`](crate::Config::with_clock).
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        let inner = &self.0;
+        Self::build(
+            inner.objective,
+            inner.target_latency,
+            inner.window,
+            inner.min_requests,
+            Arc::new(clock),
+        )
+    }
+
+    /// Returns the fraction of the requests in the current window which
+    /// completed within the target latency, or 1 if there are no requests
+    /// in the window.
+    pub fn attainment(&self) -> f64 {
+        let reqs = self.0.reqs.sum();
+        if reqs == 0 {
+            return 1.0;
+        }
+        1.0 - self.0.slow.sum() as f64 / reqs as f64
+    }
+
+    fn build(
+        objective: f64,
+        target_latency: Duration,
+        window: Duration,
+        min_requests: usize,
+        clock: SharedClock,
+    ) -> Self {
+        SloAttainment(Arc::new(Inner {
+            objective,
+            target_latency,
+            window,
+            min_requests,
+            reqs: WindowedCounter::new(window, clock.clone()),
+            slow: WindowedCounter::new(window, clock),
+        }))
+    }
+}
+
+impl super::Policy for SloAttainment {
+    fn record_success(&self) {}
+
+    fn record_failure(&self) {}
+
+    fn record_latency(&self, latency: Duration) {
+        self.0.reqs.add(1);
+        if latency > self.0.target_latency {
+            self.0.slow.add(1);
+        }
+    }
+
+    fn measures_latency(&self) -> bool {
+        true
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let reqs = self.0.reqs.sum();
+        if reqs == 0 || reqs < self.0.min_requests {
+            return None;
+        }
+        let attainment = self.attainment();
+        if attainment >= self.0.objective {
+            return None;
+        }
+        trace!(
+            attainment,
+            objective = self.0.objective,
+            "SLO attainment below objective; punishing endpoint!"
+        );
+        Some(TripReason::SloAttainment {
+            attainment,
+            objective: self.0.objective,
+            target_latency: self.0.target_latency,
+            samples: reqs,
+        })
+    }
+
+    /// Returns the fraction of the error budget which has been spent over
+    /// the current window.
+    fn severity(&self) -> f64 {
+        let reqs = self.0.reqs.sum();
+        if reqs == 0 || reqs < self.0.min_requests {
+            return 0.0;
+        }
+        ((1.0 - self.attainment()) / (1.0 - self.0.objective)).min(1.0)
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        PolicySnapshot {
+            requests: Some(self.0.reqs.sum()),
+            window: Some(self.0.window),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    fn reset(&self) {
+        self.0.reqs.reset();
+        self.0.slow.reset();
+    }
+}
+
+impl fmt::Debug for SloAttainment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SloAttainment")
+            .field("objective", &self.0.objective)
+            .field("target_latency", &self.0.target_latency)
+            .field("reqs", &self.0.reqs.sum())
+            .field("slow", &self.0.slow.sum())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, Policy};
+
+    #[test]
+    fn trips_below_objective() {
+        let clock = ManualClock::new();
+        let policy = SloAttainment::new(0.9, Duration::from_millis(200), Duration::from_secs(60))
+            .with_min_requests(10)
+            .with_clock(clock.clone());
+        for _ in 0..9 {
+            policy.record_latency(Duration::from_millis(50));
+        }
+        policy.record_latency(Duration::from_millis(500));
+        assert_eq!(0.9, policy.attainment());
+        assert_eq!(1.0, policy.severity());
+        assert!(!policy.is_punished());
+
+        policy.record_latency(Duration::from_millis(500));
+        assert!(matches!(
+            policy.punish_reason(),
+            Some(TripReason::SloAttainment { samples: 11, .. })
+        ));
+
+        // slow requests expire from the window.
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(1.0, policy.attainment());
+        assert!(!policy.is_punished());
+
+        assert_eq!(
+            "objective must be in the range (0, 1), but was 1",
+            SloAttainment::try_new(1.0, Duration::ZERO, Duration::from_secs(1))
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
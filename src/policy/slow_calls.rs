@@ -0,0 +1,177 @@
+use super::{PolicySnapshot, TripReason};
+use crate::{error::PolicyError, trace::trace};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// A [`Policy`](super::Policy) which punishes an endpoint after a number of
+/// consecutive calls take longer than a latency threshold.
+///
+/// A backend which has degraded to a fraction of its usual speed may not
+/// return any errors, and its slowest calls may still be too rare to move a
+/// percentile. This policy counts calls which take longer than
+/// `slow_call_duration` to complete, whether they succeed or fail, and
+/// punishes the endpoint once `max_slow_calls` in a row have been slow. Any
+/// call which completes faster resets the count.
+///
+/// Only requests passed through a breaker are timed, so outcomes recorded
+/// directly with the policy are ignored.
+#[derive(Clone)]
+pub struct ConsecutiveSlowCalls(Arc<Inner>);
+
+struct Inner {
+    slow_call_duration: Duration,
+    /// The number of consecutive slow calls after which the policy punishes
+    /// the endpoint.
+    max_slow_calls: usize,
+    slow_calls: AtomicUsize,
+}
+
+impl ConsecutiveSlowCalls {
+    /// Returns a new `ConsecutiveSlowCalls` policy which punishes an endpoint
+    /// once `max_slow_calls` calls in a row have taken longer than
+    /// `slow_call_duration`.
+    ///
+    /// # Panics
+    ///
+    /// If `max_slow_calls` is 0. Use [`ConsecutiveSlowCalls::try_new`] to
+    /// handle an invalid `max_slow_calls` without panicking.
+    pub fn new(slow_call_duration: Duration, max_slow_calls: usize) -> Self {
+        match Self::try_new(slow_call_duration, max_slow_calls) {
+            Ok(policy) => policy,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a new `ConsecutiveSlowCalls` policy which punishes an endpoint
+    /// once `max_slow_calls` calls in a row have taken longer than
+    /// `slow_call_duration`.
+    ///
+    /// # Errors
+    ///
+    /// If `max_slow_calls` is 0.
+    pub fn try_new(
+        slow_call_duration: Duration,
+        max_slow_calls: usize,
+    ) -> Result<Self, PolicyError> {
+        if max_slow_calls == 0 {
+            return Err(PolicyError::Zero {
+                parameter: "maximum consecutive slow calls",
+            });
+        }
+        Ok(ConsecutiveSlowCalls(Arc::new(Inner {
+            slow_call_duration,
+            max_slow_calls,
+            slow_calls: AtomicUsize::new(0),
+        })))
+    }
+}
+
+impl super::Policy for ConsecutiveSlowCalls {
+    fn record_success(&self) {}
+
+    fn record_failure(&self) {}
+
+    fn record_latency(&self, latency: Duration) {
+        if latency > self.0.slow_call_duration {
+            self.0.slow_calls.fetch_add(1, Ordering::AcqRel);
+        } else {
+            self.0.slow_calls.store(0, Ordering::Release);
+        }
+    }
+
+    fn measures_latency(&self) -> bool {
+        true
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        let calls = self.0.slow_calls.load(Ordering::Acquire);
+        if calls < self.0.max_slow_calls {
+            return None;
+        }
+        trace!(
+            calls,
+            max_slow_calls = self.0.max_slow_calls,
+            "Too many consecutive slow calls; punishing endpoint!"
+        );
+        Some(TripReason::SlowCalls {
+            calls,
+            threshold: self.0.max_slow_calls,
+            slow_call_duration: self.0.slow_call_duration,
+        })
+    }
+
+    fn severity(&self) -> f64 {
+        let calls = self.0.slow_calls.load(Ordering::Acquire);
+        (calls as f64 / self.0.max_slow_calls as f64).min(1.0)
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        PolicySnapshot::default()
+    }
+
+    fn reset(&self) {
+        self.0.slow_calls.store(0, Ordering::Release);
+    }
+}
+
+impl fmt::Debug for ConsecutiveSlowCalls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsecutiveSlowCalls")
+            .field("slow_call_duration", &self.0.slow_call_duration)
+            .field("max_slow_calls", &self.0.max_slow_calls)
+            .field("slow_calls", &self.0.slow_calls.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, error::CircuitOpen, BoxError, CircuitBreaker, Config, Policy};
+    use tower::{service_fn, Service, ServiceExt};
+
+    #[tokio::test]
+    async fn trips_on_slow_calls() {
+        let clock = ManualClock::new();
+        let policy = ConsecutiveSlowCalls::new(Duration::from_millis(100), 3);
+        let config = Config::new(policy.clone(), Duration::from_secs(5))
+            .with_clock(clock.clone())
+            .with_fail_fast(true);
+        let svc = service_fn({
+            let clock = clock.clone();
+            move |ms: u64| {
+                clock.advance(Duration::from_millis(ms));
+                async { Ok::<_, BoxError>(()) }
+            }
+        });
+        let mut breaker = CircuitBreaker::new(config, svc);
+
+        // a fast call resets the count.
+        for ms in [500, 500, 50, 500, 500] {
+            breaker.ready().await.unwrap().call(ms).await.unwrap();
+        }
+        assert!(!policy.is_punished());
+
+        breaker.ready().await.unwrap().call(500).await.unwrap();
+        let error = breaker.ready().await.unwrap().call(0).await.unwrap_err();
+        let error = error.downcast_ref::<CircuitOpen>().unwrap();
+        assert_eq!(
+            Some(&TripReason::SlowCalls {
+                calls: 3,
+                threshold: 3,
+                slow_call_duration: Duration::from_millis(100),
+            }),
+            error.reason()
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Request priorities, for shedding less important requests first.
+//!
+//! While a breaker is [degraded](crate::Config::with_degraded_shedding), it
+//! sheds a fraction of its requests before tripping. By default, every
+//! request is equally likely to be shed, but batch and background traffic
+//! can usually be sacrificed before interactive traffic. A breaker which is
+//! given a function extracting each request's [`Priority`] with
+//! [`Config::with_priority`](crate::Config::with_priority) can shed each
+//! priority tier at its own rate, with
+//! [`Config::with_priority_shedding`](crate::Config::with_priority_shedding):
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{policy::SlidingFailureRate, priority::Priority, Config};
+//!
+//! struct Job {
+//!     interactive: bool,
+//! }
+//!
+//! let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5);
+//! let config = Config::new(policy, Duration::from_secs(5))
+//!     .with_priority(|job: &Job| {
+//!         if job.interactive {
+//!             Priority::Interactive
+//!         } else {
+//!             Priority::Batch
+//!         }
+//!     })
+//!     // shed half of the batch jobs as soon as the policy is half way to
+//!     // tripping, but only a tenth of the interactive ones.
+//!     .with_priority_shedding(Priority::Batch, 0.5, 0.5)
+//!     .with_priority_shedding(Priority::Interactive, 0.5, 0.1);
+//! ```
+//!
+//! The [`http`](crate::http) and [`grpc`](crate::grpc) middleware read each
+//! request's priority from its extensions instead, so a `Priority` inserted
+//! into a request's extensions by an earlier layer is honored.
+
+/// The priority of a request, from most to least important.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum Priority {
+    /// Requests which should never be shed while the circuit is closed,
+    /// such as health checks or control plane traffic.
+    Critical,
+    /// Requests made on behalf of a user who is waiting for a response. This
+    /// is the default.
+    #[default]
+    Interactive,
+    /// Requests made by batch jobs, which can be retried later.
+    Batch,
+    /// Speculative or background requests, such as prefetching, which are
+    /// shed first.
+    Background,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rng::XorShift64, BoxError, CircuitBreaker, Config, Policy};
+    use std::time::Duration;
+    use tower::{service_fn, Service, ServiceExt};
+
+    /// A policy which is always degraded to the same severity.
+    #[derive(Clone, Debug)]
+    struct Degraded(f64);
+
+    impl Policy for Degraded {
+        fn record_success(&self) {}
+
+        fn record_failure(&self) {}
+
+        fn is_punished(&self) -> bool {
+            false
+        }
+
+        fn severity(&self) -> f64 {
+            self.0
+        }
+
+        fn reset(&self) {}
+    }
+
+    #[tokio::test]
+    async fn sheds_by_priority() {
+        let config = Config::new(Degraded(0.6), Duration::from_secs(5))
+            .with_rng(XorShift64::seed(7))
+            .with_priority(|priority: &Priority| *priority)
+            .with_degraded_shedding(0.5, 0.2)
+            .with_priority_shedding(Priority::Batch, 0.5, 0.5)
+            .with_priority_shedding(Priority::Background, 0.2, 0.5)
+            .with_priority_shedding(Priority::Background, 0.6, 1.0)
+            .with_priority_shedding(Priority::Background, 0.8, 0.1);
+        let svc = service_fn(|_: Priority| async { Ok::<_, BoxError>(()) });
+        let mut breaker = CircuitBreaker::new(config, svc);
+
+        let mut shed = Vec::new();
+        for priority in [
+            Priority::Critical,
+            Priority::Interactive,
+            Priority::Batch,
+            Priority::Background,
+        ] {
+            let mut n = 0;
+            for _ in 0..200 {
+                if breaker.ready().await.unwrap().call(priority).await.is_err() {
+                    n += 1;
+                }
+            }
+            shed.push(n);
+        }
+        assert_eq!(0, shed[0], "{shed:?}");
+        assert!((20..60).contains(&shed[1]), "{shed:?}");
+        assert!((70..130).contains(&shed[2]), "{shed:?}");
+        assert_eq!(200, shed[3], "{shed:?}");
+    }
+}
@@ -0,0 +1,116 @@
+//! Limiting how many telemetry events a breaker emits.
+#![cfg_attr(not(any(feature = "tracing", feature = "events")), allow(dead_code))]
+use crate::{clock::SharedClock, Config};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+/// Admits at most a fixed number of events in each second, counting the
+/// events it suppresses in between.
+///
+/// A circuit which is open under heavy traffic rejects every request it's
+/// sent, and logging each of those rejections can flood a logging pipeline
+/// without saying anything the first few didn't. The count is kept with
+/// relaxed atomics, so a few more events than the limit may be admitted when
+/// a second begins on several threads at once.
+pub(crate) struct RateLimiter {
+    per_second: u64,
+    clock: SharedClock,
+    anchor: Instant,
+    /// The second, since `anchor`, in which events are being counted.
+    second: AtomicU64,
+    /// The number of events admitted or suppressed in the current second.
+    count: AtomicU64,
+    /// The number of events suppressed since the last one was admitted.
+    suppressed: AtomicU64,
+}
+
+// === impl RateLimiter ===
+
+impl RateLimiter {
+    pub(crate) fn new(per_second: u32, clock: SharedClock) -> Self {
+        let anchor = clock.now();
+        RateLimiter {
+            per_second: u64::from(per_second),
+            clock,
+            anchor,
+            second: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a limiter for the events of a breaker constructed with
+    /// `config`, or `None` if they're unlimited.
+    pub(crate) fn from_config<P>(config: &Config<P>) -> Option<Self> {
+        let per_second = config.event_rate_limit?;
+        Some(RateLimiter::new(per_second, config.clock.clone()))
+    }
+
+    /// Returns the number of events suppressed since the last one was
+    /// admitted if another event may be emitted now, or `None` if the event
+    /// should be suppressed.
+    pub(crate) fn acquire(&self) -> Option<u64> {
+        let second = self
+            .clock
+            .now()
+            .saturating_duration_since(self.anchor)
+            .as_secs();
+        let current = self.second.load(Ordering::Relaxed);
+        if second > current
+            && self
+                .second
+                .compare_exchange(current, second, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.count.store(0, Ordering::Relaxed);
+        }
+        if self.count.fetch_add(1, Ordering::Relaxed) < self.per_second {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Returns the number of events suppressed by `limiter` since it last
+/// admitted one if another event may be emitted now, or `None` if the event
+/// should be suppressed. Every event is admitted if there's no limiter.
+pub(crate) fn acquire(limiter: Option<&RateLimiter>) -> Option<u64> {
+    limiter.map_or(Some(0), RateLimiter::acquire)
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("per_second", &self.per_second)
+            .field("suppressed", &self.suppressed.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn limits_each_second() {
+        let clock = ManualClock::new();
+        let limiter = RateLimiter::new(2, Arc::new(clock.clone()));
+        assert_eq!(Some(0), limiter.acquire());
+        assert_eq!(Some(0), limiter.acquire());
+        assert_eq!(None, limiter.acquire());
+        assert_eq!(None, limiter.acquire());
+
+        // the next event admitted reports how many were suppressed.
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(Some(2), limiter.acquire());
+        assert_eq!(Some(0), limiter.acquire());
+        assert_eq!(None, limiter.acquire());
+    }
+}
@@ -0,0 +1,131 @@
+//! A registry of named [`CircuitBreaker`](crate::CircuitBreaker)s.
+use crate::{handle::Shared, snapshot::BreakerSnapshot, Handle};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock, Weak},
+};
+
+/// A registry of named circuit breakers.
+///
+/// Breakers constructed with a [`Config`](crate::Config) that has both a
+/// [name](crate::Config::with_name) and a
+/// [registry](crate::Config::with_registry) register a [`Handle`] in that
+/// registry when they are constructed. The registry can then be used to look
+/// up, enumerate, and operate on all of its breakers, such as from an admin
+/// endpoint or metrics exporter.
+///
+/// The registry does not keep breakers alive: once a breaker is dropped, it
+/// is no longer returned by the registry.
+///
+/// Cloning a `BreakerRegistry` returns a new reference to the same registry.
+#[derive(Clone, Debug, Default)]
+pub struct BreakerRegistry {
+    breakers: Arc<RwLock<HashMap<String, Weak<Shared>>>>,
+}
+
+// === impl BreakerRegistry ===
+
+impl BreakerRegistry {
+    /// Returns a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a reference to the global registry.
+    ///
+    /// Breakers are not registered in the global registry unless they are
+    /// explicitly configured to use it.
+    pub fn global() -> &'static BreakerRegistry {
+        static GLOBAL: OnceLock<BreakerRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(BreakerRegistry::new)
+    }
+
+    /// Registers a breaker's `handle` in this registry.
+    ///
+    /// If a breaker with the same name is already registered, it is replaced.
+    /// Handles to breakers without a name are not registered.
+    pub fn register(&self, handle: &Handle) {
+        let Some(name) = handle.name() else {
+            return;
+        };
+        let mut breakers = self.breakers.write().unwrap();
+        // take the opportunity to clean up any breakers that no longer exist.
+        breakers.retain(|_, breaker| breaker.strong_count() > 0);
+        breakers.insert(name.to_owned(), handle.downgrade());
+    }
+
+    /// Returns a handle to the breaker registered with the given `name`, if
+    /// it exists.
+    pub fn get(&self, name: &str) -> Option<Handle> {
+        let breakers = self.breakers.read().unwrap();
+        breakers.get(name)?.upgrade().map(Handle::new)
+    }
+
+    /// Returns handles to all breakers in this registry, sorted by name.
+    pub fn handles(&self) -> Vec<Handle> {
+        let breakers = self.breakers.read().unwrap();
+        let mut handles = breakers
+            .values()
+            .filter_map(Weak::upgrade)
+            .map(Handle::new)
+            .collect::<Vec<_>>();
+        handles.sort_by(|a, b| a.name().cmp(&b.name()));
+        handles
+    }
+
+    /// Returns the names of all breakers in this registry, sorted.
+    pub fn names(&self) -> Vec<String> {
+        self.handles()
+            .iter()
+            .filter_map(|handle| handle.name().map(ToOwned::to_owned))
+            .collect()
+    }
+
+    /// Returns [snapshots](Handle::snapshot) of every breaker in this
+    /// registry, sorted by name.
+    pub fn snapshot(&self) -> Vec<BreakerSnapshot> {
+        self.handles().iter().map(Handle::snapshot).collect()
+    }
+
+    /// [Resets](Handle::reset) every breaker in this registry.
+    pub fn reset_all(&self) {
+        for handle in self.handles() {
+            handle.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(name: &'static str) -> Handle {
+        Handle::new(Arc::new(Shared::new(Some(name.into()), 0)))
+    }
+
+    #[test]
+    fn register_and_lookup() {
+        let registry = BreakerRegistry::new();
+        let foo = handle("foo");
+        let bar = handle("bar");
+        registry.register(&foo);
+        registry.register(&bar);
+
+        assert_eq!(vec!["bar", "foo"], registry.names());
+        assert_eq!(Some("foo"), registry.get("foo").unwrap().name());
+        assert!(registry.get("baz").is_none());
+    }
+
+    #[test]
+    fn dropped_breakers_are_removed() {
+        let registry = BreakerRegistry::new();
+        let foo = handle("foo");
+        registry.register(&foo);
+        registry.register(&handle("bar"));
+
+        assert_eq!(vec!["foo"], registry.names());
+        assert!(registry.get("bar").is_none());
+        drop(foo);
+        assert!(registry.handles().is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! Reloading breaker configuration from a file at runtime.
+//!
+//! This module is only available when the `reload` feature flag is enabled.
+//!
+//! [`watch_file`] polls a configuration file for changes, and applies the
+//! [`Settings`] it contains to the breakers in a [`BreakerRegistry`], using
+//! the same reconfiguration channel as [`Handle::set_trip_for`] and
+//! [`Handle::set_fail_fast`]. This allows breakers to be tuned in production
+//! without redeploying.
+//!
+//! The file's format is up to the application: `watch_file` takes a function
+//! which parses the file's contents into [`Settings`], such as
+//! `toml::from_str` or `serde_yaml::from_str`. For example, a TOML
+//! configuration file might look like this:
+//!
+//! ```toml
+//! [breakers.users-api]
+//! trip_for = "30s"
+//! fail_fast = true
+//! ```
+use crate::{parse, BreakerRegistry, Handle};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt, path::Path, time::SystemTime};
+use tokio::time::{self, Duration, MissedTickBehavior};
+
+/// Runtime-reloadable settings for the breakers in a [`BreakerRegistry`].
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[non_exhaustive]
+pub struct Settings {
+    /// Settings for each breaker, by name.
+    #[serde(default)]
+    pub breakers: BTreeMap<String, BreakerSettings>,
+}
+
+/// Runtime-reloadable settings for a single breaker.
+///
+/// Any setting which is `None` is left unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[non_exhaustive]
+pub struct BreakerSettings {
+    /// How long the breaker remains open once tripped, as a string such as
+    /// `"30s"` or `"500ms"`.
+    #[serde(default, deserialize_with = "parse::deserialize_duration")]
+    pub trip_for: Option<Duration>,
+    /// Whether requests fail fast while the circuit is open.
+    #[serde(default)]
+    pub fail_fast: Option<bool>,
+}
+
+/// Watches the file at `path` for changes, applying the [`Settings`] it
+/// contains to the breakers in `registry`.
+///
+/// The file is read and applied immediately, and then again whenever its
+/// modification time changes. Its modification time is checked once every
+/// `interval`. `parse` is called with the file's contents to parse them into
+/// [`Settings`].
+///
+/// If the file cannot be read or parsed, a warning is logged and the
+/// breakers' current configuration is left unchanged.
+///
+/// The returned future never completes; it should typically be spawned as a
+/// background task.
+pub async fn watch_file<F, E>(
+    path: impl AsRef<Path>,
+    registry: BreakerRegistry,
+    interval: Duration,
+    parse: F,
+) where
+    F: Fn(&str) -> Result<Settings, E>,
+    E: fmt::Display,
+{
+    let path = path.as_ref();
+    let mut interval = time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_modified: Option<SystemTime> = None;
+    loop {
+        interval.tick().await;
+        let modified = match tokio::fs::metadata(path).await.and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(error) => {
+                tracing::warn!(path = %path.display(), %error, "failed to stat breaker config");
+                continue;
+            }
+        };
+        if last_modified == Some(modified) {
+            continue;
+        }
+        last_modified = Some(modified);
+
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(error) => {
+                tracing::warn!(path = %path.display(), %error, "failed to read breaker config");
+                continue;
+            }
+        };
+        match parse(&contents) {
+            Ok(settings) => {
+                tracing::info!(path = %path.display(), "reloading breaker config");
+                settings.apply(&registry);
+            }
+            Err(error) => {
+                tracing::warn!(path = %path.display(), %error, "failed to parse breaker config");
+            }
+        }
+    }
+}
+
+// === impl Settings ===
+
+impl Settings {
+    /// Applies these settings to the breakers in `registry`.
+    ///
+    /// Settings for breakers which are not in the registry are ignored.
+    pub fn apply(&self, registry: &BreakerRegistry) {
+        for (name, settings) in &self.breakers {
+            match registry.get(name) {
+                Some(handle) => settings.apply(&handle),
+                None => tracing::debug!(breaker = %name, "no breaker to reconfigure"),
+            }
+        }
+    }
+}
+
+// === impl BreakerSettings ===
+
+impl BreakerSettings {
+    /// Applies these settings to the breaker referenced by `handle`.
+    pub fn apply(&self, handle: &Handle) {
+        if let Some(trip_for) = self.trip_for {
+            handle.set_trip_for(trip_for);
+        }
+        if let Some(fail_fast) = self.fail_fast {
+            handle.set_fail_fast(fail_fast);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::SlidingFailureRate, CircuitBreaker, Config};
+
+    #[tokio::test]
+    async fn watches_file() {
+        let path = std::env::temp_dir().join(format!("tower-breaker-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"breakers": {"foo": {"trip_for": "1s", "fail_fast": true}, "bar": {}}}"#,
+        )
+        .unwrap();
+
+        let registry = BreakerRegistry::new();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_name("foo")
+            .with_registry(registry.clone());
+        let breaker = CircuitBreaker::new(config, ());
+        let handle = breaker.handle();
+
+        let watch = tokio::spawn(watch_file(
+            path.clone(),
+            registry,
+            Duration::from_millis(10),
+            |s: &str| serde_json::from_str::<Settings>(s),
+        ));
+        while handle.config().trip_for != Duration::from_secs(1) {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(handle.config().fail_fast);
+
+        watch.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+}
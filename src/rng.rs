@@ -0,0 +1,104 @@
+//! Pluggable random number generation.
+//!
+//! Randomized behavior, such as [trip jitter](crate::Config::with_trip_jitter)
+//! and [failure injection](crate::chaos::FailureInjector), draws random
+//! numbers from an [`Rng`]. By default, a randomly seeded [`XorShift64`]
+//! generator is used; providing a generator with a fixed seed makes that
+//! behavior reproducible, such as in tests and simulations of
+//! many breakers.
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// A source of random numbers.
+///
+/// Implementations need not be cryptographically secure.
+pub trait Rng: fmt::Debug + Send + 'static {
+    /// Returns the next random `u64`.
+    fn next_u64(&mut self) -> u64;
+
+    /// Returns a random number in the range `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A small, fast [xorshift64*] pseudo-random number generator.
+///
+/// This is not cryptographically secure.
+///
+/// [xorshift64*]: https://en.wikipedia.org/wiki/Xorshift#xorshift*
+#[derive(Clone, Debug)]
+pub struct XorShift64(u64);
+
+/// An [`Rng`] shared between clones of the type that owns it.
+pub(crate) type SharedRng = Arc<Mutex<dyn Rng>>;
+
+pub(crate) fn shared(rng: impl Rng) -> SharedRng {
+    Arc::new(Mutex::new(rng))
+}
+
+pub(crate) fn next_f64(rng: &SharedRng) -> f64 {
+    rng.lock().unwrap().next_f64()
+}
+
+// === impl XorShift64 ===
+
+impl XorShift64 {
+    /// Returns a new randomly seeded generator.
+    pub fn from_entropy() -> Self {
+        // `RandomState` is seeded by the OS's random number generator where
+        // one is available, so generators created at the same time aren't
+        // seeded alike. mix in a counter so that generators created by the
+        // same thread don't produce the same sequence.
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        Self::seed(hasher.finish())
+    }
+
+    /// Returns a new generator with the given `seed`.
+    ///
+    /// Generators with the same seed produce the same sequence of numbers.
+    pub fn seed(seed: u64) -> Self {
+        // xorshift gets stuck at 0.
+        XorShift64(if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        })
+    }
+}
+
+impl Rng for XorShift64 {
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let mut a = XorShift64::seed(42);
+        let mut b = XorShift64::seed(42);
+        for _ in 0..100 {
+            let n = a.next_f64();
+            assert!((0.0..1.0).contains(&n));
+            assert_eq!(n, b.next_f64());
+        }
+    }
+}
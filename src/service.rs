@@ -1,121 +1,2632 @@
-use crate::{Config, Policy};
+#[cfg(feature = "events")]
+use crate::events::{Classification, OutcomeEvent};
+#[cfg(feature = "tracing")]
+use crate::rate_limit;
+#[cfg(any(feature = "tracing", feature = "events"))]
+use crate::rate_limit::RateLimiter;
+use crate::{
+    classify::{Classifier, ErrorClass},
+    clock::SharedClock,
+    console,
+    deadline::{Deadline, Latencies},
+    error::{BoxError, CircuitOpen, DeadlineUnreachable, NoTimer, ShutDown},
+    extract::Extractor,
+    handle::{Command, InFlight, Shared, TripEvent},
+    priority::Priority,
+    rng, shm,
+    snapshot::ConfigSnapshot,
+    state::StateMachine,
+    store::{StateStore, StoredState},
+    timer::{SharedTimer, Sleep},
+    trace::{debug, dyn_event, dyn_span, trace, Span},
+    CircuitState, Config, Handle, Policy, Rejection, Transition, TripReason,
+};
 use std::{
+    any::Any,
+    fmt,
     future::Future,
     pin::Pin,
-    task::{Context, Poll},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::time::{self, Instant};
+use tokio::sync::watch;
 use tower_service::Service;
 
 pub struct CircuitBreaker<P, S> {
     inner: S,
-    config: Config<P>,
-    tripped: bool,
+    /// The state of the circuit. This is shared with the breaker's
+    /// [driver](CircuitBreaker::driver), if one has been spawned.
+    circuit: Arc<Mutex<Circuit<P>>>,
+    shared: Arc<Shared>,
+    /// Whether `poll_ready` has returned `Pending` because the circuit is
+    /// open, and has not yet become ready again.
+    parked: bool,
     // TODO(eliza): exponential backoff?
-    tripped_until: Pin<Box<time::Sleep>>,
+    /// Wakes the task once the current trip ends, along with the deadline it
+    /// was created for. This is created when the breaker is first polled
+    /// while its circuit is open, is replaced when the trip's deadline
+    /// changes, and is dropped once nothing needs to wait for the trip to
+    /// end, so that idle breakers don't hold on to a timer.
+    tripped_until: Option<(Instant, Sleep)>,
+    /// Whether the next request is passed to the inner service even though
+    /// the circuit is open, because it wasn't chosen to be shed.
+    unshed: bool,
+    /// Why the next request is shed even though the circuit is closed, if
+    /// it is, because the policy is degraded or traffic is ramping up.
+    shed_closed: Option<TripReason>,
+    /// When the inner service last started returning `Pending` from
+    /// `poll_ready`, if it isn't ready yet.
+    not_ready_since: Option<Instant>,
+    prioritizer: Extractor<Priority>,
+    deadlines: Extractor<Deadline>,
+    fixed: Arc<Fixed<P>>,
+    /// Whether the next request was admitted by `poll_circuit` without
+    /// locking the circuit.
+    fast_admitted: bool,
+}
+
+/// The parts of a breaker's configuration which never change, so that
+/// requests can be admitted and their outcomes recorded without locking the
+/// circuit.
+///
+/// Each admitted request shares these with the breaker, rather than cloning
+/// them, so that passing a request through the breaker doesn't allocate.
+#[derive(Debug)]
+struct Fixed<P> {
+    policy: P,
+    classifier: Classifier,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    overload_weight: Option<usize>,
+    instruments: Instruments,
+    clock: SharedClock,
+    /// The latencies of recent successful requests, if they're tracked for
+    /// deadline admission.
+    latencies: Option<Arc<Latencies>>,
+    /// Whether requests are timed, for deadline admission, the policy, call
+    /// spans, or outcome events.
+    timed: bool,
+    #[cfg(feature = "tracing")]
+    span_level: tracing::Level,
+    /// Whether each request's span is a `circuit_breaker.call` span recording
+    /// its outcome.
+    #[cfg(feature = "tracing")]
+    call_spans: bool,
+    /// Whether the circuit only needs to be locked while it's open, when a
+    /// `Handle` has changed it, or when the policy punishes the endpoint.
+    ///
+    /// Breakers which share memory with other processes, depend on other
+    /// breakers, shed load while closed, or track latencies must lock it on
+    /// every request.
+    fast_path: bool,
+    /// Decides when the policy is consulted on the fast path, if it isn't
+    /// consulted every time.
+    sampler: Option<Sampler>,
+    /// Whether the outcomes of requests admitted before the policy was last
+    /// reset are discarded.
+    probe_only: bool,
+}
+
+/// Consults a breaker's policy on every Nth poll, adapting N so that the
+/// policy is consulted about once per interval.
+#[derive(Debug)]
+struct Sampler {
+    interval: Duration,
+    clock: SharedClock,
+    anchor: Instant,
+    /// The number of polls since the policy was last consulted.
+    polls: AtomicU32,
+    /// The number of polls between consulting the policy.
+    every: AtomicU32,
+    /// When the policy was last consulted, in nanoseconds since `anchor`.
+    last: AtomicU64,
+}
+
+/// The most polls between consulting the policy, however often the breaker
+/// is polled.
+const MAX_SAMPLE_EVERY: u32 = 1 << 16;
+
+/// The state of a breaker's circuit, along with everything needed to open
+/// and close it.
+struct Circuit<P> {
+    config: Config<P>,
+    shared: Arc<Shared>,
+    machine: StateMachine,
+    /// The number of requests and failures in the policy's window when the
+    /// circuit was last opened.
+    trip_window: (Option<usize>, Option<usize>),
+    /// The latencies of recent successful requests, for deadline admission.
+    latencies: Arc<Latencies>,
+    /// The breaker's slot in a shared memory segment, if it shares its
+    /// circuit with other processes.
+    shm: Option<shm::Slot>,
+    /// Receives configuration changes made by a `Handle`.
+    reconfigure: watch::Receiver<ConfigSnapshot>,
+    /// Wakes callers once the current trip ends.
+    timer: SharedTimer,
+    resource: console::Resource,
+    /// Limits the rejections logged, if the breaker's events are limited.
+    #[cfg(feature = "tracing")]
+    rejection_logs: Option<RateLimiter>,
 }
 
 pin_project_lite::pin_project! {
     #[derive(Debug)]
     pub struct ResponseFuture<P, F> {
+        // If this is `None`, the request was rejected because the circuit was
+        // open.
         #[pin]
-        future: F,
-        policy: P,
+        future: Option<F>,
+        rejected: Option<Rejected>,
+        admitted: Option<Admitted<P>>,
+        span: Span,
     }
 }
 
+/// A request which has been passed to a breaker's inner service.
+#[derive(Debug)]
+pub(crate) struct Admitted<P> {
+    fixed: Arc<Fixed<P>>,
+    // Tracks the request until it completes, so that shutdown can wait for
+    // it.
+    in_flight: InFlight,
+    /// When the request was admitted, if its latency is tracked.
+    started: Option<Instant>,
+    /// The number of times the policy had been reset when the request was
+    /// admitted, if its outcome is discarded once the policy is reset again.
+    policy_resets: Option<u64>,
+    /// The state of the circuit when the request was admitted.
+    #[cfg(feature = "events")]
+    state: CircuitState,
+    /// The request's span, if its outcome is recorded on it.
+    #[cfg(feature = "tracing")]
+    span: Span,
+}
+
+/// How an admitted request's outcome was classified.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(not(any(feature = "tracing", feature = "events")), allow(dead_code))]
+enum Completion {
+    Success,
+    Failure,
+    Category(&'static str),
+    Ignored,
+}
+
+/// Why a breaker rejected a request without passing it to its inner service.
+#[derive(Debug)]
+pub(crate) enum Rejected {
+    Open(CircuitOpen),
+    Deadline(DeadlineUnreachable),
+}
+
+/// Per-request instrumentation carried by a [`ResponseFuture`].
+#[derive(Clone, Debug)]
+struct Instruments {
+    resource: console::Resource,
+    #[cfg(feature = "opentelemetry")]
+    otel: Option<crate::otel::OtelMetrics>,
+    #[cfg(feature = "events")]
+    events: Option<crate::events::OutcomeSender>,
+}
+
 // === impl CircuitBreaker ===
 
 impl<P, S> CircuitBreaker<P, S>
 where
-    P: Policy + Clone,
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
 {
+    /// Returns a new `CircuitBreaker` wrapping `inner`.
+    ///
+    /// This doesn't require a Tokio runtime, so breakers may be constructed
+    /// while building service stacks during startup, or in lazily initialized
+    /// statics. The breaker's [`Timer`](crate::timer::Timer) is only used
+    /// once the breaker is polled while its circuit is open.
+    ///
+    /// # Panics
+    ///
+    /// If `config` has no timer, because the `rt-tokio` feature flag is
+    /// disabled and none was provided using [`Config::with_timer`]. Use
+    /// [`try_new`](Self::try_new) to handle this as an error instead.
+    #[track_caller]
     pub fn new(config: Config<P>, inner: S) -> Self {
-        // because we don't start in the "tripped" state, this initial sleep
-        // will not be polled...
-        let tripped_until = Box::pin(time::sleep(config.trip_for));
-        CircuitBreaker {
-            inner,
+        match Self::try_new(config, inner) {
+            Ok(breaker) => breaker,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Returns a new `CircuitBreaker` wrapping `inner`, or an error if
+    /// `config` has no timer.
+    ///
+    /// Without the `rt-tokio` feature flag, a timer must be provided using
+    /// [`Config::with_timer`], since the breaker wouldn't otherwise be able to
+    /// wake callers waiting for the circuit to close.
+    pub fn try_new(config: Config<P>, inner: S) -> Result<Self, NoTimer> {
+        let timer = config
+            .timer
+            .clone()
+            .ok_or_else(|| NoTimer::new(config.name.clone()))?;
+        #[cfg(feature = "opentelemetry")]
+        let config = Config {
+            otel: config
+                .otel
+                .as_ref()
+                .map(|otel| otel.for_breaker(config.name.as_deref())),
+            ..config
+        };
+        #[cfg(feature = "events")]
+        let config = Config {
+            outcome_events: config.outcome_events.as_ref().map(|events| {
+                match RateLimiter::from_config(&config) {
+                    Some(limiter) => events.limited(limiter),
+                    None => events.clone(),
+                }
+            }),
+            ..config
+        };
+        let shared = Shared::new(config.name.clone(), config.trip_history)
+            .with_config(ConfigSnapshot {
+                trip_for: config.trip_for,
+                fail_fast: config.fail_fast,
+            })
+            .with_policy(config.policy.clone())
+            .with_clock(config.clock.clone())
+            .with_timer(timer.clone())
+            .with_hooks(config.hooks.clone());
+        let shared = Arc::new(shared);
+        let reconfigure = shared.config.subscribe();
+        if let Some(ref registry) = config.registry {
+            registry.register(&Handle::new(shared.clone()));
+        }
+        let resource = console::Resource::new(config.name.as_deref());
+        let latencies = Arc::new(Latencies::default());
+        let instruments = Instruments {
+            resource: resource.clone(),
+            #[cfg(feature = "opentelemetry")]
+            otel: config.otel.clone(),
+            #[cfg(feature = "events")]
+            events: config.outcome_events.clone(),
+        };
+        #[cfg(feature = "tracing")]
+        let call_spans = config.call_spans;
+        #[cfg(not(feature = "tracing"))]
+        let call_spans = false;
+        let timed = config.deadline_quantile.is_some()
+            || config.policy.measures_latency()
+            || call_spans
+            || instruments.sends_events();
+        let fixed = Arc::new(Fixed {
+            policy: config.policy.clone(),
+            classifier: config.classifier.clone(),
+            overload_weight: config.overload_weight,
+            instruments,
+            clock: config.clock.clone(),
+            latencies: config.deadline_quantile.map(|_| latencies.clone()),
+            timed,
+            #[cfg(feature = "tracing")]
+            span_level: config.span_level,
+            #[cfg(feature = "tracing")]
+            call_spans,
+            fast_path: config.shared_memory.is_none()
+                && config.dependencies.is_empty()
+                && config.ramp_up.is_none()
+                && config.degraded_shedding.is_empty()
+                && config.priority_shedding.is_empty()
+                && config.deadline_quantile.is_none(),
+            sampler: config
+                .evaluation_interval
+                .map(|interval| Sampler::new(interval, config.clock.clone())),
+            probe_only: config.probe_only,
+        });
+        let prioritizer = config.prioritizer.clone();
+        let deadlines = config.deadline.clone();
+        let shm = config
+            .shared_memory
+            .as_ref()
+            .zip(config.name.as_deref())
+            .and_then(|(shm, name)| shm.slot(name));
+        let mut circuit = Circuit {
+            #[cfg(feature = "tracing")]
+            rejection_logs: RateLimiter::from_config(&config),
             config,
-            tripped: false,
-            tripped_until,
+            shared: shared.clone(),
+            machine: StateMachine::default(),
+            trip_window: (None, None),
+            latencies,
+            shm,
+            reconfigure,
+            timer,
+            resource,
+        };
+        if let Some(open_for) = circuit.config.initially_open {
+            circuit.open_initially(open_for);
+        }
+        let circuit = Arc::new(Mutex::new(circuit));
+        let peers = {
+            let circuit = circuit.lock().unwrap();
+            circuit
+                .config
+                .peers
+                .clone()
+                .zip(circuit.config.name.clone())
+        };
+        if let Some((peers, name)) = peers {
+            let circuit = Arc::downgrade(&circuit);
+            peers.subscribe(
+                &name,
+                Box::new(move |state| {
+                    let Some(circuit) = circuit.upgrade() else {
+                        return false;
+                    };
+                    let mut circuit = circuit.lock().unwrap();
+                    if circuit.apply_peer(state) {
+                        circuit.shared.wake();
+                    }
+                    true
+                }),
+            );
         }
+        Ok(CircuitBreaker {
+            inner,
+            circuit,
+            shared,
+            parked: false,
+            tripped_until: None,
+            unshed: false,
+            shed_closed: None,
+            not_ready_since: None,
+            prioritizer,
+            deadlines,
+            fixed,
+            fast_admitted: false,
+        })
     }
 
     pub fn is_tripped(&self) -> bool {
-        self.tripped
+        self.state() == CircuitState::Open
+    }
+
+    /// Returns the current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        *self.shared.state.borrow()
+    }
+
+    /// Returns a [`watch::Receiver`] that is notified whenever the state of
+    /// this circuit changes.
+    ///
+    /// This allows other tasks to react when the circuit opens or closes,
+    /// without having to poll the breaker.
+    pub fn state_receiver(&self) -> watch::Receiver<CircuitState> {
+        self.shared.state.subscribe()
+    }
+
+    /// Returns how long until the circuit is expected to close, or `None` if
+    /// it's closed or has been forced open.
+    ///
+    /// Callers can use this to schedule their own retries, or to tell users
+    /// when to try again. The same duration is included in the
+    /// [`CircuitOpen`] errors of rejected requests, as their
+    /// [`retry_after`](CircuitOpen::retry_after), and in the breaker's
+    /// [snapshots](crate::snapshot::BreakerSnapshot::open_remaining).
+    pub fn open_remaining(&self) -> Option<Duration> {
+        self.shared.open_remaining()
+    }
+
+    /// Returns a [`Handle`] for observing this breaker from other tasks.
+    pub fn handle(&self) -> Handle {
+        Handle::new(self.shared.clone())
+    }
+
+    /// Returns a future which evaluates the breaker's circuit every
+    /// `interval`, until the breaker is dropped.
+    ///
+    /// Normally, the circuit is only opened or closed when the breaker is
+    /// polled for readiness, so while the breaker is idle, its
+    /// [state](Self::state) (as seen by [`state_receiver`]s and [`Handle`]s)
+    /// may be stale: a circuit whose trip has ended stays open until the
+    /// next request, and commands sent by a [`Handle`] aren't applied. The
+    /// driver opens and closes the circuit on the breaker's behalf, so that
+    /// its state reflects reality during idle periods.
+    ///
+    /// The returned future should be spawned (e.g. with `tokio::spawn`). It
+    /// sleeps using the breaker's [`Timer`](crate::timer::Timer), and
+    /// completes once the breaker has been dropped.
+    ///
+    /// [`state_receiver`]: Self::state_receiver
+    pub fn driver(&self, interval: Duration) -> impl Future<Output = ()> + Send + 'static {
+        let circuit = Arc::downgrade(&self.circuit);
+        let timer = self.circuit.lock().unwrap().timer.clone();
+        async move {
+            loop {
+                timer.sleep(interval).await;
+                let Some(circuit) = circuit.upgrade() else {
+                    return;
+                };
+                let mut circuit = circuit.lock().unwrap();
+                if circuit.evaluate() {
+                    // if a task is parked waiting for the circuit to close,
+                    // let it know that it has.
+                    circuit.shared.wake();
+                }
+            }
+        }
+    }
+
+    /// Returns a future which shares this breaker's trips with other breakers
+    /// through `store`, syncing with the store every `interval` until the
+    /// breaker is dropped.
+    ///
+    /// Breakers which sync with the same `store` under the same `key` share
+    /// their trips: while this breaker's policy has tripped it, it publishes
+    /// the trip to the store, and while its circuit is closed, it checks the
+    /// store for a trip published by a peer, and opens for the remainder of
+    /// that trip if there is one. Trips are exchanged in wall-clock time, so
+    /// the peers' clocks should be reasonably well synchronized.
+    ///
+    /// Errors talking to the store are logged, and the breaker carries on
+    /// making its own decisions. Like the [driver](Self::driver), the
+    /// returned future should be spawned, and it also evaluates the circuit
+    /// on each sync.
+    ///
+    /// See the [`store`](crate::store) module for details.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn sync_state(
+        &self,
+        store: impl StateStore,
+        key: impl Into<String>,
+        interval: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let circuit = Arc::downgrade(&self.circuit);
+        let timer = self.circuit.lock().unwrap().timer.clone();
+        let key = key.into();
+        async move {
+            loop {
+                timer.sleep(interval).await;
+                let Some(circuit) = circuit.upgrade() else {
+                    return;
+                };
+                let published = {
+                    let mut circuit = circuit.lock().unwrap();
+                    if circuit.evaluate() {
+                        circuit.shared.wake();
+                    }
+                    circuit.stored_state()
+                };
+
+                if let Some((state, ttl)) = published {
+                    if let Err(error) = store.set(&key, state, ttl).await {
+                        debug!(key = %key, %error, "failed to publish breaker state");
+                    }
+                    continue;
+                }
+
+                match store.get(&key).await {
+                    Ok(Some(state)) => {
+                        let mut circuit = circuit.lock().unwrap();
+                        if circuit.apply_stored(&state, TripReason::Shared) {
+                            circuit.shared.wake();
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        debug!(key = %key, %error, "failed to fetch breaker state");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Polls the circuit for readiness, without polling the inner service.
+    ///
+    /// This returns `Ok(true)` if the circuit is closed and the inner service
+    /// should be polled, or `Ok(false)` if the circuit is open and the
+    /// breaker is failing fast, so `call` will reject the request.
+    pub(crate) fn poll_circuit(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool, ShutDown>> {
+        if self.shared.is_shut_down() {
+            return Poll::Ready(Err(ShutDown::new(self.shared.name.clone())));
+        }
+
+        // in the common case, the circuit is closed and will stay closed, so
+        // there's no need to lock it.
+        let fixed = &*self.fixed;
+        if fixed.fast_path
+            && self.shared.is_settled()
+            && !(fixed.sampler.as_ref().is_none_or(Sampler::is_due)
+                && fixed.policy.punish_reason().is_some())
+        {
+            self.parked = false;
+            self.tripped_until = None;
+            self.unshed = false;
+            self.shed_closed = None;
+            self.fast_admitted = true;
+            return Poll::Ready(Ok(true));
+        }
+        self.fast_admitted = false;
+
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.evaluate();
+        self.shed_closed = None;
+
+        if circuit.is_tripped() && !circuit.shed() {
+            drop(circuit);
+            self.parked = false;
+            self.tripped_until = None;
+            self.unshed = true;
+            return Poll::Ready(Ok(true));
+        }
+
+        if circuit.is_tripped() {
+            if circuit.config.fail_fast {
+                // if we're failing fast, the circuit is "ready", but `call`
+                // will reject the request, so nothing needs to wake us.
+                self.tripped_until = None;
+                return Poll::Ready(Ok(false));
+            }
+
+            // if the circuit was forced open, it stays open until it's reset,
+            // so there's no point in waking up when the trip ends.
+            if let Some(deadline) = circuit.closes_at() {
+                // wake up when the trip ends. the breaker's clock is not
+                // necessarily driven by its timer, so the clock, rather than
+                // the timer, determines whether the trip is over.
+                let remaining = deadline.saturating_duration_since(circuit.config.clock.now());
+                let timer = &circuit.timer;
+                if !matches!(self.tripped_until, Some((armed, _)) if armed == deadline) {
+                    self.tripped_until = Some((deadline, timer.sleep(remaining)));
+                }
+                let (_, sleep) = self.tripped_until.as_mut().expect("timer was just set");
+                if sleep.as_mut().poll(cx).is_ready() {
+                    // the timer fired, but the clock says the trip isn't over
+                    // yet. sleep for the remainder of the trip.
+                    let mut sleep = timer.sleep(remaining);
+                    let _ = sleep.as_mut().poll(cx);
+                    self.tripped_until = Some((deadline, sleep));
+                }
+            } else {
+                self.tripped_until = None;
+            }
+
+            if !self.parked {
+                self.parked = true;
+                circuit.record_rejection();
+                let hooks = self.shared.hooks();
+                if hooks.has_on_reject() {
+                    let error =
+                        CircuitOpen::new(circuit.config.name.clone(), circuit.machine.reason())
+                            .with_retry_after(circuit.retry_after());
+                    hooks.rejected(&Rejection::parked(&error));
+                }
+            }
+            // wake up if a handle sends us a command.
+            self.shared.register_waker(cx.waker());
+            return Poll::Pending;
+        }
+        self.shed_closed = circuit.ramp_shed();
+        drop(circuit);
+        self.parked = false;
+        self.tripped_until = None;
+        self.unshed = false;
+        // requests shed while the circuit is closed always fail fast, since
+        // the circuit may never open.
+        Poll::Ready(Ok(self.shed_closed.is_none()))
+    }
+
+    /// Decides whether a request may be passed to the inner service,
+    /// returning the request's span along with either what's needed to
+    /// record the request's outcome, or the error to reject it with.
+    pub(crate) fn admit(
+        &mut self,
+        priority: Priority,
+        deadline: Option<Deadline>,
+    ) -> (Span, Result<Admitted<P>, Rejected>) {
+        if std::mem::take(&mut self.fast_admitted) {
+            let span = self.request_span(CircuitState::Closed);
+            let admitted = self.admitted(CircuitState::Closed, &span);
+            return (span, Ok(admitted));
+        }
+
+        let circuit = self.circuit.lock().unwrap();
+        let tripped = circuit.is_tripped() && !std::mem::take(&mut self.unshed);
+        debug_assert!(
+            circuit.config.fail_fast || !tripped,
+            "tried to call a tripped circuit breaker!"
+        );
+        let span = self.request_span(circuit.state());
+        let shed = match self.shed_closed.take() {
+            _ if tripped => None,
+            Some(reason) => Some(reason),
+            None => circuit.degraded_shed(priority),
+        };
+        if let Some(reason) = shed {
+            let error = CircuitOpen::new(circuit.config.name.clone(), Some(reason));
+            let rejected = circuit.reject(Rejected::Open(error), &span);
+            return (span, Err(rejected));
+        }
+        if tripped {
+            let error = CircuitOpen::new(circuit.config.name.clone(), circuit.machine.reason())
+                .with_retry_after(circuit.retry_after());
+            let rejected = circuit.reject(Rejected::Open(error), &span);
+            return (span, Err(rejected));
+        }
+        if let Some(error) = deadline.and_then(|deadline| circuit.check_deadline(deadline)) {
+            let rejected = circuit.reject(Rejected::Deadline(error), &span);
+            return (span, Err(rejected));
+        }
+
+        let state = circuit.state();
+        drop(circuit);
+        let admitted = self.admitted(state, &span);
+        (span, Ok(admitted))
+    }
+
+    /// Returns the span for a request made while the circuit is in `state`.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn request_span(&self, state: CircuitState) -> Span {
+        #[cfg(feature = "tracing")]
+        if self.fixed.call_spans {
+            return dyn_span!(
+                self.fixed.span_level,
+                "circuit_breaker.call",
+                breaker = self.shared.name.as_deref(),
+                state = state.as_str(),
+                outcome = tracing::field::Empty,
+                latency = tracing::field::Empty,
+            );
+        }
+        dyn_span!(
+            self.fixed.span_level,
+            "circuit_breaker",
+            breaker = self.shared.name.as_deref(),
+            state = state.as_str(),
+        )
+    }
+
+    /// Passes a request refused with `rejected` to the breaker's
+    /// [rejection hooks](Config::on_reject), if it was refused because the
+    /// circuit is open.
+    ///
+    /// `req` is only called to produce the request if there are any hooks.
+    pub(crate) fn rejected<R: Any>(&self, rejected: &Rejected, req: impl FnOnce() -> R) {
+        let hooks = self.shared.hooks();
+        if let (Rejected::Open(error), true) = (rejected, hooks.has_on_reject()) {
+            let req = req();
+            hooks.rejected(&Rejection::refused(error, Some(&req)));
+        }
+    }
+
+    /// Starts tracking a request passed to the inner service while the
+    /// circuit is in `state`.
+    #[cfg_attr(
+        not(all(feature = "tracing", feature = "events")),
+        allow(unused_variables)
+    )]
+    fn admitted(&self, state: CircuitState, span: &Span) -> Admitted<P> {
+        let fixed = self.fixed.clone();
+        let started = fixed.timed.then(|| fixed.clock.now());
+        #[cfg(feature = "tracing")]
+        let span = match fixed.call_spans {
+            true => span.clone(),
+            false => Span::none(),
+        };
+        let policy_resets = fixed.probe_only.then(|| self.shared.policy_resets());
+        Admitted {
+            fixed,
+            in_flight: self.shared.start_request(),
+            started,
+            policy_resets,
+            #[cfg(feature = "events")]
+            state,
+            #[cfg(feature = "tracing")]
+            span,
+        }
+    }
+
+    /// Polls the inner service for readiness, recording how long it took to
+    /// become ready with the policy.
+    pub(crate) fn poll_inner_ready<Req>(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), S::Error>>
+    where
+        S: Service<Req>,
+    {
+        let poll = self.inner.poll_ready(cx);
+        let clock = &self.shared.clock;
+        match poll {
+            Poll::Pending => {
+                self.not_ready_since.get_or_insert_with(|| clock.now());
+            }
+            Poll::Ready(_) => {
+                // only read the clock if the service wasn't ready right away.
+                let delay = self.not_ready_since.take().map_or(Duration::ZERO, |since| {
+                    clock.now().saturating_duration_since(since)
+                });
+                self.fixed.policy.record_ready_delay(delay);
+            }
+        }
+        poll
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the current time, according to the breaker's clock.
+    #[cfg(feature = "grpc")]
+    pub(crate) fn now(&self) -> Instant {
+        self.shared.clock.now()
     }
 }
 
 impl<P, S, Req> Service<Req> for CircuitBreaker<P, S>
 where
-    P: Policy + Clone,
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
     S: Service<Req>,
+    Req: 'static,
+    S::Error: Into<BoxError>,
 {
     type Response = S::Response;
-    type Error = S::Error;
+    type Error = BoxError;
     type Future = ResponseFuture<P, S::Future>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        if self.config.policy.is_punished() {
-            tracing::trace!(
-                "service sent to the Punishment Zone for {:?}",
-                self.config.trip_for
+        if self.fixed.policy.is_disabled() {
+            return self.inner.poll_ready(cx).map_err(Into::into);
+        }
+        match self.poll_circuit(cx) {
+            Poll::Ready(Ok(true)) => self.poll_inner_ready(cx).map_err(Into::into),
+            Poll::Ready(Ok(false)) => Poll::Ready(Ok(())),
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        if self.fixed.policy.is_disabled() {
+            return ResponseFuture {
+                future: Some(self.inner.call(req)),
+                rejected: None,
+                admitted: None,
+                span: Span::none(),
+            };
+        }
+        let priority = self.prioritizer.extract(&req).unwrap_or_default();
+        let deadline = self.deadlines.extract(&req);
+        let (span, admitted) = self.admit(priority, deadline);
+        match admitted {
+            Ok(admitted) => ResponseFuture {
+                future: Some(span.in_scope(|| self.inner.call(req))),
+                rejected: None,
+                admitted: Some(admitted),
+                span,
+            },
+            Err(error) => {
+                self.rejected(&error, || req);
+                ResponseFuture {
+                    future: None,
+                    rejected: Some(error),
+                    admitted: None,
+                    span,
+                }
+            }
+        }
+    }
+}
+
+impl<P, S: Clone> Clone for CircuitBreaker<P, S> {
+    /// Returns a new breaker wrapping a clone of the inner service, which
+    /// shares this breaker's circuit.
+    fn clone(&self) -> Self {
+        CircuitBreaker {
+            inner: self.inner.clone(),
+            circuit: self.circuit.clone(),
+            shared: self.shared.clone(),
+            parked: false,
+            tripped_until: None,
+            unshed: false,
+            shed_closed: None,
+            not_ready_since: None,
+            prioritizer: self.prioritizer.clone(),
+            deadlines: self.deadlines.clone(),
+            fixed: self.fixed.clone(),
+            fast_admitted: false,
+        }
+    }
+}
+
+// === impl Circuit ===
+
+impl<P> Circuit<P>
+where
+    P: Policy + fmt::Debug,
+{
+    fn state(&self) -> CircuitState {
+        self.machine.state()
+    }
+
+    fn is_tripped(&self) -> bool {
+        self.machine.is_open()
+    }
+
+    /// Applies any pending command or configuration change from a `Handle`,
+    /// then opens or closes the circuit as the policy and the current trip
+    /// dictate.
+    ///
+    /// Returns `true` if the state of the circuit changed.
+    fn evaluate(&mut self) -> bool {
+        let before = self.state();
+        if let Some(command) = self.shared.take_command() {
+            self.apply(command);
+        }
+        if self.reconfigure.has_changed().unwrap_or(false) {
+            let config = *self.reconfigure.borrow_and_update();
+            self.reconfigure(config);
+        }
+
+        // if the circuit has been forced into a state, don't consult the
+        // policy or other processes.
+        if self.machine.forced().is_none() {
+            self.sync_shared_memory();
+            self.sync_dependencies();
+            if let Some(reason) = self.config.policy.punish_reason() {
+                // trip the breaker
+                self.trip(reason);
+            }
+        }
+
+        // are we still waiting to become un-punished? if the circuit was
+        // forced open, it stays open until it's reset.
+        if self.is_tripped() && self.machine.forced().is_none() && self.trip_expired() {
+            self.close();
+        }
+
+        self.shared.set_closes_at(self.closes_at());
+        self.state() != before
+    }
+
+    /// Publishes the circuit's state, notifying hooks if it changed.
+    ///
+    /// Returns `true` if the state changed, rather than the circuit tripping
+    /// again while it was already open.
+    fn set_state(&mut self, to: CircuitState, reason: Option<TripReason>) -> bool {
+        let from = self.shared.set_state(to);
+        if from == to {
+            return false;
+        }
+        self.config
+            .hooks
+            .state_changed(Transition { from, to, reason });
+        true
+    }
+
+    fn trip(&mut self, reason: TripReason) {
+        let retry_after = self.shared.take_retry_after();
+        let trip_for = match (retry_after, self.config.max_retry_after) {
+            (Some(retry_after), Some(max)) => retry_after.min(max),
+            _ => self.config.trip_for,
+        };
+        let trip_for = self.jittered(trip_for);
+        self.trip_for(reason, trip_for);
+    }
+
+    /// Opens the circuit for `open_for` as the breaker is constructed.
+    ///
+    /// Unlike a trip, this isn't broadcast or alerted, since the breaker may
+    /// be constructed outside a runtime, and doesn't reset the policy, which
+    /// hasn't recorded anything yet.
+    fn open_initially(&mut self, open_for: Duration) {
+        let reason = TripReason::Initial;
+        #[cfg(feature = "tracing")]
+        crate::trace::circuit_opened(
+            self.config.transition_level,
+            self.config.name.as_deref(),
+            reason,
+            Some(&self.config.policy),
+            &self.config.policy.snapshot(),
+            open_for,
+        );
+        let now = self.config.clock.now();
+        self.machine.open(now, reason, open_for);
+        self.set_state(CircuitState::Open, Some(reason));
+        self.shared.record_trip(TripEvent {
+            at: now,
+            timestamp: SystemTime::now(),
+            trip_for: open_for,
+            open_for: None,
+            reason,
+            policy: format!("{:?}", self.config.policy),
+        });
+        self.resource.opened();
+        #[cfg(feature = "opentelemetry")]
+        if let Some(otel) = self.config.otel.as_ref() {
+            otel.record_transition(CircuitState::Open);
+        }
+        self.shared.set_closes_at(self.closes_at());
+    }
+
+    /// Opens the circuit for exactly `trip_for`.
+    fn trip_for(&mut self, reason: TripReason, trip_for: Duration) {
+        #[cfg(feature = "tracing")]
+        crate::trace::circuit_opened(
+            self.config.transition_level,
+            self.config.name.as_deref(),
+            reason,
+            Some(&self.config.policy),
+            &self.config.policy.snapshot(),
+            trip_for,
+        );
+        let now = self.config.clock.now();
+        self.machine.open(now, reason, trip_for);
+        let opened = self.set_state(CircuitState::Open, Some(reason));
+        let policy = self.config.policy.snapshot();
+        self.trip_window = (policy.requests, policy.failures);
+        self.shared.record_trip(TripEvent {
+            at: now,
+            timestamp: SystemTime::now(),
+            trip_for,
+            open_for: None,
+            reason,
+            policy: format!("{:?}", self.config.policy),
+        });
+        self.reset_policy();
+        // a trip while the circuit is already open only extends it, and is
+        // still broadcast so that peers learn of the new deadline.
+        if opened {
+            self.resource.opened();
+            #[cfg(feature = "alert")]
+            if let Some(ref alerting) = self.config.alerting {
+                alerting.tripped(
+                    self.config.name.as_deref(),
+                    reason,
+                    self.shared.state.subscribe(),
+                );
+            }
+            #[cfg(feature = "opentelemetry")]
+            if let Some(otel) = self.config.otel.as_ref() {
+                otel.record_transition(CircuitState::Open);
+            }
+        }
+        self.broadcast();
+    }
+
+    /// Discards the outcomes recorded by the policy.
+    fn reset_policy(&mut self) {
+        // counted first, so that requests completing while the policy is
+        // reset don't record stale outcomes with it.
+        self.shared.record_policy_reset();
+        self.config.policy.reset();
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Reset => {
+                debug!(
+                    breaker = self.config.name.as_deref(),
+                    "resetting circuit breaker"
+                );
+                self.machine.force(None);
+                self.reset_policy();
+                if self.is_tripped() {
+                    self.close();
+                }
+                // an operator resetting the breaker wants full traffic now.
+                self.machine.end_ramp();
+            }
+            Command::Force(CircuitState::Open) => {
+                debug!(
+                    breaker = self.config.name.as_deref(),
+                    "forcing circuit breaker open"
+                );
+                self.machine.force(Some(CircuitState::Open));
+                if !self.is_tripped() {
+                    self.trip(TripReason::Forced);
+                }
+            }
+            Command::Restore(open_until) => {
+                let state = StoredState::new(CircuitState::Open, Some(open_until));
+                self.apply_stored(&state, TripReason::Restored);
+            }
+            Command::Trip(reason) => {
+                if !self.is_tripped() && self.machine.forced().is_none() {
+                    self.trip(reason);
+                }
+            }
+            Command::Force(CircuitState::Closed) => {
+                debug!(
+                    breaker = self.config.name.as_deref(),
+                    "forcing circuit breaker closed"
+                );
+                self.machine.force(Some(CircuitState::Closed));
+                self.reset_policy();
+                if self.is_tripped() {
+                    self.close();
+                }
+                self.machine.end_ramp();
+            }
+        }
+    }
+
+    fn reconfigure(&mut self, config: ConfigSnapshot) {
+        debug!(
+            breaker = self.config.name.as_deref(),
+            trip_for = ?config.trip_for,
+            fail_fast = config.fail_fast,
+            "reconfiguring circuit breaker"
+        );
+        self.config.trip_for = config.trip_for;
+        self.config.fail_fast = config.fail_fast;
+        // if the circuit is open, the new trip duration applies to the
+        // current trip.
+        self.machine.set_duration(self.jittered(config.trip_for));
+    }
+
+    /// Returns `true` if a request made while the circuit is open should be
+    /// shed.
+    fn shed(&self) -> bool {
+        let fraction = self.config.shed_fraction;
+        fraction >= 1.0
+            || self.machine.forced().is_some()
+            || rng::next_f64(&self.config.rng) < fraction
+    }
+
+    /// Returns the reason a request made while the circuit is closed should
+    /// be shed, if it should be because traffic is ramping up.
+    fn ramp_shed(&self) -> Option<TripReason> {
+        let now = self.config.clock.now();
+        let admitted = self.machine.ramp_fraction(now, self.config.ramp_up)?;
+        (rng::next_f64(&self.config.rng) >= admitted).then_some(TripReason::RampingUp { admitted })
+    }
+
+    /// Returns the reason a request of the given priority made while the
+    /// circuit is closed should be shed, if it should be because the policy
+    /// is degraded.
+    ///
+    /// Priorities with their own shedding steps are shed according to those
+    /// steps, and other priorities besides [`Priority::Critical`] according
+    /// to the breaker's degraded shedding steps.
+    fn degraded_shed(&self, priority: Priority) -> Option<TripReason> {
+        let config = &self.config;
+        let tiered = config
+            .priority_shedding
+            .iter()
+            .any(|&(p, ..)| p == priority);
+        if self.machine.forced().is_some()
+            || (!tiered && (priority == Priority::Critical || config.degraded_shedding.is_empty()))
+        {
+            return None;
+        }
+        let severity = config.policy.severity();
+        let reached = |&(step, _): &(f64, f64)| severity >= step;
+        // steps are in ascending order of severity, so the highest step
+        // reached is the last one.
+        let (_, fraction) = if tiered {
+            config
+                .priority_shedding
+                .iter()
+                .rev()
+                .filter(|&&(p, ..)| p == priority)
+                .map(|&(_, step, fraction)| (step, fraction))
+                .find(reached)
+        } else {
+            config.degraded_shedding.iter().copied().rev().find(reached)
+        }?;
+        (rng::next_f64(&config.rng) < fraction).then_some(TripReason::Degraded { severity })
+    }
+
+    /// Returns an error if a request with the given deadline almost
+    /// certainly can't complete in time.
+    fn check_deadline(&self, deadline: Deadline) -> Option<DeadlineUnreachable> {
+        let expected = self.latencies.quantile(self.config.deadline_quantile?)?;
+        let remaining = deadline.remaining(self.config.clock.now());
+        if remaining >= expected {
+            return None;
+        }
+        Some(DeadlineUnreachable::new(
+            self.config.name.clone(),
+            remaining,
+            expected,
+        ))
+    }
+
+    /// Randomly lengthens or shortens `trip_for` by up to the configured
+    /// jitter.
+    fn jittered(&self, trip_for: Duration) -> Duration {
+        let jitter = self.config.trip_jitter;
+        if jitter == 0.0 {
+            return trip_for;
+        }
+        let factor = 1.0 + jitter * (2.0 * rng::next_f64(&self.config.rng) - 1.0);
+        trip_for.mul_f64(factor)
+    }
+
+    /// Returns how long until the circuit closes, or `None` if it has been
+    /// forced open.
+    fn retry_after(&self) -> Option<Duration> {
+        let closes_at = self.closes_at()?;
+        Some(closes_at.saturating_duration_since(self.config.clock.now()))
+    }
+
+    /// Returns when the current trip ends, or `None` if the circuit is
+    /// closed or has been forced open.
+    fn closes_at(&self) -> Option<Instant> {
+        self.machine
+            .deadline()
+            .filter(|_| self.machine.forced().is_none())
+    }
+
+    /// Returns `true` if the circuit has been open for the current trip's
+    /// duration.
+    ///
+    /// If the clock jumps backwards to before the trip started (which a
+    /// custom [`Clock`](crate::clock::Clock) may do, such as one corrected
+    /// after a VM is resumed), the trip is restarted from the current time.
+    /// This limits how much the jump can extend the trip to at most one trip
+    /// duration, rather than the size of the jump.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn trip_expired(&mut self) -> bool {
+        let now = self.config.clock.now();
+        let expired = self.machine.expired(now);
+        if let Err(jump) = expired {
+            debug!(
+                breaker = self.config.name.as_deref(),
+                ?jump,
+                "clock jumped backwards; restarting trip"
             );
-            // trip the breaker
-            self.tripped = true;
-            // reset the policy
-            self.config.policy.reset();
-            self.tripped_until
-                .as_mut()
-                .reset(Instant::now() + self.config.trip_for);
         }
+        expired == Ok(true)
+    }
 
-        if self.tripped {
-            // are we still waiting to become un-punished?
-            match self.tripped_until.as_mut().poll(cx) {
-                Poll::Ready(_) => {
-                    tracing::trace!("service released from Punishment Zone");
-                    self.tripped = false;
+    /// Returns the state to publish to a state store, along with how long it
+    /// remains relevant, if the circuit was opened by this breaker's policy.
+    ///
+    /// Trips applied from the store aren't published again, so that peers
+    /// don't extend each other's trips, and forced trips are local to this
+    /// breaker.
+    fn stored_state(&self) -> Option<(StoredState, Duration)> {
+        let closes_at = self.closes_at()?;
+        if self.machine.reason() == Some(TripReason::Shared) {
+            return None;
+        }
+        let remaining = closes_at.saturating_duration_since(self.config.clock.now());
+        let (requests, failures) = self.trip_window;
+        let state = StoredState::new(CircuitState::Open, Some(SystemTime::now() + remaining))
+            .with_window(requests, failures);
+        Some((state, remaining))
+    }
+
+    /// Opens the circuit for the remainder of a trip published to a state
+    /// store by a peer, or persisted by a previous process, if it isn't
+    /// already open.
+    ///
+    /// Returns `true` if the circuit was opened.
+    fn apply_stored(&mut self, state: &StoredState, reason: TripReason) -> bool {
+        if self.is_tripped() || self.machine.forced().is_some() {
+            return false;
+        }
+        let Some(remaining) = state.remaining() else {
+            return false;
+        };
+        self.trip_for(reason, remaining);
+        self.shared.set_closes_at(self.closes_at());
+        true
+    }
+
+    /// Applies a state broadcast by a peer: a trip opens the circuit like a
+    /// trip published to a state store, and a close closes the circuit if
+    /// it was opened by a peer.
+    ///
+    /// Returns `true` if the circuit's state changed.
+    fn apply_peer(&mut self, state: &StoredState) -> bool {
+        match state.state {
+            CircuitState::Open => self.apply_stored(state, TripReason::Shared),
+            CircuitState::Closed => {
+                if !self.is_tripped()
+                    || self.machine.forced().is_some()
+                    || self.machine.reason() != Some(TripReason::Shared)
+                {
+                    return false;
                 }
-                Poll::Pending => return Poll::Pending,
+                self.close();
+                true
             }
         }
+    }
 
-        self.inner.poll_ready(cx)
+    /// Opens or closes the circuit to match the circuit shared with other
+    /// processes, if the breaker shares one.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn sync_shared_memory(&mut self) {
+        let Some(ref slot) = self.shm else {
+            return;
+        };
+        let open_until = match slot.open_until() {
+            Ok(open_until) => open_until,
+            Err(error) => {
+                trace!(breaker = self.config.name.as_deref(), %error, "failed to read shared memory");
+                return;
+            }
+        };
+        let state = match open_until {
+            Some(_) => StoredState::new(CircuitState::Open, open_until),
+            None => StoredState::new(CircuitState::Closed, None),
+        };
+        self.apply_peer(&state);
     }
 
-    fn call(&mut self, req: Req) -> Self::Future {
-        debug_assert!(!self.tripped, "tried to call a tripped circuit breaker!");
-        ResponseFuture {
-            future: self.inner.call(req),
-            policy: self.config.policy.clone(),
+    /// Holds the circuit open while any of the breakers it depends on are
+    /// open, and closes it once they've all closed, if it was opened because
+    /// of them.
+    fn sync_dependencies(&mut self) {
+        // a dependency which has been forced open has no deadline, so check
+        // it again once this breaker's trip duration has passed.
+        let remaining = self
+            .config
+            .dependencies
+            .iter()
+            .filter(|upstream| upstream.state() == CircuitState::Open)
+            .map(|upstream| upstream.open_remaining().unwrap_or(self.config.trip_for))
+            .max();
+        let dependency_trip = self.machine.reason() == Some(TripReason::DependencyOpen);
+        match remaining {
+            Some(remaining) if !self.is_tripped() => {
+                debug!(
+                    breaker = self.config.name.as_deref(),
+                    "dependency is open; opening circuit"
+                );
+                self.trip_for(TripReason::DependencyOpen, remaining);
+            }
+            Some(remaining) if dependency_trip => {
+                // stay open for as long as the dependency does.
+                self.machine.extend(self.config.clock.now(), remaining);
+            }
+            None if self.is_tripped() && dependency_trip => self.close(),
+            _ => {}
+        }
+    }
+
+    /// Publishes the circuit's state to peers and to shared memory, if the
+    /// breaker shares its circuit and the state was decided by this
+    /// breaker's policy.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn broadcast(&self) {
+        let Some(ref name) = self.config.name else {
+            return;
+        };
+        // peers learn of a dependency's trip from the dependency itself.
+        if self.machine.forced().is_some()
+            || matches!(
+                self.machine.reason(),
+                Some(TripReason::Shared | TripReason::DependencyOpen)
+            )
+        {
+            return;
+        }
+        let state = match self.stored_state() {
+            Some((state, _)) => state,
+            None => StoredState::new(CircuitState::Closed, None),
+        };
+        if let Some(ref slot) = self.shm {
+            let result = match state.open_until {
+                Some(until) => slot.record_trip(until),
+                None => slot.record_close(),
+            };
+            if let Err(error) = result {
+                debug!(breaker = %name, %error, "failed to write shared memory");
+            }
+        }
+        if let Some(ref peers) = self.config.peers {
+            peers.publish(name, &state);
+        }
+    }
+
+    /// Records that a request was refused or parked because the circuit is
+    /// open.
+    ///
+    /// When the breaker parks callers in `poll_ready`, this is recorded once
+    /// each time a caller is parked, rather than on every poll. When the
+    /// breaker fails fast, this is recorded for every rejected request.
+    fn record_rejection(&self) {
+        self.shared.record_rejection();
+        self.resource.rejected();
+        #[cfg(feature = "opentelemetry")]
+        if let Some(otel) = self.config.otel.as_ref() {
+            otel.record_rejection();
+        }
+    }
+
+    /// Records that a request was rejected with `rejected`, returning it.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn reject(&self, rejected: Rejected, span: &Span) -> Rejected {
+        #[cfg(feature = "tracing")]
+        if let Some(suppressed) = rate_limit::acquire(self.rejection_logs.as_ref()) {
+            dyn_event!(
+                self.config.decision_level,
+                breaker = self.config.name.as_deref(),
+                reason = %rejected,
+                suppressed,
+                "rejecting request"
+            );
+        }
+        self.record_rejection();
+        #[cfg(feature = "tracing")]
+        record_outcome(span, "rejected", None);
+        #[cfg(feature = "span-trace")]
+        let rejected = match rejected {
+            Rejected::Open(error) if self.config.span_traces => {
+                let span_trace = span.in_scope(tracing_error::SpanTrace::capture);
+                Rejected::Open(error.with_span_trace(span_trace))
+            }
+            rejected => rejected,
+        };
+        #[cfg(feature = "events")]
+        if let Some(ref events) = self.config.outcome_events {
+            events.send(|| OutcomeEvent {
+                breaker: self.config.name.clone(),
+                timestamp: SystemTime::now(),
+                latency: None,
+                classification: Classification::Rejected,
+                state: self.state(),
+                suppressed: 0,
+            });
+        }
+        rejected
+    }
+
+    fn close(&mut self) {
+        let open_for = self.machine.close(self.config.clock.now());
+        dyn_event!(
+            self.config.transition_level,
+            breaker = self.config.name.as_deref(),
+            ?open_for,
+            "circuit breaker closed"
+        );
+        let closed = self.set_state(CircuitState::Closed, None);
+        self.shared.record_close(open_for);
+        // a `Retry-After` sent while the circuit was open applied to the trip
+        // that just ended, if any.
+        self.shared.take_retry_after();
+        if closed {
+            self.resource.closed();
+            #[cfg(feature = "opentelemetry")]
+            if let Some(otel) = self.config.otel.as_ref() {
+                otel.record_transition(CircuitState::Closed);
+                otel.record_open_duration(open_for);
+            }
+        }
+        self.broadcast();
+    }
+}
+
+impl<P> Drop for Circuit<P> {
+    fn drop(&mut self) {
+        // the last clone of the breaker has been dropped.
+        self.shared.flush();
+    }
+}
+
+// === impl Sampler ===
+
+impl Sampler {
+    fn new(interval: Duration, clock: SharedClock) -> Self {
+        Sampler {
+            interval,
+            anchor: clock.now(),
+            clock,
+            polls: AtomicU32::new(0),
+            every: AtomicU32::new(1),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns `true` if the policy should be consulted on this poll.
+    fn is_due(&self) -> bool {
+        let every = self.every.load(Ordering::Relaxed);
+        if self.polls.fetch_add(1, Ordering::Relaxed) + 1 < every {
+            return false;
+        }
+        self.polls.store(0, Ordering::Relaxed);
+
+        // scale the number of polls between samples by how far the time
+        // since the last sample was from the interval, by at most a factor
+        // of two each time so that a burst doesn't swing it too far.
+        let now = self.clock.now().saturating_duration_since(self.anchor);
+        let now = now.as_nanos() as u64;
+        let elapsed = now.saturating_sub(self.last.swap(now, Ordering::Relaxed));
+        let target = self.interval.as_nanos() as u64;
+        let next = (u64::from(every).saturating_mul(target) / elapsed.max(1))
+            .clamp(u64::from(every / 2), u64::from(every) * 2)
+            .clamp(1, u64::from(MAX_SAMPLE_EVERY));
+        self.every.store(next as u32, Ordering::Relaxed);
+        true
+    }
+}
+
+// === impl Admitted ===
+
+impl<P: Policy> Admitted<P> {
+    /// Records the outcome of the request with the breaker's policy.
+    pub(crate) fn record(self, success: bool) {
+        let fixed = &*self.fixed;
+        let latency = self.record_latency(success);
+        if success {
+            if let Some(policy) = self.policy() {
+                policy.record_success();
+            }
+            fixed.instruments.record_success();
+            self.complete(Completion::Success, latency);
+        } else {
+            if let Some(policy) = self.policy() {
+                policy.record_failure();
+            }
+            fixed.instruments.record_failure();
+            self.complete(Completion::Failure, latency);
+        }
+    }
+
+    /// Records the outcome of a request whose response signaled that the
+    /// server is overloaded.
+    ///
+    /// If overload weighting is enabled, this records the configured number
+    /// of failures with the policy. Otherwise, the request is recorded as
+    /// `success`, like any other.
+    #[cfg(feature = "http")]
+    pub(crate) fn record_overloaded(self, success: bool) {
+        match self.fixed.overload_weight {
+            Some(weight) => self.record_failures(weight),
+            None => self.record(success),
+        }
+    }
+
+    /// Records that a long-lived stream was established as a success, without
+    /// completing the request.
+    ///
+    /// If the stream later fails, that's recorded as a separate failure.
+    #[cfg(feature = "grpc")]
+    pub(crate) fn record_established(&self) {
+        if let Some(policy) = self.policy() {
+            policy.record_success();
+        }
+        self.fixed.instruments.record_success();
+    }
+
+    /// Records that the request failed, counting it as `weight` failures
+    /// with the policy.
+    #[cfg(feature = "http")]
+    pub(crate) fn record_failures(self, weight: usize) {
+        let latency = self.record_latency(false);
+        if let Some(policy) = self.policy() {
+            for _ in 0..weight {
+                policy.record_failure();
+            }
+        }
+        self.fixed.instruments.record_failure();
+        self.complete(Completion::Failure, latency);
+    }
+
+    /// Records how long the request took with the policy, and for deadline
+    /// admission if it succeeded, if requests are timed, returning how long
+    /// it took.
+    fn record_latency(&self, success: bool) -> Option<Duration> {
+        let fixed = &*self.fixed;
+        let latency = self.elapsed()?;
+        if let Some(policy) = self.policy() {
+            policy.record_latency(latency);
+        }
+        if let Some(latencies) = fixed.latencies.as_ref().filter(|_| success) {
+            latencies.record(latency);
+        }
+        Some(latency)
+    }
+
+    /// Returns the policy with which the request's outcome is recorded, or
+    /// `None` if it's discarded because the policy was reset after the
+    /// request was admitted.
+    fn policy(&self) -> Option<&P> {
+        let shared = self.in_flight.shared();
+        self.policy_resets
+            .is_none_or(|resets| resets == shared.policy_resets())
+            .then_some(&self.fixed.policy)
+    }
+
+    /// Returns how long the request has taken, if requests are timed.
+    fn elapsed(&self) -> Option<Duration> {
+        let started = self.started?;
+        Some(self.fixed.clock.now().saturating_duration_since(started))
+    }
+
+    /// Records how the request completed on its span, and sends an outcome
+    /// event for it if the breaker sends them.
+    #[cfg_attr(
+        not(any(feature = "tracing", feature = "events")),
+        allow(unused_variables)
+    )]
+    fn complete(&self, completion: Completion, latency: Option<Duration>) {
+        #[cfg(feature = "tracing")]
+        record_outcome(&self.span, completion.as_str(), latency);
+        #[cfg(feature = "events")]
+        if let Some(ref events) = self.fixed.instruments.events {
+            events.send(|| OutcomeEvent {
+                breaker: self.in_flight.shared().name.clone(),
+                timestamp: SystemTime::now(),
+                latency,
+                classification: completion.into(),
+                state: self.state,
+                suppressed: 0,
+            });
+        }
+    }
+
+    /// Records a `Retry-After` delay sent in the request's response, which
+    /// may set the duration of the breaker's next trip.
+    #[cfg(feature = "http")]
+    pub(crate) fn record_retry_after(&self, retry_after: Duration) {
+        self.in_flight.shared().set_retry_after(retry_after);
+    }
+
+    /// Records that the request failed with `error`, unless the breaker's
+    /// error classifier ignores it.
+    pub(crate) fn record_error(self, error: &(dyn std::error::Error + 'static)) {
+        match self.fixed.classifier.classify(error) {
+            ErrorClass::Failure => self.record(false),
+            ErrorClass::Ignore => self.complete(Completion::Ignored, self.elapsed()),
+            ErrorClass::Category(category) => {
+                let latency = self.record_latency(false);
+                if let Some(policy) = self.policy() {
+                    policy.record_failure_in(category);
+                }
+                self.fixed.instruments.record_failure();
+                self.complete(Completion::Category(category), latency);
+            }
         }
     }
 }
 
+// === impl Completion ===
+
+impl Completion {
+    #[cfg(feature = "tracing")]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Completion::Success => "success",
+            Completion::Failure => "failure",
+            Completion::Category(category) => category,
+            Completion::Ignored => "ignored",
+        }
+    }
+}
+
+#[cfg(feature = "events")]
+impl From<Completion> for Classification {
+    fn from(completion: Completion) -> Self {
+        match completion {
+            Completion::Success => Classification::Success,
+            Completion::Failure => Classification::Failure,
+            Completion::Category(category) => Classification::Category(category),
+            Completion::Ignored => Classification::Ignored,
+        }
+    }
+}
+
+/// Records how a request completed on its span, if it's a
+/// `circuit_breaker.call` span.
+#[cfg(feature = "tracing")]
+fn record_outcome(span: &Span, outcome: &str, latency: Option<Duration>) {
+    span.record("outcome", outcome);
+    if let Some(latency) = latency {
+        span.record("latency", tracing::field::debug(latency));
+    }
+}
+
 // === impl ResponseFuture ===
 
 impl<P, F, T, E> Future for ResponseFuture<P, F>
 where
     F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
     P: Policy,
 {
-    type Output = Result<T, E>;
+    type Output = Result<T, BoxError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut this = self.project();
-        match this.future.as_mut().poll(cx) {
-            // TODO(eliza): integrate with response classification here...
-            Poll::Ready(Ok(res)) => {
-                this.policy.record_success();
-                Poll::Ready(Ok(res))
+        let this = self.project();
+        let _enter = this.span.enter();
+        let future = match this.future.as_pin_mut() {
+            Some(future) => future,
+            None => {
+                let error = this.rejected.take().expect("polled after completion");
+                return Poll::Ready(Err(error.into()));
             }
-            Poll::Ready(Err(err)) => {
-                this.policy.record_failure();
-                Poll::Ready(Err(err))
+        };
+        let result = ready!(future.poll(cx)).map_err(Into::into);
+        if let Some(admitted) = this.admitted.take() {
+            match result {
+                Ok(_) => admitted.record(true),
+                Err(ref error) => admitted.record_error(&**error),
             }
-            Poll::Pending => Poll::Pending,
         }
+        Poll::Ready(result)
+    }
+}
+
+// === impl Rejected ===
+
+impl Rejected {
+    #[cfg(feature = "http")]
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Rejected::Open(error) => error.retry_after(),
+            Rejected::Deadline(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejected::Open(error) => error.fmt(f),
+            Rejected::Deadline(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<Rejected> for BoxError {
+    fn from(rejected: Rejected) -> Self {
+        match rejected {
+            Rejected::Open(error) => error.into(),
+            Rejected::Deadline(error) => error.into(),
+        }
+    }
+}
+
+// === impl Instruments ===
+
+impl Instruments {
+    fn record_success(&self) {
+        self.resource.succeeded();
+        #[cfg(feature = "opentelemetry")]
+        if let Some(otel) = self.otel.as_ref() {
+            otel.record_success();
+        }
+    }
+
+    fn record_failure(&self) {
+        self.resource.failed();
+        #[cfg(feature = "opentelemetry")]
+        if let Some(otel) = self.otel.as_ref() {
+            otel.record_failure();
+        }
+    }
+
+    /// Returns `true` if an outcome event is sent for every request.
+    fn sends_events(&self) -> bool {
+        #[cfg(feature = "events")]
+        return self.events.is_some();
+        #[cfg(not(feature = "events"))]
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::SlidingFailureRate, BreakerRegistry};
+    use std::{future, task::Waker};
+    use tokio::time;
+
+    /// A service that succeeds if the request is `true`, and fails otherwise.
+    struct Svc;
+
+    impl Service<bool> for Svc {
+        type Response = ();
+        type Error = &'static str;
+        type Future = future::Ready<Result<(), &'static str>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, ok: bool) -> Self::Future {
+            future::ready(if ok { Ok(()) } else { Err("failed") })
+        }
+    }
+
+    fn breaker() -> CircuitBreaker<SlidingFailureRate, Svc> {
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        CircuitBreaker::new(Config::new(policy, Duration::from_secs(5)), Svc)
+    }
+
+    fn poll_ready(
+        breaker: &mut CircuitBreaker<SlidingFailureRate, Svc>,
+    ) -> Poll<Result<(), BoxError>> {
+        Service::<bool>::poll_ready(breaker, &mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn new_outside_runtime() {
+        let mut breaker = breaker();
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+    }
+
+    #[tokio::test]
+    async fn state_receiver_sees_transitions() {
+        time::pause();
+        let mut breaker = breaker();
+        let mut rx = breaker.state_receiver();
+        assert_eq!(CircuitState::Closed, *rx.borrow_and_update());
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert_eq!(1, breaker.handle().stats().rejected);
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(CircuitState::Open, *rx.borrow_and_update());
+
+        time::advance(Duration::from_secs(6)).await;
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(CircuitState::Closed, *rx.borrow_and_update());
+    }
+
+    #[tokio::test]
+    async fn on_state_change_hook() {
+        use std::sync::{Arc, Mutex};
+
+        time::pause();
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5)).on_state_change({
+            let transitions = transitions.clone();
+            move |t| transitions.lock().unwrap().push(t)
+        });
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+        time::advance(Duration::from_secs(6)).await;
+        assert!(poll_ready(&mut breaker).is_ready());
+
+        assert_eq!(
+            *transitions.lock().unwrap(),
+            vec![
+                Transition {
+                    from: CircuitState::Closed,
+                    to: CircuitState::Open,
+                    reason: Some(TripReason::FailureRate {
+                        rate: 1.0,
+                        threshold: 0.05,
+                        samples: 1,
+                    }),
+                },
+                Transition {
+                    from: CircuitState::Open,
+                    to: CircuitState::Closed,
+                    reason: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn on_reject_hook() {
+        use std::sync::{Arc, Mutex};
+
+        time::pause();
+        let rejections = Arc::new(Mutex::new(Vec::new()));
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5)).on_reject({
+            let rejections = rejections.clone();
+            move |rejection| {
+                let request = rejection.request::<bool>().copied();
+                rejections.lock().unwrap().push((rejection.parked, request));
+            }
+        });
+
+        // callers parked until the circuit closes are passed once each time
+        // they're parked, without a request...
+        let mut breaker = CircuitBreaker::new(config.clone(), Svc);
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert_eq!(vec![(true, None)], *rejections.lock().unwrap());
+
+        // ...while requests refused by a breaker failing fast are passed
+        // along with the request.
+        let mut breaker = CircuitBreaker::new(config.with_fail_fast(true), Svc);
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_ready());
+        let error = breaker.call(true).await.unwrap_err();
+        assert!(error.is::<CircuitOpen>());
+        assert_eq!(
+            vec![(true, None), (false, Some(true))],
+            *rejections.lock().unwrap()
+        );
+    }
+
+    #[cfg(feature = "span-trace")]
+    #[tokio::test]
+    async fn captures_span_traces() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        time::pause();
+        let subscriber = tracing_subscriber::registry().with(tracing_error::ErrorLayer::default());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_name("traced")
+            .with_fail_fast(true)
+            .with_span_traces(true);
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_ready());
+        let error = tracing::info_span!("handle_request", path = "/users")
+            .in_scope(|| breaker.call(true))
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<CircuitOpen>().unwrap();
+        let span_trace = error.span_trace().unwrap().to_string();
+        assert!(span_trace.contains("circuit_breaker"), "{span_trace}");
+        assert!(span_trace.contains("handle_request"), "{span_trace}");
+    }
+
+    #[cfg(feature = "alert")]
+    #[tokio::test]
+    async fn alerting() {
+        use crate::alert::{Alert, AlertKind, Alerting};
+        use tokio::sync::mpsc;
+
+        time::pause();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let alerting = Alerting::new(move |alert| {
+            let tx = tx.clone();
+            async move { tx.send(alert).unwrap() }
+        })
+        .open_longer_than(Duration::from_secs(3));
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_name("test")
+            .with_alerting(alerting);
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+
+        let reason = TripReason::FailureRate {
+            rate: 1.0,
+            threshold: 0.05,
+            samples: 1,
+        };
+        let alert = rx.recv().await.unwrap();
+        assert_eq!(
+            Alert {
+                breaker: Some("test".into()),
+                kind: AlertKind::Opened,
+                reason,
+            },
+            alert
+        );
+        let alert = rx.recv().await.unwrap();
+        assert_eq!(AlertKind::StillOpen(Duration::from_secs(3)), alert.kind);
+    }
+
+    #[tokio::test]
+    async fn manual_clock() {
+        use crate::clock::ManualClock;
+
+        let clock = ManualClock::new();
+        let policy =
+            SlidingFailureRate::new(Duration::from_secs(10), 0.05).with_clock(clock.clone());
+        let config = Config::new(policy, Duration::from_secs(5)).with_clock(clock.clone());
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert_eq!(Some(Duration::from_secs(5)), breaker.open_remaining());
+
+        clock.advance(Duration::from_secs(3));
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert_eq!(Duration::from_secs(3), breaker.handle().stats().time_open);
+        assert_eq!(Some(Duration::from_secs(2)), breaker.open_remaining());
+
+        clock.advance(Duration::from_secs(3));
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+        assert_eq!(None, breaker.open_remaining());
+    }
+
+    #[tokio::test]
+    async fn starts_open() {
+        use crate::clock::ManualClock;
+
+        let clock = ManualClock::new();
+        let policy =
+            SlidingFailureRate::new(Duration::from_secs(10), 0.05).with_clock(clock.clone());
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_clock(clock.clone())
+            .with_initially_open(Duration::from_secs(30));
+        let mut breaker = CircuitBreaker::new(config, Svc);
+        assert!(breaker.is_tripped());
+        assert_eq!(Some(Duration::from_secs(30)), breaker.open_remaining());
+        let trip = breaker.handle().last_trip().unwrap();
+        assert_eq!(TripReason::Initial, trip.reason);
+        assert!(poll_ready(&mut breaker).is_pending());
+
+        clock.advance(Duration::from_secs(30));
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+    }
+
+    #[tokio::test]
+    async fn probe_only_discards_stale_outcomes() {
+        use crate::clock::ManualClock;
+
+        for probe_only in [false, true] {
+            let clock = ManualClock::new();
+            let policy =
+                SlidingFailureRate::new(Duration::from_secs(60), 0.05).with_clock(clock.clone());
+            let config = Config::new(policy.clone(), Duration::from_secs(5))
+                .with_clock(clock.clone())
+                .with_probe_only(probe_only);
+            let mut breaker = CircuitBreaker::new(config, Svc);
+
+            // a request is in flight when another one trips the circuit.
+            assert!(poll_ready(&mut breaker).is_ready());
+            let stale = breaker.call(false);
+            assert!(poll_ready(&mut breaker).is_ready());
+            assert!(breaker.call(false).await.is_err());
+            assert!(poll_ready(&mut breaker).is_pending());
+
+            clock.advance(Duration::from_secs(6));
+            assert!(poll_ready(&mut breaker).is_ready());
+            assert!(stale.await.is_err());
+            let failures = if probe_only { 0 } else { 1 };
+            assert_eq!(Some(failures), policy.snapshot().failures, "{probe_only}");
+        }
+    }
+
+    #[tokio::test]
+    async fn retrip_extends_open_period() {
+        use crate::clock::ManualClock;
+
+        let clock = ManualClock::new();
+        let policy =
+            SlidingFailureRate::new(Duration::from_secs(10), 0.05).with_clock(clock.clone());
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let config = Config::new(policy.clone(), Duration::from_secs(10))
+            .with_clock(clock.clone())
+            .on_state_change({
+                let transitions = transitions.clone();
+                move |transition| {
+                    let mut transitions = transitions.lock().unwrap();
+                    transitions.push((transition.from, transition.to));
+                }
+            });
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+
+        // a failure which was in flight when the circuit opened completes,
+        // tripping it again.
+        clock.advance(Duration::from_secs(5));
+        policy.record_failure();
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert_eq!(Some(Duration::from_secs(10)), breaker.open_remaining());
+
+        clock.advance(Duration::from_secs(11));
+        assert!(poll_ready(&mut breaker).is_ready());
+        let stats = breaker.handle().stats();
+        assert_eq!(1, stats.trips);
+        assert_eq!(Duration::from_secs(16), stats.time_open);
+        assert_eq!(Duration::from_secs(16), stats.longest_open);
+        let trips = breaker.handle().recent_trips();
+        assert_eq!(1, trips.len());
+        assert_eq!(Some(Duration::from_secs(16)), trips[0].open_for);
+        assert_eq!(
+            vec![
+                (CircuitState::Closed, CircuitState::Open),
+                (CircuitState::Open, CircuitState::Closed),
+            ],
+            *transitions.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn clock_jumps_backwards() {
+        use crate::clock::Clock;
+        use std::{sync::Mutex, time::Instant};
+
+        /// A clock which can be set to an offset from a base time, in either
+        /// direction.
+        #[derive(Clone, Debug)]
+        struct Skewed {
+            base: Instant,
+            offset_secs: Arc<Mutex<i64>>,
+        }
+
+        impl Clock for Skewed {
+            fn now(&self) -> Instant {
+                let offset = *self.offset_secs.lock().unwrap();
+                let magnitude = Duration::from_secs(offset.unsigned_abs());
+                if offset < 0 {
+                    self.base - magnitude
+                } else {
+                    self.base + magnitude
+                }
+            }
+        }
+
+        let clock = Skewed {
+            base: Instant::now() + Duration::from_secs(60 * 60 * 2),
+            offset_secs: Arc::new(Mutex::new(0)),
+        };
+        let set = |secs| *clock.offset_secs.lock().unwrap() = secs;
+        let policy =
+            SlidingFailureRate::new(Duration::from_secs(10), 0.05).with_clock(clock.clone());
+        let config = Config::new(policy, Duration::from_secs(5)).with_clock(clock.clone());
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+
+        // the clock jumps back an hour. the trip restarts, rather than
+        // lasting another hour.
+        set(-3600);
+        assert!(poll_ready(&mut breaker).is_pending());
+        set(-3596);
+        assert!(poll_ready(&mut breaker).is_pending());
+        set(-3595);
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+    }
+
+    #[tokio::test]
+    async fn seeded_jitter() {
+        use crate::{clock::ManualClock, rng::XorShift64};
+
+        async fn trip_durations(seed: u64) -> Vec<Duration> {
+            let clock = ManualClock::new();
+            let policy =
+                SlidingFailureRate::new(Duration::from_secs(1), 0.05).with_clock(clock.clone());
+            let config = Config::new(policy, Duration::from_secs(5))
+                .with_clock(clock.clone())
+                .with_trip_jitter(0.5)
+                .with_rng(XorShift64::seed(seed));
+            let mut breaker = CircuitBreaker::new(config, Svc);
+            for _ in 0..4 {
+                assert!(poll_ready(&mut breaker).is_ready());
+                assert!(breaker.call(false).await.is_err());
+                assert!(poll_ready(&mut breaker).is_pending());
+                clock.advance(Duration::from_secs(8));
+            }
+            let trips = breaker.handle().recent_trips();
+            trips.iter().map(|trip| trip.trip_for).collect()
+        }
+
+        let durations = trip_durations(7).await;
+        assert_eq!(4, durations.len());
+        for duration in &durations {
+            assert!(
+                (Duration::from_millis(2500)..=Duration::from_millis(7500)).contains(duration),
+                "{duration:?} should be within 50% of 5s"
+            );
+        }
+        assert_eq!(durations, trip_durations(7).await);
+        assert_ne!(durations, trip_durations(8).await);
+    }
+
+    #[tokio::test]
+    async fn fail_fast() {
+        time::pause();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_name("test")
+            .with_fail_fast(true);
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.is_tripped());
+        let error = breaker.call(true).await.unwrap_err();
+        let error = error
+            .downcast_ref::<CircuitOpen>()
+            .expect("error should be a CircuitOpen");
+        assert_eq!(Some("test"), error.name());
+        assert!(matches!(
+            error.reason(),
+            Some(TripReason::FailureRate { samples: 1, .. })
+        ));
+        assert_eq!(1, breaker.handle().stats().rejected);
+
+        time::advance(Duration::from_secs(6)).await;
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+        assert!(breaker.call(true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn handle_reset_closes_circuit() {
+        time::pause();
+        let registry = BreakerRegistry::new();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_name("test")
+            .with_registry(registry.clone());
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+
+        registry.reset_all();
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+    }
+
+    #[tokio::test]
+    async fn closed_circuit_not_locked() {
+        time::pause();
+        let mut breaker = breaker();
+        let handle = breaker.handle();
+        let circuit = breaker.circuit.clone();
+
+        {
+            // while the circuit is closed, requests are admitted and their
+            // outcomes recorded without locking it.
+            let _locked = circuit.lock().unwrap();
+            let mut cx = Context::from_waker(Waker::noop());
+            for ok in [true, false] {
+                assert!(poll_ready(&mut breaker).is_ready());
+                let poll = std::pin::pin!(breaker.call(ok)).poll(&mut cx);
+                assert!(matches!(poll, Poll::Ready(result) if result.is_ok() == ok));
+            }
+        }
+
+        // once the policy punishes the endpoint, the circuit trips.
+        assert!(poll_ready(&mut breaker).is_pending());
+        time::advance(Duration::from_secs(6)).await;
+        assert!(poll_ready(&mut breaker).is_ready());
+
+        // commands from a handle are still applied.
+        handle.force_open();
+        assert!(poll_ready(&mut breaker).is_pending());
+        handle.reset();
+        assert!(poll_ready(&mut breaker).is_ready());
+    }
+
+    #[tokio::test]
+    async fn samples_policy_evaluation() {
+        use crate::clock::ManualClock;
+
+        let clock = ManualClock::new();
+        let policy =
+            SlidingFailureRate::new(Duration::from_secs(10), 0.05).with_clock(clock.clone());
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_clock(clock.clone())
+            .with_evaluation_interval(Duration::from_secs(1));
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        // polled far more often than once a second, the breaker consults its
+        // policy less and less often...
+        for _ in 0..8 {
+            assert!(poll_ready(&mut breaker).is_ready());
+            assert!(breaker.call(true).await.is_ok());
+        }
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+
+        // ...so it only notices the failure after a few more polls.
+        let admitted = (0..16)
+            .take_while(|_| poll_ready(&mut breaker).is_ready())
+            .count();
+        assert!((1..16).contains(&admitted), "{admitted}");
+        assert!(breaker.is_tripped());
+    }
+
+    #[tokio::test]
+    async fn snapshot() {
+        time::pause();
+        let mut breaker = breaker();
+        let handle = breaker.handle();
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(true).await.is_ok());
+        let snapshot = handle.snapshot();
+        assert_eq!(CircuitState::Closed, snapshot.state);
+        assert_eq!(Some(1), snapshot.policy.requests);
+        assert_eq!(Some(0.0), snapshot.policy.failure_rate);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+        let snapshot = handle.snapshot();
+        assert_eq!(CircuitState::Open, snapshot.state);
+        assert_eq!(Duration::from_secs(5), snapshot.config.trip_for);
+        assert_eq!(Some(Duration::from_secs(5)), snapshot.open_remaining);
+        assert_eq!(1, snapshot.stats.trips);
+        assert_eq!(1, snapshot.recent_trips.len());
+        // the policy is reset when the breaker trips.
+        assert_eq!(Some(0), snapshot.policy.requests);
+    }
+
+    #[tokio::test]
+    async fn forced_states() {
+        time::pause();
+        let mut breaker = breaker();
+        let handle = breaker.handle();
+
+        handle.force_open();
+        assert!(poll_ready(&mut breaker).is_pending());
+        assert_eq!(Some(CircuitState::Open), handle.forced());
+        assert_eq!(
+            Some(TripReason::Forced),
+            handle.last_trip().map(|t| t.reason)
+        );
+
+        // forced open circuits don't close when the trip duration elapses.
+        time::advance(Duration::from_secs(6)).await;
+        assert!(poll_ready(&mut breaker).is_pending());
+
+        handle.force_close();
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert_eq!(Some(CircuitState::Closed), handle.forced());
+
+        // forced closed circuits don't trip.
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+
+        handle.reset();
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert_eq!(None, handle.forced());
+    }
+
+    #[tokio::test]
+    async fn handle_reconfigures() {
+        time::pause();
+        let mut breaker = breaker();
+        let handle = breaker.handle();
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_pending());
+
+        // shortening the trip duration applies to the current trip.
+        handle.set_trip_for(Duration::from_secs(1));
+        assert_eq!(Duration::from_secs(1), handle.config().trip_for);
+        time::advance(Duration::from_secs(2)).await;
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
+
+        handle.set_fail_fast(true);
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.is_tripped());
+        assert!(handle.snapshot().config.fail_fast);
+    }
+
+    #[tokio::test]
+    async fn driver_evaluates_idle_breaker() {
+        time::pause();
+        let mut breaker = breaker();
+        let mut rx = breaker.state_receiver();
+        let driver = tokio::spawn(breaker.driver(Duration::from_secs(1)));
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+
+        // the driver opens and closes the circuit without the breaker being
+        // polled again.
+        rx.changed().await.unwrap();
+        assert_eq!(CircuitState::Open, *rx.borrow_and_update());
+        rx.changed().await.unwrap();
+        assert_eq!(CircuitState::Closed, *rx.borrow_and_update());
+        // the circuit is closed within one interval of the trip ending.
+        let time_open = breaker.handle().stats().time_open;
+        assert!(time_open >= Duration::from_secs(5));
+        assert!(time_open <= Duration::from_secs(6));
+
+        // the driver completes once the breaker is dropped.
+        drop(breaker);
+        driver.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shares_trips_through_store() {
+        time::pause();
+        let store = crate::store::MemoryStore::new();
+        let mut a = breaker();
+        let b = breaker();
+        let mut rx = b.state_receiver();
+        tokio::spawn(a.sync_state(store.clone(), "svc", Duration::from_secs(1)));
+        tokio::spawn(b.sync_state(store, "svc", Duration::from_secs(1)));
+
+        assert!(poll_ready(&mut a).is_ready());
+        assert!(a.call(false).await.is_err());
+
+        // `b` opens for the rest of `a`'s trip once `a` publishes it, without
+        // sending any requests.
+        rx.changed().await.unwrap();
+        assert_eq!(CircuitState::Open, *rx.borrow_and_update());
+        let trip = b.handle().last_trip().unwrap();
+        assert_eq!(TripReason::Shared, trip.reason);
+        assert!(trip.trip_for <= Duration::from_secs(5));
+        assert!(trip.trip_for > Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn persists_trips() {
+        time::pause();
+        let store = crate::store::MemoryStore::new();
+        let mut a = breaker();
+        assert!(poll_ready(&mut a).is_ready());
+        assert!(a.call(false).await.is_err());
+        assert!(poll_ready(&mut a).is_pending());
+        a.handle().persist(&store, "svc").await.unwrap();
+
+        // the next process's breaker opens for the rest of the trip.
+        let mut b = breaker();
+        assert!(b.handle().restore(&store, "svc").await.unwrap());
+        assert!(poll_ready(&mut b).is_pending());
+        let trip = b.handle().last_trip().unwrap();
+        assert_eq!(TripReason::Restored, trip.reason);
+        assert!(trip.trip_for <= Duration::from_secs(5));
+
+        // once closed, a closed state is persisted.
+        b.handle().reset();
+        assert!(poll_ready(&mut b).is_ready());
+        b.handle().persist(&store, "svc").await.unwrap();
+        assert!(!breaker().handle().restore(&store, "svc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sheds_fraction_while_open() {
+        use crate::rng::XorShift64;
+
+        time::pause();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::server(policy, Duration::from_secs(5)).with_rng(XorShift64::seed(7));
+        let mut breaker = CircuitBreaker::new(config, Svc);
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+
+        let mut shed = 0;
+        for _ in 0..200 {
+            assert!(poll_ready(&mut breaker).is_ready());
+            if let Err(error) = breaker.call(true).await {
+                assert!(error.is::<CircuitOpen>());
+                shed += 1;
+            }
+        }
+        assert!(breaker.is_tripped());
+        assert!((60..140).contains(&shed), "{shed} of 200 requests shed");
+
+        // forced open circuits shed everything.
+        breaker.handle().force_open();
+        for _ in 0..20 {
+            assert!(poll_ready(&mut breaker).is_ready());
+            assert!(breaker.call(true).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn sheds_while_degraded() {
+        time::pause();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.8);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_name("degraded")
+            .with_degraded_shedding(0.5, 1.0);
+        let mut breaker = CircuitBreaker::new(config, Svc);
+        for ok in [true, false] {
+            assert!(poll_ready(&mut breaker).is_ready());
+            assert_eq!(ok, breaker.call(ok).await.is_ok());
+        }
+
+        // part way to tripping, requests are shed without being recorded.
+        for _ in 0..3 {
+            assert!(poll_ready(&mut breaker).is_ready());
+            let error = breaker.call(true).await.unwrap_err();
+            let error = error.downcast_ref::<CircuitOpen>().unwrap();
+            assert_eq!(
+                Some(&TripReason::Degraded { severity: 0.625 }),
+                error.reason()
+            );
+            assert!(error.to_string().contains("shedding load"), "{error}");
+        }
+        assert!(!breaker.is_tripped());
+
+        breaker.handle().reset();
+        assert!(poll_ready(&mut breaker).is_ready());
+        breaker.call(true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ramps_up_after_trip() {
+        use crate::rng::XorShift64;
+
+        time::pause();
+        let policy = SlidingFailureRate::new(Duration::from_secs(1), 0.5);
+        let config = Config::new(policy, Duration::from_secs(5))
+            .with_fail_fast(true)
+            .with_ramp_up(Duration::from_secs(30))
+            .with_rng(XorShift64::seed(7));
+        let mut breaker = CircuitBreaker::new(config, Svc);
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.is_tripped());
+        time::advance(Duration::from_secs(6)).await;
+
+        let mut admitted = Vec::new();
+        for _ in 0..4 {
+            let mut n = 0;
+            for _ in 0..200 {
+                assert!(poll_ready(&mut breaker).is_ready());
+                if breaker.call(true).await.is_ok() {
+                    n += 1;
+                }
+            }
+            admitted.push(n);
+            time::advance(Duration::from_secs(10)).await;
+        }
+        assert!(!breaker.is_tripped());
+        assert!((5..40).contains(&admitted[0]), "{admitted:?}");
+        assert!((30..70).contains(&admitted[1]), "{admitted:?}");
+        assert!((70..130).contains(&admitted[2]), "{admitted:?}");
+        assert_eq!(200, admitted[3]);
+    }
+
+    #[tokio::test]
+    async fn follows_dependencies() {
+        time::pause();
+        let mut upstream = breaker();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(1))
+            .with_dependency(upstream.handle())
+            .with_fail_fast(true);
+        let mut dependent = CircuitBreaker::new(config, Svc);
+        assert!(poll_ready(&mut dependent).is_ready());
+
+        assert!(poll_ready(&mut upstream).is_ready());
+        assert!(upstream.call(false).await.is_err());
+        assert!(poll_ready(&mut upstream).is_pending());
+
+        // the dependent stays open for as long as the upstream does, rather
+        // than for its own trip duration.
+        assert!(poll_ready(&mut dependent).is_ready());
+        assert!(dependent.call(true).await.is_err());
+        assert_eq!(
+            TripReason::DependencyOpen,
+            dependent.handle().last_trip().unwrap().reason
+        );
+        time::advance(Duration::from_secs(2)).await;
+        assert!(poll_ready(&mut dependent).is_ready());
+        assert!(dependent.is_tripped());
+
+        // once the upstream closes, so does the dependent.
+        upstream.handle().reset();
+        assert!(poll_ready(&mut upstream).is_ready());
+        assert!(poll_ready(&mut dependent).is_ready());
+        assert!(!dependent.is_tripped());
+        assert!(dependent.call(true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_in_flight() {
+        use std::sync::Mutex;
+        use tokio::sync::oneshot;
+        use tower::{service_fn, ServiceExt};
+
+        time::pause();
+        let snapshots = Arc::new(Mutex::new(Vec::new()));
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5)).on_shutdown({
+            let snapshots = snapshots.clone();
+            move |snapshot| snapshots.lock().unwrap().push(snapshot.clone())
+        });
+        let svc =
+            service_fn(|rx: oneshot::Receiver<()>| async move { rx.await.map_err(|_| "canceled") });
+        let mut breaker = CircuitBreaker::new(config, svc);
+
+        let (tx, rx) = oneshot::channel();
+        let rsp = breaker.ready().await.unwrap().call(rx);
+        let shutdown = tokio::spawn(breaker.handle().shutdown(Duration::from_secs(10)));
+        tokio::task::yield_now().await;
+
+        // new requests are refused while shutting down.
+        let Err(error) = breaker.ready().await else {
+            panic!("breaker should refuse requests once shut down");
+        };
+        assert!(error.is::<ShutDown>());
+        assert!(!shutdown.is_finished());
+        assert!(snapshots.lock().unwrap().is_empty());
+
+        tx.send(()).unwrap();
+        rsp.await.unwrap();
+        shutdown.await.unwrap();
+        assert_eq!(1, snapshots.lock().unwrap().len());
+
+        // the final snapshot is only emitted once.
+        drop(breaker);
+        assert_eq!(1, snapshots.lock().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn shutdown_times_out() {
+        use tower::{service_fn, ServiceExt};
+
+        time::pause();
+        let svc = service_fn(|()| future::pending::<Result<(), &'static str>>());
+        let mut breaker = CircuitBreaker::new(
+            Config::new(
+                SlidingFailureRate::new(Duration::from_secs(10), 0.05),
+                Duration::from_secs(5),
+            ),
+            svc,
+        );
+        let _rsp = breaker.ready().await.unwrap().call(());
+
+        let start = time::Instant::now();
+        breaker.handle().shutdown(Duration::from_secs(3)).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(3));
+        assert!(elapsed < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn drop_flushes() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let flushed = Arc::new(AtomicUsize::new(0));
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config = Config::new(policy, Duration::from_secs(5)).on_shutdown({
+            let flushed = flushed.clone();
+            move |_| {
+                flushed.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        drop(CircuitBreaker::new(config, Svc));
+        assert_eq!(1, flushed.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn error_classifier() {
+        use crate::classify::ErrorClass;
+
+        time::pause();
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.05);
+        let config =
+            Config::new(policy, Duration::from_secs(5)).with_error_classifier(|error| match error
+                .to_string()
+                .as_str()
+            {
+                "failed" => ErrorClass::Ignore,
+                _ => ErrorClass::Failure,
+            });
+        let mut breaker = CircuitBreaker::new(config, Svc);
+
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(breaker.call(false).await.is_err());
+        // the error was ignored, so the circuit is still closed.
+        assert!(poll_ready(&mut breaker).is_ready());
+        assert!(!breaker.is_tripped());
     }
 }
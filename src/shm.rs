@@ -0,0 +1,331 @@
+//! Sharing circuits between processes on the same host.
+//!
+//! Pre-fork and multi-process servers run several worker processes on each
+//! host, each with its own breakers, so when a backend dies, every worker has
+//! to discover that independently. A [`SharedMemory`] segment lets the
+//! workers' breakers share a single circuit instead: when a breaker
+//! configured [with shared memory](crate::Config::with_shared_memory) trips,
+//! it records the trip in the segment, and breakers with the same
+//! [name](crate::Config::with_name) in every other worker open as soon as
+//! they're next polled, for the remainder of the trip. When the breaker that
+//! tripped closes, so do the others.
+//!
+//! The segment is a small file, which should be placed on a memory-backed
+//! filesystem such as `/dev/shm`. Unlike a [state store](crate::store) or
+//! [peer broadcasts](crate::peer), it's consulted every time a breaker is
+//! polled, so there's no delay before the other workers' breakers open.
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower_breaker::{policy::ConsecutiveFailures, shm::SharedMemory, Config};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! # let dir = std::env::temp_dir();
+//! # let path = dir.join(format!("tower-breaker-doc-{}", std::process::id()));
+//! # /*
+//! let path = "/dev/shm/my-app-breakers";
+//! # */
+//! // open the segment before forking, or in each worker.
+//! let shm = SharedMemory::open(&path)?;
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(30))
+//!     .with_name("users-api")
+//!     .with_shared_memory(shm);
+//! # drop(config);
+//! # std::fs::remove_file(&path)?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::trace::debug;
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+
+/// A segment of memory shared by breakers in several processes.
+///
+/// Clones of a `SharedMemory` refer to the same segment.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct SharedMemory(Arc<Segment>);
+
+#[derive(Debug)]
+struct Segment {
+    file: File,
+    /// File locks are held by the file, rather than by a thread, so threads
+    /// in this process must also exclude each other.
+    lock: Mutex<()>,
+}
+
+/// A breaker's slot in a [`SharedMemory`] segment.
+#[derive(Debug)]
+pub(crate) struct Slot {
+    segment: Arc<Segment>,
+    offset: u64,
+    key: u64,
+    /// The trip this breaker last recorded, so that it only clears its own
+    /// trip when it closes.
+    recorded: AtomicU64,
+}
+
+/// The number of breakers which can share a segment.
+const SLOTS: u64 = 128;
+
+/// Each slot is made up of the hash of the breaker's name, when its circuit
+/// is open until (in Unix milliseconds, or zero if it's closed), and a
+/// checksum of the two, so that a read which races with a write can be
+/// detected.
+const SLOT_LEN: u64 = 24;
+
+const CHECKSUM: u64 = 0x7477_725f_6272_6b72;
+
+// === impl SharedMemory ===
+
+impl SharedMemory {
+    /// Opens the shared memory segment at `path`, creating it if it doesn't
+    /// exist.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let segment = Segment {
+            file,
+            lock: Mutex::new(()),
+        };
+        segment.locked(|file| {
+            if file.metadata()?.len() < SLOTS * SLOT_LEN {
+                file.set_len(SLOTS * SLOT_LEN)?;
+            }
+            Ok(())
+        })?;
+        Ok(SharedMemory(Arc::new(segment)))
+    }
+
+    /// Returns the slot for the breaker named `name`, claiming one if no
+    /// breaker with that name has used the segment yet.
+    ///
+    /// If the segment is full or can't be accessed, this is logged, and the
+    /// breaker doesn't share its circuit.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn slot(&self, name: &str) -> Option<Slot> {
+        let key = hash(name);
+        match self.0.locked(|file| claim(file, key)) {
+            Ok(offset) => Some(Slot {
+                segment: self.0.clone(),
+                offset,
+                key,
+                recorded: AtomicU64::new(0),
+            }),
+            Err(error) => {
+                debug!(breaker = name, %error, "failed to claim a shared memory slot");
+                None
+            }
+        }
+    }
+}
+
+// === impl Segment ===
+
+impl Segment {
+    /// Runs `f` while holding an exclusive lock on the segment.
+    fn locked<T>(&self, f: impl FnOnce(&File) -> io::Result<T>) -> io::Result<T> {
+        let _guard = self.lock.lock().unwrap();
+        self.file.lock()?;
+        let result = f(&self.file);
+        self.file.unlock()?;
+        result
+    }
+}
+
+// === impl Slot ===
+
+impl Slot {
+    /// Returns when the shared circuit is open until, or `None` if it's
+    /// closed.
+    pub(crate) fn open_until(&self) -> io::Result<Option<SystemTime>> {
+        let (_, until) = read(&self.segment.file, self.offset)?;
+        Ok((until != 0).then(|| UNIX_EPOCH + Duration::from_millis(until)))
+    }
+
+    /// Records that the circuit is open until `until`, unless another
+    /// breaker has already recorded a longer trip.
+    pub(crate) fn record_trip(&self, until: SystemTime) -> io::Result<()> {
+        let until = unix_millis(until).max(1);
+        self.segment.locked(|file| {
+            let (_, current) = read(file, self.offset)?;
+            if current >= until {
+                return Ok(());
+            }
+            self.recorded.store(until, Ordering::Relaxed);
+            write(file, self.offset, self.key, until)
+        })
+    }
+
+    /// Records that the circuit has closed, if the current trip was recorded
+    /// by this breaker.
+    pub(crate) fn record_close(&self) -> io::Result<()> {
+        let recorded = self.recorded.swap(0, Ordering::Relaxed);
+        if recorded == 0 {
+            return Ok(());
+        }
+        self.segment.locked(|file| {
+            let (_, current) = read(file, self.offset)?;
+            if current != recorded {
+                return Ok(());
+            }
+            write(file, self.offset, self.key, 0)
+        })
+    }
+}
+
+/// Finds the slot for `key`, claiming an empty one if there's none.
+fn claim(file: &File, key: u64) -> io::Result<u64> {
+    for i in 0..SLOTS {
+        let offset = i * SLOT_LEN;
+        match read(file, offset)? {
+            (0, _) => {
+                write(file, offset, key, 0)?;
+                return Ok(offset);
+            }
+            (k, _) if k == key => return Ok(offset),
+            _ => {}
+        }
+    }
+    Err(io::Error::other("shared memory segment is full"))
+}
+
+/// Reads the slot at `offset`, retrying if it's being written concurrently.
+fn read(file: &File, offset: u64) -> io::Result<(u64, u64)> {
+    let mut buf = [0; SLOT_LEN as usize];
+    for _ in 0..8 {
+        read_exact_at(file, &mut buf, offset)?;
+        let [key, until, checksum] = [0, 8, 16]
+            .map(|i| u64::from_le_bytes(buf[i..i + 8].try_into().expect("slice is 8 bytes")));
+        if checksum == key ^ until ^ CHECKSUM || (key, until, checksum) == (0, 0, 0) {
+            return Ok((key, until));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "shared memory slot is corrupt",
+    ))
+}
+
+fn write(file: &File, offset: u64, key: u64, until: u64) -> io::Result<()> {
+    let mut buf = [0; SLOT_LEN as usize];
+    buf[0..8].copy_from_slice(&key.to_le_bytes());
+    buf[8..16].copy_from_slice(&until.to_le_bytes());
+    buf[16..24].copy_from_slice(&(key ^ until ^ CHECKSUM).to_le_bytes());
+    write_all_at(file, &buf, offset)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    match file.seek_read(buf, offset)? {
+        n if n == buf.len() => Ok(()),
+        _ => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    match file.seek_write(buf, offset)? {
+        n if n == buf.len() => Ok(()),
+        _ => Err(io::ErrorKind::WriteZero.into()),
+    }
+}
+
+/// Hashes a breaker's name with FNV-1a, which is stable across processes
+/// (unlike `std`'s `DefaultHasher`).
+fn hash(name: &str) -> u64 {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    // zero marks an empty slot.
+    hash.max(1)
+}
+
+fn unix_millis(at: SystemTime) -> u64 {
+    let millis = at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    u64::try_from(millis).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy::ConsecutiveFailures, CircuitBreaker, CircuitState, Config, TripReason};
+    use tower::{service_fn, Service, ServiceExt};
+
+    #[tokio::test]
+    async fn shares_circuit() {
+        let path = std::env::temp_dir().join(format!("tower-breaker-shm-{}", std::process::id()));
+        // each breaker opens the segment separately, as separate processes
+        // would.
+        let config = Config::new(ConsecutiveFailures::new(1), Duration::from_secs(60))
+            .with_name("users-api")
+            .with_fail_fast(true);
+        let svc = || {
+            service_fn(|ok: bool| async move {
+                if ok {
+                    Ok(())
+                } else {
+                    Err("failed")
+                }
+            })
+        };
+        let breaker = |config: Config<_>| {
+            let shm = SharedMemory::open(&path).unwrap();
+            CircuitBreaker::new(config.with_shared_memory(shm), svc())
+        };
+        let mut a = breaker(config.clone());
+        let mut b = breaker(config.clone());
+        let mut other = breaker(config.with_name("orders-api"));
+
+        assert!(a.ready().await.unwrap().call(false).await.is_err());
+        assert!(a.ready().await.unwrap().call(true).await.is_err());
+
+        // `b` opens as soon as it's polled.
+        assert!(b.ready().await.unwrap().call(true).await.is_err());
+        assert_eq!(CircuitState::Open, b.state());
+        assert_eq!(
+            Some(TripReason::Shared),
+            b.handle().last_trip().map(|trip| trip.reason)
+        );
+        assert!(other.ready().await.unwrap().call(true).await.is_ok());
+
+        // when `a` closes, so does `b`.
+        a.handle().reset();
+        assert!(a.ready().await.unwrap().call(true).await.is_ok());
+        assert!(b.ready().await.unwrap().call(true).await.is_ok());
+        assert_eq!(CircuitState::Closed, b.state());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
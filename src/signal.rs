@@ -0,0 +1,177 @@
+//! Signals describing the load on the host, for policies to consume.
+//!
+//! Policies which only see the outcomes of requests can't tell that the host
+//! itself is overloaded until requests start failing. A [`SignalSource`]
+//! reports a resource signal, such as CPU utilization, memory pressure, or
+//! the depth of the async runtime's queue, which a policy such as
+//! [`ResourceThreshold`](crate::policy::ResourceThreshold) can trip on
+//! before that happens.
+//!
+//! Sources are provided for CPU utilization and memory pressure on Linux
+//! (read from `/proc`), and for the depth of a Tokio runtime's global queue
+//! when the `runtime-signal` feature flag is enabled. Any function returning
+//! an `Option<f64>` is also a source:
+//!
+//! ```
+//! use tower_breaker::signal::SignalSource;
+//!
+//! # fn connections_in_use() -> usize { 0 }
+//! let pool_utilization = || Some(connections_in_use() as f64 / 100.0);
+//! assert_eq!(Some(0.0), pool_utilization.read());
+//! ```
+use std::{fs, sync::Mutex};
+
+/// A source of a resource signal.
+pub trait SignalSource: Send + Sync + 'static {
+    /// Returns the current value of the signal, or `None` if it can't be
+    /// read.
+    ///
+    /// This may be called whenever a breaker is polled, so sources which
+    /// are expensive to read should be read through a policy which samples
+    /// them periodically, like
+    /// [`ResourceThreshold`](crate::policy::ResourceThreshold) does.
+    fn read(&self) -> Option<f64>;
+}
+
+/// The fraction of the time the host's CPUs were busy, between 0 and 1.
+///
+/// Each read returns the utilization since the previous read (or since the
+/// host booted, for the first read). This is read from `/proc/stat`, so it's
+/// only available on Linux.
+#[derive(Debug, Default)]
+pub struct CpuUtilization {
+    /// The total and idle CPU time as of the previous read.
+    prev: Mutex<(u64, u64)>,
+}
+
+/// The fraction of the host's memory which is in use, between 0 and 1.
+///
+/// Memory which the kernel could reclaim, such as the page cache, counts as
+/// available. This is read from `/proc/meminfo`, so it's only available on
+/// Linux.
+#[derive(Debug, Default)]
+pub struct MemoryPressure {
+    _p: (),
+}
+
+/// The number of tasks waiting in a Tokio runtime's global queue.
+///
+/// A queue which keeps growing means that the runtime's workers can't keep
+/// up with the tasks being spawned. This requires the `runtime-signal`
+/// feature flag.
+#[cfg(feature = "runtime-signal")]
+#[derive(Debug)]
+pub struct RuntimeQueueDepth {
+    runtime: tokio::runtime::Handle,
+}
+
+impl<F> SignalSource for F
+where
+    F: Fn() -> Option<f64> + Send + Sync + 'static,
+{
+    fn read(&self) -> Option<f64> {
+        self()
+    }
+}
+
+// === impl CpuUtilization ===
+
+impl CpuUtilization {
+    /// Returns a new `CpuUtilization` signal.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SignalSource for CpuUtilization {
+    fn read(&self) -> Option<f64> {
+        let stat = fs::read_to_string("/proc/stat").ok()?;
+        // the first line sums the time spent in each state by every CPU:
+        // `cpu user nice system idle iowait irq softirq steal ...`.
+        let times = stat
+            .lines()
+            .next()?
+            .strip_prefix("cpu ")?
+            .split_whitespace()
+            .map(|time| time.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let total = times.iter().take(8).sum::<u64>();
+        let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+
+        let mut prev = self.prev.lock().unwrap();
+        let (prev_total, prev_idle) = std::mem::replace(&mut *prev, (total, idle));
+        let elapsed = total.checked_sub(prev_total).filter(|&t| t > 0)?;
+        let idle = idle.saturating_sub(prev_idle).min(elapsed);
+        Some(1.0 - idle as f64 / elapsed as f64)
+    }
+}
+
+// === impl MemoryPressure ===
+
+impl MemoryPressure {
+    /// Returns a new `MemoryPressure` signal.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SignalSource for MemoryPressure {
+    fn read(&self) -> Option<f64> {
+        let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+        let field = |name: &str| {
+            meminfo.lines().find_map(|line| {
+                let kb = line.strip_prefix(name)?.strip_prefix(':')?;
+                kb.trim().trim_end_matches("kB").trim().parse::<u64>().ok()
+            })
+        };
+        let total = field("MemTotal").filter(|&total| total > 0)?;
+        let available = field("MemAvailable")?.min(total);
+        Some(1.0 - available as f64 / total as f64)
+    }
+}
+
+// === impl RuntimeQueueDepth ===
+
+#[cfg(feature = "runtime-signal")]
+impl RuntimeQueueDepth {
+    /// Returns a new `RuntimeQueueDepth` signal for the runtime with the
+    /// given handle.
+    pub fn new(runtime: tokio::runtime::Handle) -> Self {
+        RuntimeQueueDepth { runtime }
+    }
+
+    /// Returns a new `RuntimeQueueDepth` signal for the current runtime.
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a Tokio runtime.
+    pub fn current() -> Self {
+        Self::new(tokio::runtime::Handle::current())
+    }
+}
+
+#[cfg(feature = "runtime-signal")]
+impl SignalSource for RuntimeQueueDepth {
+    fn read(&self) -> Option<f64> {
+        Some(self.runtime.metrics().global_queue_depth() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn reads_proc() {
+        let cpu = CpuUtilization::new();
+        for _ in 0..2 {
+            // the CPU time may not advance between reads.
+            if let Some(utilization) = cpu.read() {
+                assert!((0.0..=1.0).contains(&utilization), "{utilization}");
+            }
+        }
+        let memory = MemoryPressure::new().read().unwrap();
+        assert!((0.0..=1.0).contains(&memory), "{memory}");
+    }
+}
@@ -0,0 +1,275 @@
+//! Offline simulation of circuit breaker policies.
+//!
+//! A [`Simulation`] replays a recorded trace of requests through a
+//! [`Policy`], and reports when a breaker using that policy would have
+//! tripped and closed. This allows tuning a policy's thresholds against
+//! traces of real production traffic, without running a service.
+//!
+//! Time-based policies, such as [`SlidingFailureRate`], must read the
+//! current time from the simulation's [clock](Simulation::clock), so that
+//! they observe the trace's timestamps rather than the time at which the
+//! simulation runs:
+//!
+//! ```
+//! use tower_breaker::{policy::SlidingFailureRate, sim::{Record, Simulation}};
+//! use tokio::time::Duration;
+//!
+//! let sim = Simulation::new(Duration::from_secs(5));
+//! let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5)
+//!     .with_clock(sim.clock());
+//! let trace = (0..20).map(|i| Record {
+//!     at: Duration::from_secs(i),
+//!     success: i < 5,
+//!     latency: Duration::from_millis(100),
+//! });
+//! let report = sim.run(policy, trace);
+//! // the breaker would have first tripped 10 seconds into the trace.
+//! assert_eq!(report.events[0].at, Duration::from_secs(10));
+//! ```
+//!
+//! [`SlidingFailureRate`]: crate::policy::SlidingFailureRate
+use crate::{clock::ManualClock, CircuitState, Policy, Transition, TripReason};
+use std::time::Duration;
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/// Replays recorded traces of requests through a [`Policy`].
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    clock: ManualClock,
+    trip_for: Duration,
+}
+
+/// A single recorded request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// When the request was made, relative to the start of the trace.
+    pub at: Duration,
+    /// Whether the request succeeded.
+    pub success: bool,
+    /// How long the request took to complete.
+    pub latency: Duration,
+}
+
+/// The result of a [`Simulation`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Report {
+    /// Each state transition the breaker would have made, in order.
+    pub events: Vec<Event>,
+    /// The number of requests in the trace.
+    pub requests: usize,
+    /// The number of requests which would have been rejected because the
+    /// circuit was open.
+    pub rejected: usize,
+    /// The total time the circuit would have spent open, up to the last
+    /// request in the trace.
+    pub time_open: Duration,
+}
+
+/// A state transition in a [`Report`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Event {
+    /// When the transition occurred, relative to the start of the trace.
+    pub at: Duration,
+    /// The transition.
+    pub transition: Transition,
+}
+
+// === impl Simulation ===
+
+impl Simulation {
+    /// Returns a new `Simulation` of a breaker which remains open for
+    /// `trip_for` once tripped.
+    pub fn new(trip_for: Duration) -> Self {
+        Simulation {
+            clock: ManualClock::new(),
+            trip_for,
+        }
+    }
+
+    /// Returns the clock which time-based policies should use, so that they
+    /// observe the trace's timestamps.
+    pub fn clock(&self) -> ManualClock {
+        self.clock.clone()
+    }
+
+    /// Replays `trace` through `policy`, returning a [`Report`] of how a
+    /// breaker using it would have behaved.
+    ///
+    /// As with a real breaker, the policy is consulted when each request is
+    /// made, and each request's outcome is recorded when it completes (after
+    /// its latency has elapsed). Requests made while the circuit is open are
+    /// rejected, and their outcomes are not recorded. The trace should be
+    /// sorted by the time each request was made.
+    ///
+    /// Each simulation advances the simulation's clock, so a `Simulation`
+    /// (and the policies using its clock) should only be used to run a
+    /// single trace.
+    pub fn run<P: Policy>(&self, policy: P, trace: impl IntoIterator<Item = Record>) -> Report {
+        let mut run = Run {
+            clock: &self.clock,
+            policy,
+            trip_for: self.trip_for,
+            now: Duration::ZERO,
+            tripped_at: None,
+            in_flight: BinaryHeap::new(),
+            report: Report::default(),
+        };
+        for record in trace {
+            run.complete_until(record.at);
+            run.request(record);
+        }
+        run.complete_until(Duration::MAX);
+        if let Some(tripped_at) = run.tripped_at {
+            run.report.time_open += run.now.saturating_sub(tripped_at);
+        }
+        run.report
+    }
+}
+
+struct Run<'a, P> {
+    clock: &'a ManualClock,
+    policy: P,
+    trip_for: Duration,
+    now: Duration,
+    /// When the circuit was opened, if it is open.
+    tripped_at: Option<Duration>,
+    /// Completion times and outcomes of requests in flight.
+    in_flight: BinaryHeap<Reverse<(Duration, bool)>>,
+    report: Report,
+}
+
+impl<P: Policy> Run<'_, P> {
+    fn advance_to(&mut self, at: Duration) {
+        if at > self.now {
+            self.clock.advance(at - self.now);
+            self.now = at;
+        }
+    }
+
+    /// Records the outcomes of all requests completing at or before `at`.
+    fn complete_until(&mut self, at: Duration) {
+        while let Some(&Reverse((done, success))) = self.in_flight.peek() {
+            if done > at {
+                break;
+            }
+            self.in_flight.pop();
+            self.advance_to(done);
+            if success {
+                self.policy.record_success();
+            } else {
+                self.policy.record_failure();
+            }
+        }
+    }
+
+    /// Makes a request, as a breaker's `poll_ready` and `call` would.
+    fn request(&mut self, record: Record) {
+        self.advance_to(record.at);
+        self.report.requests += 1;
+
+        if let Some(reason) = self.policy.punish_reason() {
+            self.trip(reason);
+        }
+        if let Some(tripped_at) = self.tripped_at {
+            if self.now < tripped_at + self.trip_for {
+                self.report.rejected += 1;
+                return;
+            }
+            self.transition(CircuitState::Open, CircuitState::Closed, None);
+            self.report.time_open += self.now - tripped_at;
+            self.tripped_at = None;
+        }
+
+        self.in_flight
+            .push(Reverse((record.at + record.latency, record.success)));
+    }
+
+    fn trip(&mut self, reason: TripReason) {
+        let from = match self.tripped_at.replace(self.now) {
+            Some(tripped_at) => {
+                self.report.time_open += self.now - tripped_at;
+                CircuitState::Open
+            }
+            None => CircuitState::Closed,
+        };
+        self.transition(from, CircuitState::Open, Some(reason));
+        self.policy.reset();
+    }
+
+    fn transition(&mut self, from: CircuitState, to: CircuitState, reason: Option<TripReason>) {
+        self.report.events.push(Event {
+            at: self.now,
+            transition: Transition { from, to, reason },
+        });
+    }
+}
+
+// === impl Report ===
+
+impl Report {
+    /// Returns the number of times the breaker would have tripped.
+    pub fn trips(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| event.transition.to == CircuitState::Open)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{ConsecutiveFailures, SlidingFailureRate};
+
+    fn record(secs: u64, success: bool) -> Record {
+        Record {
+            at: Duration::from_secs(secs),
+            success,
+            latency: Duration::from_millis(500),
+        }
+    }
+
+    #[test]
+    fn trips_and_closes() {
+        let sim = Simulation::new(Duration::from_secs(5));
+        let trace = [
+            record(0, false),
+            record(1, false),
+            record(2, true),
+            record(3, true),
+            record(7, true),
+            record(8, true),
+        ];
+        let report = sim.run(ConsecutiveFailures::new(2), trace);
+
+        assert_eq!(6, report.requests);
+        assert_eq!(2, report.rejected);
+        assert_eq!(Duration::from_secs(5), report.time_open);
+        assert_eq!(
+            vec![
+                (Duration::from_secs(2), CircuitState::Open),
+                (Duration::from_secs(7), CircuitState::Closed),
+            ],
+            report
+                .events
+                .iter()
+                .map(|e| (e.at, e.transition.to))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn time_based_policies_use_trace_time() {
+        let sim = Simulation::new(Duration::from_secs(5));
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5).with_clock(sim.clock());
+        // failures spread out over a longer period than the window never trip.
+        let trace = (0..10).flat_map(|i| [record(i * 20, false), record(i * 20 + 10, true)]);
+        let report = sim.run(policy, trace);
+        assert_eq!(0, report.trips());
+        assert_eq!(20, report.requests);
+    }
+}
@@ -0,0 +1,165 @@
+//! Point-in-time snapshots of circuit breakers, for debugging and
+//! introspection.
+//!
+//! When the `serde` feature flag is enabled, snapshots implement
+//! `serde::Serialize`, so they can be included in existing debug endpoints.
+//! A [`BreakerStatus`] summarizes a breaker in a single line, for logs and
+//! command-line tools.
+use crate::{
+    handle::{Stats, TripEvent},
+    policy::{PolicySnapshot, TripReason},
+    CircuitState,
+};
+use std::{fmt, time::Duration};
+
+/// A point-in-time snapshot of a [`CircuitBreaker`](crate::CircuitBreaker).
+///
+/// This is returned by [`Handle::snapshot`](crate::Handle::snapshot) and
+/// [`BreakerRegistry::snapshot`](crate::BreakerRegistry::snapshot).
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct BreakerSnapshot {
+    /// The breaker's name, if it has one.
+    pub name: Option<String>,
+    /// The current state of the breaker's circuit.
+    pub state: CircuitState,
+    /// The state the breaker's circuit has been forced into by an operator,
+    /// if any.
+    pub forced: Option<CircuitState>,
+    /// How long until the circuit is expected to close, or `None` if it's
+    /// closed or has been forced open.
+    pub open_remaining: Option<Duration>,
+    /// The breaker's configuration.
+    pub config: ConfigSnapshot,
+    /// A summary of the breaker's policy.
+    pub policy: PolicySnapshot,
+    /// Cumulative statistics describing the breaker's history.
+    pub stats: Stats,
+    /// The most recent times the breaker tripped, oldest first.
+    pub recent_trips: Vec<TripEvent>,
+}
+
+/// A summary of a [`CircuitBreaker`](crate::CircuitBreaker)'s current status,
+/// which can be displayed on a single line.
+///
+/// This is returned by [`Handle::status`](crate::Handle::status). Its
+/// `Display` implementation describes the breaker's state, why it's open,
+/// and its policy's statistics, such as:
+///
+/// ```text
+/// users-api: open for another 4.2s (failure rate 0.6 exceeded 0.5 over 20 requests); 20 requests, 12 failures (60.0%)
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct BreakerStatus {
+    /// The breaker's name, if it has one.
+    pub name: Option<String>,
+    /// The current state of the breaker's circuit.
+    pub state: CircuitState,
+    /// The state the breaker's circuit has been forced into by an operator,
+    /// if any.
+    pub forced: Option<CircuitState>,
+    /// How long until the circuit is expected to close, or `None` if it's
+    /// closed or has been forced open.
+    pub remaining: Option<Duration>,
+    /// Why the circuit is open, or `None` if it's closed.
+    pub reason: Option<TripReason>,
+    /// A summary of the breaker's policy.
+    pub policy: PolicySnapshot,
+}
+
+/// A summary of a [`Config`](crate::Config), as included in a
+/// [`BreakerSnapshot`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct ConfigSnapshot {
+    /// How long the breaker remains open once tripped.
+    pub trip_for: Duration,
+    /// Whether requests fail fast while the circuit is open.
+    pub fail_fast: bool,
+}
+
+// === impl BreakerStatus ===
+
+impl fmt::Display for BreakerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref name) = self.name {
+            write!(f, "{name}: ")?;
+        }
+        match self.forced {
+            Some(state) => write!(f, "forced {state}")?,
+            None => write!(f, "{}", self.state)?,
+        }
+        if let Some(remaining) = self.remaining {
+            write!(f, " for another {remaining:.1?}")?;
+        }
+        if let Some(ref reason) = self.reason {
+            write!(f, " ({reason})")?;
+        }
+
+        let policy = &self.policy;
+        let mut sep = "; ";
+        let mut stat = |f: &mut fmt::Formatter<'_>, stat: fmt::Arguments<'_>| {
+            let result = write!(f, "{sep}{stat}");
+            sep = ", ";
+            result
+        };
+        if let Some(requests) = policy.requests {
+            stat(f, format_args!("{requests} requests"))?;
+        }
+        if let Some(failures) = policy.failures {
+            stat(f, format_args!("{failures} failures"))?;
+            if let Some(rate) = policy.failure_rate {
+                write!(f, " ({:.1}%)", rate * 100.0)?;
+            }
+        }
+        if let Some(failures) = policy.consecutive_failures {
+            stat(f, format_args!("{failures} consecutive failures"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_status() {
+        let mut status = BreakerStatus {
+            name: Some("users-api".to_owned()),
+            state: CircuitState::Open,
+            forced: None,
+            remaining: Some(Duration::from_millis(4200)),
+            reason: Some(TripReason::FailureRate {
+                rate: 0.6,
+                threshold: 0.5,
+                samples: 20,
+            }),
+            policy: PolicySnapshot {
+                requests: Some(20),
+                failures: Some(12),
+                failure_rate: Some(0.6),
+                ..PolicySnapshot::default()
+            },
+        };
+        assert_eq!(
+            "users-api: open for another 4.2s (failure rate 0.6 exceeded 0.5 over 20 requests); \
+             20 requests, 12 failures (60.0%)",
+            status.to_string()
+        );
+
+        status.name = None;
+        status.forced = Some(CircuitState::Closed);
+        status.remaining = None;
+        status.reason = None;
+        status.policy = PolicySnapshot {
+            consecutive_failures: Some(2),
+            ..PolicySnapshot::default()
+        };
+        assert_eq!("forced closed; 2 consecutive failures", status.to_string());
+    }
+}
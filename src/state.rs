@@ -0,0 +1,204 @@
+//! The state machine deciding whether a breaker's circuit is open.
+use crate::{CircuitState, TripReason};
+use std::time::{Duration, Instant};
+
+/// The state of a breaker's circuit, and the transitions between its states.
+///
+/// This only tracks whether the circuit is open, the trip which opened it,
+/// and whether it's been forced into a state. Deciding _when_ to open and
+/// close the circuit, by consulting the policy, peers and handles, and
+/// publishing each transition, is left to the breaker. The current time is
+/// passed to every method which needs it, so the state machine can be
+/// tested without a clock.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StateMachine {
+    open: bool,
+    /// The current trip if the circuit is open, or the last trip if it's
+    /// closed.
+    trip: Option<Trip>,
+    /// When the circuit opened, if it's open. A trip while the circuit is
+    /// already open extends the time it's open, rather than restarting it.
+    opened_at: Option<Instant>,
+    /// The state the circuit has been forced into by a `Handle`, if any.
+    forced: Option<CircuitState>,
+    /// When the circuit last closed after a trip ended, if traffic should
+    /// ramp up since then.
+    ramping_since: Option<Instant>,
+}
+
+/// A single period for which a circuit was opened.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Trip {
+    at: Instant,
+    /// How long the trip lasts.
+    duration: Duration,
+    reason: TripReason,
+}
+
+/// The fractions of requests admitted over each successive part of the ramp
+/// up after a trip, until every request is admitted.
+const RAMP_UP: [f64; 3] = [0.1, 0.25, 0.5];
+
+// === impl StateMachine ===
+
+impl StateMachine {
+    pub(crate) fn state(&self) -> CircuitState {
+        if self.open {
+            CircuitState::Open
+        } else {
+            CircuitState::Closed
+        }
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Returns the state the circuit has been forced into, if any.
+    pub(crate) fn forced(&self) -> Option<CircuitState> {
+        self.forced
+    }
+
+    /// Returns the reason the circuit was last opened.
+    pub(crate) fn reason(&self) -> Option<TripReason> {
+        self.trip.map(|trip| trip.reason)
+    }
+
+    /// Returns when the current trip ends, or `None` if the circuit is
+    /// closed.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        let trip = self.trip.filter(|_| self.open)?;
+        Some(trip.at + trip.duration)
+    }
+
+    /// Opens the circuit at `now`, for `duration`.
+    ///
+    /// If the circuit is already open, the current trip is replaced, but the
+    /// circuit is still considered to have been open since it first opened.
+    pub(crate) fn open(&mut self, now: Instant, reason: TripReason, duration: Duration) {
+        if !self.open {
+            self.opened_at = Some(now);
+        }
+        self.open = true;
+        self.trip = Some(Trip {
+            at: now,
+            duration,
+            reason,
+        });
+    }
+
+    /// Closes the circuit at `now`, returning how long it was open, across
+    /// every trip since it opened.
+    ///
+    /// Traffic ramps up from `now`, unless the ramp is [ended](Self::end_ramp).
+    pub(crate) fn close(&mut self, now: Instant) -> Duration {
+        self.open = false;
+        self.ramping_since = Some(now);
+        self.opened_at
+            .take()
+            .map_or(Duration::ZERO, |at| now.saturating_duration_since(at))
+    }
+
+    /// Forces the circuit to remain in `state` until it's forced into another
+    /// state, or releases it if `state` is `None`.
+    ///
+    /// This doesn't open or close the circuit itself.
+    pub(crate) fn force(&mut self, state: Option<CircuitState>) {
+        self.forced = state;
+    }
+
+    /// Admits every request from now on, if traffic is ramping up.
+    pub(crate) fn end_ramp(&mut self) {
+        self.ramping_since = None;
+    }
+
+    /// Sets the duration of the current trip.
+    pub(crate) fn set_duration(&mut self, duration: Duration) {
+        if let Some(ref mut trip) = self.trip {
+            trip.duration = duration;
+        }
+    }
+
+    /// Keeps the circuit open for at least `remaining` after `now`.
+    pub(crate) fn extend(&mut self, now: Instant, remaining: Duration) {
+        if let Some(ref mut trip) = self.trip {
+            let elapsed = now.saturating_duration_since(trip.at);
+            trip.duration = trip.duration.max(elapsed + remaining);
+        }
+    }
+
+    /// Returns `true` if the circuit is open and the current trip has ended
+    /// as of `now`.
+    ///
+    /// If `now` is before the trip started, because the clock jumped
+    /// backwards, the trip is restarted from `now`, and the size of the jump
+    /// is returned as an error. This limits how much the jump can extend the
+    /// trip to at most one trip duration, rather than the size of the jump.
+    pub(crate) fn expired(&mut self, now: Instant) -> Result<bool, Duration> {
+        let Some(trip) = self.trip.as_mut() else {
+            return Ok(false);
+        };
+        if !self.open {
+            return Ok(false);
+        }
+        if now < trip.at {
+            let jump = trip.at - now;
+            trip.at = now;
+            self.opened_at = self.opened_at.map(|at| at.min(now));
+            return Err(jump);
+        }
+        Ok(now >= trip.at + trip.duration)
+    }
+
+    /// Returns the fraction of requests admitted as of `now` while traffic
+    /// ramps up for `ramp_up` after a trip, or `None` if every request is
+    /// admitted.
+    pub(crate) fn ramp_fraction(&self, now: Instant, ramp_up: Option<Duration>) -> Option<f64> {
+        let ramp_up = ramp_up.filter(|_| self.forced.is_none() && !self.open)?;
+        let elapsed = now.saturating_duration_since(self.ramping_since?);
+        if elapsed >= ramp_up {
+            return None;
+        }
+        let step = elapsed.as_secs_f64() / ramp_up.as_secs_f64() * RAMP_UP.len() as f64;
+        Some(RAMP_UP[(step as usize).min(RAMP_UP.len() - 1)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut machine = StateMachine::default();
+        assert_eq!(CircuitState::Closed, machine.state());
+        assert_eq!(None, machine.deadline());
+        assert_eq!(Ok(false), machine.expired(start));
+
+        machine.open(start, TripReason::Forced, secs(10));
+        assert_eq!(Some(start + secs(10)), machine.deadline());
+        assert_eq!(Ok(false), machine.expired(start + secs(5)));
+
+        // a dependency keeps the circuit open for longer.
+        machine.extend(start + secs(5), secs(10));
+        assert_eq!(Ok(false), machine.expired(start + secs(10)));
+        assert_eq!(Ok(true), machine.expired(start + secs(15)));
+
+        // a clock which jumps backwards restarts the trip.
+        assert_eq!(Err(secs(1)), machine.expired(start - secs(1)));
+        assert_eq!(Some(start + secs(14)), machine.deadline());
+
+        // once the trip ends, traffic ramps up.
+        assert_eq!(secs(16), machine.close(start + secs(15)));
+        assert_eq!(Duration::ZERO, machine.close(start + secs(15)));
+        assert_eq!(Some(TripReason::Forced), machine.reason());
+        let ramp_up = Some(secs(30));
+        assert_eq!(Some(0.1), machine.ramp_fraction(start + secs(15), ramp_up));
+        assert_eq!(Some(0.5), machine.ramp_fraction(start + secs(40), ramp_up));
+        assert_eq!(None, machine.ramp_fraction(start + secs(45), ramp_up));
+        machine.force(Some(CircuitState::Closed));
+        assert_eq!(None, machine.ramp_fraction(start + secs(15), ramp_up));
+    }
+}
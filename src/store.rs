@@ -0,0 +1,257 @@
+//! Sharing trip decisions between breakers through a distributed store.
+//!
+//! Each replica of a horizontally scaled service normally has its own
+//! breaker, so when a backend dies, every replica has to learn that
+//! independently, by sending it requests until its own policy trips. A
+//! [`StateStore`] lets the replicas share their breakers' state instead: when
+//! one replica's breaker trips, it publishes the trip to the store, and the
+//! other replicas' breakers open for the remainder of the trip when they next
+//! [sync](crate::CircuitBreaker::sync_state) with the store.
+//!
+//! Stores can also be used to [persist](crate::Handle::persist) a breaker's
+//! state across restarts, so that a rolling restart during an outage doesn't
+//! make every breaker rediscover the outage.
+//!
+//! This module provides an in-process [`MemoryStore`], which is mostly useful
+//! for testing, a [`FileStore`] for persisting states across restarts, and a
+//! [`RedisStore`] when the `redis` feature flag is enabled. Other stores can
+//! be supported by implementing [`StateStore`].
+//!
+//! ```
+//! use std::time::Duration;
+//! use tower::service_fn;
+//! use tower_breaker::{policy::ConsecutiveFailures, store::MemoryStore, CircuitBreaker, Config};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let store = MemoryStore::new();
+//! let config = Config::new(ConsecutiveFailures::new(5), Duration::from_secs(30));
+//! let breaker = CircuitBreaker::new(config, service_fn(|req: String| async move {
+//!     Ok::<_, std::convert::Infallible>(req)
+//! }));
+//! tokio::spawn(breaker.sync_state(store, "users-api", Duration::from_secs(1)));
+//! # }
+//! ```
+use crate::{BoxError, CircuitState};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::{self, Future},
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+mod file;
+#[cfg(feature = "redis")]
+mod redis;
+pub use self::file::FileStore;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStore;
+
+/// A store in which breakers share their state.
+pub trait StateStore: fmt::Debug + Send + Sync + 'static {
+    /// Returns the state stored under `key`, or `None` if nothing is stored
+    /// under `key`.
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredState>>;
+
+    /// Stores `state` under `key`.
+    ///
+    /// `ttl` is how long the state remains relevant. Stores which support
+    /// expiry may remove the state once `ttl` has elapsed.
+    fn set<'a>(&'a self, key: &'a str, state: StoredState, ttl: Duration) -> StoreFuture<'a, ()>;
+
+    /// Adds `requests` and `failures` to the window counts stored under
+    /// `key`, storing a closed state with those counts if nothing is stored
+    /// under `key`.
+    ///
+    /// This is used by [`ClusterFailureRate`](crate::policy::ClusterFailureRate)
+    /// to aggregate outcomes across replicas. By default, this gets the
+    /// stored state and sets the sum, which loses counts added concurrently
+    /// by other replicas; stores which can update the counts atomically
+    /// should override it.
+    fn add_window<'a>(
+        &'a self,
+        key: &'a str,
+        requests: usize,
+        failures: usize,
+        ttl: Duration,
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let state = self.get(key).await?;
+            let state = add_window(state, requests, failures);
+            self.set(key, state, ttl).await
+        })
+    }
+}
+
+/// A future returned by a [`StateStore`].
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+/// A breaker's state, as shared through a [`StateStore`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StoredState {
+    /// The state of the circuit.
+    pub state: CircuitState,
+    /// When the circuit is expected to close, if it's open.
+    pub open_until: Option<SystemTime>,
+    /// The number of requests in the policy's window when the breaker
+    /// tripped, if the policy tracks it.
+    pub requests: Option<usize>,
+    /// The number of failed requests in the policy's window when the breaker
+    /// tripped, if the policy tracks it.
+    pub failures: Option<usize>,
+}
+
+/// An in-process [`StateStore`].
+///
+/// Clones of a `MemoryStore` share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    states: Arc<Mutex<HashMap<String, StoredState>>>,
+}
+
+// === impl StoredState ===
+
+impl StoredState {
+    /// Returns a new `StoredState` for a circuit in `state`, which is expected
+    /// to close at `open_until` if it's open.
+    pub fn new(state: CircuitState, open_until: Option<SystemTime>) -> Self {
+        StoredState {
+            state,
+            open_until,
+            requests: None,
+            failures: None,
+        }
+    }
+
+    /// Sets the number of requests and failures in the policy's window.
+    pub fn with_window(self, requests: Option<usize>, failures: Option<usize>) -> Self {
+        StoredState {
+            requests,
+            failures,
+            ..self
+        }
+    }
+
+    /// Returns how long until the circuit is expected to close, or `None` if
+    /// it's closed or should already have closed.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        if self.state != CircuitState::Open {
+            return None;
+        }
+        let remaining = self.open_until?.duration_since(SystemTime::now()).ok()?;
+        Some(remaining).filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Encodes the state as
+    /// `<state> <open until, in Unix ms> <requests> <failures>`, with `-` for
+    /// missing values.
+    pub(crate) fn encode(&self) -> String {
+        fn field(value: Option<impl ToString>) -> String {
+            value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+        }
+
+        let open_until = self.open_until.map(|at| {
+            at.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+        });
+        format!(
+            "{} {} {} {}",
+            self.state.as_str(),
+            field(open_until),
+            field(self.requests),
+            field(self.failures),
+        )
+    }
+
+    /// Decodes a state encoded by [`StoredState::encode`].
+    pub(crate) fn decode(value: &str) -> Result<Self, BoxError> {
+        fn field<T: FromStr>(field: Option<&str>) -> Result<Option<T>, BoxError> {
+            match field {
+                Some("-") => Ok(None),
+                Some(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("invalid field {value:?}").into()),
+                None => Err("missing field".into()),
+            }
+        }
+
+        let mut fields = value.split(' ');
+        let state = match fields.next() {
+            Some("open") => CircuitState::Open,
+            Some("closed") => CircuitState::Closed,
+            _ => return Err(format!("invalid stored state {value:?}").into()),
+        };
+        let open_until =
+            field::<u64>(fields.next())?.map(|millis| UNIX_EPOCH + Duration::from_millis(millis));
+        let requests = field(fields.next())?;
+        let failures = field(fields.next())?;
+        Ok(StoredState::new(state, open_until).with_window(requests, failures))
+    }
+}
+
+// === impl MemoryStore ===
+
+impl MemoryStore {
+    /// Returns a new, empty `MemoryStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredState>> {
+        let state = self.states.lock().unwrap().get(key).cloned();
+        Box::pin(future::ready(Ok(state)))
+    }
+
+    fn set<'a>(&'a self, key: &'a str, state: StoredState, _: Duration) -> StoreFuture<'a, ()> {
+        self.states.lock().unwrap().insert(key.to_owned(), state);
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn add_window<'a>(
+        &'a self,
+        key: &'a str,
+        requests: usize,
+        failures: usize,
+        _: Duration,
+    ) -> StoreFuture<'a, ()> {
+        let mut states = self.states.lock().unwrap();
+        let state = add_window(states.remove(key), requests, failures);
+        states.insert(key.to_owned(), state);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+impl<S: StateStore> StateStore for Arc<S> {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredState>> {
+        (**self).get(key)
+    }
+
+    fn set<'a>(&'a self, key: &'a str, state: StoredState, ttl: Duration) -> StoreFuture<'a, ()> {
+        (**self).set(key, state, ttl)
+    }
+
+    fn add_window<'a>(
+        &'a self,
+        key: &'a str,
+        requests: usize,
+        failures: usize,
+        ttl: Duration,
+    ) -> StoreFuture<'a, ()> {
+        (**self).add_window(key, requests, failures, ttl)
+    }
+}
+
+fn add_window(state: Option<StoredState>, requests: usize, failures: usize) -> StoredState {
+    let state = state.unwrap_or_else(|| StoredState::new(CircuitState::Closed, None));
+    let requests = state.requests.unwrap_or(0) + requests;
+    let failures = state.failures.unwrap_or(0) + failures;
+    state.with_window(Some(requests), Some(failures))
+}
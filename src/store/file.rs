@@ -0,0 +1,138 @@
+use super::{StateStore, StoreFuture, StoredState};
+use crate::BoxError;
+use std::{
+    collections::BTreeMap,
+    fs, future, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A [`StateStore`] which keeps breakers' states in a small file.
+///
+/// This is intended for [persisting](crate::Handle::persist) breakers'
+/// states across restarts of a single process, rather than for sharing
+/// states between replicas: each state is written to the file by replacing
+/// it, so only one process should use the file at a time. Keys may not
+/// contain tabs or newlines.
+///
+/// The file is read and written synchronously, which is fine for the
+/// occasional accesses made at startup and shutdown. The `ttl` passed to
+/// [`set`](StateStore::set) is ignored; open states expire anyway once the
+/// time they were open until has passed.
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    path: Arc<Path>,
+    /// Serializes updates to the file from this process.
+    lock: Arc<Mutex<()>>,
+}
+
+// === impl FileStore ===
+
+impl FileStore {
+    /// Returns a new `FileStore` which keeps states in the file at `path`.
+    ///
+    /// The file is created when a state is first stored.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileStore {
+            path: path.into().into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, String>, BoxError> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(error) => return Err(error.into()),
+        };
+        contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (key, state) = line
+                    .split_once('\t')
+                    .ok_or_else(|| format!("invalid line {line:?}"))?;
+                Ok((key.to_owned(), state.to_owned()))
+            })
+            .collect()
+    }
+
+    fn write(&self, states: &BTreeMap<String, String>) -> io::Result<()> {
+        let contents = states
+            .iter()
+            .map(|(key, state)| format!("{key}\t{state}\n"))
+            .collect::<String>();
+        // write the new contents alongside the file and rename it into
+        // place, so that a crash doesn't leave a partially written file.
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn set_blocking(&self, key: &str, state: &StoredState) -> Result<(), BoxError> {
+        if key.contains(['\t', '\n']) {
+            return Err(format!("invalid key {key:?}").into());
+        }
+        let _lock = self.lock.lock().unwrap();
+        let mut states = self.read()?;
+        states.insert(key.to_owned(), state.encode());
+        self.write(&states)?;
+        Ok(())
+    }
+}
+
+impl StateStore for FileStore {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredState>> {
+        let state = self.read().and_then(|states| {
+            states
+                .get(key)
+                .map(|state| StoredState::decode(state))
+                .transpose()
+        });
+        Box::pin(future::ready(state))
+    }
+
+    fn set<'a>(&'a self, key: &'a str, state: StoredState, _: Duration) -> StoreFuture<'a, ()> {
+        Box::pin(future::ready(self.set_blocking(key, &state)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CircuitState;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn get_and_set() {
+        let path = std::env::temp_dir().join(format!("tower-breaker-store-{}", std::process::id()));
+        let store = FileStore::new(&path);
+        assert_eq!(None, store.get("users").await.unwrap());
+
+        let open_until = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let open = StoredState::new(CircuitState::Open, Some(open_until));
+        let closed = StoredState::new(CircuitState::Closed, None);
+        let ttl = Duration::from_secs(5);
+        store.set("users", open.clone(), ttl).await.unwrap();
+        store.set("orders", closed.clone(), ttl).await.unwrap();
+
+        // a new store reads the same file.
+        let store = FileStore::new(&path);
+        assert_eq!(Some(open), store.get("users").await.unwrap());
+        assert_eq!(Some(closed.clone()), store.get("orders").await.unwrap());
+        store.set("users", closed.clone(), ttl).await.unwrap();
+        assert_eq!(Some(closed), store.get("users").await.unwrap());
+        assert!(store
+            .set(
+                "bad\tkey",
+                StoredState::new(CircuitState::Open, Some(SystemTime::now())),
+                ttl
+            )
+            .await
+            .is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,339 @@
+use super::{StateStore, StoreFuture, StoredState};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A [`StateStore`] backed by a Redis server.
+///
+/// Each breaker's state is stored as a string under its key, prefixed with
+/// `tower-breaker:` by default, and expires once the trip it describes has
+/// ended. Window counts are [added](StateStore::add_window) atomically by a
+/// Lua script, so replicas adding to the same window don't lose each other's
+/// counts.
+///
+/// The store speaks the Redis protocol over a plain TCP connection, which is
+/// opened on first use and reopened after an I/O error. Authentication, TLS,
+/// and Redis Cluster are not supported. Commands are run on Tokio's blocking
+/// thread pool, so a Tokio runtime is required.
+///
+/// This requires the `redis` feature flag.
+#[derive(Clone, Debug)]
+pub struct RedisStore {
+    addr: Arc<str>,
+    prefix: Arc<str>,
+    timeout: Duration,
+    conn: Arc<Mutex<Option<Connection>>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+/// A reply to a Redis command.
+#[derive(Debug, PartialEq, Eq)]
+enum Reply {
+    Nil,
+    Simple(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Error(String),
+}
+
+/// Adds `ARGV[1]` requests and `ARGV[2]` failures to the state encoded by
+/// `StoredState::encode` under `KEYS[1]`, and expires it after `ARGV[3]`
+/// milliseconds.
+const ADD_WINDOW: &str = "\
+local value = redis.call('GET', KEYS[1])
+local fields = {}
+if value then
+    for field in string.gmatch(value, '%S+') do
+        fields[#fields + 1] = field
+    end
+end
+local requests = (tonumber(fields[3]) or 0) + tonumber(ARGV[1])
+local failures = (tonumber(fields[4]) or 0) + tonumber(ARGV[2])
+local state = string.format('%s %s %d %d', fields[1] or 'closed', fields[2] or '-', requests, failures)
+return redis.call('SET', KEYS[1], state, 'PX', ARGV[3])
+";
+
+// === impl RedisStore ===
+
+impl RedisStore {
+    /// Returns a new `RedisStore` which connects to the Redis server at
+    /// `addr` (such as `"127.0.0.1:6379"`).
+    pub fn new(addr: impl Into<String>) -> Self {
+        RedisStore {
+            addr: addr.into().into(),
+            prefix: "tower-breaker:".into(),
+            timeout: Duration::from_secs(1),
+            conn: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets the prefix added to each breaker's key. By default, this is
+    /// `tower-breaker:`.
+    pub fn with_key_prefix(self, prefix: impl Into<String>) -> Self {
+        RedisStore {
+            prefix: prefix.into().into(),
+            ..self
+        }
+    }
+
+    /// Sets how long to wait when connecting to the server, and for each
+    /// read and write. By default, this is one second.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        RedisStore { timeout, ..self }
+    }
+
+    /// Runs a command on the blocking thread pool.
+    fn command(&self, args: Vec<Vec<u8>>) -> StoreFuture<'static, Reply> {
+        let store = self.clone();
+        Box::pin(async move {
+            let reply = tokio::task::spawn_blocking(move || store.command_blocking(&args)).await?;
+            match reply? {
+                Reply::Error(error) => Err(format!("redis error: {error}").into()),
+                reply => Ok(reply),
+            }
+        })
+    }
+
+    fn command_blocking(&self, args: &[Vec<u8>]) -> io::Result<Reply> {
+        let mut conn = self.conn.lock().unwrap();
+        let result = match *conn {
+            Some(ref mut conn) => conn.command(args),
+            None => conn
+                .insert(Connection::connect(&self.addr, self.timeout)?)
+                .command(args),
+        };
+        if result.is_err() {
+            // the connection may be in an unknown state, so reconnect next
+            // time.
+            *conn = None;
+        }
+        result
+    }
+
+    fn key(&self, key: &str) -> Vec<u8> {
+        format!("{}{key}", self.prefix).into_bytes()
+    }
+}
+
+impl StateStore for RedisStore {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredState>> {
+        let command = self.command(vec![b"GET".to_vec(), self.key(key)]);
+        Box::pin(async move {
+            match command.await? {
+                Reply::Nil => Ok(None),
+                Reply::Bulk(value) => StoredState::decode(std::str::from_utf8(&value)?).map(Some),
+                reply => Err(format!("unexpected reply to GET: {reply:?}").into()),
+            }
+        })
+    }
+
+    fn set<'a>(&'a self, key: &'a str, state: StoredState, ttl: Duration) -> StoreFuture<'a, ()> {
+        // `PX` must be positive.
+        let ttl = ttl.as_millis().max(1);
+        let command = self.command(vec![
+            b"SET".to_vec(),
+            self.key(key),
+            state.encode().into_bytes(),
+            b"PX".to_vec(),
+            ttl.to_string().into_bytes(),
+        ]);
+        Box::pin(async move {
+            command.await?;
+            Ok(())
+        })
+    }
+
+    fn add_window<'a>(
+        &'a self,
+        key: &'a str,
+        requests: usize,
+        failures: usize,
+        ttl: Duration,
+    ) -> StoreFuture<'a, ()> {
+        let ttl = ttl.as_millis().max(1);
+        let command = self.command(vec![
+            b"EVAL".to_vec(),
+            ADD_WINDOW.as_bytes().to_vec(),
+            b"1".to_vec(),
+            self.key(key),
+            requests.to_string().into_bytes(),
+            failures.to_string().into_bytes(),
+            ttl.to_string().into_bytes(),
+        ]);
+        Box::pin(async move {
+            command.await?;
+            Ok(())
+        })
+    }
+}
+
+// === impl Connection ===
+
+impl Connection {
+    fn connect(addr: &str, timeout: Duration) -> io::Result<Self> {
+        let addr = std::net::ToSocketAddrs::to_socket_addrs(addr)?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+        let writer = TcpStream::connect_timeout(&addr, timeout)?;
+        writer.set_read_timeout(Some(timeout))?;
+        writer.set_write_timeout(Some(timeout))?;
+        writer.set_nodelay(true)?;
+        Ok(Connection {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    fn command(&mut self, args: &[Vec<u8>]) -> io::Result<Reply> {
+        let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            buf.extend_from_slice(arg);
+            buf.extend_from_slice(b"\r\n");
+        }
+        self.writer.write_all(&buf)?;
+        read_reply(&mut self.reader)
+    }
+}
+
+/// Reads a single non-array reply.
+fn read_reply(reader: &mut impl BufRead) -> io::Result<Reply> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let line = line
+        .strip_suffix("\r\n")
+        .ok_or_else(|| invalid("reply missing CRLF"))?;
+    let (kind, rest) = line
+        .split_at_checked(1)
+        .ok_or_else(|| invalid("empty reply"))?;
+    match kind {
+        "+" => Ok(Reply::Simple(rest.to_owned())),
+        "-" => Ok(Reply::Error(rest.to_owned())),
+        ":" => rest
+            .parse()
+            .map(Reply::Integer)
+            .map_err(|_| invalid("invalid integer reply")),
+        "$" => {
+            let len: i64 = rest.parse().map_err(|_| invalid("invalid bulk length"))?;
+            let Ok(len) = usize::try_from(len) else {
+                return Ok(Reply::Nil);
+            };
+            let mut value = vec![0; len + 2];
+            reader.read_exact(&mut value)?;
+            if !value.ends_with(b"\r\n") {
+                return Err(invalid("bulk reply missing CRLF"));
+            }
+            value.truncate(len);
+            Ok(Reply::Bulk(value))
+        }
+        _ => Err(invalid("unsupported reply type")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CircuitState;
+    use std::{collections::HashMap, io::Read, net::TcpListener, thread, time::UNIX_EPOCH};
+
+    /// Serves `GET`, `SET`, and `ADD_WINDOW` script commands from a single
+    /// connection.
+    fn fake_redis() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut values = HashMap::<Vec<u8>, Vec<u8>>::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let argc: usize = line.trim_end()[1..].parse().unwrap();
+                let mut args = Vec::new();
+                for _ in 0..argc {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let len: usize = line.trim_end()[1..].parse().unwrap();
+                    let mut arg = vec![0; len + 2];
+                    reader.read_exact(&mut arg).unwrap();
+                    arg.truncate(len);
+                    args.push(arg);
+                }
+                let reply = match &args[0][..] {
+                    b"GET" => match values.get(&args[1]) {
+                        Some(value) => {
+                            let mut reply = format!("${}\r\n", value.len()).into_bytes();
+                            reply.extend_from_slice(value);
+                            reply.extend_from_slice(b"\r\n");
+                            reply
+                        }
+                        None => b"$-1\r\n".to_vec(),
+                    },
+                    b"SET" => {
+                        assert_eq!(b"PX", &args[3][..]);
+                        values.insert(args[1].clone(), args[2].clone());
+                        b"+OK\r\n".to_vec()
+                    }
+                    b"EVAL" => {
+                        assert_eq!(ADD_WINDOW.as_bytes(), &args[1][..]);
+                        assert_eq!(b"1", &args[2][..]);
+                        let arg = |i: usize| -> usize {
+                            std::str::from_utf8(&args[i]).unwrap().parse().unwrap()
+                        };
+                        let state = values
+                            .get(&args[3])
+                            .map(|value| StoredState::decode(std::str::from_utf8(value).unwrap()))
+                            .transpose()
+                            .unwrap();
+                        let state = crate::store::add_window(state, arg(4), arg(5));
+                        values.insert(args[3].clone(), state.encode().into_bytes());
+                        b"+OK\r\n".to_vec()
+                    }
+                    _ => b"-ERR unknown command\r\n".to_vec(),
+                };
+                writer.write_all(&reply).unwrap();
+                line.clear();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn get_and_set() {
+        let store = RedisStore::new(fake_redis());
+        assert_eq!(None, store.get("users").await.unwrap());
+
+        let open_until = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let state =
+            StoredState::new(CircuitState::Open, Some(open_until)).with_window(Some(20), None);
+        store
+            .set("users", state.clone(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(Some(state), store.get("users").await.unwrap());
+        assert_eq!(None, store.get("orders").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn add_window() {
+        let store = RedisStore::new(fake_redis());
+        let ttl = Duration::from_secs(60);
+        store.add_window("users/1", 3, 1, ttl).await.unwrap();
+        store.add_window("users/1", 2, 2, ttl).await.unwrap();
+        let state = store.get("users/1").await.unwrap().unwrap();
+        assert_eq!(CircuitState::Closed, state.state);
+        assert_eq!((Some(5), Some(3)), (state.requests, state.failures));
+    }
+}
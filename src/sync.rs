@@ -0,0 +1,259 @@
+//! A circuit breaker for blocking function calls.
+//!
+//! The [`CircuitBreaker`] in this module guards plain, synchronous function
+//! calls rather than a [`Service`](tower_service::Service), for codebases
+//! which mix async clients with blocking clients (such as database drivers).
+//! It is configured with the same [`Config`] and uses the same
+//! [policies](crate::policy) as the async breaker.
+//!
+//! Because a blocking caller cannot wait for the circuit to close without
+//! blocking its thread, calls made while the circuit is open always fail
+//! fast with a [`CircuitOpen`] error, regardless of
+//! [`Config::fail_fast`].
+//!
+//! ```
+//! use tower_breaker::{
+//!     error::CallError, policy::ConsecutiveFailures, sync::CircuitBreaker, Config,
+//! };
+//! use std::time::Duration;
+//!
+//! let config = Config::new(ConsecutiveFailures::new(2), Duration::from_secs(5));
+//! let breaker = CircuitBreaker::new(config);
+//!
+//! for _ in 0..2 {
+//!     let result = breaker.call(|| Err::<(), _>("connection refused"));
+//!     assert!(matches!(result, Err(CallError::Inner("connection refused"))));
+//! }
+//!
+//! // the circuit is now open, so the function isn't called.
+//! let result = breaker.call(|| -> Result<(), &str> { unreachable!() });
+//! assert!(matches!(result, Err(CallError::Open(_))));
+//! ```
+#[cfg(feature = "tracing")]
+use crate::rate_limit::{self, RateLimiter};
+use crate::{
+    error::{CallError, CircuitOpen},
+    trace::dyn_event,
+    CircuitState, Config, Policy, Rejection, Transition, TripReason,
+};
+use std::{fmt, sync::Mutex, time::Instant};
+
+/// A circuit breaker guarding blocking function calls.
+///
+/// A `CircuitBreaker` may be shared between threads (e.g. by wrapping it in
+/// an [`Arc`](std::sync::Arc)).
+pub struct CircuitBreaker<P> {
+    config: Config<P>,
+    state: Mutex<State>,
+    /// Limits the rejections logged, if the breaker's events are limited.
+    #[cfg(feature = "tracing")]
+    rejection_logs: Option<RateLimiter>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// If the circuit is open, when it was opened and why.
+    tripped: Option<(Instant, TripReason)>,
+}
+
+// === impl CircuitBreaker ===
+
+impl<P: Policy> CircuitBreaker<P> {
+    /// Returns a new `CircuitBreaker` with the provided `config`.
+    pub fn new(config: Config<P>) -> Self {
+        CircuitBreaker {
+            #[cfg(feature = "tracing")]
+            rejection_logs: RateLimiter::from_config(&config),
+            config,
+            state: Mutex::new(State { tripped: None }),
+        }
+    }
+
+    /// Calls `f` if the circuit is closed, recording its result with the
+    /// breaker's policy.
+    ///
+    /// If the circuit is open, `f` is not called, and this returns
+    /// [`CallError::Open`].
+    pub fn call<T, E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<T, CallError<E>> {
+        self.acquire().map_err(|error| {
+            #[cfg(feature = "tracing")]
+            if let Some(suppressed) = rate_limit::acquire(self.rejection_logs.as_ref()) {
+                dyn_event!(
+                    self.config.decision_level,
+                    breaker = self.config.name.as_deref(),
+                    reason = %error,
+                    suppressed,
+                    "rejecting call"
+                );
+            }
+            self.config
+                .hooks
+                .rejected(&Rejection::refused(&error, None));
+            CallError::Open(error)
+        })?;
+        match f() {
+            Ok(rsp) => {
+                self.config.policy.record_success();
+                Ok(rsp)
+            }
+            Err(error) => {
+                self.config.policy.record_failure();
+                Err(CallError::Inner(error))
+            }
+        }
+    }
+
+    /// Returns the current state of the circuit.
+    ///
+    /// Like the async breaker, the circuit is opened and closed when a call
+    /// is attempted, so this reflects the state as of the last call.
+    pub fn state(&self) -> CircuitState {
+        match self.state.lock().unwrap().tripped {
+            Some(_) => CircuitState::Open,
+            None => CircuitState::Closed,
+        }
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.state() == CircuitState::Open
+    }
+
+    /// Returns a reference to the breaker's policy.
+    pub fn policy(&self) -> &P {
+        &self.config.policy
+    }
+
+    /// Returns an error if a call may not be made because the circuit is
+    /// open, opening or closing the circuit as necessary.
+    fn acquire(&self) -> Result<(), CircuitOpen> {
+        let mut state = self.state.lock().unwrap();
+        if state.tripped.is_none() {
+            if let Some(reason) = self.config.policy.punish_reason() {
+                self.trip(&mut state, reason);
+            }
+        }
+
+        let Some((tripped_at, reason)) = state.tripped else {
+            return Ok(());
+        };
+        let now = self.config.clock.now();
+        if now < tripped_at {
+            // the clock jumped backwards: restart the trip, rather than
+            // extending it by the size of the jump.
+            state.tripped = Some((now, reason));
+        }
+        let open_for = now.saturating_duration_since(tripped_at);
+        if open_for < self.config.trip_for {
+            return Err(CircuitOpen::new(self.config.name.clone(), Some(reason)));
+        }
+
+        dyn_event!(
+            self.config.transition_level,
+            breaker = self.config.name.as_deref(),
+            ?open_for,
+            "circuit breaker closed"
+        );
+        state.tripped = None;
+        self.config.hooks.state_changed(Transition {
+            from: CircuitState::Open,
+            to: CircuitState::Closed,
+            reason: None,
+        });
+        Ok(())
+    }
+
+    fn trip(&self, state: &mut State, reason: TripReason) {
+        #[cfg(feature = "tracing")]
+        crate::trace::circuit_opened(
+            self.config.transition_level,
+            self.config.name.as_deref(),
+            reason,
+            None,
+            &self.config.policy.snapshot(),
+            self.config.trip_for,
+        );
+        state.tripped = Some((self.config.clock.now(), reason));
+        self.config.policy.reset();
+        self.config.hooks.state_changed(Transition {
+            from: CircuitState::Closed,
+            to: CircuitState::Open,
+            reason: Some(reason),
+        });
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for CircuitBreaker<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("config", &self.config)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, policy::ConsecutiveFailures};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[test]
+    fn trips_and_recovers() {
+        let clock = ManualClock::new();
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let config = Config::new(ConsecutiveFailures::new(2), Duration::from_secs(5))
+            .with_clock(clock.clone())
+            .on_state_change({
+                let transitions = transitions.clone();
+                move |transition| transitions.lock().unwrap().push(transition.to)
+            });
+        let breaker = CircuitBreaker::new(config);
+
+        assert_eq!(Ok(1), breaker.call(|| Ok::<_, ()>(1)).map_err(drop));
+        assert!(breaker.call(|| Err::<(), _>(())).is_err());
+        assert!(breaker.call(|| Err::<(), _>(())).is_err());
+        assert!(!breaker.is_tripped());
+
+        let mut called = false;
+        let result = breaker.call(|| {
+            called = true;
+            Ok::<_, ()>(())
+        });
+        assert!(matches!(result, Err(CallError::Open(_))));
+        assert!(!called);
+        assert!(breaker.is_tripped());
+
+        clock.advance(Duration::from_secs(5));
+        assert!(breaker.call(|| Ok::<_, ()>(())).is_ok());
+        assert!(!breaker.is_tripped());
+        assert_eq!(
+            vec![CircuitState::Open, CircuitState::Closed],
+            *transitions.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let config = Config::new(ConsecutiveFailures::new(100), Duration::from_secs(5));
+        let breaker = Arc::new(CircuitBreaker::new(config));
+        let threads = (0..4)
+            .map(|_| {
+                let breaker = breaker.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        let _ = breaker.call(|| Err::<(), _>(()));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let result = breaker.call(|| Ok::<_, ()>(()));
+        assert!(matches!(result, Err(CallError::Open(_))));
+    }
+}
@@ -0,0 +1,480 @@
+//! Utilities for testing circuit breaker configurations.
+//!
+//! This module is only available when the `testing` feature flag is enabled.
+//!
+//! A [`Harness`] wraps a [`CircuitBreaker`] around a scripted service, so
+//! that tests can drive the breaker through a sequence of request outcomes
+//! and the passage of time, and then assert on the state transitions that
+//! occurred. For example:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! use tower_breaker::{
+//!     policy::SlidingFailureRate,
+//!     testing::{Harness, Outcome, Step},
+//!     CircuitState, Config,
+//! };
+//! use tokio::time::Duration;
+//!
+//! let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5);
+//! let mut harness = Harness::new(Config::new(policy, Duration::from_secs(5)));
+//!
+//! let outcomes = harness
+//!     .run([Step::Succeed, Step::Fail, Step::Fail, Step::Succeed])
+//!     .await;
+//! assert_eq!(
+//!     outcomes,
+//!     [Outcome::Success, Outcome::Failure, Outcome::Failure, Outcome::Rejected]
+//! );
+//! assert_eq!(harness.state(), CircuitState::Open);
+//!
+//! harness.run([Step::Advance(Duration::from_secs(5))]).await;
+//! assert_eq!(harness.state(), CircuitState::Closed);
+//! assert_eq!(harness.take_transitions().len(), 2);
+//! # }
+//! ```
+//!
+//! Time is advanced using [`tokio::time::advance`], so the Tokio clock must
+//! be [paused](tokio::time::pause) when a script includes
+//! [`Step::Advance`].
+//!
+//! A [`MockPolicy`] is a [`Policy`] whose decisions are controlled by the
+//! test, for testing middleware stacks that include a breaker without
+//! depending on the behavior of a real policy.
+//!
+//! For integration tests of breakers wrapping other services (such as
+//! `tower-test`'s mock service), [`drive_until_tripped`] sends requests
+//! until the circuit opens, and the [`assert_open!`](crate::assert_open) and
+//! [`assert_closed!`](crate::assert_closed) macros assert on the state of a
+//! breaker, [`Handle`], or [`Harness`].
+use crate::{
+    policy::PolicySnapshot, BoxError, CircuitBreaker, CircuitState, Config, Handle, Policy,
+    Transition, TripReason,
+};
+use std::{
+    fmt, future,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::time::{self, Duration};
+use tower_service::Service;
+
+/// Drives a [`CircuitBreaker`] through scripted request outcomes.
+///
+/// See the [module-level documentation](self) for details.
+pub struct Harness<P> {
+    breaker: CircuitBreaker<P, Scripted>,
+    transitions: Arc<Mutex<Vec<Transition>>>,
+}
+
+/// A single step in a script run by a [`Harness`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Step {
+    /// Make a request which succeeds.
+    Succeed,
+    /// Make a request which fails.
+    Fail,
+    /// Advance the Tokio clock by the given duration.
+    Advance(Duration),
+}
+
+/// The outcome of a request made by a [`Harness`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Outcome {
+    /// The request was passed to the service, and succeeded.
+    Success,
+    /// The request was passed to the service, and failed.
+    Failure,
+    /// The request was not passed to the service, because the circuit was
+    /// open.
+    Rejected,
+}
+
+/// Asserts that a circuit breaker's circuit is open.
+///
+/// The argument may be anything with a `state()` method returning a
+/// [`CircuitState`](crate::CircuitState), such as a
+/// [`CircuitBreaker`](crate::CircuitBreaker), [`Handle`](crate::Handle), or
+/// [`Harness`](crate::testing::Harness). An optional message may be
+/// provided, as with [`assert!`].
+#[macro_export]
+macro_rules! assert_open {
+    ($breaker:expr $(,)?) => {
+        $crate::assert_open!($breaker, "expected circuit to be open")
+    };
+    ($breaker:expr, $($msg:tt)+) => {
+        match $breaker.state() {
+            $crate::CircuitState::Open => {}
+            state => panic!("{} (state: {})", format_args!($($msg)+), state),
+        }
+    };
+}
+
+/// Asserts that a circuit breaker's circuit is closed.
+///
+/// See [`assert_open!`](crate::assert_open) for details.
+#[macro_export]
+macro_rules! assert_closed {
+    ($breaker:expr $(,)?) => {
+        $crate::assert_closed!($breaker, "expected circuit to be closed")
+    };
+    ($breaker:expr, $($msg:tt)+) => {
+        match $breaker.state() {
+            $crate::CircuitState::Closed => {}
+            state => panic!("{} (state: {})", format_args!($($msg)+), state),
+        }
+    };
+}
+
+/// Sends requests produced by `make_request` through `breaker` until its
+/// circuit opens, returning the number of requests that completed before it
+/// did.
+///
+/// Requests are sent one at a time, and each response is awaited before the
+/// next request is sent, so the inner service must complete requests without
+/// further intervention from the test. Errors returned by the service are
+/// ignored.
+///
+/// Returns `None` if the circuit is still closed after `max_requests`
+/// requests.
+pub async fn drive_until_tripped<P, S, Req>(
+    breaker: &mut CircuitBreaker<P, S>,
+    mut make_request: impl FnMut() -> Req,
+    max_requests: usize,
+) -> Option<usize>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+    Req: 'static,
+{
+    for sent in 0..=max_requests {
+        // poll once, so that the breaker consults its policy.
+        let _ = future::poll_fn(|cx| Poll::Ready(breaker.poll_ready(cx))).await;
+        if breaker.is_tripped() {
+            return Some(sent);
+        }
+        if sent == max_requests {
+            break;
+        }
+        future::poll_fn(|cx| breaker.poll_ready(cx)).await.ok()?;
+        let _ = breaker.call(make_request()).await;
+    }
+    None
+}
+
+/// A [`Policy`] whose punishment decision is controlled by the test, and
+/// which records the outcomes it is notified of.
+///
+/// Cloning a `MockPolicy` returns a new reference to the same policy, so a
+/// test can keep a clone to control a policy that has been moved into a
+/// breaker.
+#[derive(Clone, Debug, Default)]
+pub struct MockPolicy(Arc<Mutex<MockState>>);
+
+#[derive(Debug, Default)]
+struct MockState {
+    punish: Option<TripReason>,
+    successes: usize,
+    failures: usize,
+    resets: usize,
+}
+
+/// A service which succeeds if the request is `true`, and fails otherwise.
+#[derive(Debug)]
+struct Scripted;
+
+// === impl Harness ===
+
+impl<P> Harness<P>
+where
+    P: Policy + Clone + fmt::Debug + Send + Sync + 'static,
+{
+    /// Returns a new `Harness` driving a breaker constructed with `config`.
+    pub fn new(config: Config<P>) -> Self {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let config = config.on_state_change({
+            let transitions = transitions.clone();
+            move |transition| transitions.lock().unwrap().push(transition)
+        });
+        Harness {
+            breaker: CircuitBreaker::new(config, Scripted),
+            transitions,
+        }
+    }
+
+    /// Runs each step in `script`, returning the outcome of each request
+    /// made.
+    pub async fn run(&mut self, script: impl IntoIterator<Item = Step>) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+        for step in script {
+            match step {
+                Step::Succeed => outcomes.push(self.request(true).await),
+                Step::Fail => outcomes.push(self.request(false).await),
+                Step::Advance(duration) => time::advance(duration).await,
+            }
+        }
+        outcomes
+    }
+
+    /// Makes a single request which succeeds if `ok` is `true`, and fails
+    /// otherwise.
+    ///
+    /// If the breaker is not immediately ready, or if it rejects the request
+    /// because it is configured to [fail fast](Config::with_fail_fast), the
+    /// request is [rejected](Outcome::Rejected).
+    pub async fn request(&mut self, ok: bool) -> Outcome {
+        let ready = future::poll_fn(|cx| Poll::Ready(self.breaker.poll_ready(cx))).await;
+        match ready {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(_)) | Poll::Pending => return Outcome::Rejected,
+        }
+        let rejected = self.breaker.is_tripped();
+        match self.breaker.call(ok).await {
+            Ok(()) => Outcome::Success,
+            Err(_) if rejected => Outcome::Rejected,
+            Err(_) => Outcome::Failure,
+        }
+    }
+
+    /// Returns the current state of the breaker's circuit.
+    ///
+    /// Like the breaker itself, this does not reflect the passage of time
+    /// until the breaker is next polled for readiness. Use [`poll`] to
+    /// update the breaker's state without making a request.
+    ///
+    /// [`poll`]: Harness::poll
+    pub fn state(&mut self) -> CircuitState {
+        self.poll();
+        self.breaker.state()
+    }
+
+    /// Polls the breaker for readiness once, without making a request.
+    pub fn poll(&mut self) {
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let _ = Service::<bool>::poll_ready(&mut self.breaker, &mut cx);
+    }
+
+    /// Returns a [`Handle`] to the breaker.
+    pub fn handle(&self) -> Handle {
+        self.breaker.handle()
+    }
+
+    /// Returns the transitions that have occurred since the last call to
+    /// `take_transitions`, oldest first.
+    pub fn take_transitions(&self) -> Vec<Transition> {
+        std::mem::take(&mut *self.transitions.lock().unwrap())
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for Harness<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Harness")
+            .field("transitions", &self.transitions.lock().unwrap())
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl MockPolicy ===
+
+impl MockPolicy {
+    /// Returns a new `MockPolicy`, which does not punish the service.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Punishes the service, with [`TripReason::Unspecified`].
+    ///
+    /// The service remains punished until the policy is
+    /// [pardoned](MockPolicy::pardon) or reset. Since breakers reset their
+    /// policy when they trip, this will typically trip the breaker once.
+    pub fn punish(&self) {
+        self.punish_with(TripReason::Unspecified);
+    }
+
+    /// Punishes the service with the given `reason`.
+    pub fn punish_with(&self, reason: TripReason) {
+        self.0.lock().unwrap().punish = Some(reason);
+    }
+
+    /// Stops punishing the service.
+    pub fn pardon(&self) {
+        self.0.lock().unwrap().punish = None;
+    }
+
+    /// Returns the number of successes recorded by this policy.
+    pub fn successes(&self) -> usize {
+        self.0.lock().unwrap().successes
+    }
+
+    /// Returns the number of failures recorded by this policy.
+    pub fn failures(&self) -> usize {
+        self.0.lock().unwrap().failures
+    }
+
+    /// Returns the number of times this policy has been reset.
+    pub fn resets(&self) -> usize {
+        self.0.lock().unwrap().resets
+    }
+}
+
+impl Policy for MockPolicy {
+    fn record_success(&self) {
+        self.0.lock().unwrap().successes += 1;
+    }
+
+    fn record_failure(&self) {
+        self.0.lock().unwrap().failures += 1;
+    }
+
+    fn is_punished(&self) -> bool {
+        self.punish_reason().is_some()
+    }
+
+    fn punish_reason(&self) -> Option<TripReason> {
+        self.0.lock().unwrap().punish
+    }
+
+    fn snapshot(&self) -> PolicySnapshot {
+        let state = self.0.lock().unwrap();
+        PolicySnapshot {
+            requests: Some(state.successes + state.failures),
+            failures: Some(state.failures),
+            ..PolicySnapshot::default()
+        }
+    }
+
+    /// Resets the policy, which stops punishing the service.
+    ///
+    /// The recorded outcomes are not cleared, so that tests can inspect every
+    /// outcome recorded over the policy's lifetime.
+    fn reset(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.punish = None;
+        state.resets += 1;
+    }
+}
+
+// === impl Scripted ===
+
+impl Service<bool> for Scripted {
+    type Response = ();
+    type Error = BoxError;
+    type Future = future::Ready<Result<(), BoxError>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, ok: bool) -> Self::Future {
+        future::ready(if ok {
+            Ok(())
+        } else {
+            Err("scripted failure".into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::SlidingFailureRate;
+
+    #[tokio::test(start_paused = true)]
+    async fn window_and_trip_expiry() {
+        let policy = SlidingFailureRate::new(Duration::from_secs(10), 0.5);
+        let mut harness = Harness::new(Config::new(policy, Duration::from_secs(5)));
+
+        // failures that slide out of the window don't count.
+        let outcomes = harness
+            .run([
+                Step::Succeed,
+                Step::Fail,
+                Step::Advance(Duration::from_secs(11)),
+                Step::Succeed,
+                Step::Succeed,
+                Step::Fail,
+            ])
+            .await;
+        assert!(!outcomes.contains(&Outcome::Rejected));
+        assert_eq!(CircuitState::Closed, harness.state());
+
+        let outcomes = harness.run([Step::Fail, Step::Fail, Step::Succeed]).await;
+        assert_eq!(
+            vec![Outcome::Failure, Outcome::Failure, Outcome::Rejected],
+            outcomes
+        );
+
+        harness.run([Step::Advance(Duration::from_secs(4))]).await;
+        assert_eq!(CircuitState::Open, harness.state());
+        harness.run([Step::Advance(Duration::from_secs(1))]).await;
+        assert_eq!(CircuitState::Closed, harness.state());
+
+        let transitions = harness.take_transitions();
+        assert_eq!(
+            vec![
+                (CircuitState::Closed, CircuitState::Open),
+                (CircuitState::Open, CircuitState::Closed)
+            ],
+            transitions
+                .iter()
+                .map(|t| (t.from, t.to))
+                .collect::<Vec<_>>()
+        );
+        assert!(harness.take_transitions().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drive_until_tripped() {
+        let policy = MockPolicy::new();
+        let config = Config::new(policy.clone(), Duration::from_secs(5));
+        let mut breaker = CircuitBreaker::new(config, Scripted);
+
+        assert_eq!(
+            None,
+            super::drive_until_tripped(&mut breaker, || true, 3).await
+        );
+        crate::assert_closed!(breaker);
+
+        let mut sent = 0;
+        let tripped = super::drive_until_tripped(
+            &mut breaker,
+            || {
+                sent += 1;
+                if sent == 2 {
+                    policy.punish();
+                }
+                true
+            },
+            10,
+        )
+        .await;
+        assert_eq!(Some(2), tripped);
+        crate::assert_open!(breaker.handle(), "breaker should trip");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mock_policy() {
+        let policy = MockPolicy::new();
+        let mut harness = Harness::new(Config::new(policy.clone(), Duration::from_secs(5)));
+
+        harness.run([Step::Succeed, Step::Fail]).await;
+        assert_eq!(1, policy.successes());
+        assert_eq!(1, policy.failures());
+
+        policy.punish_with(TripReason::Forced);
+        assert_eq!(vec![Outcome::Rejected], harness.run([Step::Succeed]).await);
+        assert_eq!(1, policy.resets());
+        assert_eq!(
+            Some(TripReason::Forced),
+            harness.take_transitions()[0].reason
+        );
+
+        harness.run([Step::Advance(Duration::from_secs(5))]).await;
+        assert_eq!(CircuitState::Closed, harness.state());
+        assert_eq!(1, policy.successes());
+    }
+}
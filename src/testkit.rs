@@ -0,0 +1,367 @@
+//! Fake services for exercising circuit breakers.
+//!
+//! This module is only available when the `testkit` feature flag is enabled.
+//!
+//! Each service in this module echoes its request back as its response, and
+//! fails with an [`InjectedFailure`] error when it is configured to fail. For
+//! example, to check that a breaker opens once a service starts failing:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use tower_breaker::{
+//!     error::CircuitOpen, policy::ConsecutiveFailures, testkit::FailAfter, CircuitBreaker, Config,
+//! };
+//! use tokio::time::Duration;
+//! use tower::ServiceExt;
+//!
+//! let config = Config::new(ConsecutiveFailures::new(3), Duration::from_secs(5))
+//!     .with_fail_fast(true);
+//! let mut breaker = CircuitBreaker::new(config, FailAfter::new(2));
+//!
+//! for _ in 0..2 {
+//!     (&mut breaker).oneshot(()).await.unwrap();
+//! }
+//! for _ in 0..3 {
+//!     assert!((&mut breaker).oneshot(()).await.is_err());
+//! }
+//! let error = (&mut breaker).oneshot(()).await.unwrap_err();
+//! assert!(error.is::<CircuitOpen>());
+//! # }
+//! ```
+use crate::{
+    error::InjectedFailure,
+    rng::{self, Rng, SharedRng, XorShift64},
+    timer::{self, SharedTimer, Sleep, Timer},
+};
+use std::{
+    fmt,
+    future::{self, Future},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower_service::Service;
+
+/// A service that fails every request.
+#[derive(Clone, Debug, Default)]
+pub struct AlwaysFail {
+    _p: (),
+}
+
+/// A service that fails a fixed number of requests, and then succeeds.
+///
+/// Clones of a `FailFirst` share the same count of requests.
+#[derive(Clone, Debug)]
+pub struct FailFirst {
+    failures: usize,
+    calls: Arc<AtomicUsize>,
+}
+
+/// A service that succeeds for a fixed number of requests, and then fails.
+///
+/// Clones of a `FailAfter` share the same count of requests.
+#[derive(Clone, Debug)]
+pub struct FailAfter {
+    successes: usize,
+    calls: Arc<AtomicUsize>,
+}
+
+/// A service that randomly fails a fraction of requests.
+///
+/// Clones of a `Flaky` service share the same [`Rng`].
+#[derive(Clone, Debug)]
+pub struct Flaky {
+    error_rate: f64,
+    rng: SharedRng,
+}
+
+/// A service that succeeds, but randomly delays a fraction of requests.
+///
+/// Clones of a `LatencySpikes` service share the same [`Rng`].
+#[derive(Clone, Debug)]
+pub struct LatencySpikes {
+    latency: Duration,
+    rate: f64,
+    rng: SharedRng,
+    timer: SharedTimer,
+}
+
+pin_project_lite::pin_project! {
+    /// The response future returned by [`LatencySpikes`].
+    pub struct Delayed<T> {
+        delay: Option<Sleep>,
+        response: Option<T>,
+    }
+}
+
+type Ready<T> = future::Ready<Result<T, InjectedFailure>>;
+
+fn respond<T>(fail: bool, req: T) -> Ready<T> {
+    future::ready(if fail {
+        Err(InjectedFailure::new())
+    } else {
+        Ok(req)
+    })
+}
+
+fn assert_rate(name: &str, rate: f64) {
+    assert!(
+        (0.0..=1.0).contains(&rate),
+        "{name} ({rate}) must be in the range [0, 1]"
+    );
+}
+
+// === impl AlwaysFail ===
+
+impl AlwaysFail {
+    /// Returns a new `AlwaysFail` service.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<Req> Service<Req> for AlwaysFail {
+    type Response = Req;
+    type Error = InjectedFailure;
+    type Future = Ready<Req>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        respond(true, req)
+    }
+}
+
+// === impl FailFirst ===
+
+impl FailFirst {
+    /// Returns a service that fails the first `failures` requests.
+    pub fn new(failures: usize) -> Self {
+        FailFirst {
+            failures,
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<Req> Service<Req> for FailFirst {
+    type Response = Req;
+    type Error = InjectedFailure;
+    type Future = Ready<Req>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed);
+        respond(calls < self.failures, req)
+    }
+}
+
+// === impl FailAfter ===
+
+impl FailAfter {
+    /// Returns a service that fails every request after the first
+    /// `successes`.
+    pub fn new(successes: usize) -> Self {
+        FailAfter {
+            successes,
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<Req> Service<Req> for FailAfter {
+    type Response = Req;
+    type Error = InjectedFailure;
+    type Future = Ready<Req>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed);
+        respond(calls >= self.successes, req)
+    }
+}
+
+// === impl Flaky ===
+
+impl Flaky {
+    /// Returns a service that fails the given fraction of requests, between
+    /// 0 and 1.
+    ///
+    /// # Panics
+    ///
+    /// If `error_rate` is less than 0 or greater than 1.
+    pub fn new(error_rate: f64) -> Self {
+        assert_rate("error rate", error_rate);
+        Flaky {
+            error_rate,
+            rng: rng::shared(XorShift64::from_entropy()),
+        }
+    }
+
+    /// Sets the [`Rng`] used to decide which requests fail.
+    pub fn with_rng(self, rng: impl Rng) -> Self {
+        Flaky {
+            rng: rng::shared(rng),
+            ..self
+        }
+    }
+}
+
+impl<Req> Service<Req> for Flaky {
+    type Response = Req;
+    type Error = InjectedFailure;
+    type Future = Ready<Req>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        respond(rng::next_f64(&self.rng) < self.error_rate, req)
+    }
+}
+
+// === impl LatencySpikes ===
+
+impl LatencySpikes {
+    /// Returns a service that delays the given fraction of requests, between
+    /// 0 and 1, by `latency`.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is less than 0 or greater than 1.
+    pub fn new(latency: Duration, rate: f64) -> Self {
+        assert_rate("latency rate", rate);
+        LatencySpikes {
+            latency,
+            rate,
+            rng: rng::shared(XorShift64::from_entropy()),
+            timer: timer::default(),
+        }
+    }
+
+    /// Sets the [`Rng`] used to decide which requests are delayed.
+    pub fn with_rng(self, rng: impl Rng) -> Self {
+        LatencySpikes {
+            rng: rng::shared(rng),
+            ..self
+        }
+    }
+
+    /// Sets the [`Timer`] used to delay requests.
+    pub fn with_timer(self, timer: impl Timer) -> Self {
+        LatencySpikes {
+            timer: Arc::new(timer),
+            ..self
+        }
+    }
+}
+
+impl<Req> Service<Req> for LatencySpikes {
+    type Response = Req;
+    type Error = InjectedFailure;
+    type Future = Delayed<Req>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let delay = (rng::next_f64(&self.rng) < self.rate).then(|| self.timer.sleep(self.latency));
+        Delayed {
+            delay,
+            response: Some(req),
+        }
+    }
+}
+
+// === impl Delayed ===
+
+impl<T: fmt::Debug> fmt::Debug for Delayed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Delayed")
+            .field("delayed", &self.delay.is_some())
+            .field("response", &self.response)
+            .finish()
+    }
+}
+
+impl<T> Future for Delayed<T> {
+    type Output = Result<T, InjectedFailure>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(delay) = this.delay.as_mut() {
+            std::task::ready!(delay.as_mut().poll(cx));
+            *this.delay = None;
+        }
+        let response = this.response.take().expect("polled after completion");
+        Poll::Ready(Ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+    use tower::ServiceExt;
+
+    async fn outcomes<S>(mut svc: S, n: usize) -> Vec<bool>
+    where
+        S: Service<(), Response = (), Error = InjectedFailure>,
+    {
+        let mut outcomes = Vec::with_capacity(n);
+        for _ in 0..n {
+            outcomes.push(svc.ready().await.unwrap().call(()).await.is_ok());
+        }
+        outcomes
+    }
+
+    #[tokio::test]
+    async fn counted() {
+        assert_eq!([false; 3], *outcomes(AlwaysFail::new(), 3).await);
+        assert_eq!(
+            [false, false, true, true],
+            *outcomes(FailFirst::new(2), 4).await
+        );
+        assert_eq!(
+            [true, true, false, false],
+            *outcomes(FailAfter::new(2), 4).await
+        );
+    }
+
+    #[tokio::test]
+    async fn flaky() {
+        assert_eq!([true; 10], *outcomes(Flaky::new(0.0), 10).await);
+        assert_eq!([false; 10], *outcomes(Flaky::new(1.0), 10).await);
+
+        let seeded = |seed| Flaky::new(0.5).with_rng(XorShift64::seed(seed));
+        assert_eq!(outcomes(seeded(3), 32).await, outcomes(seeded(3), 32).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn latency_spikes() {
+        let svc = LatencySpikes::new(Duration::from_secs(1), 1.0);
+        let start = time::Instant::now();
+        svc.clone().oneshot(()).await.unwrap();
+        assert_eq!(Duration::from_secs(1), start.elapsed());
+
+        let svc = LatencySpikes::new(Duration::from_secs(1), 0.0);
+        let start = time::Instant::now();
+        svc.oneshot(()).await.unwrap();
+        assert_eq!(Duration::ZERO, start.elapsed());
+    }
+}
@@ -0,0 +1,349 @@
+//! Pluggable timers.
+//!
+//! When a breaker's circuit opens, callers waiting for the breaker to become
+//! ready are woken once the trip ends. A [`Timer`] provides the sleep futures
+//! used to wake them, so that breakers are not tied to a particular async
+//! runtime. By default, Tokio's timer is used when the `rt-tokio` feature
+//! flag is enabled (which it is by default).
+//!
+//! Without the `rt-tokio` feature, a timer must be provided for each breaker
+//! using [`Config::with_timer`](crate::Config::with_timer); otherwise,
+//! constructing the breaker fails with a [`NoTimer`](crate::error::NoTimer)
+//! error. A [`ManualClock`](crate::clock::ManualClock) is also a timer, for
+//! tests. [`FailureInjector`](crate::chaos::FailureInjector)s that inject
+//! latency, and the background tasks which sleep between evaluations, also
+//! need a timer, and panic if they sleep without one.
+//!
+//! # Timer Wheels
+//!
+//! Each caller waiting for a trip to end holds its own sleep future, so a
+//! [`KeyedCircuitBreaker`](crate::keyed::KeyedCircuitBreaker) with thousands
+//! of open circuits may hold thousands of runtime timers. A [`TimerWheel`]
+//! instead tracks every sleep in one shared wheel, which is advanced by a
+//! single [driver](TimerWheel::driver) task using a single timer, at the cost
+//! of waking callers up to one tick late.
+//!
+//! # WebAssembly
+//!
+//! Disabling `rt-tokio` removes the dependency on Tokio's timer, but the
+//! breaker still measures trips using [`std::time::Instant`], and timestamps
+//! trips and outcomes with [`std::time::SystemTime::now`], neither of which
+//! is available on `wasm32-unknown-unknown`. Supporting that target requires
+//! replacement `Instant` and `SystemTime` types (such as those provided by
+//! the `web-time` crate), which are not yet supported.
+use crate::clock::{self, Clock, SharedClock};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// A source of sleep futures.
+pub trait Timer: fmt::Debug + Send + Sync + 'static {
+    /// Returns a future that completes once `duration` has elapsed.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// A future returned by a [`Timer`], which completes once a duration has
+/// elapsed.
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// A [`Timer`] backed by Tokio's timer.
+///
+/// This requires the `rt-tokio` feature flag, and is the default timer when
+/// that feature is enabled.
+#[cfg(feature = "rt-tokio")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TokioTimer;
+
+/// A [`Timer`] which tracks every sleep in a shared hashed timer wheel,
+/// rather than creating a timer for each one.
+///
+/// Cloning a `TimerWheel` returns a new reference to the same wheel. The
+/// wheel is advanced once per `resolution` by the future returned by
+/// [`driver`](TimerWheel::driver), which must be spawned for any of its
+/// sleeps to complete. Each sleep completes on the first tick after its
+/// duration has elapsed.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone)]
+pub struct TimerWheel(Arc<Wheel>);
+
+struct Wheel {
+    resolution: Duration,
+    clock: SharedClock,
+    timer: SharedTimer,
+    started: Instant,
+    state: Mutex<WheelState>,
+}
+
+#[derive(Default)]
+struct WheelState {
+    /// The sleeps waiting for each tick, indexed by the tick modulo the
+    /// number of slots.
+    slots: Vec<Vec<Entry>>,
+    /// The last tick whose sleeps have been woken.
+    ticked: u64,
+    next_id: u64,
+}
+
+struct Entry {
+    id: u64,
+    tick: u64,
+    waker: Waker,
+}
+
+/// A sleep registered with a [`TimerWheel`].
+struct WheelSleep {
+    wheel: Arc<Wheel>,
+    tick: u64,
+    /// The sleep's entry in the wheel, once it's been polled.
+    id: Option<u64>,
+}
+
+/// The number of slots in a [`TimerWheel`]. Sleeps more than this many ticks
+/// away share a slot with sooner ones, and are skipped until their tick.
+const SLOTS: usize = 64;
+
+pub(crate) type SharedTimer = Arc<dyn Timer>;
+
+/// Returns the timer of the async runtime enabled by feature flags, if any.
+pub(crate) fn runtime() -> Option<SharedTimer> {
+    #[cfg(feature = "rt-tokio")]
+    return Some(Arc::new(TokioTimer));
+    #[cfg(not(feature = "rt-tokio"))]
+    return None;
+}
+
+pub(crate) fn default() -> SharedTimer {
+    runtime().unwrap_or_else(|| Arc::new(NoTimer))
+}
+
+/// The default timer of background tasks when no runtime's timer is
+/// enabled.
+#[derive(Debug)]
+struct NoTimer;
+
+// === impl TokioTimer ===
+
+#[cfg(feature = "rt-tokio")]
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+// === impl NoTimer ===
+
+impl Timer for NoTimer {
+    fn sleep(&self, _: Duration) -> Sleep {
+        panic!(
+            "no timer configured: enable the `rt-tokio` feature flag, or provide a `Timer` \
+            using `Config::with_timer`"
+        )
+    }
+}
+
+// === impl TimerWheel ===
+
+impl TimerWheel {
+    /// Returns a new `TimerWheel` which advances once per `resolution`.
+    ///
+    /// # Panics
+    ///
+    /// If `resolution` is zero.
+    pub fn new(resolution: Duration) -> Self {
+        assert!(!resolution.is_zero(), "timer wheel resolution must be > 0");
+        Self::build(resolution, clock::default(), default())
+    }
+
+    /// Returns this wheel, reading the current time from the provided
+    /// [`Clock`] rather than from Tokio.
+    ///
+    /// This must be called before the wheel is used.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self::build(self.0.resolution, Arc::new(clock), self.0.timer.clone())
+    }
+
+    /// Returns this wheel, advancing it using the provided [`Timer`] rather
+    /// than Tokio's timer.
+    ///
+    /// This must be called before the wheel is used.
+    pub fn with_timer(self, timer: impl Timer) -> Self {
+        Self::build(self.0.resolution, self.0.clock.clone(), Arc::new(timer))
+    }
+
+    fn build(resolution: Duration, clock: SharedClock, timer: SharedTimer) -> Self {
+        let started = clock.now();
+        TimerWheel(Arc::new(Wheel {
+            resolution,
+            clock,
+            timer,
+            started,
+            state: Mutex::new(WheelState {
+                slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+                ..WheelState::default()
+            }),
+        }))
+    }
+
+    /// Returns a future which advances the wheel once per tick, until the
+    /// wheel and every sleep it has created have been dropped.
+    ///
+    /// The returned future should be spawned (e.g. with `tokio::spawn`).
+    pub fn driver(&self) -> impl Future<Output = ()> + Send + 'static {
+        let wheel = Arc::downgrade(&self.0);
+        let timer = self.0.timer.clone();
+        let resolution = self.0.resolution;
+        async move {
+            loop {
+                timer.sleep(resolution).await;
+                let Some(wheel) = Weak::upgrade(&wheel) else {
+                    return;
+                };
+                wheel.advance();
+            }
+        }
+    }
+}
+
+impl Timer for TimerWheel {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let elapsed = self.0.clock.now().saturating_duration_since(self.0.started) + duration;
+        // round up, so that sleeps never complete early.
+        let tick = elapsed.as_nanos().div_ceil(self.0.resolution.as_nanos()) as u64;
+        Box::pin(WheelSleep {
+            wheel: self.0.clone(),
+            tick,
+            id: None,
+        })
+    }
+}
+
+impl fmt::Debug for TimerWheel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0.state.lock().unwrap();
+        f.debug_struct("TimerWheel")
+            .field("resolution", &self.0.resolution)
+            .field("ticked", &state.ticked)
+            .field("sleeps", &state.slots.iter().map(Vec::len).sum::<usize>())
+            .finish()
+    }
+}
+
+// === impl Wheel ===
+
+impl Wheel {
+    /// Returns the number of ticks which have elapsed since the wheel was
+    /// created.
+    fn now(&self) -> u64 {
+        let elapsed = self.clock.now().saturating_duration_since(self.started);
+        (elapsed.as_nanos() / self.resolution.as_nanos()) as u64
+    }
+
+    /// Wakes every sleep whose tick has been reached.
+    fn advance(&self) {
+        let now = self.now();
+        let mut woken = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            if now <= state.ticked {
+                return;
+            }
+            // every slot has been passed once the wheel has advanced a full
+            // turn.
+            for tick in state.ticked + 1..=now.min(state.ticked + SLOTS as u64) {
+                let slot = &mut state.slots[tick as usize % SLOTS];
+                let mut i = 0;
+                while i < slot.len() {
+                    if slot[i].tick <= now {
+                        woken.push(slot.swap_remove(i).waker);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            state.ticked = now;
+        }
+        for waker in woken {
+            waker.wake();
+        }
+    }
+}
+
+// === impl WheelSleep ===
+
+impl Future for WheelSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.wheel.now() >= self.tick {
+            self.deregister();
+            return Poll::Ready(());
+        }
+        let this = &mut *self;
+        let mut state = this.wheel.state.lock().unwrap();
+        let id = *this.id.get_or_insert_with(|| {
+            state.next_id += 1;
+            state.next_id
+        });
+        let slot = &mut state.slots[this.tick as usize % SLOTS];
+        match slot.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) => entry.waker.clone_from(cx.waker()),
+            // the entry hasn't been added yet, or the wheel woke it before
+            // the clock reached its tick.
+            None => slot.push(Entry {
+                id,
+                tick: this.tick,
+                waker: cx.waker().clone(),
+            }),
+        }
+        Poll::Pending
+    }
+}
+
+impl WheelSleep {
+    fn deregister(&mut self) {
+        let Some(id) = self.id.take() else {
+            return;
+        };
+        let mut state = self.wheel.state.lock().unwrap();
+        state.slots[self.tick as usize % SLOTS].retain(|entry| entry.id != id);
+    }
+}
+
+impl Drop for WheelSleep {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+
+#[cfg(all(test, feature = "rt-tokio"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn wheel_wakes_sleeps() {
+        let wheel = TimerWheel::new(Duration::from_millis(10));
+        tokio::spawn(wheel.driver());
+
+        // sleeps complete on the first tick after their duration.
+        let start = tokio::time::Instant::now();
+        wheel.sleep(Duration::from_millis(25)).await;
+        assert_eq!(Duration::from_millis(30), start.elapsed());
+
+        // including sleeps longer than a full turn of the wheel.
+        let start = tokio::time::Instant::now();
+        wheel.sleep(Duration::from_secs(2)).await;
+        assert_eq!(Duration::from_secs(2), start.elapsed());
+
+        // a dropped sleep leaves nothing behind in the wheel.
+        let sleep = wheel.sleep(Duration::from_secs(1));
+        let dropped = tokio::time::timeout(Duration::from_millis(100), sleep).await;
+        assert!(dropped.is_err());
+        assert!(format!("{wheel:?}").contains("sleeps: 0"), "{wheel:?}");
+    }
+}
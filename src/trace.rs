@@ -0,0 +1,131 @@
+//! Macros for emitting `tracing` events and spans.
+//!
+//! When the `tracing` feature flag is disabled, these macros expand to
+//! nothing, and spans are replaced with a no-op [`Span`].
+//!
+//! `tracing`'s macros require the level to be a constant, so `dyn_event!`
+//! and `dyn_span!` just `match` over each level and expand to the
+//! corresponding macro invocation, for events and spans at a
+//! runtime-configured [`Level`](tracing::Level).
+
+#[cfg(feature = "tracing")]
+macro_rules! dyn_event {
+    ($lvl:expr, $($arg:tt)+) => {
+        match $lvl {
+            ::tracing::Level::ERROR => ::tracing::event!(::tracing::Level::ERROR, $($arg)+),
+            ::tracing::Level::WARN => ::tracing::event!(::tracing::Level::WARN, $($arg)+),
+            ::tracing::Level::INFO => ::tracing::event!(::tracing::Level::INFO, $($arg)+),
+            ::tracing::Level::DEBUG => ::tracing::event!(::tracing::Level::DEBUG, $($arg)+),
+            ::tracing::Level::TRACE => ::tracing::event!(::tracing::Level::TRACE, $($arg)+),
+        }
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! dyn_span {
+    ($lvl:expr, $($arg:tt)+) => {
+        match $lvl {
+            ::tracing::Level::ERROR => ::tracing::span!(::tracing::Level::ERROR, $($arg)+),
+            ::tracing::Level::WARN => ::tracing::span!(::tracing::Level::WARN, $($arg)+),
+            ::tracing::Level::INFO => ::tracing::span!(::tracing::Level::INFO, $($arg)+),
+            ::tracing::Level::DEBUG => ::tracing::span!(::tracing::Level::DEBUG, $($arg)+),
+            ::tracing::Level::TRACE => ::tracing::span!(::tracing::Level::TRACE, $($arg)+),
+        }
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! trace {
+    ($($arg:tt)+) => { ::tracing::trace!($($arg)+) };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)+) => { ::tracing::debug!($($arg)+) };
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! dyn_event {
+    ($($arg:tt)+) => {};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! dyn_span {
+    ($($arg:tt)+) => {
+        $crate::trace::Span::none()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace {
+    ($($arg:tt)+) => {};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)+) => {};
+}
+
+/// A no-op stand-in for [`tracing::Span`].
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Debug)]
+pub(crate) struct Span {
+    _p: (),
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Entered {
+    _p: (),
+}
+
+pub(crate) use {debug, dyn_event, dyn_span, trace};
+
+/// Emits the event recording that a breaker's circuit opened.
+///
+/// The policy's snapshot is flattened into the event's fields, so that a
+/// single event explains why the breaker tripped.
+#[cfg(feature = "tracing")]
+pub(crate) fn circuit_opened(
+    level: tracing::Level,
+    breaker: Option<&str>,
+    reason: crate::TripReason,
+    policy: Option<&dyn std::fmt::Debug>,
+    snapshot: &crate::policy::PolicySnapshot,
+    trip_for: std::time::Duration,
+) {
+    dyn_event!(
+        level,
+        breaker,
+        %reason,
+        policy = policy.map(tracing::field::debug),
+        requests = snapshot.requests,
+        failures = snapshot.failures,
+        failure_rate = snapshot.failure_rate,
+        max_failure_rate = snapshot.max_failure_rate,
+        consecutive_failures = snapshot.consecutive_failures,
+        max_consecutive_failures = snapshot.max_consecutive_failures,
+        window = snapshot.window.map(tracing::field::debug),
+        ?trip_for,
+        "circuit breaker opened"
+    );
+}
+
+// === impl Span ===
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn none() -> Self {
+        Span { _p: () }
+    }
+
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    pub(crate) fn enter(&self) -> Entered {
+        Entered { _p: () }
+    }
+}
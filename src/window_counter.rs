@@ -1,18 +1,22 @@
-use std::sync::{
-    atomic::{AtomicU64, AtomicUsize, Ordering},
-    Mutex,
+use crate::clock::SharedClock;
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
 };
-use tokio::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct WindowedCounter {
+    clock: SharedClock,
     anchor: Instant,
     epoch: AtomicU64,
     epoch_index: Mutex<usize>,
 
-    /// Buckets tracking the number of events counted over `1/NUM_BUCKETS` of
-    /// the time window.
-    buckets: [AtomicUsize; NUM_BUCKETS],
+    /// Buckets tracking the number of events counted over an equal fraction
+    /// of the time window.
+    buckets: Box<[AtomicUsize]>,
 
     /// The fraction of the time window represented by one bucket, in
     /// milliseconds.
@@ -21,34 +25,65 @@ pub struct WindowedCounter {
     /// Writes to the current time slice, committed when expiring past buckets.
     current: AtomicUsize,
 }
-const NUM_BUCKETS: usize = 10;
+/// The number of buckets a window is divided into by default.
+pub(crate) const NUM_BUCKETS: usize = 10;
 
 impl WindowedCounter {
-    pub fn new(window: Duration) -> Self {
-        // clippy doesn't like interior mutable items in `const`s, because
-        // mutating an instance of the `const` value will not mutate the const.
-        // that is the *correct* behavior here, as the const is used just as an
-        // array initializer; every time it's referenced, it *should* produce a
-        // new value. therefore, this warning is incorrect in this case.
-        //
-        // see https://github.com/rust-lang/rust-clippy/issues/7665
-        #[allow(clippy::declare_interior_mutable_const)]
-        const ATOMIC_USIZE_ZERO: AtomicUsize = AtomicUsize::new(0);
+    pub fn new(window: Duration, clock: SharedClock) -> Self {
+        Self::with_buckets(window, NUM_BUCKETS, clock)
+    }
 
+    /// Returns a new counter over `window`, divided into `buckets` buckets.
+    ///
+    /// More buckets expire counts more smoothly as the window slides, at the
+    /// cost of more memory and more work each time the window advances.
+    ///
+    /// Buckets are at least 1ms wide, since a bucket any narrower would round
+    /// down to 0ms and expire every count each time the window advanced.
+    pub fn with_buckets(window: Duration, buckets: usize, clock: SharedClock) -> Self {
+        debug_assert!(buckets > 0, "a windowed counter needs at least one bucket");
+        let bucket_window_ms = window.as_millis() / buckets.max(1) as u128;
         WindowedCounter {
-            anchor: Instant::now(),
+            anchor: clock.now(),
+            clock,
             epoch: AtomicU64::new(0),
             epoch_index: Mutex::new(0),
-            buckets: [ATOMIC_USIZE_ZERO; NUM_BUCKETS],
-            bucket_window_ms: (window / (NUM_BUCKETS) as u32).as_millis() as u64,
+            buckets: (0..buckets).map(|_| AtomicUsize::new(0)).collect(),
+            bucket_window_ms: bucket_window_ms.clamp(1, u64::MAX as u128) as u64,
             current: AtomicUsize::new(0),
         }
     }
 
     pub fn add(&self, amount: usize) {
         self.expire();
-        // TODO(eliza): probably doesn't need to be seqcst...
-        self.current.fetch_add(amount, Ordering::SeqCst);
+        // the count is only summed after the fact, so nothing is ordered
+        // against this write.
+        self.current.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /// Adds `amount` to the count as though it had been added `ago` in the
+    /// past.
+    ///
+    /// Counts older than the window are discarded.
+    pub fn add_ago(&self, amount: usize, ago: Duration) {
+        self.expire();
+        let epoch_index = self.epoch_index.lock().unwrap();
+        let epoch = self.epoch.load(Ordering::Acquire) as i128;
+        let at = self.elapsed().as_millis() as i128 - ago.as_millis() as i128;
+        if at >= epoch {
+            self.current.fetch_add(amount, Ordering::SeqCst);
+            return;
+        }
+
+        // the number of buckets before the current one that `at` falls in.
+        let bucket_window_ms = self.bucket_window_ms.max(1) as i128;
+        let behind = ((epoch - at + bucket_window_ms - 1) / bucket_window_ms) as usize;
+        let num_buckets = self.buckets.len();
+        if behind >= num_buckets {
+            return;
+        }
+        let i = (*epoch_index + num_buckets - behind) % num_buckets;
+        self.buckets[i].fetch_add(amount, Ordering::SeqCst);
     }
 
     pub fn sum(&self) -> usize {
@@ -62,7 +97,7 @@ impl WindowedCounter {
 
     pub fn reset(&self) {
         let mut epoch_index = self.epoch_index.lock().unwrap();
-        let my_epoch = self.anchor.elapsed().as_millis() as u64;
+        let my_epoch = self.elapsed().as_millis() as u64;
         self.epoch.store(my_epoch, Ordering::SeqCst);
         self.current.store(0, Ordering::SeqCst);
         for bucket in &self.buckets {
@@ -72,6 +107,16 @@ impl WindowedCounter {
         *epoch_index = 0;
     }
 
+    /// Returns the clock this counter reads the current time from.
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    /// Returns the time elapsed since this counter was created.
+    fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.anchor)
+    }
+
     /// Expire all counts outside the time window.
     fn expire(&self) {
         // the unchecked cast to `u64` is almost certainly fine here, since the
@@ -80,9 +125,13 @@ impl WindowedCounter {
         // `u64::MAX` milliseconds, that would be 1.8E16 seconds, or around 584
         // million years.... if you're still running this code in 500 million
         // years, you probably have worse problems...
-        let mut my_epoch = self.anchor.elapsed().as_millis() as u64;
+        //
+        // load the current epoch *before* reading the clock: another thread
+        // may advance the epoch concurrently, and reading the clock second
+        // ensures that our time is never behind the epoch we compare it to.
         let cur_epoch = self.epoch.load(Ordering::Acquire);
-        let mut delta = my_epoch - cur_epoch;
+        let mut my_epoch = self.elapsed().as_millis() as u64;
+        let mut delta = my_epoch.saturating_sub(cur_epoch);
 
         if delta < self.bucket_window_ms {
             // still in the current epoch's bucket, we don't need to do
@@ -91,7 +140,8 @@ impl WindowedCounter {
         }
 
         let mut epoch_index = self.epoch_index.lock().unwrap();
-        my_epoch = self.anchor.elapsed().as_millis() as u64;
+        my_epoch = self.elapsed().as_millis() as u64;
+        delta = my_epoch.saturating_sub(cur_epoch);
         match self
             .epoch
             .compare_exchange(cur_epoch, my_epoch, Ordering::AcqRel, Ordering::Acquire)
@@ -100,10 +150,21 @@ impl WindowedCounter {
             Ok(_) => {}
             // someone else advanced the epoch while we were doing math, nothing
             // else for us to do here...
-            Err(actual) => {
-                dbg!(actual);
-                return;
+            Err(_) => return,
+        }
+
+        // if the entire window has elapsed since the epoch last advanced
+        // (such as after the process was suspended, or its VM was paused),
+        // every count has expired. rather than clearing the buckets one
+        // window at a time, reset the whole counter.
+        let num_buckets = self.buckets.len();
+        if delta >= self.bucket_window_ms * num_buckets as u64 {
+            self.current.store(0, Ordering::SeqCst);
+            for bucket in &self.buckets {
+                bucket.store(0, Ordering::SeqCst);
             }
+            *epoch_index = 0;
+            return;
         }
 
         // commit all the writes in the current bucket
@@ -112,11 +173,11 @@ impl WindowedCounter {
 
         // clear all the buckets that we've passed over based on the elapsed
         // time delta.
-        let mut i = (*epoch_index + 1) % NUM_BUCKETS;
+        let mut i = (*epoch_index + 1) % num_buckets;
         while delta > self.bucket_window_ms {
             self.buckets[i].store(0, Ordering::SeqCst);
             delta -= self.bucket_window_ms;
-            i = (i + 1) % NUM_BUCKETS;
+            i = (i + 1) % num_buckets;
         }
 
         // always zero the current bucket, since its count will be stored in
@@ -129,10 +190,11 @@ impl WindowedCounter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock;
     use tokio::time;
 
     fn counter() -> WindowedCounter {
-        WindowedCounter::new(time::Duration::from_secs(3))
+        WindowedCounter::new(time::Duration::from_secs(3), clock::default())
     }
 
     // fn dump(ctr: &WindowedCounter) {
@@ -202,7 +264,7 @@ mod tests {
     #[tokio::test]
     async fn sliding_over_large_window() {
         time::pause();
-        let ctr = WindowedCounter::new(Duration::from_secs(20));
+        let ctr = WindowedCounter::new(Duration::from_secs(20), clock::default());
 
         for i in 0..21 {
             ctr.add(dbg!(i % 3));
@@ -260,4 +322,102 @@ mod tests {
         dbg!(time::advance(Duration::from_secs(100)).await);
         assert_eq!(0, dbg!(ctr.sum()));
     }
+
+    #[tokio::test]
+    async fn add_ago() {
+        time::pause();
+        let ctr = WindowedCounter::new(Duration::from_secs(10), clock::default());
+
+        ctr.add_ago(1, Duration::ZERO);
+        ctr.add_ago(1, Duration::from_secs(3));
+        ctr.add_ago(1, Duration::from_millis(8500));
+        ctr.add_ago(1, Duration::from_secs(11));
+        assert_eq!(3, ctr.sum());
+
+        time::advance(Duration::from_secs(2)).await;
+        assert_eq!(2, ctr.sum());
+
+        time::advance(Duration::from_secs(6)).await;
+        assert_eq!(1, ctr.sum());
+    }
+
+    #[test]
+    fn narrow_buckets() {
+        use std::sync::Arc;
+
+        // 1000 buckets over 500ms would each be 0.5ms wide.
+        let clock = clock::ManualClock::new();
+        let ctr = WindowedCounter::with_buckets(
+            Duration::from_millis(500),
+            1000,
+            Arc::new(clock.clone()),
+        );
+        for _ in 0..10 {
+            ctr.add(1);
+            clock.advance(Duration::from_micros(100));
+        }
+        assert_eq!(10, ctr.sum());
+    }
+
+    #[test]
+    fn large_clock_jump() {
+        use crate::clock::ManualClock;
+        use std::sync::Arc;
+
+        let clock = ManualClock::new();
+        let ctr = WindowedCounter::new(Duration::from_secs(10), Arc::new(clock.clone()));
+        ctr.add(1);
+        clock.advance(Duration::from_secs(1));
+        ctr.add(1);
+        assert_eq!(2, ctr.sum());
+
+        // a jump of many windows (e.g. after resuming from suspend) expires
+        // everything, without stepping through each elapsed bucket.
+        clock.advance(Duration::from_secs(60 * 60 * 24 * 365 * 100));
+        assert_eq!(0, ctr.sum());
+
+        // the counter continues to work normally afterwards.
+        ctr.add(3);
+        clock.advance(Duration::from_secs(5));
+        ctr.add(1);
+        assert_eq!(4, ctr.sum());
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(1, ctr.sum());
+    }
+
+    #[test]
+    fn concurrent_adds_are_not_lost() {
+        use crate::clock::ManualClock;
+        use std::{sync::Arc, thread};
+
+        const THREADS: usize = 4;
+        const ADDS: usize = 10_000;
+
+        let clock = ManualClock::new();
+        let ctr = Arc::new(WindowedCounter::new(
+            Duration::from_secs(10),
+            Arc::new(clock.clone()),
+        ));
+        let adders = (0..THREADS)
+            .map(|_| {
+                let ctr = ctr.clone();
+                thread::spawn(move || {
+                    for _ in 0..ADDS {
+                        ctr.add(1);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        // rotate through buckets while the adders are running, but stay
+        // within the window, so that nothing is expired.
+        for _ in 0..NUM_BUCKETS - 1 {
+            clock.advance(Duration::from_secs(1));
+            thread::yield_now();
+        }
+        for adder in adders {
+            adder.join().unwrap();
+        }
+
+        assert_eq!(THREADS * ADDS, ctr.sum());
+    }
 }